@@ -0,0 +1,117 @@
+//! writes functions out as binary chunks, lua 5.1's `ldump.c`. the chunk
+//! is made for this machine, the same as `luac` would make here, so it can
+//! be loaded again by us or by lua itself.
+
+use std::rc::Rc;
+
+use crate::value::Value;
+use crate::bytecode::{Proto, Source};
+use crate::bytecode::undump::{SIGNATURE, VERSION, FORMAT, TYPE_NIL, TYPE_BOOLEAN, TYPE_NUMBER, TYPE_STRING};
+
+pub fn dump(proto : &Proto) -> Vec<u8> {
+    //! the function, and all the functions inside of it, as a binary chunk
+
+    let mut writer = Writer { code : Vec::new() };
+
+    writer.header();
+    writer.function(proto, None);
+
+    writer.code
+}
+
+struct Writer {
+    code : Vec<u8>,
+}
+
+impl Writer {
+    fn header(&mut self) {
+        self.code.extend_from_slice(SIGNATURE);
+        self.code.extend_from_slice(&[
+            VERSION,
+            FORMAT,
+            cfg!(target_endian = "little") as u8,
+            std::mem::size_of::<i32>() as u8,
+            std::mem::size_of::<usize>() as u8,
+            std::mem::size_of::<u32>() as u8,
+            std::mem::size_of::<f64>() as u8,
+            // the numbers aren't whole numbers
+            0,
+        ]);
+    }
+
+    fn function(&mut self, proto : &Proto, parent : Option<&Rc<Source>>) {
+        //! everything in the same order `undump` reads it
+
+        // the functions inside of a chunk have the same name as it, so it
+        // is only written once. the name we have is the one it is shown
+        // as, so it is written as one that is used as it is.
+        match parent {
+            Some(parent) if Rc::ptr_eq(parent, &proto.source) => self.size(0),
+            _ => self.string(format!("={}", proto.source.name).as_bytes()),
+        }
+
+        self.integer(proto.line_defined);
+        self.integer(proto.last_line_defined);
+        self.code.extend_from_slice(&[proto.upvalues as u8, proto.parameters as u8, proto.is_vararg, proto.max_stack as u8]);
+
+        self.integer(proto.code.len());
+        for instruction in proto.code.iter() {
+            self.code.extend_from_slice(&instruction.0.to_ne_bytes());
+        }
+
+        self.integer(proto.constants.len());
+        for constant in proto.constants.iter() {
+            match constant {
+                Value::Boolean(boolean) => self.code.extend_from_slice(&[TYPE_BOOLEAN, *boolean as u8]),
+                Value::Number(number) => {
+                    self.code.push(TYPE_NUMBER);
+                    self.code.extend_from_slice(&number.to_ne_bytes());
+                },
+                Value::String(string) => {
+                    self.code.push(TYPE_STRING);
+                    self.string(string.as_bytes());
+                },
+                // `nil`, the compiler doesn't make any other kind of constant
+                _ => self.code.push(TYPE_NIL),
+            }
+        }
+
+        self.integer(proto.protos.len());
+        for child in proto.protos.iter() {
+            self.function(child, Some(&proto.source));
+        }
+
+        self.integer(proto.lines.len());
+        for line in proto.lines.iter() {
+            self.integer(*line);
+        }
+
+        self.integer(proto.locals.len());
+        for local in proto.locals.iter() {
+            self.string(local.name.as_bytes());
+            self.integer(local.start);
+            self.integer(local.end);
+        }
+
+        self.integer(proto.upvalue_names.len());
+        for name in proto.upvalue_names.iter() {
+            self.string(name.as_bytes());
+        }
+    }
+
+    fn integer(&mut self, number : usize) {
+        self.code.extend_from_slice(&(number as i32).to_ne_bytes());
+    }
+
+    fn size(&mut self, size : usize) {
+        self.code.extend_from_slice(&size.to_ne_bytes());
+    }
+
+    fn string(&mut self, string : &[u8]) {
+        //! strings end with a `\0`, which is counted in the size
+
+        self.size(string.len() + 1);
+        self.code.extend_from_slice(string);
+        self.code.push(0);
+    }
+}
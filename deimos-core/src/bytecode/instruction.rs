@@ -0,0 +1,379 @@
+//! the instructions the virtual machine runs. they are the same ones lua
+//! 5.1 has, packed into 32 bits the same way, so chunks compiled by `luac`
+//! can be run and ours can be read by other tools.
+//!
+//! every instruction has an opcode and up to three arguements, `A` is a
+//! register and `B` and `C` are registers or constants (`RK`). some use a
+//! bigger `Bx` instead of `B` and `C`, which is signed for the jumps.
+//!
+//! ```text
+//!  31       23       14      6     0
+//!  |   B    |   C    |   A   |  op |
+//!  |       Bx        |   A   |  op |
+//! ```
+
+use std::fmt;
+
+const SIZE_OP : u32 = 6;
+const SIZE_A : u32 = 8;
+const SIZE_B : u32 = 9;
+const SIZE_C : u32 = 9;
+const SIZE_BX : u32 = SIZE_B + SIZE_C;
+
+const POS_A : u32 = SIZE_OP;
+const POS_C : u32 = POS_A + SIZE_A;
+const POS_B : u32 = POS_C + SIZE_C;
+const POS_BX : u32 = POS_C;
+
+pub const MAXARG_A : usize = (1 << SIZE_A) - 1;
+pub const MAXARG_C : usize = (1 << SIZE_C) - 1;
+pub const MAXARG_BX : usize = (1 << SIZE_BX) - 1;
+pub const MAXARG_SBX : i32 = (MAXARG_BX >> 1) as i32;
+
+/// the bit of a `B` or `C` arguement that makes it a constant
+pub const BIT_RK : usize = 1 << (SIZE_B - 1);
+
+/// the biggest constant index that fits in a `B` or `C` arguement
+pub const MAX_INDEX_RK : usize = BIT_RK - 1;
+
+/// how many values of a table constructor are put in the table at a time
+pub const FIELDS_PER_FLUSH : usize = 50;
+
+/// the most registers a function can use
+pub const MAX_STACK : usize = 250;
+
+/// used for an `A` that doesn't point at any register
+pub const NO_REGISTER : usize = MAXARG_A;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OpCode {
+    Move,       // A B      R(A) := R(B)
+    LoadK,      // A Bx     R(A) := Kst(Bx)
+    LoadBool,   // A B C    R(A) := (Bool)B; if (C) pc++
+    LoadNil,    // A B      R(A) := ... := R(B) := nil
+    GetUpval,   // A B      R(A) := UpValue[B]
+    GetGlobal,  // A Bx     R(A) := Gbl[Kst(Bx)]
+    GetTable,   // A B C    R(A) := R(B)[RK(C)]
+    SetGlobal,  // A Bx     Gbl[Kst(Bx)] := R(A)
+    SetUpval,   // A B      UpValue[B] := R(A)
+    SetTable,   // A B C    R(A)[RK(B)] := RK(C)
+    NewTable,   // A B C    R(A) := {} (size = B,C)
+    SelfOp,     // A B C    R(A+1) := R(B); R(A) := R(B)[RK(C)]
+    Add,        // A B C    R(A) := RK(B) + RK(C)
+    Sub,        // A B C    R(A) := RK(B) - RK(C)
+    Mul,        // A B C    R(A) := RK(B) * RK(C)
+    Div,        // A B C    R(A) := RK(B) / RK(C)
+    Mod,        // A B C    R(A) := RK(B) % RK(C)
+    Pow,        // A B C    R(A) := RK(B) ^ RK(C)
+    Unm,        // A B      R(A) := -R(B)
+    Not,        // A B      R(A) := not R(B)
+    Len,        // A B      R(A) := length of R(B)
+    Concat,     // A B C    R(A) := R(B).. ... ..R(C)
+    Jmp,        // sBx      pc+=sBx
+    Eq,         // A B C    if ((RK(B) == RK(C)) ~= A) then pc++
+    Lt,         // A B C    if ((RK(B) <  RK(C)) ~= A) then pc++
+    Le,         // A B C    if ((RK(B) <= RK(C)) ~= A) then pc++
+    Test,       // A C      if not (R(A) <=> C) then pc++
+    TestSet,    // A B C    if (R(B) <=> C) then R(A) := R(B) else pc++
+    Call,       // A B C    R(A), ... ,R(A+C-2) := R(A)(R(A+1), ... ,R(A+B-1))
+    TailCall,   // A B C    return R(A)(R(A+1), ... ,R(A+B-1))
+    Return,     // A B      return R(A), ... ,R(A+B-2)
+    ForLoop,    // A sBx    R(A)+=R(A+2); if R(A) <?= R(A+1) then { pc+=sBx; R(A+3)=R(A) }
+    ForPrep,    // A sBx    R(A)-=R(A+2); pc+=sBx
+    TForLoop,   // A C      R(A+3), ... ,R(A+2+C) := R(A)(R(A+1), R(A+2)); if R(A+3) ~= nil then R(A+2)=R(A+3) else pc++
+    SetList,    // A B C    R(A)[(C-1)*FPF+i] := R(A+i), 1 <= i <= B
+    Close,      // A        close all variables in the stack up to (>=) R(A)
+    Closure,    // A Bx     R(A) := closure(KPROTO[Bx], R(A), ... ,R(A+n))
+    VarArg,     // A B      R(A), R(A+1), ..., R(A+B-1) = vararg
+}
+
+/// all the opcodes, in the order of their numbers
+const OPCODES : [OpCode; 38] = [
+    OpCode::Move, OpCode::LoadK, OpCode::LoadBool, OpCode::LoadNil, OpCode::GetUpval,
+    OpCode::GetGlobal, OpCode::GetTable, OpCode::SetGlobal, OpCode::SetUpval, OpCode::SetTable,
+    OpCode::NewTable, OpCode::SelfOp, OpCode::Add, OpCode::Sub, OpCode::Mul,
+    OpCode::Div, OpCode::Mod, OpCode::Pow, OpCode::Unm, OpCode::Not,
+    OpCode::Len, OpCode::Concat, OpCode::Jmp, OpCode::Eq, OpCode::Lt,
+    OpCode::Le, OpCode::Test, OpCode::TestSet, OpCode::Call, OpCode::TailCall,
+    OpCode::Return, OpCode::ForLoop, OpCode::ForPrep, OpCode::TForLoop, OpCode::SetList,
+    OpCode::Close, OpCode::Closure, OpCode::VarArg,
+];
+
+/// how the arguements are laid out, named the way lua's `lopcodes.h` does
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Mode {
+    ABC,
+    ABx,
+    AsBx,
+}
+
+/// what a `B` or `C` arguement is used for
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArgMode {
+    // not used
+    N,
+    // used, but not as a register or constant
+    U,
+    // a register or a jump offset
+    R,
+    // a constant or a register
+    K,
+}
+
+impl OpCode {
+    pub fn from_number(number : u32) -> Option<OpCode> {
+        OPCODES.get(number as usize).cloned()
+    }
+
+    pub fn name(self) -> &'static str {
+        //! the name `luac -l` shows
+
+        match self {
+            OpCode::Move => "MOVE",
+            OpCode::LoadK => "LOADK",
+            OpCode::LoadBool => "LOADBOOL",
+            OpCode::LoadNil => "LOADNIL",
+            OpCode::GetUpval => "GETUPVAL",
+            OpCode::GetGlobal => "GETGLOBAL",
+            OpCode::GetTable => "GETTABLE",
+            OpCode::SetGlobal => "SETGLOBAL",
+            OpCode::SetUpval => "SETUPVAL",
+            OpCode::SetTable => "SETTABLE",
+            OpCode::NewTable => "NEWTABLE",
+            OpCode::SelfOp => "SELF",
+            OpCode::Add => "ADD",
+            OpCode::Sub => "SUB",
+            OpCode::Mul => "MUL",
+            OpCode::Div => "DIV",
+            OpCode::Mod => "MOD",
+            OpCode::Pow => "POW",
+            OpCode::Unm => "UNM",
+            OpCode::Not => "NOT",
+            OpCode::Len => "LEN",
+            OpCode::Concat => "CONCAT",
+            OpCode::Jmp => "JMP",
+            OpCode::Eq => "EQ",
+            OpCode::Lt => "LT",
+            OpCode::Le => "LE",
+            OpCode::Test => "TEST",
+            OpCode::TestSet => "TESTSET",
+            OpCode::Call => "CALL",
+            OpCode::TailCall => "TAILCALL",
+            OpCode::Return => "RETURN",
+            OpCode::ForLoop => "FORLOOP",
+            OpCode::ForPrep => "FORPREP",
+            OpCode::TForLoop => "TFORLOOP",
+            OpCode::SetList => "SETLIST",
+            OpCode::Close => "CLOSE",
+            OpCode::Closure => "CLOSURE",
+            OpCode::VarArg => "VARARG",
+        }
+    }
+
+    pub fn mode(self) -> Mode {
+        match self {
+            OpCode::LoadK | OpCode::GetGlobal | OpCode::SetGlobal | OpCode::Closure => Mode::ABx,
+            OpCode::Jmp | OpCode::ForLoop | OpCode::ForPrep => Mode::AsBx,
+            _ => Mode::ABC,
+        }
+    }
+
+    pub fn b_mode(self) -> ArgMode {
+        match self {
+            OpCode::LoadK | OpCode::GetGlobal | OpCode::SetGlobal | OpCode::SetTable |
+            OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div | OpCode::Mod | OpCode::Pow |
+            OpCode::Eq | OpCode::Lt | OpCode::Le => ArgMode::K,
+            OpCode::Move | OpCode::LoadNil | OpCode::GetTable | OpCode::SelfOp | OpCode::Unm |
+            OpCode::Not | OpCode::Len | OpCode::Concat | OpCode::Jmp | OpCode::Test |
+            OpCode::TestSet | OpCode::ForLoop | OpCode::ForPrep => ArgMode::R,
+            OpCode::TForLoop | OpCode::Close => ArgMode::N,
+            _ => ArgMode::U,
+        }
+    }
+
+    pub fn c_mode(self) -> ArgMode {
+        match self {
+            OpCode::GetTable | OpCode::SetTable | OpCode::SelfOp | OpCode::Add | OpCode::Sub |
+            OpCode::Mul | OpCode::Div | OpCode::Mod | OpCode::Pow | OpCode::Eq | OpCode::Lt |
+            OpCode::Le => ArgMode::K,
+            OpCode::Concat => ArgMode::R,
+            OpCode::LoadBool | OpCode::NewTable | OpCode::Test | OpCode::TestSet | OpCode::Call |
+            OpCode::TailCall | OpCode::TForLoop | OpCode::SetList => ArgMode::U,
+            _ => ArgMode::N,
+        }
+    }
+
+    pub fn sets_a(self) -> bool {
+        //! if the instruction changes register `A`
+
+        !matches!(self,
+            OpCode::SetGlobal | OpCode::SetUpval | OpCode::SetTable | OpCode::Jmp | OpCode::Eq |
+            OpCode::Lt | OpCode::Le | OpCode::Return | OpCode::TForLoop | OpCode::SetList |
+            OpCode::Close)
+    }
+
+    pub fn is_test(self) -> bool {
+        //! if the instruction is a test, the next one is always a jump
+
+        matches!(self, OpCode::Eq | OpCode::Lt | OpCode::Le | OpCode::Test | OpCode::TestSet | OpCode::TForLoop)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Instruction(pub u32);
+
+impl fmt::Debug for Instruction {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.opcode().mode() {
+            Mode::ABC => write!(f, "{} {} {} {}", self.opcode().name(), self.a(), self.b(), self.c()),
+            Mode::ABx => write!(f, "{} {} {}", self.opcode().name(), self.a(), self.bx()),
+            Mode::AsBx => write!(f, "{} {} {}", self.opcode().name(), self.a(), self.sbx()),
+        }
+    }
+}
+
+impl Instruction {
+    pub fn abc(op : OpCode, a : usize, b : usize, c : usize) -> Instruction {
+        Instruction(op as u32 | (a as u32) << POS_A | (b as u32) << POS_B | (c as u32) << POS_C)
+    }
+
+    pub fn abx(op : OpCode, a : usize, bx : usize) -> Instruction {
+        Instruction(op as u32 | (a as u32) << POS_A | (bx as u32) << POS_BX)
+    }
+
+    pub fn asbx(op : OpCode, a : usize, sbx : i32) -> Instruction {
+        Instruction::abx(op, a, (sbx + MAXARG_SBX) as usize)
+    }
+
+    pub fn is_valid(self) -> bool {
+        //! if the opcode is one we know, everything else about the
+        //! instruction fits
+
+        OpCode::from_number(self.0 & mask(SIZE_OP)).is_some()
+    }
+
+    pub fn opcode(self) -> OpCode {
+        //! only instructions that are valid should be asked for their
+        //! opcode, the ones that come from a file are checked first
+
+        OPCODES[(self.0 & mask(SIZE_OP)) as usize]
+    }
+
+    pub fn a(self) -> usize {
+        ((self.0 >> POS_A) & mask(SIZE_A)) as usize
+    }
+
+    pub fn b(self) -> usize {
+        ((self.0 >> POS_B) & mask(SIZE_B)) as usize
+    }
+
+    pub fn c(self) -> usize {
+        ((self.0 >> POS_C) & mask(SIZE_C)) as usize
+    }
+
+    pub fn bx(self) -> usize {
+        ((self.0 >> POS_BX) & mask(SIZE_BX)) as usize
+    }
+
+    pub fn sbx(self) -> i32 {
+        self.bx() as i32 - MAXARG_SBX
+    }
+
+    pub fn set_opcode(&mut self, op : OpCode) {
+        self.0 = (self.0 & !mask(SIZE_OP)) | op as u32;
+    }
+
+    pub fn set_a(&mut self, a : usize) {
+        self.0 = (self.0 & !(mask(SIZE_A) << POS_A)) | (a as u32) << POS_A;
+    }
+
+    pub fn set_b(&mut self, b : usize) {
+        self.0 = (self.0 & !(mask(SIZE_B) << POS_B)) | (b as u32) << POS_B;
+    }
+
+    pub fn set_c(&mut self, c : usize) {
+        self.0 = (self.0 & !(mask(SIZE_C) << POS_C)) | (c as u32) << POS_C;
+    }
+
+    pub fn set_sbx(&mut self, sbx : i32) {
+        let bx = (sbx + MAXARG_SBX) as u32;
+        self.0 = (self.0 & !(mask(SIZE_BX) << POS_BX)) | bx << POS_BX;
+    }
+}
+
+pub fn is_constant(rk : usize) -> bool {
+    //! if the `B` or `C` arguement is a constant and not a register
+
+    rk & BIT_RK != 0
+}
+
+pub fn as_constant(index : usize) -> usize {
+    //! the `B` or `C` arguement for the constant
+
+    index | BIT_RK
+}
+
+pub fn constant_index(rk : usize) -> usize {
+    rk & !BIT_RK
+}
+
+pub fn int_to_fb(mut x : usize) -> usize {
+    //! the sizes of a new table are kept as a "floating point byte",
+    //! `eeeeexxx` is `1xxx * 2^(eeeee - 1)`, rounded up
+
+    let mut e = 0;
+    while x >= 16 {
+        x = (x + 1) >> 1;
+        e += 1;
+    }
+
+    match x < 8 {
+        true => x,
+        false => ((e + 1) << 3) | (x - 8),
+    }
+}
+
+pub fn fb_to_int(x : usize) -> usize {
+    let e = (x >> 3) & 31;
+    match e {
+        0 => x,
+        e => ((x & 7) + 8) << (e - 1),
+    }
+}
+
+fn mask(size : u32) -> u32 {
+    (1 << size) - 1
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::bytecode::instruction::*;
+
+    #[test]
+    pub fn encoding() {
+        // the same numbers `luac` makes for these
+        assert_eq!(Instruction::abc(OpCode::Return, 0, 1, 0).0, 0x0080_001e);
+        assert_eq!(Instruction::abx(OpCode::GetGlobal, 0, 0).0, 0x0000_0005);
+        assert_eq!(Instruction::asbx(OpCode::Jmp, 0, 1).0, 0x8000_0016);
+
+        let mut instruction = Instruction::abc(OpCode::Add, 3, as_constant(7), 200);
+        assert_eq!((instruction.opcode(), instruction.a(), instruction.b(), instruction.c()), (OpCode::Add, 3, 263, 200));
+        assert!(is_constant(instruction.b()) && !is_constant(instruction.c()));
+
+        instruction.set_c(as_constant(2));
+        instruction.set_opcode(OpCode::Sub);
+        assert_eq!((instruction.opcode(), instruction.a(), constant_index(instruction.c())), (OpCode::Sub, 3, 2));
+
+        let mut jump = Instruction::asbx(OpCode::ForLoop, 1, -5);
+        assert_eq!(jump.sbx(), -5);
+        jump.set_sbx(MAXARG_SBX);
+        assert_eq!((jump.sbx(), jump.a()), (MAXARG_SBX, 1));
+
+        assert!(!Instruction(63).is_valid());
+        for size in [0, 7, 8, 15, 16, 100, 1000, 12345].iter() {
+            assert!(fb_to_int(int_to_fb(*size)) >= *size);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! a listing of the compiled code, the same one `luac -l` prints. each
+//! function has a header with its sizes, and then its instructions with
+//! the line they came from and what the constants they use are. the full
+//! listing (`luac -l -l`) also has the constants, locals and upvalues.
+//!
+//! it is laid out exactly like `luac`'s so the two can be compared, the
+//! only difference is the addresses of the functions.
+
+use std::fmt::Write;
+
+use crate::value::Value;
+use crate::bytecode::Proto;
+use crate::bytecode::instruction::{OpCode, Mode, ArgMode, is_constant, constant_index};
+
+pub fn listing(proto : &Proto, full : bool) -> String {
+    //! the function and all the functions inside of it
+
+    let mut text = String::new();
+    list_function(&mut text, proto, full);
+    text
+}
+
+// PRIVATE FUNCTIONS /////////////////////////////////////
+
+fn list_function(text : &mut String, proto : &Proto, full : bool) {
+    list_header(text, proto);
+    list_code(text, proto);
+
+    if full {
+        list_debug(text, proto);
+    }
+
+    for child in proto.protos.iter() {
+        list_function(text, child, full);
+    }
+}
+
+fn list_header(text : &mut String, proto : &Proto) {
+    let _ = writeln!(text, "\n{} <{}:{},{}> ({} instruction{}, {} bytes at {:p})",
+        if proto.is_main() { "main" } else { "function" },
+        proto.source.name,
+        proto.line_defined,
+        proto.last_line_defined,
+        proto.code.len(), plural(proto.code.len()),
+        proto.code.len() * 4,
+        proto);
+
+    let _ = write!(text, "{}{} param{}, {} slot{}, {} upvalue{}, ",
+        proto.parameters, if proto.is_vararg != 0 { "+" } else { "" }, plural(proto.parameters),
+        proto.max_stack, plural(proto.max_stack),
+        proto.upvalues, plural(proto.upvalues));
+
+    let _ = writeln!(text, "{} local{}, {} constant{}, {} function{}",
+        proto.locals.len(), plural(proto.locals.len()),
+        proto.constants.len(), plural(proto.constants.len()),
+        proto.protos.len(), plural(proto.protos.len()));
+}
+
+fn list_code(text : &mut String, proto : &Proto) {
+    let mut pc = 0;
+    while pc < proto.code.len() {
+        let instruction = proto.code[pc];
+
+        let line = match proto.line(pc) {
+            0 => String::from("-"),
+            line => line.to_string(),
+        };
+        let _ = write!(text, "\t{}\t[{}]\t", pc + 1, line);
+
+        if !instruction.is_valid() {
+            let _ = writeln!(text, "{:<9}\t{}", "?", instruction.0);
+            pc += 1;
+            continue;
+        }
+
+        let op = instruction.opcode();
+        let (a, b, c, bx, sbx) = (instruction.a(), instruction.b(), instruction.c(), instruction.bx(), instruction.sbx());
+
+        let _ = write!(text, "{:<9}\t", op.name());
+        let _ = match op.mode() {
+            Mode::ABC => {
+                let _ = write!(text, "{}", a);
+                if op.b_mode() != ArgMode::N {
+                    let _ = write!(text, " {}", rk(b));
+                }
+                if op.c_mode() != ArgMode::N {
+                    let _ = write!(text, " {}", rk(c));
+                }
+                Ok(())
+            },
+            Mode::ABx if op.b_mode() == ArgMode::K => write!(text, "{} {}", a, -1 - bx as i64),
+            Mode::ABx => write!(text, "{} {}", a, bx),
+            Mode::AsBx if op == OpCode::Jmp => write!(text, "{}", sbx),
+            Mode::AsBx => write!(text, "{} {}", a, sbx),
+        };
+
+        let _ = match op {
+            OpCode::LoadK => write!(text, "\t; {}", constant(proto, bx)),
+            OpCode::GetUpval | OpCode::SetUpval => write!(text, "\t; {}", proto.upvalue_names.get(b).map(|name| name.to_string()).unwrap_or_else(|| String::from("-"))),
+            OpCode::GetGlobal | OpCode::SetGlobal => write!(text, "\t; {}", proto.constants.get(bx).map(|name| name.to_string()).unwrap_or_default()),
+            OpCode::GetTable | OpCode::SelfOp if is_constant(c) => write!(text, "\t; {}", constant(proto, constant_index(c))),
+            // `luac` leaves out `MOD`, so it is left out here too
+            OpCode::SetTable | OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div | OpCode::Pow |
+            OpCode::Eq | OpCode::Lt | OpCode::Le if is_constant(b) || is_constant(c) => {
+                write!(text, "\t; {} {}", rk_constant(proto, b), rk_constant(proto, c))
+            },
+            OpCode::Jmp | OpCode::ForLoop | OpCode::ForPrep => write!(text, "\t; to {}", pc as i64 + 2 + sbx as i64),
+            OpCode::Closure => match proto.protos.get(bx) {
+                Some(child) => write!(text, "\t; {:p}", &**child),
+                None => Ok(()),
+            },
+            // a big `C` is in the next instruction, which isn't listed
+            OpCode::SetList if c == 0 => {
+                pc += 1;
+                write!(text, "\t; {}", proto.code.get(pc).map(|count| count.0).unwrap_or(0))
+            },
+            OpCode::SetList => write!(text, "\t; {}", c),
+            _ => Ok(()),
+        };
+
+        text.push('\n');
+        pc += 1;
+    }
+}
+
+fn list_debug(text : &mut String, proto : &Proto) {
+    //! the constants, locals and upvalues
+
+    let _ = writeln!(text, "constants ({}) for {:p}:", proto.constants.len(), proto);
+    for i in 0 .. proto.constants.len() {
+        let _ = writeln!(text, "\t{}\t{}", i + 1, constant(proto, i));
+    }
+
+    let _ = writeln!(text, "locals ({}) for {:p}:", proto.locals.len(), proto);
+    for (i, local) in proto.locals.iter().enumerate() {
+        let _ = writeln!(text, "\t{}\t{}\t{}\t{}", i, local.name, local.start + 1, local.end + 1);
+    }
+
+    let _ = writeln!(text, "upvalues ({}) for {:p}:", proto.upvalue_names.len(), proto);
+    for (i, name) in proto.upvalue_names.iter().enumerate() {
+        let _ = writeln!(text, "\t{}\t{}", i, name);
+    }
+}
+
+fn plural(count : usize) -> &'static str {
+    if count == 1 { "" } else { "s" }
+}
+
+fn rk(arg : usize) -> i64 {
+    //! constants are shown as negative numbers, starting at `-1`
+
+    match is_constant(arg) {
+        true => -1 - constant_index(arg) as i64,
+        false => arg as i64,
+    }
+}
+
+fn rk_constant(proto : &Proto, arg : usize) -> String {
+    match is_constant(arg) {
+        true => constant(proto, constant_index(arg)),
+        false => String::from("-"),
+    }
+}
+
+fn constant(proto : &Proto, index : usize) -> String {
+    //! strings are quoted and escaped the way they would be in C
+
+    match proto.constants.get(index) {
+        Some(Value::String(string)) => {
+            let mut quoted = String::from("\"");
+            for &byte in string.as_bytes() {
+                match byte {
+                    b'"' => quoted.push_str("\\\""),
+                    b'\\' => quoted.push_str("\\\\"),
+                    0x07 => quoted.push_str("\\a"),
+                    0x08 => quoted.push_str("\\b"),
+                    0x0c => quoted.push_str("\\f"),
+                    b'\n' => quoted.push_str("\\n"),
+                    b'\r' => quoted.push_str("\\r"),
+                    b'\t' => quoted.push_str("\\t"),
+                    0x0b => quoted.push_str("\\v"),
+                    byte if byte.is_ascii_graphic() || byte == b' ' => quoted.push(byte as char),
+                    byte => { let _ = write!(quoted, "\\{:03}", byte); },
+                }
+            }
+            quoted.push('"');
+            quoted
+        },
+        Some(value) => value.to_string(),
+        None => String::from("?"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::chunk::Chunk;
+    use crate::compiler::compile;
+    use crate::bytecode::listing::listing;
+
+    #[test]
+    pub fn luac_listing() {
+        let proto = compile(Chunk::from_str("local a = {} a.x = a.y + 1 print(\"hi\\n\")", Some("testfile.lua")).unwrap()).unwrap();
+        let text = listing(&proto, true);
+        let lines : Vec<&str> = text.lines().collect();
+
+        // the same as `luac -l -l`, except for where the functions are
+        assert!(lines[1].starts_with("main <testfile.lua:0,0> (8 instructions, 32 bytes at 0x"));
+        assert_eq!(lines[2], "0+ params, 3 slots, 0 upvalues, 1 local, 5 constants, 0 functions");
+        assert_eq!(lines[3 .. 11].to_vec(), vec![
+            "\t1\t[1]\tNEWTABLE \t0 0 0",
+            "\t2\t[1]\tGETTABLE \t1 0 -2\t; \"y\"",
+            "\t3\t[1]\tADD      \t1 1 -3\t; - 1",
+            "\t4\t[1]\tSETTABLE \t0 -1 1\t; \"x\" -",
+            "\t5\t[1]\tGETGLOBAL\t1 -4\t; print",
+            "\t6\t[1]\tLOADK    \t2 -5\t; \"hi\\n\"",
+            "\t7\t[1]\tCALL     \t1 2 1",
+            "\t8\t[1]\tRETURN   \t0 1",
+        ]);
+
+        assert!(lines[11].starts_with("constants (5) for 0x"));
+        assert_eq!(lines[12 .. 17].to_vec(), vec!["\t1\t\"x\"", "\t2\t\"y\"", "\t3\t1", "\t4\t\"print\"", "\t5\t\"hi\\n\""]);
+        assert!(lines[17].starts_with("locals (1) for 0x"));
+        assert_eq!(lines[18], "\t0\ta\t2\t8");
+        assert!(lines[19].starts_with("upvalues (0) for 0x"));
+        assert_eq!(lines.len(), 20);
+    }
+}
@@ -0,0 +1,201 @@
+//! the compiled form of a lua function, what the compiler makes from the
+//! parsed tree and what the virtual machine runs. it is laid out like lua
+//! 5.1's own function prototypes: the instructions, the constants they use,
+//! the functions defined inside of it, and the debug information that lets
+//! errors say which line and which variable they are about.
+
+pub mod instruction;
+pub mod dump;
+pub mod undump;
+pub mod listing;
+mod verify;
+
+use std::rc::Rc;
+
+use failure::Error;
+
+use crate::value::{Value, LuaString};
+use crate::error::runtime::RuntimeError;
+use crate::error::codeinfo::CodeInformation;
+use crate::bytecode::instruction::{Instruction, OpCode, is_constant, constant_index};
+
+/// the function takes `...`
+pub const VARARG_ISVARARG : u8 = 2;
+
+/// the function has the old `arg` table as a parameter
+pub const VARARG_HASARG : u8 = 1;
+
+/// the function never uses `...`, so it needs `arg` made for it
+pub const VARARG_NEEDSARG : u8 = 4;
+
+/// the code that functions were compiled from, all the functions of a
+/// chunk share it so errors can show the line they happened on.
+pub struct Source {
+    pub name : String,
+    // empty for functions that were loaded already compiled
+    pub code : String,
+}
+
+impl CodeInformation for Source {
+    fn raw_code(&self) -> String { self.code.to_string() }
+    fn file_name(&self) -> String { self.name.to_string() }
+}
+
+/// a local variable, and the instructions where it can be seen
+#[derive(Debug, Clone)]
+pub struct LocalVariable {
+    pub name : LuaString,
+    // the first instruction where it is active
+    pub start : usize,
+    // the first instruction where it isn't active anymore
+    pub end : usize,
+}
+
+pub struct Proto {
+    pub source : Rc<Source>,
+    // where the function starts and ends, `0` for a whole chunk
+    pub line_defined : usize,
+    pub last_line_defined : usize,
+    pub upvalues : usize,
+    pub parameters : usize,
+    // the `VARARG_*` flags
+    pub is_vararg : u8,
+    // how many registers the function needs
+    pub max_stack : usize,
+    pub code : Vec<Instruction>,
+    pub constants : Vec<Value>,
+    pub protos : Vec<Rc<Proto>>,
+
+    // the debug information, the line of each instruction, the part of
+    // the code it came from, and the names of the variables
+    pub lines : Vec<usize>,
+    pub spans : Vec<(usize, usize)>,
+    pub locals : Vec<LocalVariable>,
+    pub upvalue_names : Vec<LuaString>,
+}
+
+impl Proto {
+    pub fn is_main(&self) -> bool {
+        //! if this is a whole chunk and not a function inside of one
+
+        self.line_defined == 0
+    }
+
+    pub fn line(&self, pc : usize) -> usize {
+        //! the line of the instruction, `0` if we don't know it
+
+        self.lines.get(pc).cloned().unwrap_or(0)
+    }
+
+    pub fn error(&self, pc : usize, description : &str) -> Error {
+        //! an error that points at the code the instruction came from
+
+        let (start, end) = self.spans.get(pc).cloned().unwrap_or((0, 0));
+        RuntimeError::execution_at(&*self.source, self.line(pc), start, end, description)
+    }
+
+    pub fn local_name(&self, register : usize, pc : usize) -> Option<&LuaString> {
+        //! the name of the local that is in the register at the
+        //! instruction, locals are in the registers in the order they
+        //! were declared.
+
+        let mut n = register + 1;
+        for local in self.locals.iter().take_while(|local| local.start <= pc) {
+            if pc < local.end {
+                n -= 1;
+                if n == 0 {
+                    return Some(&local.name);
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn variable_name(&self, pc : usize, register : usize) -> Option<(&'static str, String)> {
+        //! what the value in the register is called at the instruction,
+        //! like `("global", "x")`, found by looking at what put it there.
+
+        if let Some(name) = self.local_name(register, pc) {
+            return Some(("local", name.to_string()));
+        }
+
+        let instruction = self.last_change(pc, register);
+        match instruction.opcode() {
+            OpCode::GetGlobal => self.constant_name(instruction.bx()).map(|name| ("global", name)),
+            OpCode::Move if instruction.b() < instruction.a() => self.variable_name(pc, instruction.b()),
+            OpCode::GetTable => Some(("field", self.key_name(instruction.c()))),
+            OpCode::GetUpval => Some(("upvalue", self.upvalue_names.get(instruction.b()).map(|name| name.to_string()).unwrap_or_else(|| String::from("?")))),
+            OpCode::SelfOp => Some(("method", self.key_name(instruction.c()))),
+            _ => None,
+        }
+    }
+
+    pub fn function_name(&self, pc : usize) -> Option<(&'static str, String)> {
+        //! the name of the function the instruction is calling
+
+        let instruction = *self.code.get(pc)?;
+        match instruction.opcode() {
+            OpCode::Call | OpCode::TailCall | OpCode::TForLoop => self.variable_name(pc, instruction.a()),
+            _ => None,
+        }
+    }
+
+    // PRIVATE FUNCTIONS /////////////////////////////////////
+
+    fn constant_name(&self, index : usize) -> Option<String> {
+        match self.constants.get(index) {
+            Some(Value::String(name)) => Some(name.to_string_lossy()),
+            _ => None,
+        }
+    }
+
+    fn key_name(&self, rk : usize) -> String {
+        //! the name of a table key, only constant strings have one
+
+        match is_constant(rk) {
+            true => self.constant_name(constant_index(rk)).unwrap_or_else(|| String::from("?")),
+            false => String::from("?"),
+        }
+    }
+
+    fn last_change(&self, last_pc : usize, register : usize) -> Instruction {
+        //! the last instruction before `last_pc` that put something in the
+        //! register, following the jumps forward so the branches that
+        //! were skipped don't count. gives back the final return if
+        //! nothing did.
+
+        let mut last = self.code.len() - 1;
+        let mut pc = 0;
+
+        while pc < last_pc {
+            let instruction = self.code[pc];
+            let op = instruction.opcode();
+            let a = instruction.a();
+
+            if op.sets_a() && a == register {
+                last = pc;
+            }
+
+            match op {
+                OpCode::LoadNil if a <= register && register <= instruction.b() => last = pc,
+                OpCode::SelfOp if register == a + 1 => last = pc,
+                OpCode::TForLoop if register >= a + 2 => last = pc,
+                OpCode::Call | OpCode::TailCall if register >= a => last = pc,
+                OpCode::ForLoop | OpCode::ForPrep | OpCode::Jmp => {
+                    let destination = pc as i64 + 1 + instruction.sbx() as i64;
+                    if (pc as i64) < destination && destination <= last_pc as i64 {
+                        pc = destination as usize - 1;
+                    }
+                },
+                OpCode::SetList if instruction.c() == 0 => pc += 1,
+                OpCode::Closure => pc += self.protos.get(instruction.bx()).map(|proto| proto.upvalues).unwrap_or(0),
+                _ => { },
+            }
+
+            pc += 1;
+        }
+
+        self.code[last]
+    }
+}
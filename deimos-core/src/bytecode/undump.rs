@@ -0,0 +1,304 @@
+//! reads functions that were compiled ahead of time, the binary chunks that
+//! `luac` and `string.dump` make. it is lua 5.1's `lundump.c`, but it reads
+//! chunks made on any machine lua 5.1 runs on and not just ones like this
+//! one, as long as the numbers are doubles.
+//!
+//! a chunk is a header and then the main function, each function has its
+//! instructions, constants, the functions inside of it and the debug
+//! information, in that order.
+
+use std::rc::Rc;
+
+use failure::Error;
+
+use crate::chunk::chunk_id;
+use crate::value::{Value, LuaString};
+use crate::error::runtime::RuntimeError;
+use crate::bytecode::{Proto, Source, LocalVariable};
+use crate::bytecode::instruction::Instruction;
+use crate::bytecode::verify;
+
+/// what every binary chunk starts with
+pub const SIGNATURE : &[u8] = b"\x1bLua";
+
+/// lua 5.1
+pub const VERSION : u8 = 0x51;
+
+/// the official format, and not one of someone's own
+pub const FORMAT : u8 = 0;
+
+// the types of the constants
+pub const TYPE_NIL : u8 = 0;
+pub const TYPE_BOOLEAN : u8 = 1;
+pub const TYPE_NUMBER : u8 = 3;
+pub const TYPE_STRING : u8 = 4;
+
+pub fn is_binary(code : &[u8]) -> bool {
+    //! if the code is a binary chunk and not lua code, lua only looks at
+    //! the first byte so it is the header that says if it can be read.
+
+    code.first() == Some(&SIGNATURE[0])
+}
+
+pub fn undump(code : &[u8], chunk_name : &str) -> Result<Rc<Proto>,Error> {
+    //! reads the binary chunk, the chunk name is only for the errors. the
+    //! functions keep the name of the code they were compiled from.
+
+    let mut reader = Reader {
+        code,
+        position : 0,
+        chunk_name,
+        little_endian : true,
+        int_size : 4,
+        size_t_size : 8,
+    };
+
+    reader.header()?;
+
+    // the chunks that were dumped without their names
+    let unnamed = Rc::new(Source { name : chunk_id(b"=?"), code : String::new() });
+    Ok(Rc::new(reader.function(&unnamed)?))
+}
+
+struct Reader<'a> {
+    code : &'a [u8],
+    position : usize,
+    chunk_name : &'a str,
+
+    // what the machine it was made on is like
+    little_endian : bool,
+    int_size : usize,
+    size_t_size : usize,
+}
+
+impl<'a> Reader<'a> {
+    fn header(&mut self) -> Result<(),Error> {
+        //! the signature, the version, and what size everything is
+
+        let header = self.block(12)?;
+        if &header[.. 4] != SIGNATURE || header[4] != VERSION || header[5] != FORMAT {
+            return Err(self.error("bad header"));
+        }
+
+        let (endianness, int_size, size_t_size, instruction_size, number_size, integral) =
+            (header[6], header[7] as usize, header[8] as usize, header[9], header[10], header[11]);
+
+        if endianness > 1 || (int_size != 4 && int_size != 8) || (size_t_size != 4 && size_t_size != 8)
+            || instruction_size != 4 || number_size != 8 || integral != 0 {
+            return Err(self.error("bad header"));
+        }
+
+        self.little_endian = endianness == 1;
+        self.int_size = int_size;
+        self.size_t_size = size_t_size;
+
+        Ok(())
+    }
+
+    fn function(&mut self, parent : &Rc<Source>) -> Result<Proto,Error> {
+        // the functions inside of a chunk don't repeat its name
+        let source = match self.string()? {
+            Some(name) => Rc::new(Source { name : chunk_id(&name), code : String::new() }),
+            None => parent.clone(),
+        };
+
+        let line_defined = self.integer()?;
+        let last_line_defined = self.integer()?;
+        let upvalues = self.byte()? as usize;
+        let parameters = self.byte()? as usize;
+        let is_vararg = self.byte()?;
+        let max_stack = self.byte()? as usize;
+
+        let mut code = Vec::new();
+        for _ in 0 .. self.integer()? {
+            code.push(Instruction(self.unsigned(4)? as u32));
+        }
+
+        let mut constants = Vec::new();
+        for _ in 0 .. self.integer()? {
+            constants.push(match self.byte()? {
+                TYPE_NIL => Value::Nil,
+                TYPE_BOOLEAN => Value::Boolean(self.byte()? != 0),
+                TYPE_NUMBER => Value::Number(self.number()?),
+                TYPE_STRING => match self.string()? {
+                    Some(string) => Value::String(LuaString::new(&string)),
+                    None => return Err(self.error("bad constant")),
+                },
+                _ => return Err(self.error("bad constant")),
+            });
+        }
+
+        let mut protos = Vec::new();
+        for _ in 0 .. self.integer()? {
+            protos.push(Rc::new(self.function(&source)?));
+        }
+
+        let mut lines = Vec::new();
+        for _ in 0 .. self.integer()? {
+            lines.push(self.integer()?);
+        }
+
+        let mut locals = Vec::new();
+        for _ in 0 .. self.integer()? {
+            let name = self.string()?.unwrap_or_default();
+            locals.push(LocalVariable {
+                name : LuaString::from(name),
+                start : self.integer()?,
+                end : self.integer()?,
+            });
+        }
+
+        let mut upvalue_names = Vec::new();
+        for _ in 0 .. self.integer()? {
+            let name = self.string()?.unwrap_or_default();
+            upvalue_names.push(LuaString::from(name));
+        }
+
+        let proto = Proto {
+            source,
+            line_defined,
+            last_line_defined,
+            upvalues,
+            parameters,
+            is_vararg,
+            max_stack,
+            // there isn't any code to point at
+            spans : vec![(0, 0); code.len()],
+            code,
+            constants,
+            protos,
+            lines,
+            locals,
+            upvalue_names,
+        };
+
+        match verify::check_code(&proto) {
+            true => Ok(proto),
+            false => Err(self.error("bad code")),
+        }
+    }
+
+    fn block(&mut self, size : usize) -> Result<&'a [u8],Error> {
+        let code = self.code;
+        match code.get(self.position .. self.position.saturating_add(size)) {
+            Some(block) => {
+                self.position += size;
+                Ok(block)
+            },
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8,Error> {
+        Ok(self.block(1)?[0])
+    }
+
+    fn unsigned(&mut self, size : usize) -> Result<u64,Error> {
+        //! a number the size of one on the machine it was made on
+
+        let little_endian = self.little_endian;
+        let bytes = self.block(size)?.iter();
+
+        let fold = |number : u64, byte : &u8| number << 8 | *byte as u64;
+        Ok(match little_endian {
+            true => bytes.rev().fold(0, fold),
+            false => bytes.fold(0, fold),
+        })
+    }
+
+    fn integer(&mut self) -> Result<usize,Error> {
+        //! an `int`, which can't be less than zero in a chunk
+
+        let size = self.int_size;
+        let number = self.unsigned(size)?;
+
+        // moving the sign bit to the top
+        let number = (number << (64 - 8 * size)) as i64 >> (64 - 8 * size);
+        match number < 0 {
+            true => Err(self.error("bad integer")),
+            false => Ok(number as usize),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64,Error> {
+        Ok(f64::from_bits(self.unsigned(8)?))
+    }
+
+    fn string(&mut self) -> Result<Option<Vec<u8>>,Error> {
+        //! the size counts the `\0` at the end, a size of `0` is no string
+
+        let size = self.size_t_size;
+        match self.unsigned(size)? as usize {
+            0 => Ok(None),
+            size => {
+                let string = self.block(size)?;
+                Ok(Some(string[.. size - 1].to_vec()))
+            },
+        }
+    }
+
+    fn error(&self, why : &str) -> Error {
+        RuntimeError::general(&format!("{}: {} in precompiled chunk", self.chunk_name, why))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::bytecode::undump::undump;
+    use crate::bytecode::dump::dump;
+    use crate::bytecode::instruction::{Instruction, OpCode};
+    use crate::value::Value;
+
+    // what `luac 5.1` makes for `print("hi")` in `hi.lua` on a 64 bit pc
+    const PRINT_HI : &[u8] = b"\x1bLua\x51\x00\x01\x04\x08\x04\x08\x00\
+        \x08\x00\x00\x00\x00\x00\x00\x00@hi.lua\x00\
+        \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x02\x02\
+        \x04\x00\x00\x00\
+        \x05\x00\x00\x00\x41\x40\x00\x00\x1c\x40\x00\x01\x1e\x00\x80\x00\
+        \x02\x00\x00\x00\
+        \x04\x06\x00\x00\x00\x00\x00\x00\x00print\x00\
+        \x04\x03\x00\x00\x00\x00\x00\x00\x00hi\x00\
+        \x00\x00\x00\x00\
+        \x04\x00\x00\x00\x01\x00\x00\x00\x01\x00\x00\x00\x01\x00\x00\x00\x01\x00\x00\x00\
+        \x00\x00\x00\x00\
+        \x00\x00\x00\x00";
+
+    #[test]
+    pub fn luac_chunk() {
+        let proto = undump(PRINT_HI, "hi.luac").unwrap();
+
+        assert_eq!(proto.source.name, "hi.lua");
+        assert!(proto.is_main());
+        assert_eq!((proto.max_stack, proto.is_vararg, proto.line(3)), (2, 2, 1));
+
+        let ops : Vec<OpCode> = proto.code.iter().map(|instruction| instruction.opcode()).collect();
+        assert_eq!(ops, vec![OpCode::GetGlobal, OpCode::LoadK, OpCode::Call, OpCode::Return]);
+        match (&proto.constants[0], &proto.constants[1]) {
+            (Value::String(print), Value::String(hi)) => assert_eq!((print.as_bytes(), hi.as_bytes()), (&b"print"[..], &b"hi"[..])),
+            _ => panic!("expected two strings"),
+        }
+
+        // only the source is written differently, as `=hi.lua`
+        if cfg!(all(target_endian = "little", target_pointer_width = "64")) {
+            let dumped = dump(&proto);
+            assert_eq!(&dumped[.. 12], &PRINT_HI[.. 12]);
+            assert_eq!(&dumped[20 .. 27], b"=hi.lua");
+            assert_eq!(&dumped[27 ..], &PRINT_HI[27 ..]);
+        }
+    }
+
+    #[test]
+    pub fn bad_chunks() {
+        let message = |code : &[u8]| format!("{}", undump(code, "chunk").err().expect("an error"));
+
+        assert!(message(b"\x1bLub").contains("chunk: unexpected end in precompiled chunk"));
+        assert!(message(b"\x1bLua\x52\x00\x01\x04\x08\x04\x08\x00").contains("bad header"));
+        assert!(message(&PRINT_HI[.. 60]).contains("unexpected end"));
+
+        // the call is now a jump past the end of the function
+        let mut bad = PRINT_HI.to_vec();
+        bad[52 .. 56].copy_from_slice(&Instruction::asbx(OpCode::Jmp, 0, 100).0.to_le_bytes());
+        assert!(message(&bad).contains("bad code"));
+    }
+}
@@ -0,0 +1,185 @@
+//! checks that a function's instructions make sense before they are run,
+//! lua's `luaG_checkcode`. the compiler only makes good code, this is for
+//! the chunks that are loaded already compiled, so a broken or hand made
+//! one gets an error instead of taking the virtual machine down. what
+//! can't be known until it runs, like the values a `FORLOOP` finds, is
+//! checked by the virtual machine.
+
+use crate::value::Value;
+use crate::bytecode::{Proto, VARARG_ISVARARG, VARARG_HASARG, VARARG_NEEDSARG};
+use crate::bytecode::instruction::{Instruction, OpCode, Mode, ArgMode, MAX_STACK, is_constant, constant_index};
+
+pub fn check_code(proto : &Proto) -> bool {
+    //! if every instruction only uses the registers, constants, upvalues
+    //! and functions that are there, and every jump lands on an instruction
+
+    if !check_header(proto) {
+        return false;
+    }
+
+    let mut pc = 0;
+    while pc < proto.code.len() {
+        if !check_instruction(proto, pc) {
+            return false;
+        }
+
+        // the count after a `SETLIST` isn't an instruction
+        if is_set_list_count(proto.code[pc]) {
+            pc += 1;
+        }
+        pc += 1;
+    }
+
+    true
+}
+
+// PRIVATE FUNCTIONS /////////////////////////////////////
+
+fn check_header(proto : &Proto) -> bool {
+    //! the parts of the function that aren't instructions, lua's `precheck`
+
+    proto.max_stack <= MAX_STACK
+        && proto.parameters + (proto.is_vararg & VARARG_HASARG) as usize <= proto.max_stack
+        && (proto.is_vararg & VARARG_NEEDSARG == 0 || proto.is_vararg & VARARG_HASARG != 0)
+        && proto.upvalue_names.len() <= proto.upvalues
+        && (proto.lines.is_empty() || proto.lines.len() == proto.code.len())
+        && proto.code.last().map(|last| last.is_valid() && last.opcode() == OpCode::Return).unwrap_or(false)
+}
+
+fn check_instruction(proto : &Proto, pc : usize) -> bool {
+    let instruction = proto.code[pc];
+    if !instruction.is_valid() {
+        return false;
+    }
+
+    let op = instruction.opcode();
+    let a = instruction.a();
+    let register = |register : usize| register < proto.max_stack;
+
+    if !register(a) {
+        return false;
+    }
+
+    let (b, c) = match op.mode() {
+        Mode::ABC => {
+            let (b, c) = (instruction.b(), instruction.c());
+            if !check_arg(proto, b, op.b_mode()) || !check_arg(proto, c, op.c_mode()) {
+                return false;
+            }
+            (b as i64, c as i64)
+        },
+        Mode::ABx => {
+            let bx = instruction.bx();
+            if op.b_mode() == ArgMode::K && bx >= proto.constants.len() {
+                return false;
+            }
+            (bx as i64, 0)
+        },
+        Mode::AsBx => {
+            let sbx = instruction.sbx() as i64;
+            if op.b_mode() == ArgMode::R && !check_jump(proto, pc as i64 + 1 + sbx) {
+                return false;
+            }
+            (sbx, 0)
+        },
+    };
+
+    // a test is always followed by the jump it skips
+    if op.is_test() && !(pc + 2 < proto.code.len() && next_is(proto, pc, &[OpCode::Jmp])) {
+        return false;
+    }
+
+    match op {
+        OpCode::LoadBool if c == 1 => pc + 2 < proto.code.len() && !is_set_list_count(proto.code[pc + 1]),
+        OpCode::GetUpval | OpCode::SetUpval => (b as usize) < proto.upvalues,
+        OpCode::GetGlobal | OpCode::SetGlobal => matches!(proto.constants[b as usize], Value::String(_)),
+        OpCode::SelfOp => register(a + 1),
+        // at least two things are joined
+        OpCode::Concat => b < c,
+        OpCode::TForLoop => c >= 1 && register(a + 2 + c as usize),
+        OpCode::ForLoop | OpCode::ForPrep => register(a + 3),
+        OpCode::Call | OpCode::TailCall => {
+            (b == 0 || register(a + b as usize - 1))
+                && match c - 1 {
+                    // everything it gives back is used by the next one
+                    -1 => next_is_open(proto, pc),
+                    0 => true,
+                    results => register(a + results as usize - 1),
+                }
+        },
+        OpCode::Return => b <= 1 || register(a + b as usize - 2),
+        OpCode::SetList => {
+            (b == 0 || register(a + b as usize))
+                // a big `C` is the next instruction
+                && (c != 0 || (pc + 1 < proto.code.len() - 1 && proto.code[pc + 1].0 != 0))
+        },
+        OpCode::Closure => match proto.protos.get(b as usize) {
+            None => false,
+            Some(child) => {
+                // the instructions that say what it captures
+                pc + child.upvalues < proto.code.len()
+                    && (1 ..= child.upvalues).all(|i| next_is(proto, pc + i - 1, &[OpCode::GetUpval, OpCode::Move]))
+            },
+        },
+        OpCode::VarArg => {
+            proto.is_vararg & VARARG_ISVARARG != 0 && proto.is_vararg & VARARG_NEEDSARG == 0
+                && match b - 1 {
+                    -1 => next_is_open(proto, pc),
+                    0 => true,
+                    count => register(a + count as usize - 1),
+                }
+        },
+        _ => true,
+    }
+}
+
+fn check_arg(proto : &Proto, arg : usize, mode : ArgMode) -> bool {
+    match mode {
+        ArgMode::N => arg == 0,
+        ArgMode::U => true,
+        ArgMode::R => arg < proto.max_stack,
+        ArgMode::K => match is_constant(arg) {
+            true => constant_index(arg) < proto.constants.len(),
+            false => arg < proto.max_stack,
+        },
+    }
+}
+
+fn check_jump(proto : &Proto, destination : i64) -> bool {
+    //! the jump lands on an instruction, and not on the number that a
+    //! `SETLIST` keeps in the instruction after it. the `SETLIST`s before
+    //! it are counted because a count can look like a `SETLIST` too, an
+    //! odd number of them means it really is one.
+
+    if destination < 0 || destination >= proto.code.len() as i64 {
+        return false;
+    }
+
+    let destination = destination as usize;
+    let set_lists = proto.code[.. destination].iter().rev()
+        .take_while(|instruction| is_set_list_count(**instruction))
+        .count();
+
+    set_lists % 2 == 0
+}
+
+fn is_set_list_count(instruction : Instruction) -> bool {
+    //! a `SETLIST` with its count in the next instruction
+
+    instruction.is_valid() && instruction.opcode() == OpCode::SetList && instruction.c() == 0
+}
+
+fn next_is(proto : &Proto, pc : usize, ops : &[OpCode]) -> bool {
+    match proto.code.get(pc + 1) {
+        Some(next) => next.is_valid() && ops.contains(&next.opcode()),
+        None => false,
+    }
+}
+
+fn next_is_open(proto : &Proto, pc : usize) -> bool {
+    //! the instruction after one that leaves an open number of values on
+    //! the stack has to be one that takes them all, lua's `luaG_checkopenop`
+
+    next_is(proto, pc, &[OpCode::Call, OpCode::TailCall, OpCode::Return, OpCode::SetList])
+        && proto.code[pc + 1].b() == 0
+}
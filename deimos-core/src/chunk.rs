@@ -0,0 +1,65 @@
+//! a chunk is a piece of lua code that has gone through the scanner and the 
+//! parser and is ready to be compiled. the chunk owns both the code and the parsed
+//! tree, so anything made from it (like the errors) can still point back at the 
+//! code after the scanner and parser are gone.
+
+use failure::Error;
+
+use crate::scanner::Scanner;
+use crate::parser::Parser;
+use crate::element::{Element, CodeElement};
+use crate::error::codeinfo::CodeInformation;
+use crate::coderef::CodeRef::CodeRef;
+
+pub struct Chunk {
+    pub file_name : String,
+    pub raw_code : String,
+    pub block : CodeElement,
+}
+
+impl CodeInformation for Chunk {
+    fn raw_code(&self) -> String { self.raw_code.to_string() }
+    fn file_name(&self) -> String { self.file_name.to_string() }
+}
+
+impl Chunk {
+    pub fn from_str(raw_code : &str, file_name : Option<&str>) -> Result<Chunk,Error> {
+        //! scans and parses the code, giving back something that can be 
+        //! compiled.
+
+        let scanner = Scanner::from_str(raw_code, file_name)?;
+        let file_name = scanner.file_name.to_string();
+
+        let block = match Parser::from_scanner(scanner)?.blocks {
+            Some(block) => block,
+            None => CodeRef { item : Element::new(), code_start : 0, code_end : 0, line_number : 1 },
+        };
+
+        Ok(Chunk {
+            file_name,
+            raw_code : raw_code.to_string(),
+            block,
+        })
+    }
+}
+
+pub fn chunk_id(source : &[u8]) -> String {
+    //! the name of a chunk the way it shows up in messages, from lua's
+    //! `luaO_chunkid`. `=name` is used as it is, `@file` is a file and
+    //! anything else is the code itself.
+
+    // how long the names can be, lua's `LUA_IDSIZE`
+    const ID_SIZE : usize = 60;
+
+    match source {
+        [b'=', name @ ..] => String::from_utf8_lossy(&name[.. name.len().min(ID_SIZE - 1)]).into_owned(),
+        [b'@', file @ ..] if file.len() < ID_SIZE => String::from_utf8_lossy(file).into_owned(),
+        [b'@', file @ ..] => format!("...{}", String::from_utf8_lossy(&file[file.len() - (ID_SIZE - 4) ..])),
+        code => {
+            let line = code.iter().position(|&byte| byte == b'\n' || byte == b'\r').unwrap_or(code.len());
+            let length = line.min(ID_SIZE - 17);
+            let dots = if length < code.len() { "..." } else { "" };
+            format!("[string \"{}{}\"]", String::from_utf8_lossy(&code[.. length]), dots)
+        },
+    }
+}
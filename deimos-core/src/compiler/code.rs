@@ -0,0 +1,1164 @@
+//! making the instructions of a single function, this follows lua's own
+//! code generator (`lcode.c`) so the code we make is the same as what
+//! `luac` makes for the same source.
+//!
+//! an expression is kept as an `Exp` for as long as possible, so its value
+//! can be put straight where it is needed instead of always going through
+//! a new register. the conditions are lists of jumps that get patched once
+//! we know where they go, the list is linked through the jump offsets.
+//!
+//! errors don't stop the compiler, the first one is kept and given back
+//! when the function is finished. nothing it made is ever run.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use failure::Error;
+
+use crate::bytecode::{Proto, Source, LocalVariable};
+use crate::bytecode::instruction::{
+    Instruction, OpCode, MAXARG_SBX, MAXARG_BX, MAXARG_C, MAX_INDEX_RK, MAX_STACK,
+    NO_REGISTER, FIELDS_PER_FLUSH, is_constant, as_constant};
+use crate::value::{Value, LuaString};
+use crate::interpreter::Arithmetic;
+use crate::error::parser::ParserError;
+
+/// the end of a jump list
+pub const NO_JUMP : i32 = -1;
+
+/// how many values an expression gives when it is asked for all of them
+pub const MULTIPLE : i32 = -1;
+
+/// the most locals a function can have at once
+const MAX_LOCALS : usize = 200;
+
+/// the most upvalues a function can have
+const MAX_UPVALUES : usize = 60;
+
+/// where an expression's value is, or how to get it
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    // no value at all, like an empty expression list
+    Void,
+    Nil,
+    True,
+    False,
+    // the index of the constant
+    Constant(usize),
+    Number(f64),
+    // the register of the local
+    Local(usize),
+    // the index of the upvalue
+    Upvalue(usize),
+    // the constant with the name of the global
+    Global(usize),
+    // the register with the table, and the key as a register or constant
+    Indexed(usize, usize),
+    // a test, the pc of its jump
+    Jump(usize),
+    // the pc of an instruction that can put its value in any register
+    Relocatable(usize),
+    // the value is already in this register
+    NonRelocatable(usize),
+    // the pc of the call
+    Call(usize),
+    // the pc of the `...`
+    VarArg(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Exp {
+    pub kind : Kind,
+    // the jumps to patch when the expression is true, and when it is false
+    pub t : i32,
+    pub f : i32,
+}
+
+impl Exp {
+    pub fn new(kind : Kind) -> Exp {
+        Exp { kind, t : NO_JUMP, f : NO_JUMP }
+    }
+
+    pub fn has_multiple(&self) -> bool {
+        //! if the expression can give any number of values
+
+        matches!(self.kind, Kind::Call(_) | Kind::VarArg(_))
+    }
+
+    fn has_jumps(&self) -> bool {
+        self.t != self.f
+    }
+
+    fn numeral(&self) -> Option<f64> {
+        match (self.kind, self.has_jumps()) {
+            (Kind::Number(number), false) => Some(number),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UnaryOp {
+    Minus,
+    Not,
+    Len,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinaryOp {
+    Add, Sub, Mul, Div, Mod, Pow,
+    Concat,
+    Eq, Ne, Lt, Le, Gt, Ge,
+    And, Or,
+}
+
+/// where an upvalue comes from in the function around the one using it
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UpvalueSource {
+    Local(usize),
+    Upvalue(usize),
+}
+
+/// a block of statements, the locals declared in it go away at its end
+struct Block {
+    // the `break`s that jump to the end of the block
+    break_list : i32,
+    // how many locals there were when the block started
+    active_count : usize,
+    // if a function captured one of its locals
+    upvalue : bool,
+    // if it is a loop
+    breakable : bool,
+}
+
+/// the function that is being compiled
+pub struct FunctionState {
+    source : Rc<Source>,
+    // where in the code we are, every instruction is given this
+    pub line : usize,
+    pub span : (usize, usize),
+
+    code : Vec<Instruction>,
+    lines : Vec<usize>,
+    spans : Vec<(usize, usize)>,
+    constants : Vec<Value>,
+    constant_indexes : HashMap<Value, usize>,
+    protos : Vec<Rc<Proto>>,
+    locals : Vec<LocalVariable>,
+    upvalue_names : Vec<LuaString>,
+    upvalues : Vec<UpvalueSource>,
+
+    // the locals that can be seen, by their index in `locals`. the ones
+    // past `active_count` are declared but don't exist yet.
+    active : Vec<usize>,
+    pub active_count : usize,
+    blocks : Vec<Block>,
+    // the first register that isn't used
+    pub free_register : usize,
+    // the jumps that go to the next instruction
+    jump_to_here : i32,
+    // the last instruction something jumps to
+    last_target : i32,
+
+    pub parameters : usize,
+    pub is_vararg : u8,
+    max_stack : usize,
+    line_defined : usize,
+    pub last_line_defined : usize,
+
+    // the first thing that went wrong
+    error : Option<Error>,
+}
+
+impl FunctionState {
+    pub fn new(source : Rc<Source>, line_defined : usize) -> FunctionState {
+        FunctionState {
+            source,
+            line : line_defined,
+            span : (0, 0),
+            code : Vec::new(),
+            lines : Vec::new(),
+            spans : Vec::new(),
+            constants : Vec::new(),
+            constant_indexes : HashMap::new(),
+            protos : Vec::new(),
+            locals : Vec::new(),
+            upvalue_names : Vec::new(),
+            upvalues : Vec::new(),
+            active : Vec::new(),
+            active_count : 0,
+            blocks : Vec::new(),
+            free_register : 0,
+            jump_to_here : NO_JUMP,
+            last_target : -1,
+            parameters : 0,
+            is_vararg : 0,
+            // registers 0 and 1 can always be used
+            max_stack : 2,
+            line_defined,
+            last_line_defined : 0,
+            error : None,
+        }
+    }
+
+    pub fn finish(mut self) -> (Proto, Vec<UpvalueSource>, Option<Error>) {
+        //! the final return, and everything the function needs to run. the
+        //! upvalues say what the function has to capture when it is made.
+
+        self.remove_locals(0);
+        self.ret(0, 0);
+
+        let proto = Proto {
+            source : self.source,
+            line_defined : self.line_defined,
+            last_line_defined : self.last_line_defined,
+            upvalues : self.upvalues.len(),
+            parameters : self.parameters,
+            is_vararg : self.is_vararg,
+            max_stack : self.max_stack,
+            code : self.code,
+            constants : self.constants,
+            protos : self.protos,
+            lines : self.lines,
+            spans : self.spans,
+            locals : self.locals,
+            upvalue_names : self.upvalue_names,
+        };
+
+        (proto, self.upvalues, self.error)
+    }
+
+    pub fn fail(&mut self, description : &str) {
+        //! remembers the error, if it is the first one
+
+        if self.error.is_none() {
+            self.error = Some(ParserError::unexpected(&*self.source, self.line, self.span.0, self.span.1, description));
+        }
+    }
+
+    pub fn keep_error(&mut self, error : Option<Error>) {
+        //! takes the error of a function inside of this one, it happened
+        //! after anything that went wrong here
+
+        if self.error.is_none() {
+            self.error = error;
+        }
+    }
+
+    pub fn source(&self) -> Rc<Source> {
+        self.source.clone()
+    }
+
+    pub fn pc(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn add_proto(&mut self, proto : Proto) -> usize {
+        self.protos.push(Rc::new(proto));
+        self.protos.len() - 1
+    }
+
+    // INSTRUCTIONS //////////////////////////////////////////
+
+    pub fn code_abc(&mut self, op : OpCode, a : usize, b : usize, c : usize) -> usize {
+        self.code(Instruction::abc(op, a, b, c))
+    }
+
+    pub fn code_abx(&mut self, op : OpCode, a : usize, bx : usize) -> usize {
+        self.code(Instruction::abx(op, a, bx))
+    }
+
+    pub fn code_asbx(&mut self, op : OpCode, a : usize, sbx : i32) -> usize {
+        self.code(Instruction::asbx(op, a, sbx))
+    }
+
+    pub fn fix_line(&mut self, line : usize) {
+        //! changes the line of the last instruction
+
+        if let Some(last) = self.lines.last_mut() {
+            *last = line;
+        }
+    }
+
+    pub fn instruction_mut(&mut self, pc : usize) -> &mut Instruction {
+        &mut self.code[pc]
+    }
+
+    pub fn ret(&mut self, first : usize, count : i32) {
+        self.code_abc(OpCode::Return, first, (count + 1) as usize, 0);
+    }
+
+    pub fn load_nil(&mut self, from : usize, n : usize) {
+        //! sets the registers to `nil`, joining it with the instruction
+        //! before if that one sets nils too
+
+        if self.pc() as i32 > self.last_target {
+            match self.code.last().cloned() {
+                // at the start of the function everything is already nil
+                None if from >= self.active_count => return,
+                Some(previous) if previous.opcode() == OpCode::LoadNil => {
+                    let (previous_from, previous_to) = (previous.a(), previous.b());
+                    if previous_from <= from && from <= previous_to + 1 {
+                        if from + n - 1 > previous_to {
+                            self.code.last_mut().expect("an instruction").set_b(from + n - 1);
+                        }
+                        return;
+                    }
+                },
+                _ => { },
+            }
+        }
+
+        self.code_abc(OpCode::LoadNil, from, from + n - 1, 0);
+    }
+
+    pub fn set_list(&mut self, base : usize, count : usize, to_store : i32) {
+        //! puts the values after the table into it, the `C` is which
+        //! group of `FIELDS_PER_FLUSH` they are
+
+        let c = (count.max(1) - 1) / FIELDS_PER_FLUSH + 1;
+        let b = match to_store {
+            MULTIPLE => 0,
+            to_store => to_store as usize,
+        };
+
+        if c <= MAXARG_C {
+            self.code_abc(OpCode::SetList, base, b, c);
+        } else {
+            // too big, the next "instruction" is the number itself
+            self.code_abc(OpCode::SetList, base, b, 0);
+            self.code(Instruction(c as u32));
+        }
+
+        self.free_register = base + 1;
+    }
+
+    // JUMPS /////////////////////////////////////////////////
+
+    pub fn jump(&mut self) -> i32 {
+        //! a jump that still needs to be patched, the jumps that were
+        //! going to here go with it
+
+        let jump_to_here = std::mem::replace(&mut self.jump_to_here, NO_JUMP);
+        let mut jump = self.code_asbx(OpCode::Jmp, 0, NO_JUMP) as i32;
+        self.concat(&mut jump, jump_to_here);
+        jump
+    }
+
+    pub fn get_label(&mut self) -> usize {
+        //! marks the next instruction as something that is jumped to
+
+        self.last_target = self.pc() as i32;
+        self.pc()
+    }
+
+    pub fn patch_list(&mut self, list : i32, target : usize) {
+        match target == self.pc() {
+            true => self.patch_to_here(list),
+            false => self.patch_list_aux(list, target, NO_REGISTER, target),
+        }
+    }
+
+    pub fn patch_to_here(&mut self, list : i32) {
+        self.get_label();
+
+        let mut jump_to_here = self.jump_to_here;
+        self.concat(&mut jump_to_here, list);
+        self.jump_to_here = jump_to_here;
+    }
+
+    pub fn concat(&mut self, first : &mut i32, second : i32) {
+        //! adds the second list to the end of the first one
+
+        if second == NO_JUMP {
+            return;
+        }
+
+        if *first == NO_JUMP {
+            *first = second;
+            return;
+        }
+
+        let mut list = *first;
+        loop {
+            let next = self.get_jump(list as usize);
+            if next == NO_JUMP { break; }
+            list = next;
+        }
+
+        self.fix_jump(list as usize, second as usize);
+    }
+
+    // REGISTERS /////////////////////////////////////////////
+
+    pub fn check_stack(&mut self, n : usize) {
+        let size = self.free_register + n;
+        if size > self.max_stack {
+            if size >= MAX_STACK {
+                self.fail("function or expression too complex");
+            }
+            self.max_stack = size;
+        }
+    }
+
+    pub fn reserve_registers(&mut self, n : usize) {
+        self.check_stack(n);
+        self.free_register += n;
+    }
+
+    // CONSTANTS /////////////////////////////////////////////
+
+    pub fn string_constant(&mut self, string : LuaString) -> usize {
+        self.add_constant(Value::String(string))
+    }
+
+    pub fn number_constant(&mut self, number : f64) -> usize {
+        self.add_constant(Value::Number(number))
+    }
+
+    // LOCALS AND UPVALUES ///////////////////////////////////
+
+    pub fn declare_local(&mut self, name : LuaString, n : usize) {
+        //! the `n`th new local, it can't be seen until `adjust_locals`
+
+        if self.active_count + n + 1 > MAX_LOCALS {
+            self.fail_limit(MAX_LOCALS, "local variables");
+        }
+
+        self.locals.push(LocalVariable { name, start : 0, end : 0 });
+        self.active.truncate(self.active_count + n);
+        self.active.push(self.locals.len() - 1);
+    }
+
+    pub fn adjust_locals(&mut self, count : usize) {
+        //! the new locals can be seen from here on
+
+        self.active_count += count;
+        for i in self.active_count - count .. self.active_count {
+            let local = self.active[i];
+            self.locals[local].start = self.pc();
+        }
+    }
+
+    pub fn set_local_start(&mut self) {
+        //! the newest local can only be seen from here on
+
+        let local = self.active[self.active_count - 1];
+        self.locals[local].start = self.pc();
+    }
+
+    pub fn search_local(&self, name : &LuaString) -> Option<usize> {
+        //! the register of the local with the name
+
+        (0 .. self.active_count).rev().find(|i| self.locals[self.active[*i]].name == *name)
+    }
+
+    pub fn mark_upvalue(&mut self, register : usize) {
+        //! the block the local is in has to close it when it ends
+
+        if let Some(block) = self.blocks.iter_mut().rev().find(|block| block.active_count <= register) {
+            block.upvalue = true;
+        }
+    }
+
+    pub fn index_upvalue(&mut self, name : &LuaString, source : UpvalueSource) -> usize {
+        if let Some(index) = self.upvalues.iter().position(|upvalue| *upvalue == source) {
+            return index;
+        }
+
+        if self.upvalues.len() + 1 > MAX_UPVALUES {
+            self.fail_limit(MAX_UPVALUES, "upvalues");
+        }
+
+        self.upvalues.push(source);
+        self.upvalue_names.push(name.clone());
+        self.upvalues.len() - 1
+    }
+
+    // BLOCKS ////////////////////////////////////////////////
+
+    pub fn enter_block(&mut self, breakable : bool) {
+        self.blocks.push(Block { break_list : NO_JUMP, active_count : self.active_count, upvalue : false, breakable });
+    }
+
+    pub fn leave_block(&mut self) {
+        let block = self.blocks.pop().expect("a block");
+
+        self.remove_locals(block.active_count);
+        if block.upvalue {
+            self.code_abc(OpCode::Close, block.active_count, 0, 0);
+        }
+
+        self.free_register = self.active_count;
+        self.patch_to_here(block.break_list);
+    }
+
+    pub fn block_has_upvalue(&self) -> bool {
+        self.blocks.last().map(|block| block.upvalue).unwrap_or(false)
+    }
+
+    pub fn break_loop(&mut self) {
+        //! jumps out of the loop we are in, closing any locals that were
+        //! captured on the way out
+
+        let mut upvalue = false;
+        let mut found = None;
+        for (i, block) in self.blocks.iter().enumerate().rev() {
+            if block.breakable {
+                found = Some(i);
+                break;
+            }
+            upvalue |= block.upvalue;
+        }
+
+        let i = match found {
+            Some(i) => i,
+            None => return self.fail("no loop to break"),
+        };
+
+        if upvalue {
+            let active_count = self.blocks[i].active_count;
+            self.code_abc(OpCode::Close, active_count, 0, 0);
+        }
+
+        let jump = self.jump();
+        let mut break_list = self.blocks[i].break_list;
+        self.concat(&mut break_list, jump);
+        self.blocks[i].break_list = break_list;
+    }
+
+    // EXPRESSIONS ///////////////////////////////////////////
+
+    pub fn set_returns(&mut self, exp : &mut Exp, results : i32) {
+        //! how many values the call or `...` should give
+
+        match exp.kind {
+            Kind::Call(pc) => self.code[pc].set_c((results + 1) as usize),
+            Kind::VarArg(pc) => {
+                self.code[pc].set_b((results + 1) as usize);
+                let free_register = self.free_register;
+                self.code[pc].set_a(free_register);
+                self.reserve_registers(1);
+            },
+            _ => { },
+        }
+    }
+
+    pub fn set_multiple_returns(&mut self, exp : &mut Exp) {
+        self.set_returns(exp, MULTIPLE);
+    }
+
+    pub fn set_one_return(&mut self, exp : &mut Exp) {
+        match exp.kind {
+            Kind::Call(pc) => exp.kind = Kind::NonRelocatable(self.code[pc].a()),
+            Kind::VarArg(pc) => {
+                self.code[pc].set_b(2);
+                exp.kind = Kind::Relocatable(pc);
+            },
+            _ => { },
+        }
+    }
+
+    pub fn discharge_vars(&mut self, exp : &mut Exp) {
+        //! variables are read into something that has a value
+
+        match exp.kind {
+            Kind::Local(register) => exp.kind = Kind::NonRelocatable(register),
+            Kind::Upvalue(index) => exp.kind = Kind::Relocatable(self.code_abc(OpCode::GetUpval, 0, index, 0)),
+            Kind::Global(name) => exp.kind = Kind::Relocatable(self.code_abx(OpCode::GetGlobal, 0, name)),
+            Kind::Indexed(table, key) => {
+                self.free(key);
+                self.free(table);
+                exp.kind = Kind::Relocatable(self.code_abc(OpCode::GetTable, 0, table, key));
+            },
+            Kind::Call(_) | Kind::VarArg(_) => self.set_one_return(exp),
+            _ => { },
+        }
+    }
+
+    pub fn exp_to_next_register(&mut self, exp : &mut Exp) {
+        self.discharge_vars(exp);
+        self.free_exp(exp);
+        self.reserve_registers(1);
+        let register = self.free_register - 1;
+        self.exp_to_register(exp, register);
+    }
+
+    pub fn exp_to_any_register(&mut self, exp : &mut Exp) -> usize {
+        self.discharge_vars(exp);
+
+        if let Kind::NonRelocatable(register) = exp.kind {
+            if !exp.has_jumps() {
+                return register;
+            }
+
+            // put the jumps' values in it, unless it is a local
+            if register >= self.active_count {
+                self.exp_to_register(exp, register);
+                return register;
+            }
+        }
+
+        self.exp_to_next_register(exp);
+        match exp.kind {
+            Kind::NonRelocatable(register) => register,
+            _ => unreachable!("the expression was put in a register"),
+        }
+    }
+
+    pub fn exp_to_value(&mut self, exp : &mut Exp) {
+        match exp.has_jumps() {
+            true => { self.exp_to_any_register(exp); },
+            false => self.discharge_vars(exp),
+        }
+    }
+
+    pub fn exp_to_rk(&mut self, exp : &mut Exp) -> usize {
+        //! the expression as a `B` or `C` arguement, a constant if it fits
+
+        self.exp_to_value(exp);
+
+        let fits = self.constants.len() <= MAX_INDEX_RK;
+        let constant = match exp.kind {
+            Kind::Nil if fits => Some(self.add_constant(Value::Nil)),
+            Kind::True if fits => Some(self.add_constant(Value::Boolean(true))),
+            Kind::False if fits => Some(self.add_constant(Value::Boolean(false))),
+            Kind::Number(number) if fits => Some(self.number_constant(number)),
+            Kind::Constant(index) if index <= MAX_INDEX_RK => Some(index),
+            _ => None,
+        };
+
+        match constant {
+            Some(index) => {
+                exp.kind = Kind::Constant(index);
+                as_constant(index)
+            },
+            None => self.exp_to_any_register(exp),
+        }
+    }
+
+    pub fn store_var(&mut self, var : &Exp, exp : &mut Exp) {
+        match var.kind {
+            Kind::Local(register) => {
+                self.free_exp(exp);
+                self.exp_to_register(exp, register);
+                return;
+            },
+            Kind::Upvalue(index) => {
+                let value = self.exp_to_any_register(exp);
+                self.code_abc(OpCode::SetUpval, value, index, 0);
+            },
+            Kind::Global(name) => {
+                let value = self.exp_to_any_register(exp);
+                self.code_abx(OpCode::SetGlobal, value, name);
+            },
+            Kind::Indexed(table, key) => {
+                let value = self.exp_to_rk(exp);
+                self.code_abc(OpCode::SetTable, table, key, value);
+            },
+            _ => unreachable!("only variables can be assigned to"),
+        }
+
+        self.free_exp(exp);
+    }
+
+    pub fn self_op(&mut self, exp : &mut Exp, key : &mut Exp) {
+        //! `object:method`, the method and then the object go in the
+        //! next two registers
+
+        let object = self.exp_to_any_register(exp);
+        self.free_exp(exp);
+
+        let function = self.free_register;
+        self.reserve_registers(2);
+        let key_rk = self.exp_to_rk(key);
+        self.code_abc(OpCode::SelfOp, function, object, key_rk);
+        self.free_exp(key);
+
+        exp.kind = Kind::NonRelocatable(function);
+    }
+
+    pub fn indexed(&mut self, table : &mut Exp, key : &mut Exp) {
+        //! `table[key]`, the table has to be in a register already
+
+        let key = self.exp_to_rk(key);
+        if let Kind::NonRelocatable(register) | Kind::Local(register) = table.kind {
+            table.kind = Kind::Indexed(register, key);
+        }
+    }
+
+    pub fn go_if_true(&mut self, exp : &mut Exp) {
+        //! keeps going when the expression is true, jumps away when it is
+        //! false
+
+        self.discharge_vars(exp);
+
+        let jump = match exp.kind {
+            Kind::Constant(_) | Kind::Number(_) | Kind::True => NO_JUMP,
+            Kind::False => self.jump(),
+            Kind::Jump(pc) => {
+                self.invert_jump(pc);
+                pc as i32
+            },
+            _ => self.jump_on_condition(exp, false),
+        };
+
+        let mut f = exp.f;
+        self.concat(&mut f, jump);
+        exp.f = f;
+
+        self.patch_to_here(exp.t);
+        exp.t = NO_JUMP;
+    }
+
+    pub fn prefix(&mut self, op : UnaryOp, exp : &mut Exp) {
+        let mut zero = Exp::new(Kind::Number(0.0));
+
+        match op {
+            UnaryOp::Minus => {
+                if exp.numeral().is_none() {
+                    self.exp_to_any_register(exp);
+                }
+                self.code_arithmetic(OpCode::Unm, exp, &mut zero);
+            },
+            UnaryOp::Not => self.code_not(exp),
+            UnaryOp::Len => {
+                self.exp_to_any_register(exp);
+                self.code_arithmetic(OpCode::Len, exp, &mut zero);
+            },
+        }
+    }
+
+    pub fn infix(&mut self, op : BinaryOp, exp : &mut Exp) {
+        //! the first operand, before the second one is compiled
+
+        match op {
+            BinaryOp::And => self.go_if_true(exp),
+            BinaryOp::Or => self.go_if_false(exp),
+            // the operands have to be next to each other
+            BinaryOp::Concat => self.exp_to_next_register(exp),
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod | BinaryOp::Pow => {
+                if exp.numeral().is_none() {
+                    self.exp_to_rk(exp);
+                }
+            },
+            _ => { self.exp_to_rk(exp); },
+        }
+    }
+
+    pub fn postfix(&mut self, op : BinaryOp, first : &mut Exp, second : &mut Exp) {
+        //! the operation itself, once both operands are compiled
+
+        match op {
+            BinaryOp::And => {
+                self.discharge_vars(second);
+                let mut f = second.f;
+                self.concat(&mut f, first.f);
+                second.f = f;
+                *first = *second;
+            },
+            BinaryOp::Or => {
+                self.discharge_vars(second);
+                let mut t = second.t;
+                self.concat(&mut t, first.t);
+                second.t = t;
+                *first = *second;
+            },
+            BinaryOp::Concat => {
+                self.exp_to_value(second);
+
+                // `a .. b .. c` is one instruction for all of them
+                match second.kind {
+                    Kind::Relocatable(pc) if self.code[pc].opcode() == OpCode::Concat => {
+                        self.free_exp(first);
+                        if let Kind::NonRelocatable(register) = first.kind {
+                            self.code[pc].set_b(register);
+                        }
+                        first.kind = Kind::Relocatable(pc);
+                    },
+                    _ => {
+                        self.exp_to_next_register(second);
+                        self.code_arithmetic(OpCode::Concat, first, second);
+                    },
+                }
+            },
+            BinaryOp::Add => self.code_arithmetic(OpCode::Add, first, second),
+            BinaryOp::Sub => self.code_arithmetic(OpCode::Sub, first, second),
+            BinaryOp::Mul => self.code_arithmetic(OpCode::Mul, first, second),
+            BinaryOp::Div => self.code_arithmetic(OpCode::Div, first, second),
+            BinaryOp::Mod => self.code_arithmetic(OpCode::Mod, first, second),
+            BinaryOp::Pow => self.code_arithmetic(OpCode::Pow, first, second),
+            BinaryOp::Eq => self.code_comparison(OpCode::Eq, true, first, second),
+            BinaryOp::Ne => self.code_comparison(OpCode::Eq, false, first, second),
+            BinaryOp::Lt => self.code_comparison(OpCode::Lt, true, first, second),
+            BinaryOp::Le => self.code_comparison(OpCode::Le, true, first, second),
+            BinaryOp::Gt => self.code_comparison(OpCode::Lt, false, first, second),
+            BinaryOp::Ge => self.code_comparison(OpCode::Le, false, first, second),
+        }
+    }
+
+    // PRIVATE FUNCTIONS /////////////////////////////////////
+
+    fn code(&mut self, instruction : Instruction) -> usize {
+        //! adds the instruction, the jumps waiting for the next
+        //! instruction now point at it
+
+        self.discharge_jump_to_here();
+
+        self.code.push(instruction);
+        self.lines.push(self.line);
+        self.spans.push(self.span);
+        self.code.len() - 1
+    }
+
+    fn fail_limit(&mut self, limit : usize, what : &str) {
+        let description = match self.line_defined {
+            0 => format!("main function has more than {} {}", limit, what),
+            line => format!("function at line {} has more than {} {}", line, limit, what),
+        };
+        self.fail(&description);
+    }
+
+    fn add_constant(&mut self, value : Value) -> usize {
+        if let Some(index) = self.constant_indexes.get(&value) {
+            return *index;
+        }
+
+        if self.constants.len() > MAXARG_BX {
+            self.fail("constant table overflow");
+        }
+
+        self.constants.push(value.clone());
+        self.constant_indexes.insert(value, self.constants.len() - 1);
+        self.constants.len() - 1
+    }
+
+    fn remove_locals(&mut self, level : usize) {
+        while self.active_count > level {
+            self.active_count -= 1;
+            let local = self.active[self.active_count];
+            self.locals[local].end = self.pc();
+        }
+        self.active.truncate(self.active_count);
+    }
+
+    fn free(&mut self, register : usize) {
+        //! lets go of the register if it was a temporary one, they are
+        //! always let go of in the opposite order they were taken
+
+        if !is_constant(register) && register >= self.active_count {
+            self.free_register -= 1;
+        }
+    }
+
+    fn free_exp(&mut self, exp : &Exp) {
+        if let Kind::NonRelocatable(register) = exp.kind {
+            self.free(register);
+        }
+    }
+
+    fn fix_jump(&mut self, pc : usize, destination : usize) {
+        let offset = destination as i64 - (pc as i64 + 1);
+        if offset.abs() > MAXARG_SBX as i64 {
+            return self.fail("control structure too long");
+        }
+
+        self.code[pc].set_sbx(offset as i32);
+    }
+
+    fn get_jump(&self, pc : usize) -> i32 {
+        //! where the jump goes, or the end of the list
+
+        match self.code[pc].sbx() {
+            NO_JUMP => NO_JUMP,
+            offset => pc as i32 + 1 + offset,
+        }
+    }
+
+    fn jump_control(&self, pc : usize) -> usize {
+        //! the test that decides if the jump is taken, or the jump itself
+
+        match pc >= 1 && self.code[pc - 1].opcode().is_test() {
+            true => pc - 1,
+            false => pc,
+        }
+    }
+
+    fn need_value(&self, mut list : i32) -> bool {
+        //! if any of the jumps doesn't leave a value behind
+
+        while list != NO_JUMP {
+            if self.code[self.jump_control(list as usize)].opcode() != OpCode::TestSet {
+                return true;
+            }
+            list = self.get_jump(list as usize);
+        }
+
+        false
+    }
+
+    fn patch_test_register(&mut self, node : usize, register : usize) -> bool {
+        //! makes a `TESTSET` put its value in the register, or turns it
+        //! into a `TEST` if the value isn't needed
+
+        let control = self.jump_control(node);
+        let instruction = self.code[control];
+        if instruction.opcode() != OpCode::TestSet {
+            return false;
+        }
+
+        match register != NO_REGISTER && register != instruction.b() {
+            true => self.code[control].set_a(register),
+            false => self.code[control] = Instruction::abc(OpCode::Test, instruction.b(), 0, instruction.c()),
+        }
+
+        true
+    }
+
+    fn remove_values(&mut self, mut list : i32) {
+        while list != NO_JUMP {
+            self.patch_test_register(list as usize, NO_REGISTER);
+            list = self.get_jump(list as usize);
+        }
+    }
+
+    fn patch_list_aux(&mut self, mut list : i32, value_target : usize, register : usize, default_target : usize) {
+        while list != NO_JUMP {
+            let next = self.get_jump(list as usize);
+            match self.patch_test_register(list as usize, register) {
+                true => self.fix_jump(list as usize, value_target),
+                false => self.fix_jump(list as usize, default_target),
+            }
+            list = next;
+        }
+    }
+
+    fn discharge_jump_to_here(&mut self) {
+        let list = std::mem::replace(&mut self.jump_to_here, NO_JUMP);
+        let pc = self.pc();
+        self.patch_list_aux(list, pc, NO_REGISTER, pc);
+    }
+
+    fn code_label(&mut self, a : usize, b : usize, jump : usize) -> usize {
+        self.get_label();
+        self.code_abc(OpCode::LoadBool, a, b, jump)
+    }
+
+    fn discharge_to_register(&mut self, exp : &mut Exp, register : usize) {
+        self.discharge_vars(exp);
+
+        match exp.kind {
+            Kind::Nil => self.load_nil(register, 1),
+            Kind::True => { self.code_abc(OpCode::LoadBool, register, 1, 0); },
+            Kind::False => { self.code_abc(OpCode::LoadBool, register, 0, 0); },
+            Kind::Constant(index) => { self.code_abx(OpCode::LoadK, register, index); },
+            Kind::Number(number) => {
+                let index = self.number_constant(number);
+                self.code_abx(OpCode::LoadK, register, index);
+            },
+            Kind::Relocatable(pc) => self.code[pc].set_a(register),
+            Kind::NonRelocatable(from) => if from != register {
+                self.code_abc(OpCode::Move, register, from, 0);
+            },
+            // nothing to put anywhere
+            _ => return,
+        }
+
+        exp.kind = Kind::NonRelocatable(register);
+    }
+
+    fn discharge_to_any_register(&mut self, exp : &mut Exp) {
+        if let Kind::NonRelocatable(_) = exp.kind {
+            return;
+        }
+
+        self.reserve_registers(1);
+        let register = self.free_register - 1;
+        self.discharge_to_register(exp, register);
+    }
+
+    fn exp_to_register(&mut self, exp : &mut Exp, register : usize) {
+        self.discharge_to_register(exp, register);
+
+        if let Kind::Jump(pc) = exp.kind {
+            let mut t = exp.t;
+            self.concat(&mut t, pc as i32);
+            exp.t = t;
+        }
+
+        if exp.has_jumps() {
+            // the tests that don't leave their value need a true or false
+            let mut load_false = NO_JUMP;
+            let mut load_true = NO_JUMP;
+
+            if self.need_value(exp.t) || self.need_value(exp.f) {
+                let jump = match exp.kind {
+                    Kind::Jump(_) => NO_JUMP,
+                    _ => self.jump(),
+                };
+                load_false = self.code_label(register, 0, 1) as i32;
+                load_true = self.code_label(register, 1, 0) as i32;
+                self.patch_to_here(jump);
+            }
+
+            let end = self.get_label();
+            self.patch_list_aux(exp.f, end, register, load_false as usize);
+            self.patch_list_aux(exp.t, end, register, load_true as usize);
+        }
+
+        exp.t = NO_JUMP;
+        exp.f = NO_JUMP;
+        exp.kind = Kind::NonRelocatable(register);
+    }
+
+    fn invert_jump(&mut self, pc : usize) {
+        let control = self.jump_control(pc);
+        let a = self.code[control].a();
+        self.code[control].set_a(if a == 0 { 1 } else { 0 });
+    }
+
+    fn jump_on_condition(&mut self, exp : &mut Exp, condition : bool) -> i32 {
+        //! a test of the expression and a jump taken when it is the
+        //! condition
+
+        if let Kind::Relocatable(pc) = exp.kind {
+            let instruction = self.code[pc];
+
+            // `not x` can test `x` the other way round
+            if instruction.opcode() == OpCode::Not {
+                self.code.pop();
+                self.lines.pop();
+                self.spans.pop();
+                return self.condition_jump(OpCode::Test, instruction.b(), 0, if condition { 0 } else { 1 });
+            }
+        }
+
+        self.discharge_to_any_register(exp);
+        self.free_exp(exp);
+
+        match exp.kind {
+            Kind::NonRelocatable(register) => self.condition_jump(OpCode::TestSet, NO_REGISTER, register, condition as usize),
+            _ => unreachable!("the expression was put in a register"),
+        }
+    }
+
+    fn condition_jump(&mut self, op : OpCode, a : usize, b : usize, c : usize) -> i32 {
+        self.code_abc(op, a, b, c);
+        self.jump()
+    }
+
+    fn go_if_false(&mut self, exp : &mut Exp) {
+        //! keeps going when the expression is false, jumps away when it
+        //! is true
+
+        self.discharge_vars(exp);
+
+        let jump = match exp.kind {
+            Kind::Nil | Kind::False => NO_JUMP,
+            Kind::True => self.jump(),
+            Kind::Jump(pc) => pc as i32,
+            _ => self.jump_on_condition(exp, true),
+        };
+
+        let mut t = exp.t;
+        self.concat(&mut t, jump);
+        exp.t = t;
+
+        self.patch_to_here(exp.f);
+        exp.f = NO_JUMP;
+    }
+
+    fn code_not(&mut self, exp : &mut Exp) {
+        self.discharge_vars(exp);
+
+        match exp.kind {
+            Kind::Nil | Kind::False => exp.kind = Kind::True,
+            Kind::Constant(_) | Kind::Number(_) | Kind::True => exp.kind = Kind::False,
+            Kind::Jump(pc) => self.invert_jump(pc),
+            Kind::Relocatable(_) | Kind::NonRelocatable(_) => {
+                self.discharge_to_any_register(exp);
+                self.free_exp(exp);
+                if let Kind::NonRelocatable(register) = exp.kind {
+                    exp.kind = Kind::Relocatable(self.code_abc(OpCode::Not, 0, register, 0));
+                }
+            },
+            _ => unreachable!("not of a variable"),
+        }
+
+        // the true and false jumps swap
+        std::mem::swap(&mut exp.t, &mut exp.f);
+        self.remove_values(exp.f);
+        self.remove_values(exp.t);
+    }
+
+    fn fold_constants(&self, op : OpCode, first : &mut Exp, second : &Exp) -> bool {
+        //! works out arithmetic on numbers while compiling, but never
+        //! divides by zero or makes a NaN
+
+        let (a, b) = match (first.numeral(), second.numeral()) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return false,
+        };
+
+        let arithmetic = match op {
+            OpCode::Add => Arithmetic::Add,
+            OpCode::Sub => Arithmetic::Sub,
+            OpCode::Mul => Arithmetic::Mul,
+            OpCode::Div if b != 0.0 => Arithmetic::Div,
+            OpCode::Mod if b != 0.0 => Arithmetic::Mod,
+            OpCode::Pow => Arithmetic::Pow,
+            OpCode::Unm => Arithmetic::Unm,
+            _ => return false,
+        };
+
+        let result = arithmetic.apply(a, b);
+        if result.is_nan() {
+            return false;
+        }
+
+        first.kind = Kind::Number(result);
+        true
+    }
+
+    fn code_arithmetic(&mut self, op : OpCode, first : &mut Exp, second : &mut Exp) {
+        if self.fold_constants(op, first, second) {
+            return;
+        }
+
+        let c = match op {
+            OpCode::Unm | OpCode::Len => 0,
+            _ => self.exp_to_rk(second),
+        };
+        let b = self.exp_to_rk(first);
+
+        // the registers are let go of newest first
+        match b > c {
+            true => {
+                self.free_exp(first);
+                self.free_exp(second);
+            },
+            false => {
+                self.free_exp(second);
+                self.free_exp(first);
+            },
+        }
+
+        first.kind = Kind::Relocatable(self.code_abc(op, 0, b, c));
+    }
+
+    fn code_comparison(&mut self, op : OpCode, condition : bool, first : &mut Exp, second : &mut Exp) {
+        let mut b = self.exp_to_rk(first);
+        let mut c = self.exp_to_rk(second);
+        self.free_exp(second);
+        self.free_exp(first);
+
+        // `a > b` is `b < a`
+        let mut condition = condition;
+        if !condition && op != OpCode::Eq {
+            std::mem::swap(&mut b, &mut c);
+            condition = true;
+        }
+
+        first.kind = Kind::Jump(self.condition_jump(op, condition as usize, b, c) as usize);
+    }
+}
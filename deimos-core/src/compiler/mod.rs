@@ -0,0 +1,978 @@
+//! the compiler turns the parsed tree of a chunk into bytecode, a `Proto`
+//! for the chunk with the protos of the functions inside of it. it walks the
+//! tree the way lua's parser walks the tokens, making the same code as lua
+//! does for each statement.
+//!
+//! every instruction gets the line and the part of the code of the element
+//! it was made for, so the errors when running it point to the right place.
+
+mod code;
+
+use std::rc::Rc;
+
+use failure::Error;
+
+use crate::chunk::Chunk;
+use crate::element::CodeElement;
+use crate::token::Token;
+use crate::scanner;
+use crate::parser::MAX_LEVELS;
+use crate::value::LuaString;
+use crate::bytecode::undump;
+use crate::bytecode::{Proto, Source, VARARG_ISVARARG, VARARG_HASARG, VARARG_NEEDSARG};
+use crate::bytecode::instruction::{OpCode, FIELDS_PER_FLUSH, int_to_fb};
+use crate::compiler::code::{FunctionState, Exp, Kind, UnaryOp, BinaryOp, UpvalueSource, NO_JUMP, MULTIPLE};
+
+pub fn compile(chunk : Chunk) -> Result<Rc<Proto>,Error> {
+    //! compiles the chunk into the function that runs it, the chunk is a
+    //! vararg function without any parameters.
+
+    let Chunk { file_name, raw_code, block } = chunk;
+
+    // the final return is on the line of the last thing in the chunk
+    let last_line = block.i().elements().iter()
+        .rev()
+        .find(|statement| !statement.i().is_comment())
+        .map(|statement| line_at(&raw_code, statement.code_end()))
+        .unwrap_or(1);
+
+    let source = Rc::new(Source { name : file_name, code : raw_code });
+    let mut compiler = Compiler { functions : vec![FunctionState::new(source, 0)], levels : 0 };
+
+    compiler.function().is_vararg = VARARG_ISVARARG;
+    compiler.statements(&block);
+    compiler.function().line = last_line;
+
+    let function = compiler.functions.pop().expect("the main function");
+    match function.finish() {
+        (_, _, Some(error)) => Err(error),
+        (proto, _, None) => Ok(Rc::new(proto)),
+    }
+}
+
+pub fn load(code : &[u8], chunk_name : &str) -> Result<Rc<Proto>,Error> {
+    //! compiles the code, or reads it if it is a binary chunk that was
+    //! already compiled. the chunk name is what the errors call it.
+
+    match undump::is_binary(code) {
+        true => undump::undump(code, chunk_name),
+        false => compile(Chunk::from_str(&scanner::decode_source(code), Some(chunk_name))?),
+    }
+}
+
+/// the functions being compiled, each one inside of the one before it
+struct Compiler {
+    functions : Vec<FunctionState>,
+    // how deep in expressions we are, the parser already stops the tree
+    // from being too deep but the walk shouldn't count on that
+    levels : usize,
+}
+
+impl Compiler {
+    fn function(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().expect("a function")
+    }
+
+    fn enter(&mut self, element : &CodeElement) -> (usize, (usize, usize)) {
+        //! the instructions made from here on are for the element, gives
+        //! back where we were before
+
+        let function = self.function();
+        let previous = (function.line, function.span);
+        function.line = element.line_number();
+        function.span = (element.code_start(), element.code_end());
+        previous
+    }
+
+    fn leave(&mut self, (line, span) : (usize, (usize, usize))) {
+        let function = self.function();
+        function.line = line;
+        function.span = span;
+    }
+
+    // STATEMENTS ////////////////////////////////////////////
+
+    fn block(&mut self, block : &CodeElement) {
+        self.function().enter_block(false);
+        self.statements(block);
+        self.function().leave_block();
+    }
+
+    fn statements(&mut self, block : &CodeElement) {
+        for statement in block.i().elements() {
+            self.statement(statement);
+
+            // the temporary registers are let go of after each statement
+            let function = self.function();
+            function.free_register = function.active_count;
+        }
+    }
+
+    fn statement(&mut self, statement : &CodeElement) {
+        let position = self.enter(statement);
+
+        let element = statement.i();
+        let identifiers = element.identifiers();
+        let elements = element.elements();
+
+        if let Some(token) = element.get_token() {
+            match token.item() {
+                Token::Break => self.function().break_loop(),
+                Token::Return => self.function().ret(0, 0),
+                // comments are kept in the tree, but don't do anything
+                _ => { },
+            }
+        } else if is_call(statement) {
+            // only the call itself, none of its values are kept
+            let exp = self.expression(statement);
+            if let Kind::Call(pc) = exp.kind {
+                self.function().instruction_mut(pc).set_c(1);
+            }
+        } else {
+            match identifiers[0].item() {
+                Token::Return => self.return_statement(&elements[0]),
+                Token::Equal => self.assignment(&elements[0], &elements[1]),
+                Token::Do => self.block(&elements[0]),
+                Token::While => self.while_statement(&elements[0], &elements[1]),
+                Token::Repeat => self.repeat_statement(&elements[0], &elements[1]),
+                Token::If => self.if_statement(elements),
+                Token::For if identifiers[1] == Token::Equal => self.numeric_for(statement),
+                Token::For => self.generic_for(&elements[0], &elements[1], &elements[2]),
+                Token::Function => self.function_statement(statement, &elements[0], &elements[1]),
+                Token::Local if identifiers.len() == 2 && identifiers[1] == Token::Function =>
+                    self.local_function(statement, &elements[0], &elements[1]),
+                Token::Local => self.local_statement(elements),
+                _ => self.function().fail("not a statement"),
+            }
+        }
+
+        self.leave(position);
+    }
+
+    fn return_statement(&mut self, exps : &CodeElement) {
+        //! return explist, a call by itself is a tail call
+
+        let (count, mut exp) = self.expression_list(exps);
+
+        let (first, count) = match exp.has_multiple() {
+            true => {
+                self.function().set_multiple_returns(&mut exp);
+                if let (Kind::Call(pc), 1) = (exp.kind, count) {
+                    self.function().instruction_mut(pc).set_opcode(OpCode::TailCall);
+                }
+                (self.function().active_count, MULTIPLE)
+            },
+            false if count == 1 => (self.function().exp_to_any_register(&mut exp), 1),
+            false => {
+                self.function().exp_to_next_register(&mut exp);
+                (self.function().active_count, count as i32)
+            },
+        };
+
+        self.function().ret(first, count);
+    }
+
+    fn assignment(&mut self, vars : &CodeElement, exps : &CodeElement) {
+        //! varlist `=´ explist, the values are all worked out before any
+        //! of them are assigned
+
+        let mut targets : Vec<Exp> = Vec::new();
+        for var in vars.i().elements() {
+            let target = self.expression(var);
+            if let Kind::Local(register) = target.kind {
+                self.check_conflict(&mut targets, register);
+            }
+            targets.push(target);
+        }
+
+        let (count, mut exp) = self.expression_list(exps);
+        let mut targets = targets.into_iter().rev();
+        let last = targets.next().expect("a variable");
+
+        if count == vars.i().elements().len() {
+            self.function().set_one_return(&mut exp);
+            self.function().store_var(&last, &mut exp);
+        } else {
+            self.adjust_assign(vars.i().elements().len(), count, &mut exp);
+            if count > vars.i().elements().len() {
+                self.function().free_register -= count - vars.i().elements().len();
+            }
+            self.store_top(&last);
+        }
+
+        // the others are in the registers before it, last one first
+        for target in targets {
+            self.store_top(&target);
+        }
+    }
+
+    fn store_top(&mut self, target : &Exp) {
+        let mut exp = Exp::new(Kind::NonRelocatable(self.function().free_register - 1));
+        self.function().store_var(target, &mut exp);
+    }
+
+    fn check_conflict(&mut self, targets : &mut [Exp], register : usize) {
+        //! a local that is assigned to might be used as a table or a key
+        //! by one of the variables before it, they get a copy of the old
+        //! value so they don't see the new one
+
+        let function = self.function();
+        let copy = function.free_register;
+        let mut conflict = false;
+
+        for target in targets.iter_mut() {
+            if let Kind::Indexed(ref mut table, ref mut key) = target.kind {
+                if *table == register {
+                    conflict = true;
+                    *table = copy;
+                }
+                if *key == register {
+                    conflict = true;
+                    *key = copy;
+                }
+            }
+        }
+
+        if conflict {
+            function.code_abc(OpCode::Move, copy, register, 0);
+            function.reserve_registers(1);
+        }
+    }
+
+    fn adjust_assign(&mut self, variables : usize, count : usize, exp : &mut Exp) {
+        //! makes the expressions give as many values as there are variables
+
+        let function = self.function();
+        let extra = variables as i32 - count as i32;
+
+        if exp.has_multiple() {
+            // the call or `...` gives the missing values
+            let extra = (extra + 1).max(0);
+            function.set_returns(exp, extra);
+            if extra > 1 {
+                function.reserve_registers(extra as usize - 1);
+            }
+        } else {
+            if exp.kind != Kind::Void {
+                function.exp_to_next_register(exp);
+            }
+            if extra > 0 {
+                let register = function.free_register;
+                function.reserve_registers(extra as usize);
+                function.load_nil(register, extra as usize);
+            }
+        }
+    }
+
+    fn condition(&mut self, exp : &CodeElement) -> i32 {
+        //! the jumps taken when the condition is false
+
+        let mut exp = self.expression(exp);
+        if exp.kind == Kind::Nil {
+            exp.kind = Kind::False;
+        }
+
+        self.function().go_if_true(&mut exp);
+        exp.f
+    }
+
+    fn while_statement(&mut self, condition : &CodeElement, block : &CodeElement) {
+        let start = self.function().get_label();
+        let exit = self.condition(condition);
+
+        self.function().enter_block(true);
+        self.block(block);
+
+        let function = self.function();
+        let jump = function.jump();
+        function.patch_list(jump, start);
+        function.leave_block();
+        function.patch_to_here(exit);
+    }
+
+    fn repeat_statement(&mut self, block : &CodeElement, condition : &CodeElement) {
+        //! repeat block until exp, the condition can see the locals of the
+        //! block
+
+        let start = self.function().get_label();
+        self.function().enter_block(true);
+        self.function().enter_block(false);
+
+        self.statements(block);
+        let exit = self.condition(condition);
+
+        let function = self.function();
+        match function.block_has_upvalue() {
+            false => {
+                function.leave_block();
+                function.patch_list(exit, start);
+            },
+            true => {
+                // the locals have to be closed before going around again
+                function.break_loop();
+                function.patch_to_here(exit);
+                function.leave_block();
+                let jump = function.jump();
+                function.patch_list(jump, start);
+            },
+        }
+
+        function.leave_block();
+    }
+
+    fn if_statement(&mut self, elements : &[Box<CodeElement>]) {
+        //! if exp then block {elseif exp then block} [else block] end
+
+        let mut escape = NO_JUMP;
+        let mut false_list = NO_JUMP;
+
+        for (i, pair) in elements.chunks(2).enumerate() {
+            if i > 0 {
+                let function = self.function();
+                let jump = function.jump();
+                function.concat(&mut escape, jump);
+                function.patch_to_here(false_list);
+                false_list = NO_JUMP;
+            }
+
+            match pair.len() {
+                // the else block
+                1 => self.block(&pair[0]),
+                _ => {
+                    false_list = self.condition(&pair[0]);
+                    self.block(&pair[1]);
+                },
+            }
+        }
+
+        let function = self.function();
+        function.concat(&mut escape, false_list);
+        function.patch_to_here(escape);
+    }
+
+    fn numeric_for(&mut self, statement : &CodeElement) {
+        //! for Name `=´ exp `,´ exp [`,´ exp] do block end
+
+        let elements = statement.i().elements();
+
+        self.function().enter_block(true);
+
+        let function = self.function();
+        let base = function.free_register;
+        function.declare_local(LuaString::from("(for index)"), 0);
+        function.declare_local(LuaString::from("(for limit)"), 1);
+        function.declare_local(LuaString::from("(for step)"), 2);
+        function.declare_local(token_name(&elements[0]), 3);
+
+        for exp in elements[1 .. elements.len() - 1].iter() {
+            let mut exp = self.expression(exp);
+            self.function().exp_to_next_register(&mut exp);
+        }
+
+        // the step is 1 if it isn't given
+        if elements.len() == 4 {
+            let function = self.function();
+            let one = function.number_constant(1.0);
+            let register = function.free_register;
+            function.code_abx(OpCode::LoadK, register, one);
+            function.reserve_registers(1);
+        }
+
+        self.for_body(base, statement.line_number(), 1, true, &elements[elements.len() - 1]);
+        self.function().leave_block();
+    }
+
+    fn generic_for(&mut self, names : &CodeElement, exps : &CodeElement, block : &CodeElement) {
+        //! for namelist in explist do block end, the explist gives the
+        //! function, the state and the first control value
+
+        self.function().enter_block(true);
+
+        let function = self.function();
+        let base = function.free_register;
+        function.declare_local(LuaString::from("(for generator)"), 0);
+        function.declare_local(LuaString::from("(for state)"), 1);
+        function.declare_local(LuaString::from("(for control)"), 2);
+        for (i, name) in names.i().elements().iter().enumerate() {
+            function.declare_local(token_name(name), 3 + i);
+        }
+
+        let position = self.enter(exps);
+        let (count, mut exp) = self.expression_list(exps);
+        self.adjust_assign(3, count, &mut exp);
+        self.function().check_stack(3);
+        self.leave(position);
+
+        self.for_body(base, exps.line_number(), names.i().elements().len(), false, block);
+        self.function().leave_block();
+    }
+
+    fn for_body(&mut self, base : usize, line : usize, variables : usize, numeric : bool, block : &CodeElement) {
+        let function = self.function();
+        function.adjust_locals(3);
+
+        let prep = match numeric {
+            true => function.code_asbx(OpCode::ForPrep, base, NO_JUMP) as i32,
+            false => function.jump(),
+        };
+
+        // the loop's own variables
+        function.enter_block(false);
+        function.adjust_locals(variables);
+        function.reserve_registers(variables);
+        self.block(block);
+
+        let function = self.function();
+        function.leave_block();
+        function.patch_to_here(prep);
+
+        let end = match numeric {
+            true => function.code_asbx(OpCode::ForLoop, base, NO_JUMP) as i32,
+            false => function.code_abc(OpCode::TForLoop, base, 0, variables) as i32,
+        };
+        function.fix_line(line);
+
+        let end = match numeric {
+            true => end,
+            false => function.jump(),
+        };
+        function.patch_list(end, prep as usize + 1);
+    }
+
+    fn function_statement(&mut self, statement : &CodeElement, funcname : &CodeElement, funcbody : &CodeElement) {
+        //! function funcname funcbody
+
+        let var = match funcname.i().get_token() {
+            Some(_) => self.expression(funcname),
+            None => {
+                // Name {`.´ Name} [`:´ Name], the names after the first are
+                // fields of the one before
+                let names = funcname.i().elements();
+                let mut var = self.expression(&names[0]);
+                for name in names[1 ..].iter() {
+                    self.field(&mut var, name);
+                }
+                var
+            },
+        };
+
+        let mut function = self.function_body(funcbody, statement.line_number());
+        self.function().store_var(&var, &mut function);
+        self.function().fix_line(statement.line_number());
+    }
+
+    fn local_function(&mut self, statement : &CodeElement, name : &CodeElement, funcbody : &CodeElement) {
+        //! local function Name funcbody, the function can see itself
+
+        let function = self.function();
+        function.declare_local(token_name(name), 0);
+        let var = Exp::new(Kind::Local(function.free_register));
+        function.reserve_registers(1);
+        function.adjust_locals(1);
+
+        let mut body = self.function_body(funcbody, statement.line_number());
+        let function = self.function();
+        function.store_var(&var, &mut body);
+
+        // the debug information only has it once it has a value
+        function.set_local_start();
+    }
+
+    fn local_statement(&mut self, elements : &[Box<CodeElement>]) {
+        //! local namelist [`=´ explist], the new locals can't be seen by
+        //! their own expressions
+
+        let names = elements[0].i().elements();
+        for (i, name) in names.iter().enumerate() {
+            self.function().declare_local(token_name(name), i);
+        }
+
+        let (count, mut exp) = match elements.len() {
+            2 => self.expression_list(&elements[1]),
+            _ => (0, Exp::new(Kind::Void)),
+        };
+
+        self.adjust_assign(names.len(), count, &mut exp);
+        self.function().adjust_locals(names.len());
+    }
+
+    // EXPRESSIONS ///////////////////////////////////////////
+
+    fn expression_list(&mut self, exps : &CodeElement) -> (usize, Exp) {
+        //! all the expressions but the last go in the next registers, the
+        //! last one is given back as it is so it can give more values
+
+        let exps = exps.i().elements();
+
+        let mut exp = self.expression(&exps[0]);
+        for next in exps[1 ..].iter() {
+            self.function().exp_to_next_register(&mut exp);
+            exp = self.expression(next);
+        }
+
+        (exps.len(), exp)
+    }
+
+    fn expression(&mut self, exp : &CodeElement) -> Exp {
+        let position = self.enter(exp);
+        self.levels += 1;
+
+        let exp = match self.levels > MAX_LEVELS {
+            true => {
+                self.function().fail("chunk has too many syntax levels");
+                Exp::new(Kind::Nil)
+            },
+            false => self.expression_inner(exp),
+        };
+
+        self.levels -= 1;
+        self.leave(position);
+        exp
+    }
+
+    fn expression_inner(&mut self, exp : &CodeElement) -> Exp {
+        let element = exp.i();
+
+        if let Some(token) = element.get_token() {
+            return match token.item() {
+                Token::Nil => Exp::new(Kind::Nil),
+                Token::True => Exp::new(Kind::True),
+                Token::False => Exp::new(Kind::False),
+                Token::Number(number) => Exp::new(Kind::Number(*number)),
+                Token::String(string) | Token::MultiLineString(string) => Exp::new(Kind::Constant(self.function().string_constant(string.clone()))),
+                Token::TriplePeriod => {
+                    let function = self.function();
+                    // it doesn't need `arg` if it uses `...`
+                    function.is_vararg &= !VARARG_NEEDSARG;
+                    Exp::new(Kind::VarArg(function.code_abc(OpCode::VarArg, 0, 1, 0)))
+                },
+                Token::Identifier(name) => self.single_variable(name),
+                _ => {
+                    self.function().fail("unexpected symbol");
+                    Exp::new(Kind::Nil)
+                },
+            };
+        }
+
+        let identifiers = element.identifiers();
+        let elements = element.elements();
+
+        // functioncall
+        if is_call(exp) {
+            return self.call(elements);
+        }
+
+        match identifiers[0].item() {
+            // unop exp
+            op if elements.len() == 1 && op.is_unop() => {
+                let mut operand = self.expression(&elements[0]);
+                let op = match op {
+                    Token::Not => UnaryOp::Not,
+                    Token::Minus => UnaryOp::Minus,
+                    _ => UnaryOp::Len,
+                };
+                self.function().prefix(op, &mut operand);
+                operand
+            },
+
+            // exp binop exp
+            op if elements.len() == 2 && op.is_binop() => {
+                let op = binary_op(op);
+                let mut first = self.expression(&elements[0]);
+                self.function().infix(op, &mut first);
+                let mut second = self.expression(&elements[1]);
+                self.function().postfix(op, &mut first, &mut second);
+                first
+            },
+
+            // `(´ exp `)´, only ever one value
+            Token::LeftParen if elements.len() == 1 => {
+                let mut exp = self.expression(&elements[0]);
+                self.function().discharge_vars(&mut exp);
+                exp
+            },
+
+            // function funcbody
+            Token::Function => self.function_body(&elements[0], exp.line_number()),
+
+            // tableconstructor
+            Token::LeftMoustache => self.table_constructor(exp),
+
+            // prefixexp `.´ Name
+            Token::Period => {
+                let mut table = self.expression(&elements[0]);
+                self.field(&mut table, &elements[1]);
+                table
+            },
+
+            // prefixexp `[´ exp `]´
+            _ => {
+                let mut table = self.expression(&elements[0]);
+                self.function().exp_to_any_register(&mut table);
+                let mut key = self.expression(&elements[1]);
+                self.function().exp_to_value(&mut key);
+                self.function().indexed(&mut table, &mut key);
+                table
+            },
+        }
+    }
+
+    fn field(&mut self, table : &mut Exp, name : &CodeElement) {
+        let function = self.function();
+        function.exp_to_any_register(table);
+        let mut key = Exp::new(Kind::Constant(function.string_constant(token_name(name))));
+        function.indexed(table, &mut key);
+    }
+
+    fn single_variable(&mut self, name : &LuaString) -> Exp {
+        //! a local, an upvalue from one of the functions around this one,
+        //! or a global
+
+        let level = self.functions.len() - 1;
+        match self.find_variable(level, name, true) {
+            Some(kind) => Exp::new(kind),
+            None => {
+                let function = self.function();
+                Exp::new(Kind::Global(function.string_constant(name.clone())))
+            },
+        }
+    }
+
+    fn find_variable(&mut self, level : usize, name : &LuaString, base : bool) -> Option<Kind> {
+        if let Some(register) = self.functions[level].search_local(name) {
+            // a function inside is using it
+            if !base {
+                self.functions[level].mark_upvalue(register);
+            }
+            return Some(Kind::Local(register));
+        }
+
+        if level == 0 {
+            return None;
+        }
+
+        let source = match self.find_variable(level - 1, name, false)? {
+            Kind::Local(register) => UpvalueSource::Local(register),
+            Kind::Upvalue(index) => UpvalueSource::Upvalue(index),
+            _ => return None,
+        };
+
+        Some(Kind::Upvalue(self.functions[level].index_upvalue(name, source)))
+    }
+
+    fn call(&mut self, elements : &[Box<CodeElement>]) -> Exp {
+        //! prefixexp args | prefixexp `:´ Name args
+
+        let mut function = self.expression(&elements[0]);
+
+        let args = match elements.len() {
+            // the object is the first arguement
+            3 => {
+                let state = self.function();
+                let mut key = Exp::new(Kind::Constant(state.string_constant(token_name(&elements[1]))));
+                state.self_op(&mut function, &mut key);
+                &elements[2]
+            },
+            _ => {
+                self.function().exp_to_next_register(&mut function);
+                &elements[1]
+            },
+        };
+
+        self.call_arguments(&mut function, args);
+        function
+    }
+
+    fn call_arguments(&mut self, function : &mut Exp, args : &CodeElement) {
+        //! `(´ [explist] `)´ | tableconstructor | String
+
+        let mut arguments = match args.i().get_token() {
+            Some(_) => self.expression(args),
+            None => match args.i().identifiers()[0].item() {
+                Token::LeftParen => match args.i().elements().first() {
+                    Some(exps) => {
+                        let (_, mut exp) = self.expression_list(exps);
+                        self.function().set_multiple_returns(&mut exp);
+                        exp
+                    },
+                    None => Exp::new(Kind::Void),
+                },
+                _ => self.table_constructor(args),
+            },
+        };
+
+        let base = match function.kind {
+            Kind::NonRelocatable(register) => register,
+            _ => unreachable!("the function was put in a register"),
+        };
+
+        let position = self.enter(args);
+        let state = self.function();
+        let count = match arguments.has_multiple() {
+            true => MULTIPLE,
+            false => {
+                if arguments.kind != Kind::Void {
+                    state.exp_to_next_register(&mut arguments);
+                }
+                (state.free_register - (base + 1)) as i32
+            },
+        };
+
+        function.kind = Kind::Call(state.code_abc(OpCode::Call, base, (count + 1) as usize, 2));
+        // the call leaves one value, where the function was
+        state.free_register = base + 1;
+        self.leave(position);
+    }
+
+    fn table_constructor(&mut self, exp : &CodeElement) -> Exp {
+        //! `{´ [fieldlist] `}´, the sizes are set once we know them
+
+        let function = self.function();
+        let pc = function.code_abc(OpCode::NewTable, 0, 0, 0);
+        let mut table = Exp::new(Kind::Relocatable(pc));
+        function.exp_to_next_register(&mut table);
+        let register = function.free_register - 1;
+
+        let fields = match exp.i().elements().first() {
+            Some(fieldlist) => fieldlist.i().elements(),
+            None => &[],
+        };
+
+        let mut pending = Exp::new(Kind::Void);
+        let mut array_size = 0;
+        let mut hash_size = 0;
+        let mut to_store = 0;
+
+        for field in fields {
+            // the item before goes in its register, and a group of them
+            // goes in the table
+            if pending.kind != Kind::Void {
+                let function = self.function();
+                function.exp_to_next_register(&mut pending);
+                pending = Exp::new(Kind::Void);
+                if to_store == FIELDS_PER_FLUSH {
+                    function.set_list(register, array_size, to_store as i32);
+                    to_store = 0;
+                }
+            }
+
+            let identifiers = field.i().identifiers();
+            let elements = field.i().elements();
+
+            let is_record = (identifiers.len() == 3 && identifiers[0] == Token::LeftBracket)
+                || (identifiers.len() == 1 && identifiers[0] == Token::Equal && elements.len() == 2 && elements[0].i().get_token().is_some());
+
+            if !is_record {
+                pending = self.expression(field);
+                array_size += 1;
+                to_store += 1;
+                continue;
+            }
+
+            // `[´ exp `]´ `=´ exp | Name `=´ exp
+            let position = self.enter(field);
+            let free_register = self.function().free_register;
+            let mut key = match identifiers[0].item() {
+                Token::LeftBracket => {
+                    let mut key = self.expression(&elements[0]);
+                    self.function().exp_to_value(&mut key);
+                    key
+                },
+                _ => {
+                    let function = self.function();
+                    Exp::new(Kind::Constant(function.string_constant(token_name(&elements[0]))))
+                },
+            };
+            hash_size += 1;
+
+            let key = self.function().exp_to_rk(&mut key);
+            let mut value = self.expression(&elements[1]);
+            let function = self.function();
+            let value = function.exp_to_rk(&mut value);
+            function.code_abc(OpCode::SetTable, register, key, value);
+            function.free_register = free_register;
+            self.leave(position);
+        }
+
+        // the last item can give all of its values
+        let function = self.function();
+        if to_store > 0 {
+            match pending.has_multiple() {
+                true => {
+                    function.set_multiple_returns(&mut pending);
+                    function.set_list(register, array_size, MULTIPLE);
+                    array_size -= 1;
+                },
+                false => {
+                    if pending.kind != Kind::Void {
+                        function.exp_to_next_register(&mut pending);
+                    }
+                    function.set_list(register, array_size, to_store as i32);
+                },
+            }
+        }
+
+        let instruction = function.instruction_mut(pc);
+        instruction.set_b(int_to_fb(array_size));
+        instruction.set_c(int_to_fb(hash_size));
+
+        table
+    }
+
+    fn function_body(&mut self, funcbody : &CodeElement, line : usize) -> Exp {
+        //! `(´ [parlist] `)´ block end, compiled as a new function. gives
+        //! back the closure that makes it.
+
+        let elements = funcbody.i().elements();
+        let source = self.functions[0].source();
+        self.functions.push(FunctionState::new(source, line));
+
+        // parlist ::= namelist [`,´ `...´] | `...´
+        let (names, is_vararg) : (&[Box<CodeElement>], bool) = match elements.len() {
+            2 => {
+                let parlist = &elements[0];
+                match parlist.i().get_token() {
+                    Some(_) => (&[], true),
+                    None => match parlist.i().elements().last() {
+                        Some(last) if last.i().matches_token(Token::TriplePeriod) => (parlist.i().elements()[0].i().elements(), true),
+                        _ => (parlist.i().elements(), false),
+                    },
+                }
+            },
+            _ => (&[], false),
+        };
+
+        let function = self.function();
+        for (i, name) in names.iter().enumerate() {
+            function.declare_local(token_name(name), i);
+        }
+
+        // the extra arguements are in `arg` too, unless `...` is used
+        if is_vararg {
+            function.declare_local(LuaString::from("arg"), names.len());
+            function.is_vararg = VARARG_HASARG | VARARG_NEEDSARG | VARARG_ISVARARG;
+        }
+
+        let count = names.len() + is_vararg as usize;
+        function.adjust_locals(count);
+        function.parameters = function.active_count - (function.is_vararg & VARARG_HASARG) as usize;
+        function.reserve_registers(count);
+
+        self.statements(&elements[elements.len() - 1]);
+
+        // the final return is on the line of the `end`
+        let end_line = funcbody.i().identifiers().last().map(|end| end.line_number()).unwrap_or(line);
+        let function = self.function();
+        function.last_line_defined = end_line;
+        function.line = end_line;
+
+        let (proto, upvalues, error) = self.functions.pop().expect("the function").finish();
+
+        let function = self.function();
+        function.keep_error(error);
+        let index = function.add_proto(proto);
+        let pc = function.code_abx(OpCode::Closure, 0, index);
+
+        // the upvalues it captures follow it, as if they were moved into it
+        for upvalue in upvalues {
+            match upvalue {
+                UpvalueSource::Local(register) => function.code_abc(OpCode::Move, 0, register, 0),
+                UpvalueSource::Upvalue(index) => function.code_abc(OpCode::GetUpval, 0, index, 0),
+            };
+        }
+
+        Exp::new(Kind::Relocatable(pc))
+    }
+}
+
+fn is_call(exp : &CodeElement) -> bool {
+    //! `prefixexp args` or `prefixexp `:´ Name args`, checked by the shape
+    //! of the element.
+
+    let identifiers = exp.i().identifiers();
+    let elements = exp.i().elements();
+
+    (identifiers.is_empty() && elements.len() == 2)
+    || (identifiers.len() == 1 && identifiers[0] == Token::Colon && elements.len() == 3)
+}
+
+fn token_name(element : &CodeElement) -> LuaString {
+    //! the name of an identifier element
+
+    match element.i().get_token().map(|token| token.item()) {
+        Some(Token::Identifier(name)) => name.clone(),
+        _ => LuaString::from(""),
+    }
+}
+
+fn binary_op(token : &Token) -> BinaryOp {
+    match token {
+        Token::Plus => BinaryOp::Add,
+        Token::Minus => BinaryOp::Sub,
+        Token::Star => BinaryOp::Mul,
+        Token::Slash => BinaryOp::Div,
+        Token::Percent => BinaryOp::Mod,
+        Token::Carrot => BinaryOp::Pow,
+        Token::DoublePeriod => BinaryOp::Concat,
+        Token::EqualEqual => BinaryOp::Eq,
+        Token::NotEqual => BinaryOp::Ne,
+        Token::LessThan => BinaryOp::Lt,
+        Token::LessEqual => BinaryOp::Le,
+        Token::GreaterThan => BinaryOp::Gt,
+        Token::GreaterEqual => BinaryOp::Ge,
+        Token::And => BinaryOp::And,
+        _ => BinaryOp::Or,
+    }
+}
+
+fn line_at(code : &str, position : usize) -> usize {
+    //! the line the position in the code is on
+
+    let end = position.min(code.len());
+    1 + code.as_bytes()[.. end].iter().filter(|byte| **byte == b'\n').count()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::chunk::Chunk;
+    use crate::compiler::compile;
+    use crate::bytecode::instruction::OpCode;
+
+    fn code(source : &str) -> Vec<(OpCode, usize, i64, usize)> {
+        //! the instructions as `(op, A, B or sBx or Bx, C)`
+
+        let proto = compile(Chunk::from_str(source, None).unwrap()).unwrap();
+        proto.code.iter()
+            .map(|instruction| match instruction.opcode() {
+                OpCode::Jmp | OpCode::ForLoop | OpCode::ForPrep => (instruction.opcode(), instruction.a(), instruction.sbx() as i64, 0),
+                OpCode::LoadK | OpCode::GetGlobal | OpCode::SetGlobal | OpCode::Closure => (instruction.opcode(), instruction.a(), instruction.bx() as i64, 0),
+                op => (op, instruction.a(), instruction.b() as i64, instruction.c()),
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn same_code_as_luac() {
+        use OpCode::*;
+
+        assert_eq!(code("local a = 1; return a + 2"), vec![
+            (LoadK, 0, 0, 0), (Add, 1, 0, 257), (Return, 1, 2, 0), (Return, 0, 1, 0)]);
+
+        assert_eq!(code("if x then y = 1 else y = 2 end"), vec![
+            (GetGlobal, 0, 0, 0), (Test, 0, 0, 0), (Jmp, 0, 3, 0), (LoadK, 0, 2, 0), (SetGlobal, 0, 1, 0),
+            (Jmp, 0, 2, 0), (LoadK, 0, 3, 0), (SetGlobal, 0, 1, 0), (Return, 0, 1, 0)]);
+
+        assert_eq!(code("return f(1)"), vec![
+            (GetGlobal, 0, 0, 0), (LoadK, 1, 1, 0), (TailCall, 0, 2, 0), (Return, 0, 0, 0), (Return, 0, 1, 0)]);
+    }
+
+    #[test]
+    pub fn lines() {
+        let proto = compile(Chunk::from_str("local a = 1\n\nlocal b = a +\n  2\nprint(b)", None).unwrap()).unwrap();
+        assert_eq!(proto.lines, vec![1, 3, 5, 5, 5, 5]);
+
+        // nothing that breaks out of a loop that isn't there gets run
+        assert!(compile(Chunk::from_str("if x then break end", None).unwrap()).is_err());
+    }
+}
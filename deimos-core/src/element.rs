@@ -120,6 +120,14 @@ impl Element {
 
 	}
 
+	pub fn identifiers(&self) -> &[CodeToken] {
+		&self.identifiers
+	}
+
+	pub fn elements(&self) -> &[Box<CodeElement>] {
+		&self.elements
+	}
+
 	pub fn add_to_elements(&mut self, element : CodeElement) {
 		self.elements.push(Box::new(element));
 	}
@@ -149,6 +157,9 @@ impl Element {
 
         let Element { ref identifiers, ref elements } = self;
 
+        // an empty block is still a block, `do end` is valid lua
+        if identifiers.len() == 0 && elements.len() == 0 { return true; }
+
         if identifiers.len() == 0 && elements.len() > 0 {
         	// checks that all but the elements are statements
         	for i in 0 .. elements.len()-1 {
@@ -1,6 +1,7 @@
 pub mod codeinfo; use codeinfo::CodeInfo;
 pub mod scanner;
 pub mod parser;
+pub mod runtime;
 
 const LEFT_PADDING : &str = "  ";
 const MARKER : &str = "^";
@@ -74,7 +75,7 @@ fn get_position_on_line(code : &str, start : usize) -> usize {
         loop {
             if pos == 0 { break; }
 
-            if Token::is_eol(&code[pos - 1 .. pos]) { break; }
+            if Token::is_eol(code.get(pos - 1 .. pos).unwrap_or("")) { break; }
             pos = pos - 1;
         }    
 
@@ -83,8 +84,7 @@ fn get_position_on_line(code : &str, start : usize) -> usize {
 
     // and we go forward until we don't get a whitespace
     for i in line_start .. code.len() {
-        if &code[i .. i+1] != " " {
-            println!("{} {} {}",start, line_start, i);
+        if code.get(i .. i+1) != Some(" ") {
             return start - i;
         }
     }
@@ -106,7 +106,7 @@ fn slice_code(code : &str, start : usize) -> String {
         loop {
             if pos == 0 { break; }
 
-            if Token::is_eol(&code[pos - 1 .. pos]) { break; }
+            if Token::is_eol(code.get(pos - 1 .. pos).unwrap_or("")) { break; }
             pos = pos - 1;
         }    
 
@@ -120,7 +120,7 @@ fn slice_code(code : &str, start : usize) -> String {
         loop {
             if pos >= code.len() { break; }
 
-            if Token::is_eol(&code[pos .. pos+1]) { break; }
+            if Token::is_eol(code.get(pos .. pos+1).unwrap_or("")) { break; }
             pos = pos + 1;
         }    
 
@@ -131,7 +131,7 @@ fn slice_code(code : &str, start : usize) -> String {
 
     // now we need to remove the leading zeros (if any)
     for i in 0 .. code_slice.len() {
-        if &code_slice[i .. i+1] != " " {
+        if code_slice.get(i .. i+1) != Some(" ") {
             return code_slice[i ..].to_string();
         }
     }
@@ -1,4 +1,3 @@
-use crate::parser::Parser;
 use failure_derive::Fail;
 use failure::Error;
 
@@ -38,18 +37,28 @@ impl std::fmt::Display for ParserError {
 
 
 impl ParserError {
+    pub fn message(&self) -> String {
+        //! the error message the way lua would show it, `file:line: description`
+
+        match self {
+            ParserError::GEN(desc) => desc.to_string(),
+            ParserError::NOSTATEMENT(info) | ParserError::EXPECT(info) | ParserError::UNTERMINATED(info) =>
+                format!("{}:{}: {}", info.file_name, info.line_number, info.description),
+        }
+    }
+
     pub fn general(description : &str) -> Error {
         //! creates a general error
 
         ParserError::GEN(description.to_string()).into()
     }
 
-    pub fn not_a_statement(parser : &Parser, line : usize, start : usize, end : usize) -> Error {
+    pub fn not_a_statement<T : CodeInformation>(parser : &T, line : usize, start : usize, end : usize, description : &str) -> Error {
         //! creates an 'cant reduce to statement' error
 
         let mut code_info = CodeInformation::into_codeinfo(parser);
 
-        code_info.description = String::from("can't reduce to a single statement");
+        code_info.description = description.to_string();
         code_info.span = end - start;
         code_info.cursor_pos = start;
         code_info.line_number = line;
@@ -57,7 +66,7 @@ impl ParserError {
         ParserError::NOSTATEMENT(code_info).into()
     }
 
-    pub fn unexpected(parser : &Parser, line : usize, start : usize, end : usize, description : &str) -> Error {
+    pub fn unexpected<T : CodeInformation>(parser : &T, line : usize, start : usize, end : usize, description : &str) -> Error {
         //! creates an 'cant reduce to statement' error
 
         let mut code_info = CodeInformation::into_codeinfo(parser);
@@ -70,7 +79,7 @@ impl ParserError {
         ParserError::EXPECT(code_info).into()
     }
 
-    pub fn unterminated(parser : &Parser, line : usize, start : usize, end : usize, description : &str) -> Error {
+    pub fn unterminated<T : CodeInformation>(parser : &T, line : usize, start : usize, end : usize, description : &str) -> Error {
         //! creates an 'cant reduce to statement' error
 
         let mut code_info = CodeInformation::into_codeinfo(parser);
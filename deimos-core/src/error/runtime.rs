@@ -0,0 +1,143 @@
+use failure_derive::Fail;
+use failure::Error;
+
+use crate::element::CodeElement;
+use crate::error::{
+    display_error_general, display_error,
+    codeinfo::{ CodeInformation, CodeInfo }};
+
+#[derive(Debug,Fail)]
+pub enum RuntimeError {
+    #[fail]
+    GEN(String),        // general error
+
+    #[fail]
+    EXEC(CodeInfo),     // something went wrong while running an element
+
+    #[fail]
+    VALUE(ErrorValue),  // a value given to lua's `error`
+
+    #[fail]
+    TRACE(Box<RuntimeError>, String),   // an error with the traceback of where it happened
+}
+
+/// what was given to `error`, the actual value is kept by the interpreter
+/// because lua values can't leave the thread they were made on.
+#[derive(Debug)]
+pub struct ErrorValue {
+    // the value as text, for when the error isn't caught
+    pub message : String,
+    // which value the interpreter is holding for us
+    pub id : usize,
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        //! doing it this way so i only need to have the format defined
+        //! once, allowing all error types to have the same formatting.
+
+        match self {
+            RuntimeError::GEN(desc) => display_error_general(f, &desc),
+            RuntimeError::EXEC(info) => display_error(f, "runtime error", &info),
+            RuntimeError::VALUE(value) => display_error_general(f, &value.message),
+            RuntimeError::TRACE(error, traceback) => write!(f, "{}\n{}", error, traceback),
+        }
+    }
+}
+
+impl RuntimeError {
+    pub fn general(description : &str) -> Error {
+        //! creates a general error, used when we don't have any
+        //! code to point at.
+
+        RuntimeError::GEN(description.to_string()).into()
+    }
+
+    pub fn execution<T : CodeInformation>(source : &T, element : &CodeElement, description : &str) -> Error {
+        //! creates an error pointing at the element that was being executed
+        //! when things went wrong.
+
+        let mut code_info = CodeInformation::into_codeinfo(source);
+
+        code_info.description = description.to_string();
+        code_info.span = element.code_end() - element.code_start();
+        code_info.cursor_pos = element.code_start();
+        code_info.line_number = element.line_number();
+
+        RuntimeError::EXEC(code_info).into()
+    }
+
+    pub fn execution_at<T : CodeInformation>(source : &T, line : usize, start : usize, end : usize, description : &str) -> Error {
+        //! creates an error pointing at a part of the code, for when we
+        //! only know where the element was and not the element itself.
+
+        let mut code_info = CodeInformation::into_codeinfo(source);
+
+        code_info.description = description.to_string();
+        code_info.span = end - start;
+        code_info.cursor_pos = start;
+        code_info.line_number = line;
+
+        RuntimeError::EXEC(code_info).into()
+    }
+
+    pub fn message(&self) -> String {
+        //! the error message the way lua would show it, with the file
+        //! and line number in front if we know where it happened.
+
+        match self {
+            RuntimeError::GEN(desc) => desc.to_string(),
+            RuntimeError::EXEC(info) => format!("{}:{}: {}", info.file_name, info.line_number, info.description),
+            RuntimeError::VALUE(value) => value.message.to_string(),
+            RuntimeError::TRACE(error, _) => error.message(),
+        }
+    }
+
+    pub fn traceback(&self) -> Option<&str> {
+        //! the lua stack traceback from when the error happened
+
+        match self {
+            RuntimeError::TRACE(_, traceback) => Some(traceback),
+            _ => None,
+        }
+    }
+
+    pub fn inner(&self) -> &RuntimeError {
+        //! the error without the traceback
+
+        match self {
+            RuntimeError::TRACE(error, _) => error.inner(),
+            error => error,
+        }
+    }
+}
+
+/// a limit set on the interpreter was reached, these stop the script and
+/// can't be caught by `pcall`, so they always make it back to rust.
+#[derive(Debug,Fail)]
+pub enum LimitError {
+    #[fail]
+    Instructions(u64),  // ran more instructions than it was allowed
+
+    #[fail]
+    Memory(usize),      // used more bytes than it was allowed, after collecting
+
+    #[fail]
+    CallDepth(usize),   // called more functions inside of each other than it was allowed
+
+    #[fail]
+    Cancelled,          // the cancel flag was set
+}
+
+impl std::fmt::Display for LimitError {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            LimitError::Instructions(limit) => format!("instruction limit of {} exceeded", limit),
+            LimitError::Memory(limit) => format!("memory limit of {} bytes exceeded", limit),
+            LimitError::CallDepth(limit) => format!("call depth limit of {} exceeded", limit),
+            LimitError::Cancelled => "execution cancelled".to_string(),
+        };
+
+        display_error_general(f, &description)
+    }
+}
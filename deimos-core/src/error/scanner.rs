@@ -38,6 +38,16 @@ impl std::fmt::Display for ScannerError {
 
 
 impl ScannerError {
+    pub fn message(&self) -> String {
+        //! the error message the way lua would show it, `file:line: description`
+
+        match self {
+            ScannerError::GEN(desc) => desc.to_string(),
+            ScannerError::UCS(info) | ScannerError::IC(info) | ScannerError::NP(info) =>
+                format!("{}:{}: {}", info.file_name, info.line_number, info.description),
+        }
+    }
+
     pub fn general(description : &str) -> Error {
         //! creates a general error
 
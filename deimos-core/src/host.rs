@@ -0,0 +1,526 @@
+//! the host is everything outside of lua that the libraries can reach,
+//! the filesystem, the standard streams, the environment and the clock.
+//!
+//! `io` and `os` only ever go through the interpreter's host, so an
+//! embedder can give it a host that denies, fakes or records all of it.
+//! `SystemHost` is the real machine and `SandboxHost` keeps the clock and
+//! the output streams but refuses everything else.
+
+use std::io::{self, Read, Write, Seek, SeekFrom};
+use std::fs::{self, File, OpenOptions};
+use std::cell::Cell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// how big the buffers are when nothing else is asked for, C's `BUFSIZ`
+pub const BUFFER_SIZE : usize = 8192;
+
+/// how a file should be opened, from the mode string given to `io.open`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpenMode {
+    pub read : bool,
+    pub write : bool,
+    pub append : bool,
+    pub truncate : bool,
+    pub create : bool,
+}
+
+impl OpenMode {
+    pub fn parse(mode : &str) -> Option<OpenMode> {
+        //! reads a C `fopen` mode, one of `r`, `w` or `a`, then an optional
+        //! `+`, then any number of `b`s which don't change anything.
+
+        let mut chars = mode.chars().peekable();
+        let first = chars.next()?;
+        let update = chars.peek() == Some(&'+');
+        if update { chars.next(); }
+        if !chars.all(|c| c == 'b') { return None; }
+
+        match first {
+            'r' => Some(OpenMode { read : true, write : update, append : false, truncate : false, create : false }),
+            'w' => Some(OpenMode { read : update, write : true, append : false, truncate : true, create : true }),
+            'a' => Some(OpenMode { read : update, write : true, append : true, truncate : false, create : true }),
+            _ => None,
+        }
+    }
+}
+
+/// when the writes to a file are passed on, from `file:setvbuf`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Buffering {
+    No,
+    Full(usize),
+    Line(usize),
+}
+
+/// the timezone at a moment in time
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Timezone {
+    /// seconds east of UTC
+    pub offset : i64,
+    /// if daylight saving time is in effect
+    pub dst : bool,
+    pub name : String,
+}
+
+impl Timezone {
+    pub fn utc() -> Timezone {
+        Timezone { offset : 0, dst : false, name : "UTC".to_string() }
+    }
+}
+
+/// an open file, or one of the standard streams
+pub trait HostFile {
+    fn read(&mut self, buffer : &mut [u8]) -> io::Result<usize>;
+    fn write(&mut self, bytes : &[u8]) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+    fn seek(&mut self, position : SeekFrom) -> io::Result<u64>;
+
+    fn set_buffering(&mut self, _buffering : Buffering) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn read_to_end(&mut self, bytes : &mut Vec<u8>) -> io::Result<usize> {
+        //! reads everything that is left, giving back how much that was
+
+        let start = bytes.len();
+        let mut chunk = [0; BUFFER_SIZE];
+        loop {
+            match self.read(&mut chunk) {
+                Ok(0) => return Ok(bytes.len() - start),
+                Ok(count) => bytes.extend_from_slice(&chunk[.. count]),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+pub trait Host {
+    fn open(&self, path : &str, mode : OpenMode) -> io::Result<Box<dyn HostFile>>;
+    /// a file that is removed when it is closed
+    fn tmpfile(&self) -> io::Result<Box<dyn HostFile>>;
+    fn stdin(&self) -> Box<dyn HostFile>;
+    fn stdout(&self) -> Box<dyn HostFile>;
+    fn stderr(&self) -> Box<dyn HostFile>;
+
+    fn remove(&self, path : &str) -> io::Result<()>;
+    fn rename(&self, from : &str, to : &str) -> io::Result<()>;
+    /// the name of a new file that can be used for temporary things
+    fn tmpname(&self) -> io::Result<String>;
+
+    fn getenv(&self, name : &str) -> Option<String>;
+    /// seconds since the unix epoch
+    fn time(&self) -> i64;
+    /// seconds of cpu time the program has used
+    fn clock(&self) -> f64;
+    fn timezone(&self, time : i64) -> Timezone;
+    /// ends the program, only comes back if the host won't do it
+    fn exit(&self, code : i32) -> io::Result<()>;
+}
+
+// SYSTEM HOST ///////////////////////////////////////////
+
+/// the real filesystem, environment and clock of the machine
+pub struct SystemHost;
+
+impl Host for SystemHost {
+    fn open(&self, path : &str, mode : OpenMode) -> io::Result<Box<dyn HostFile>> {
+        let file = OpenOptions::new()
+            .read(mode.read)
+            .write(mode.write && !mode.append)
+            .append(mode.append)
+            .truncate(mode.truncate)
+            .create(mode.create)
+            .open(path)?;
+
+        Ok(Box::new(SystemFile::new(file, mode)))
+    }
+
+    fn tmpfile(&self) -> io::Result<Box<dyn HostFile>> {
+        let path = self.tmpname()?;
+        let file = self.open(&path, OpenMode { read : true, write : true, append : false, truncate : true, create : true });
+        // the file stays around while it is open, but nothing can find it
+        fs::remove_file(&path)?;
+        file
+    }
+
+    fn stdin(&self) -> Box<dyn HostFile> {
+        Box::new(Standard::Input)
+    }
+
+    fn stdout(&self) -> Box<dyn HostFile> {
+        Box::new(Standard::Output)
+    }
+
+    fn stderr(&self) -> Box<dyn HostFile> {
+        Box::new(Standard::Error)
+    }
+
+    fn remove(&self, path : &str) -> io::Result<()> {
+        //! like C's `remove`, empty folders can be removed too
+
+        match fs::metadata(path)?.is_dir() {
+            true => fs::remove_dir(path),
+            false => fs::remove_file(path),
+        }
+    }
+
+    fn rename(&self, from : &str, to : &str) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn tmpname(&self) -> io::Result<String> {
+        //! makes an empty file in the temp folder so the name can't be
+        //! taken by anyone else
+
+        thread_local! { static COUNT : Cell<u64> = const { Cell::new(0) }; }
+
+        for _ in 0 .. 100 {
+            let count = COUNT.with(|count| { count.set(count.get() + 1); count.get() });
+            let name = format!("lua_{:x}_{:x}", std::process::id(), count);
+            let path = std::env::temp_dir().join(name);
+
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(path.to_string_lossy().into_owned()),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(error),
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::AlreadyExists, "unable to generate a unique filename"))
+    }
+
+    fn getenv(&self, name : &str) -> Option<String> {
+        std::env::var_os(name).map(|value| value.to_string_lossy().into_owned())
+    }
+
+    fn time(&self) -> i64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(time) => time.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64),
+        }
+    }
+
+    fn clock(&self) -> f64 {
+        process_clock()
+    }
+
+    fn timezone(&self, time : i64) -> Timezone {
+        local_timezone(time)
+    }
+
+    fn exit(&self, code : i32) -> io::Result<()> {
+        io::stdout().flush()?;
+        io::stderr().flush()?;
+        std::process::exit(code)
+    }
+}
+
+/// a file on the disk, buffered like a C `FILE` so that `setvbuf` works
+/// the same way, the writes stay here until the buffer is full.
+pub struct SystemFile {
+    file : File,
+    mode : OpenMode,
+    buffering : Buffering,
+    // written but not given to the file yet
+    unwritten : Vec<u8>,
+    // read from the file but not given out yet, starting at `read_position`
+    unread : Vec<u8>,
+    read_position : usize,
+}
+
+impl SystemFile {
+    pub fn new(file : File, mode : OpenMode) -> SystemFile {
+        SystemFile {
+            file, mode,
+            buffering : Buffering::Full(BUFFER_SIZE),
+            unwritten : Vec::new(),
+            unread : Vec::new(),
+            read_position : 0,
+        }
+    }
+
+    fn write_out(&mut self) -> io::Result<()> {
+        if !self.unwritten.is_empty() {
+            let bytes = std::mem::take(&mut self.unwritten);
+            self.file.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+
+    fn forget_reads(&mut self) -> io::Result<()> {
+        //! moves the file back to where the reading got to, so a write
+        //! after a read goes where it is expected
+
+        let remaining = self.unread.len() - self.read_position;
+        self.unread.clear();
+        self.read_position = 0;
+
+        if remaining > 0 {
+            self.file.seek(SeekFrom::Current(-(remaining as i64)))?;
+        }
+        Ok(())
+    }
+}
+
+impl HostFile for SystemFile {
+    fn read(&mut self, buffer : &mut [u8]) -> io::Result<usize> {
+        if !self.mode.read { return Err(io::Error::from_raw_os_error(libc::EBADF)); }
+        self.write_out()?;
+
+        if self.read_position == self.unread.len() {
+            if buffer.len() >= BUFFER_SIZE {
+                return self.file.read(buffer);
+            }
+
+            self.unread.resize(BUFFER_SIZE, 0);
+            let count = self.file.read(&mut self.unread);
+            self.unread.truncate(*count.as_ref().unwrap_or(&0));
+            self.read_position = 0;
+            count?;
+        }
+
+        let available = &self.unread[self.read_position ..];
+        let count = available.len().min(buffer.len());
+        buffer[.. count].copy_from_slice(&available[.. count]);
+        self.read_position += count;
+
+        Ok(count)
+    }
+
+    fn write(&mut self, bytes : &[u8]) -> io::Result<()> {
+        if !self.mode.write { return Err(io::Error::from_raw_os_error(libc::EBADF)); }
+        self.forget_reads()?;
+
+        self.unwritten.extend_from_slice(bytes);
+        match self.buffering {
+            Buffering::No => self.write_out(),
+            Buffering::Full(size) if self.unwritten.len() >= size => self.write_out(),
+            Buffering::Line(size) if self.unwritten.len() >= size || bytes.contains(&b'\n') => self.write_out(),
+            _ => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_out()?;
+        self.file.flush()
+    }
+
+    fn seek(&mut self, position : SeekFrom) -> io::Result<u64> {
+        self.write_out()?;
+
+        let remaining = (self.unread.len() - self.read_position) as i64;
+        self.unread.clear();
+        self.read_position = 0;
+
+        match position {
+            SeekFrom::Current(offset) => self.file.seek(SeekFrom::Current(offset - remaining)),
+            position => self.file.seek(position),
+        }
+    }
+
+    fn set_buffering(&mut self, buffering : Buffering) -> io::Result<()> {
+        self.write_out()?;
+        self.buffering = buffering;
+        Ok(())
+    }
+}
+
+impl Drop for SystemFile {
+    fn drop(&mut self) {
+        self.write_out().ok();
+    }
+}
+
+/// the standard streams of the process, rust already buffers them
+enum Standard {
+    Input,
+    Output,
+    Error,
+}
+
+impl HostFile for Standard {
+    fn read(&mut self, buffer : &mut [u8]) -> io::Result<usize> {
+        match self {
+            Standard::Input => io::stdin().read(buffer),
+            _ => Err(io::Error::from_raw_os_error(libc::EBADF)),
+        }
+    }
+
+    fn write(&mut self, bytes : &[u8]) -> io::Result<()> {
+        match self {
+            Standard::Input => Err(io::Error::from_raw_os_error(libc::EBADF)),
+            Standard::Output => io::stdout().write_all(bytes),
+            Standard::Error => io::stderr().write_all(bytes),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Standard::Input => Ok(()),
+            Standard::Output => io::stdout().flush(),
+            Standard::Error => io::stderr().flush(),
+        }
+    }
+
+    fn seek(&mut self, _ : SeekFrom) -> io::Result<u64> {
+        Err(io::Error::from_raw_os_error(libc::ESPIPE))
+    }
+}
+
+// SANDBOX HOST //////////////////////////////////////////
+
+/// for code that can't be trusted, the clock and writing to the output
+/// work but the files, the environment, the input and `os.exit` don't.
+pub struct SandboxHost;
+
+impl SandboxHost {
+    fn denied<T>() -> io::Result<T> {
+        Err(io::Error::from_raw_os_error(libc::EACCES))
+    }
+}
+
+impl Host for SandboxHost {
+    fn open(&self, _ : &str, _ : OpenMode) -> io::Result<Box<dyn HostFile>> {
+        SandboxHost::denied()
+    }
+
+    fn tmpfile(&self) -> io::Result<Box<dyn HostFile>> {
+        SandboxHost::denied()
+    }
+
+    fn stdin(&self) -> Box<dyn HostFile> {
+        Box::new(NoInput)
+    }
+
+    fn stdout(&self) -> Box<dyn HostFile> {
+        SystemHost.stdout()
+    }
+
+    fn stderr(&self) -> Box<dyn HostFile> {
+        SystemHost.stderr()
+    }
+
+    fn remove(&self, _ : &str) -> io::Result<()> {
+        SandboxHost::denied()
+    }
+
+    fn rename(&self, _ : &str, _ : &str) -> io::Result<()> {
+        SandboxHost::denied()
+    }
+
+    fn tmpname(&self) -> io::Result<String> {
+        SandboxHost::denied()
+    }
+
+    fn getenv(&self, _ : &str) -> Option<String> {
+        None
+    }
+
+    fn time(&self) -> i64 {
+        SystemHost.time()
+    }
+
+    fn clock(&self) -> f64 {
+        SystemHost.clock()
+    }
+
+    fn timezone(&self, time : i64) -> Timezone {
+        SystemHost.timezone(time)
+    }
+
+    fn exit(&self, _ : i32) -> io::Result<()> {
+        SandboxHost::denied()
+    }
+}
+
+/// an input that is always at the end
+struct NoInput;
+
+impl HostFile for NoInput {
+    fn read(&mut self, _ : &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+
+    fn write(&mut self, _ : &[u8]) -> io::Result<()> {
+        Err(io::Error::from_raw_os_error(libc::EBADF))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn seek(&mut self, _ : SeekFrom) -> io::Result<u64> {
+        Err(io::Error::from_raw_os_error(libc::ESPIPE))
+    }
+}
+
+#[cfg(unix)]
+fn process_clock() -> f64 {
+    //! the cpu time used by the process, like C's `clock`
+
+    let mut time = libc::timespec { tv_sec : 0, tv_nsec : 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut time) };
+    time.tv_sec as f64 + time.tv_nsec as f64 / 1e9
+}
+
+#[cfg(not(unix))]
+fn process_clock() -> f64 {
+    //! without a cpu clock this is the wall time since it was first asked
+    //! for, which is near enough to the start of the process
+
+    static START : std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    START.get_or_init(std::time::Instant::now).elapsed().as_secs_f64()
+}
+
+#[cfg(unix)]
+fn local_timezone(time : i64) -> Timezone {
+    let mut tm : libc::tm = unsafe { std::mem::zeroed() };
+    let time = time as libc::time_t;
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return Timezone::utc();
+    }
+
+    let name = match tm.tm_zone.is_null() {
+        true => String::new(),
+        false => unsafe { std::ffi::CStr::from_ptr(tm.tm_zone) }.to_string_lossy().into_owned(),
+    };
+
+    Timezone { offset : tm.tm_gmtoff as i64, dst : tm.tm_isdst > 0, name }
+}
+
+#[cfg(not(unix))]
+fn local_timezone(_ : i64) -> Timezone {
+    //! there's no `localtime_r` to ask, so local time is UTC
+
+    Timezone::utc()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::host::{OpenMode, Host, SandboxHost};
+
+    #[test]
+    pub fn open_modes() {
+        let mode = OpenMode::parse("r").unwrap();
+        assert!(mode.read && !mode.write);
+        let mode = OpenMode::parse("a+b").unwrap();
+        assert!(mode.read && mode.write && mode.append && mode.create);
+        let mode = OpenMode::parse("wb").unwrap();
+        assert!(!mode.read && mode.truncate);
+
+        assert_eq!(OpenMode::parse("rw"), None);
+        assert_eq!(OpenMode::parse("x"), None);
+        assert_eq!(OpenMode::parse(""), None);
+    }
+
+    #[test]
+    pub fn sandbox_denies() {
+        assert!(SandboxHost.open("file.txt", OpenMode::parse("r").unwrap()).is_err());
+        assert!(SandboxHost.remove("file.txt").is_err());
+        assert!(SandboxHost.exit(1).is_err());
+        assert_eq!(SandboxHost.getenv("HOME"), None);
+        assert!(SandboxHost.time() > 0);
+    }
+}
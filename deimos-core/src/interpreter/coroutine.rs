@@ -0,0 +1,190 @@
+//! running threads, the coroutines. each thread keeps its own call stack
+//! so errors and tracebacks only show the functions inside of it.
+//!
+//! a thread runs on a clone of the interpreter that made it, which it
+//! keeps while it is suspended. those clones aren't counted as the
+//! program's, so when the program drops the last one of its own the
+//! threads are closed instead of keeping the interpreter alive.
+
+use std::rc::{Rc, Weak};
+use std::cell::Cell;
+
+use failure::Error;
+use corosensei::CoroutineResult;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, Thread, ThreadStatus};
+use crate::error::runtime::RuntimeError;
+
+/// how many threads can resume each other before we give up, the
+/// same limit lua has.
+const MAX_THREAD_DEPTH : usize = 200;
+
+/// how many clones of the interpreter the program has
+pub(crate) struct Handles {
+    count : Cell<usize>,
+}
+
+/// the interpreter's place in the count, the clones the threads run on
+/// aren't counted
+pub(crate) struct Handle {
+    handles : Rc<Handles>,
+    counted : bool,
+}
+
+impl Handle {
+    pub fn new() -> Handle {
+        Handle {
+            handles : Rc::new(Handles { count : Cell::new(1) }),
+            counted : true,
+        }
+    }
+
+    fn is_last(&self) -> bool {
+        self.counted && self.handles.count.get() == 1
+    }
+}
+
+impl Clone for Handle {
+    fn clone(&self) -> Handle {
+        if self.counted {
+            self.handles.count.set(self.handles.count.get() + 1);
+        }
+
+        Handle {
+            handles : self.handles.clone(),
+            counted : self.counted,
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if self.counted {
+            self.handles.count.set(self.handles.count.get() - 1);
+        }
+    }
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        //! once the program is done with the interpreter nothing can
+        //! resume the threads, they're closed so the clones they're
+        //! holding go away with them.
+
+        if self.handle.is_last() {
+            for thread in self.threads_in_heap() {
+                thread.close();
+            }
+        }
+    }
+}
+
+impl Interpreter {
+    pub fn create_thread(&self, function : Value) -> Result<Rc<Thread>,Error> {
+        //! a new suspended thread that will run the function
+
+        let thread = Rc::new(Thread::new(function, self.globals(), Rc::downgrade(&self.handle.handles))?);
+        self.track(&Value::Thread(thread.clone()))?;
+
+        Ok(thread)
+    }
+
+    pub fn running(&self) -> Option<Rc<Thread>> {
+        //! the thread that is running, `None` if it is the main one
+
+        self.threads.borrow().last().cloned()
+    }
+
+    pub fn resume(&self, thread : &Rc<Thread>, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+        //! starts or continues the thread, gives back what it yielded or
+        //! returned. an error inside the thread ends it and comes out here.
+
+        if !Weak::ptr_eq(thread.owner(), &Rc::downgrade(&self.handle.handles)) {
+            return Err(RuntimeError::general("cannot resume coroutine from another interpreter"));
+        }
+
+        match thread.status() {
+            ThreadStatus::Suspended => { },
+            ThreadStatus::Dead => return Err(RuntimeError::general("cannot resume dead coroutine")),
+            _ => return Err(RuntimeError::general("cannot resume non-suspended coroutine")),
+        }
+
+        if self.threads.borrow().len() >= MAX_THREAD_DEPTH {
+            return Err(RuntimeError::general("C stack overflow"));
+        }
+        self.check_cancelled()?;
+
+        if let Some(current) = self.running() {
+            current.set_status(ThreadStatus::Normal);
+        }
+        thread.set_status(ThreadStatus::Running);
+        self.threads.borrow_mut().push(thread.clone());
+
+        // the thread starts with nothing on its stack, and puts its own
+        // back whenever it is resumed after yielding
+        let stack = self.stack.replace(Vec::new());
+        let call_depth = self.call_depth.replace(0);
+        let stack_limit = self.stack_limit.replace(thread.stack_limit());
+
+        let result = self.counting(|| thread.resume(self, args));
+
+        *self.stack.borrow_mut() = stack;
+        self.call_depth.set(call_depth);
+        self.stack_limit.set(stack_limit);
+
+        self.threads.borrow_mut().pop();
+        if let Some(current) = self.running() {
+            current.set_status(ThreadStatus::Running);
+        }
+
+        match result {
+            CoroutineResult::Yield(values) => {
+                thread.set_status(ThreadStatus::Suspended);
+                Ok(values)
+            },
+            CoroutineResult::Return(result) => {
+                thread.set_status(ThreadStatus::Dead);
+                result
+            },
+        }
+    }
+
+    pub fn yield_values(&self, values : Vec<Value>) -> Result<Vec<Value>,Error> {
+        //! pauses the running thread, the values are given to whoever
+        //! resumed it. gives back what the thread is resumed with next.
+
+        let thread = match self.running() {
+            Some(thread) => thread,
+            None => return Err(RuntimeError::general("attempt to yield from outside a coroutine")),
+        };
+
+        // the calls are kept with the thread while it is suspended, so
+        // the collector can see what they are holding on to
+        thread.save_calls(self.stack.replace(Vec::new()));
+        let call_depth = self.call_depth.get();
+        let stack_limit = self.stack_limit.get();
+
+        let args = Thread::suspend(thread, values);
+
+        let thread = self.running().expect("the thread that was resumed");
+        *self.stack.borrow_mut() = thread.take_calls();
+        self.call_depth.set(call_depth);
+        self.stack_limit.set(stack_limit);
+
+        Ok(args)
+    }
+
+    // PRIVATE FUNCTIONS /////////////////////////////////////
+
+    pub(crate) fn thread_clone(&self) -> Interpreter {
+        //! a clone for a thread to run on, it isn't one of the program's
+
+        let mut interpreter = self.clone();
+        interpreter.handle = Handle {
+            handles : self.handle.handles.clone(),
+            counted : false,
+        };
+        interpreter
+    }
+}
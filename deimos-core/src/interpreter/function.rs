@@ -0,0 +1,130 @@
+//! calling lua from rust, like the callbacks a script sets up for the
+//! program that runs it to call later.
+//!
+//! a `Function` is a handle to something lua can call, it keeps the
+//! function alive for as long as rust has it and lets it go when it is
+//! dropped, so nothing needs to be unregistered. an error from the call
+//! is a `RuntimeError` with the lua traceback of where it happened.
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, IntoLua, FromLua, IntoLuaMulti, FromLuaMulti};
+use crate::error::runtime::RuntimeError;
+
+/// a lua function, or anything with a `__call` metamethod, held by rust
+#[derive(Clone)]
+pub struct Function {
+    value : Value,
+}
+
+impl Function {
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    pub fn call<A : IntoLuaMulti, R : FromLuaMulti>(&self, interpreter : &Interpreter, args : A) -> Result<R,Error> {
+        //! calls the function with the arguements turned into lua values,
+        //! and turns what it returns into `R`. use a tuple for more than
+        //! one of either, or `ReturnValues` for however many there are.
+
+        let args = args.into_lua_multi(interpreter)?;
+        let values = interpreter.call(&self.value, args)?;
+        R::from_lua_multi(values, interpreter)
+    }
+}
+
+impl IntoLua for Function {
+    fn into_lua(self, _ : &Interpreter) -> Result<Value,Error> {
+        Ok(self.value)
+    }
+}
+
+impl FromLua for Function {
+    fn from_lua(value : Value, interpreter : &Interpreter) -> Result<Function,Error> {
+        match value.is_function() || !interpreter.metamethod(&value, "__call").is_nil() {
+            true => Ok(Function { value }),
+            false => Err(RuntimeError::general(&format!("cannot convert a {} value to function", value.type_name()))),
+        }
+    }
+}
+
+impl Interpreter {
+    pub fn global<T : FromLua>(&self, name : &str) -> Result<T,Error> {
+        //! the global turned into `T`, like `Function` for a callback
+
+        T::from_lua(self.get_global(name), self)
+            .map_err(|error| conversion_error(&format!("global '{}'", name), error))
+    }
+
+    pub fn field<T : FromLua>(&self, object : &Value, key : &str) -> Result<T,Error> {
+        //! `object.key` turned into `T`, using `__index` like lua would
+
+        let value = self.index(object, &Value::from(key))?;
+        T::from_lua(value, self)
+            .map_err(|error| conversion_error(&format!("field '{}'", key), error))
+    }
+}
+
+// PRIVATE FUNCTIONS /////////////////////////////////////
+
+fn conversion_error(what : &str, error : Error) -> Error {
+    //! says which value couldn't be converted, like the standard library
+    //! says which arguement
+
+    match error.downcast::<RuntimeError>() {
+        Ok(error) => RuntimeError::general(&format!("bad {} ({})", what, error.message())),
+        Err(error) => error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::interpreter::{Interpreter, Function};
+    use crate::value::Value;
+    use crate::error::runtime::RuntimeError;
+
+    #[test]
+    pub fn calling_lua() {
+        let interpreter = Interpreter::new();
+
+        let code = r#"
+            local total = 0
+            function on_update(dt)
+                total = total + dt
+                return total, "updated"
+            end
+            player = { hurt = function(self, n) if n > 10 then error("too much damage") end end }
+            cache = setmetatable({ on_update }, { __mode = "v" })
+        "#;
+        interpreter.run(code, Some("testfile.lua")).unwrap();
+
+        let on_update : Function = interpreter.global("on_update").unwrap();
+        assert_eq!(on_update.call::<_, f64>(&interpreter, 0.5).unwrap(), 0.5);
+        let (total, status) : (f64, String) = on_update.call(&interpreter, (1.0,)).unwrap();
+        assert_eq!((total, status.as_str()), (1.5, "updated"));
+
+        // the error has the lua traceback, and says where it happened
+        let player : Value = interpreter.global("player").unwrap();
+        let hurt : Function = interpreter.field(&player, "hurt").unwrap();
+        let error = hurt.call::<_, ()>(&interpreter, (player.clone(), 20)).unwrap_err();
+        let error = error.downcast_ref::<RuntimeError>().unwrap();
+        assert_eq!(error.message(), "testfile.lua:7: too much damage");
+        assert!(error.traceback().unwrap().contains("testfile.lua:7"));
+
+        let missing = interpreter.global::<Function>("on_draw").err().unwrap();
+        assert_eq!(missing.downcast_ref::<RuntimeError>().unwrap().message(), "bad global 'on_draw' (cannot convert a nil value to function)");
+
+        // the handle keeps the function alive even when lua lets go of it,
+        // and lets go of it when it is dropped
+        interpreter.set_global("on_update", Value::Nil);
+        interpreter.collect_garbage().unwrap();
+        assert_eq!(on_update.call::<_, f64>(&interpreter, 1.0).unwrap(), 2.5);
+        assert_eq!(interpreter.run("return cache[1] ~= nil", None).unwrap().into_values(), vec![Value::Boolean(true)]);
+
+        drop(on_update);
+        interpreter.collect_garbage().unwrap();
+        assert_eq!(interpreter.run("return cache[1] == nil", None).unwrap().into_values(), vec![Value::Boolean(true)]);
+    }
+}
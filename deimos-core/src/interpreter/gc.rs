@@ -0,0 +1,648 @@
+//! the garbage collector, what lua's `lgc.c` is. values are shared with
+//! `Rc` so most of them go away as soon as nothing is using them, but
+//! tables and functions that point at each other never get there. the
+//! collector finds those and breaks them apart.
+//!
+//! it can't see what rust is holding on to, like the registers of the
+//! functions that are running, so it works the other way around. it counts
+//! how many references each object gets from the other objects it knows
+//! about, and anything that has more than that is being used from
+//! somewhere else. those are the roots, and everything they can't get to
+//! is garbage. rust functions and userdata show what they hold with
+//! `trace`, and a suspended coroutine shows the functions, registers and
+//! open upvalues of the calls on its stack. what a rust function on its
+//! stack is holding can't be seen, so those stay roots until it finishes.
+//!
+//! the collection is always done all at once, a `step` only counts the
+//! work it was given and collects once that would have been a whole cycle.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::bytecode::Proto;
+use crate::value::{self, Allocations, Value, Table, LuaFunction, Upvalue, NativeClosure, AnyUserData, Thread, ThreadStatus};
+
+/// how much the memory in use grows before the next collection, in
+/// percent of what was in use after the last one
+const DEFAULT_PAUSE : usize = 200;
+/// how much faster than the program makes things the collector works
+const DEFAULT_STEP_MULTIPLIER : usize = 200;
+/// how much work is a step, lua's `GCSTEPSIZE`
+const STEP_SIZE : usize = 1024;
+
+pub(crate) struct Heap {
+    // every object that was made since the last collection, and all the
+    // ones that were still alive after it
+    objects : Vec<WeakObject>,
+    // userdata with a metatable that haven't been finalized, they're held
+    // here so they can't go away before their `__gc` is called
+    finalizable : Vec<Rc<AnyUserData>>,
+    // the collected userdata that are waiting for their `__gc`, the next
+    // one is at the end
+    finalizing : Vec<Rc<AnyUserData>>,
+    // the tables that grew and the strings that were made while this
+    // interpreter was running
+    allocations : Rc<Allocations>,
+    // the bytes in use after the last collection and made since then
+    live : usize,
+    allocated : usize,
+    threshold : usize,
+    // the work the steps have done towards the next collection
+    progress : usize,
+    pause : usize,
+    step_multiplier : usize,
+    running : bool,
+    collecting : bool,
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap {
+            objects : Vec::new(),
+            finalizable : Vec::new(),
+            finalizing : Vec::new(),
+            allocations : Rc::new(Allocations::default()),
+            live : 0,
+            allocated : 0,
+            threshold : 0,
+            progress : 0,
+            pause : DEFAULT_PAUSE,
+            step_multiplier : DEFAULT_STEP_MULTIPLIER,
+            running : true,
+            collecting : false,
+        }
+    }
+
+    fn in_use(&self) -> usize {
+        self.live + self.allocated
+    }
+}
+
+impl Interpreter {
+    pub fn collect_garbage(&self) -> Result<(),Error> {
+        //! a full collection, and then the `__gc` of the userdata that
+        //! were collected. the finalizer is only ever called once.
+
+        self.collect();
+        self.finalize()
+    }
+
+    pub(crate) fn track(&self, value : &Value) -> Result<(),Error> {
+        //! remembers a new table, function or thread so the collector can
+        //! find it, and collects if enough has been made since last time
+
+        let object = match Object::from_value(value) {
+            Some(object) => object,
+            None => return Ok(()),
+        };
+
+        {
+            let mut heap = self.heap.borrow_mut();
+            heap.allocated += object.size();
+            heap.objects.extend(object.downgrade());
+        }
+
+        self.check_garbage()
+    }
+
+    pub(crate) fn check_garbage(&self) -> Result<(),Error> {
+        //! collects if enough has been made since last time, for the
+        //! strings and the tables that grew, which aren't tracked
+
+        let collect = {
+            let mut heap = self.heap.borrow_mut();
+            heap.allocated += heap.allocations.take_bytes();
+            heap.running && !heap.collecting && heap.in_use() >= heap.threshold
+        };
+
+        if collect {
+            self.collect_garbage()?;
+        }
+        self.check_memory()
+    }
+
+    pub(crate) fn watch_finalizer(&self, data : &Rc<AnyUserData>) {
+        //! remembers the userdata so its `__gc` can be called once
+        //! nothing else is using it
+
+        let mut heap = self.heap.borrow_mut();
+        if !data.is_finalized() && !heap.finalizable.iter().any(|watched| Rc::ptr_eq(watched, data)) {
+            heap.finalizable.push(data.clone());
+        }
+    }
+
+    pub(crate) fn is_collecting(&self) -> bool {
+        self.heap.borrow().collecting
+    }
+
+    pub(crate) fn memory_in_use(&self) -> usize {
+        //! about how many bytes the lua values are using, in the same
+        //! sizes lua's would have
+
+        let mut heap = self.heap.borrow_mut();
+        heap.allocated += heap.allocations.take_bytes();
+        heap.in_use()
+    }
+
+    pub(crate) fn threads_in_heap(&self) -> Vec<Rc<Thread>> {
+        //! every thread the collector knows about
+
+        self.heap.borrow().objects.iter().filter_map(|object| match object {
+            WeakObject::Thread(thread) => thread.upgrade(),
+            _ => None,
+        }).collect()
+    }
+
+    pub(crate) fn counting<T, F : FnOnce() -> T>(&self, function : F) -> T {
+        //! runs the function with the tables and strings it makes counted
+        //! to this interpreter
+
+        let allocations = Rc::downgrade(&self.heap.borrow().allocations);
+        let before = value::count_to(allocations);
+        let result = function();
+        value::count_to(before);
+        result
+    }
+
+    pub(crate) fn step_garbage(&self, size : usize) -> Result<bool,Error> {
+        //! does `size` kilobytes worth of collecting, gives back if that
+        //! finished a cycle
+
+        let finished = {
+            let mut heap = self.heap.borrow_mut();
+            heap.allocated += heap.allocations.take_bytes();
+            heap.progress += (size + 1) * STEP_SIZE / 100 * heap.step_multiplier;
+            heap.progress >= heap.in_use()
+        };
+
+        if finished {
+            self.collect_garbage()?;
+        }
+
+        Ok(finished)
+    }
+
+    pub(crate) fn set_collecting(&self, running : bool) {
+        //! stops or restarts the automatic collections
+
+        let mut heap = self.heap.borrow_mut();
+        heap.running = running;
+        heap.threshold = heap.in_use();
+    }
+
+    pub(crate) fn set_collector_pause(&self, pause : usize) -> usize {
+        std::mem::replace(&mut self.heap.borrow_mut().pause, pause)
+    }
+
+    pub(crate) fn set_collector_step_multiplier(&self, step_multiplier : usize) -> usize {
+        std::mem::replace(&mut self.heap.borrow_mut().step_multiplier, step_multiplier)
+    }
+
+    // PRIVATE FUNCTIONS /////////////////////////////////////
+
+    fn collect(&self) {
+        let (objects, finalizable) = {
+            let mut heap = self.heap.borrow_mut();
+            if heap.collecting { return; }
+            heap.collecting = true;
+            (std::mem::take(&mut heap.objects), std::mem::take(&mut heap.finalizable))
+        };
+
+        let mut graph = Graph::default();
+        for object in objects.iter().filter_map(WeakObject::upgrade) {
+            graph.add(object);
+        }
+        for data in finalizable.iter() {
+            graph.add(Object::UserData(data.clone()));
+        }
+        graph.gather();
+        graph.count_references(&finalizable);
+        graph.mark_roots();
+
+        // the userdata nothing can get to are finalized, the ones with a
+        // `__gc` are kept alive until it has been called
+        let mut kept = Vec::new();
+        let mut finalizing = Vec::new();
+        for data in finalizable {
+            if graph.is_marked(&Object::UserData(data.clone())) {
+                kept.push(data);
+                continue;
+            }
+
+            data.set_finalized();
+            if !self.metamethod(&Value::UserData(data.clone()), "__gc").is_nil() {
+                finalizing.push(data);
+            }
+        }
+        for data in finalizing.iter() {
+            graph.mark_from(&Object::UserData(data.clone()));
+        }
+
+        graph.clear_weak_tables();
+        let live = graph.live_size();
+        let survivors = graph.survivors();
+        graph.break_garbage();
+
+        let mut heap = self.heap.borrow_mut();
+        heap.objects.splice(0 .. 0, survivors);
+        heap.finalizable.splice(0 .. 0, kept);
+        finalizing.append(&mut heap.finalizing);
+        heap.finalizing = finalizing;
+        // the strings are counted once the garbage is gone, some of them
+        // went with it
+        heap.live = live + heap.allocations.strings_in_use();
+        heap.allocated = 0;
+        heap.allocations.take_bytes();
+        heap.progress = 0;
+        heap.threshold = heap.live * heap.pause / 100;
+        heap.collecting = false;
+    }
+
+    fn finalize(&self) -> Result<(),Error> {
+        //! calls the `__gc` of the collected userdata, the last one made
+        //! first. if one has an error the rest are left for next time.
+
+        loop {
+            let data = match self.heap.borrow_mut().finalizing.pop() {
+                Some(data) => data,
+                None => return Ok(()),
+            };
+
+            let value = Value::UserData(data);
+            match self.metamethod(&value, "__gc") {
+                Value::Nil => { },
+                function => { self.call(&function, vec![value])?; },
+            }
+        }
+    }
+}
+
+/// something the collector keeps track of, everything that can be part of
+/// a cycle
+#[derive(Clone)]
+enum Object {
+    Table(Rc<RefCell<Table>>),
+    Function(Rc<LuaFunction>),
+    Upvalue(Upvalue),
+    Closure(Rc<dyn NativeClosure>),
+    UserData(Rc<AnyUserData>),
+    Thread(Rc<Thread>),
+}
+
+enum WeakObject {
+    Table(Weak<RefCell<Table>>),
+    Function(Weak<LuaFunction>),
+    UserData(Weak<AnyUserData>),
+    Thread(Weak<Thread>),
+}
+
+impl Object {
+    fn from_value(value : &Value) -> Option<Object> {
+        match value {
+            Value::Table(table) => Some(Object::Table(table.clone())),
+            Value::Function(function) => Some(Object::Function(function.clone())),
+            Value::NativeClosure(function) => Some(Object::Closure(function.clone())),
+            Value::UserData(data) => Some(Object::UserData(data.clone())),
+            Value::Thread(thread) => Some(Object::Thread(thread.clone())),
+            _ => None,
+        }
+    }
+
+    fn downgrade(&self) -> Option<WeakObject> {
+        //! upvalues and rust functions are only ever found through other
+        //! objects, so they're never remembered on their own
+
+        match self {
+            Object::Table(table) => Some(WeakObject::Table(Rc::downgrade(table))),
+            Object::Function(function) => Some(WeakObject::Function(Rc::downgrade(function))),
+            Object::UserData(data) => Some(WeakObject::UserData(Rc::downgrade(data))),
+            Object::Thread(thread) => Some(WeakObject::Thread(Rc::downgrade(thread))),
+            Object::Upvalue(_) | Object::Closure(_) => None,
+        }
+    }
+
+    fn address(&self) -> usize {
+        match self {
+            Object::Table(table) => Rc::as_ptr(table) as *const () as usize,
+            Object::Function(function) => Rc::as_ptr(function) as *const () as usize,
+            Object::Upvalue(upvalue) => Rc::as_ptr(upvalue) as *const () as usize,
+            Object::Closure(function) => Rc::as_ptr(function) as *const () as usize,
+            Object::UserData(data) => Rc::as_ptr(data) as *const () as usize,
+            Object::Thread(thread) => Rc::as_ptr(thread) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Table(table) => Rc::strong_count(table),
+            Object::Function(function) => Rc::strong_count(function),
+            Object::Upvalue(upvalue) => Rc::strong_count(upvalue),
+            Object::Closure(function) => Rc::strong_count(function),
+            Object::UserData(data) => Rc::strong_count(data),
+            Object::Thread(thread) => Rc::strong_count(thread),
+        }
+    }
+
+    fn size(&self) -> usize {
+        //! about what lua's would be, the code of functions is counted
+        //! on its own because they share it
+
+        match self {
+            Object::Table(table) => table.try_borrow().map(|table| table.size()).unwrap_or(56),
+            Object::Function(function) => 40 + 8 * function.upvalues().len(),
+            Object::Upvalue(_) => 40,
+            Object::Closure(_) => 40,
+            Object::UserData(_) => 64,
+            Object::Thread(_) => 1024,
+        }
+    }
+
+    fn references(&self, strong_only : bool) -> Option<Vec<Object>> {
+        //! the objects this one holds on to, `None` if it can't be looked
+        //! at right now because it is being changed. rust functions and
+        //! userdata give what they hold with their `trace`, and suspended
+        //! threads give what the calls on their stack hold.
+
+        let mut references = Vec::new();
+
+        match self {
+            Object::Table(table) => {
+                let table = table.try_borrow().ok()?;
+                let (weak_keys, weak_values) = match strong_only {
+                    true => weakness(&table),
+                    false => (false, false),
+                };
+                table.trace(!weak_keys, !weak_values, &mut |value| references.extend(Object::from_value(value)));
+                references.extend(table.metatable().map(Object::Table));
+            },
+            Object::Function(function) => {
+                references.extend(function.upvalues().iter().cloned().map(Object::Upvalue));
+                references.push(Object::Table(function.environment()));
+            },
+            Object::Upvalue(upvalue) => references.extend(Object::from_value(&*upvalue.try_borrow().ok()?)),
+            Object::Closure(function) => function.trace(&mut |value| references.extend(Object::from_value(value))),
+            Object::UserData(data) => {
+                data.trace(&mut |value| references.extend(Object::from_value(value)))?;
+                references.extend(data.metatable().map(Object::Table));
+            },
+            Object::Thread(thread) => {
+                references.push(Object::Table(thread.environment()));
+
+                let function = thread.function();
+                references.extend(function.as_ref().and_then(Object::from_value));
+
+                // once it has started its stack holds the function too.
+                // the stacks of the ones that are running keep changing,
+                // so only the suspended ones are looked at
+                if thread.status() == ThreadStatus::Suspended && thread.is_started() {
+                    references.extend(function.as_ref().and_then(Object::from_value));
+
+                    let mut upvalues = Vec::new();
+                    for call in thread.calls()?.iter() {
+                        call.trace(&mut |value| references.extend(Object::from_value(value)), &mut |upvalue| upvalues.push(Object::Upvalue(upvalue.clone())))?;
+                    }
+                    references.append(&mut upvalues);
+                }
+            },
+        }
+
+        Some(references)
+    }
+}
+
+impl WeakObject {
+    fn upgrade(&self) -> Option<Object> {
+        match self {
+            WeakObject::Table(table) => table.upgrade().map(Object::Table),
+            WeakObject::Function(function) => function.upgrade().map(Object::Function),
+            WeakObject::UserData(data) => data.upgrade().map(Object::UserData),
+            WeakObject::Thread(thread) => thread.upgrade().map(Object::Thread),
+        }
+    }
+}
+
+/// everything the collector could find, and what it knows about each one
+#[derive(Default)]
+struct Graph {
+    objects : Vec<Object>,
+    known : HashMap<usize, usize>,
+    // the references each one has from outside of the graph
+    references : Vec<isize>,
+    marked : Vec<bool>,
+}
+
+impl Graph {
+    fn add(&mut self, object : Object) {
+        let address = object.address();
+        if !self.known.contains_key(&address) {
+            self.known.insert(address, self.objects.len());
+            self.objects.push(object);
+        }
+    }
+
+    fn index(&self, object : &Object) -> Option<usize> {
+        self.known.get(&object.address()).cloned()
+    }
+
+    fn is_marked(&self, object : &Object) -> bool {
+        self.index(object).map(|i| self.marked[i]).unwrap_or(true)
+    }
+
+    fn gather(&mut self) {
+        //! finds everything the objects can get to
+
+        let mut i = 0;
+        while i < self.objects.len() {
+            if let Some(references) = self.objects[i].references(false) {
+                references.into_iter().for_each(|object| self.add(object));
+            }
+            i += 1;
+        }
+    }
+
+    fn count_references(&mut self, finalizable : &[Rc<AnyUserData>]) {
+        //! takes the references the objects have to each other out of
+        //! their counts, what is left is held from outside. the graph has
+        //! one of its own too.
+
+        self.references = self.objects.iter().map(|object| object.strong_count() as isize - 1).collect();
+        self.marked = vec![false; self.objects.len()];
+
+        for data in finalizable {
+            if let Some(i) = self.index(&Object::UserData(data.clone())) {
+                self.references[i] -= 1;
+            }
+        }
+
+        for i in 0 .. self.objects.len() {
+            match self.objects[i].references(false) {
+                Some(references) => for object in references {
+                    if let Some(j) = self.index(&object) {
+                        self.references[j] -= 1;
+                    }
+                },
+                // what it holds on to wasn't gathered either, so it is
+                // kept as it is
+                None => self.references[i] = isize::MAX,
+            }
+        }
+    }
+
+    fn mark_roots(&mut self) {
+        let roots : Vec<usize> = (0 .. self.objects.len()).filter(|&i| self.references[i] > 0).collect();
+        self.mark(roots);
+    }
+
+    fn mark_from(&mut self, object : &Object) {
+        if let Some(i) = self.index(object) {
+            self.mark(vec![i]);
+        }
+    }
+
+    fn mark(&mut self, mut stack : Vec<usize>) {
+        //! everything that can be gotten to from these, without going
+        //! through the weak parts of tables
+
+        while let Some(i) = stack.pop() {
+            if self.marked[i] { continue; }
+            self.marked[i] = true;
+
+            for object in self.objects[i].references(true).unwrap_or_default() {
+                if let Some(j) = self.index(&object) {
+                    if !self.marked[j] { stack.push(j); }
+                }
+            }
+        }
+    }
+
+    fn is_dead(&self, value : &Value) -> bool {
+        match Object::from_value(value) {
+            Some(object) => !self.is_marked(&object),
+            None => false,
+        }
+    }
+
+    fn clear_weak_tables(&self) {
+        //! takes the collected keys and values out of the weak tables.
+        //! values that are finalized userdata are taken out too, even if
+        //! the finalizer brought them back, but keys stay until the
+        //! userdata is really gone.
+
+        for (i, object) in self.objects.iter().enumerate() {
+            let table = match object {
+                Object::Table(table) if self.marked[i] => table,
+                _ => continue,
+            };
+
+            let (weak_keys, weak_values) = match table.try_borrow() {
+                Ok(table) => weakness(&table),
+                Err(_) => continue,
+            };
+            if !weak_keys && !weak_values { continue; }
+
+            if let Ok(mut table) = table.try_borrow_mut() {
+                table.retain(&|key, value| {
+                    !(weak_keys && self.is_dead(key))
+                        && !(weak_values && (self.is_dead(value) || is_finalized(value)))
+                });
+            }
+        }
+    }
+
+    fn live_size(&self) -> usize {
+        //! the size of everything that is left, the code of the functions
+        //! is only counted once however many closures share it
+
+        let mut protos = HashSet::new();
+        let mut size = 0;
+
+        for (i, object) in self.objects.iter().enumerate() {
+            if !self.marked[i] { continue; }
+            size += object.size();
+            if let Object::Function(function) = object {
+                size += proto_size(function.proto(), &mut protos);
+            }
+        }
+
+        size
+    }
+
+    fn survivors(&self) -> Vec<WeakObject> {
+        self.objects.iter().enumerate()
+            .filter(|&(i, _)| self.marked[i])
+            .filter_map(|(_, object)| object.downgrade())
+            .collect()
+    }
+
+    fn break_garbage(self) {
+        //! empties the garbage so the cycles come apart, it all goes away
+        //! once the graph lets go of it
+
+        let mut trash = Vec::new();
+
+        for (i, object) in self.objects.iter().enumerate() {
+            if self.marked[i] { continue; }
+
+            match object {
+                Object::Table(table) => if let Ok(mut table) = table.try_borrow_mut() {
+                    trash.extend(table.take_all());
+                },
+                Object::Upvalue(upvalue) => if let Ok(mut upvalue) = upvalue.try_borrow_mut() {
+                    trash.push(std::mem::replace(&mut *upvalue, Value::Nil));
+                },
+                Object::UserData(data) => {
+                    trash.extend(data.metatable().map(Value::Table));
+                    data.set_metatable(None);
+                },
+                Object::Thread(thread) => trash.extend(thread.close()),
+                Object::Function(_) | Object::Closure(_) => { },
+            }
+        }
+
+        drop(trash);
+    }
+}
+
+fn weakness(table : &Table) -> (bool, bool) {
+    //! if the keys and values of the table are weak, from its `__mode`
+
+    let metatable = match table.metatable() {
+        Some(metatable) => metatable,
+        None => return (false, false),
+    };
+
+    let mode = match metatable.try_borrow() {
+        Ok(metatable) => metatable.get_str("__mode"),
+        Err(_) => return (false, false),
+    };
+
+    match mode {
+        Value::String(mode) => (mode.as_bytes().contains(&b'k'), mode.as_bytes().contains(&b'v')),
+        _ => (false, false),
+    }
+}
+
+fn is_finalized(value : &Value) -> bool {
+    match value {
+        Value::UserData(data) => data.is_finalized(),
+        _ => false,
+    }
+}
+
+fn proto_size(proto : &Rc<Proto>, counted : &mut HashSet<usize>) -> usize {
+    if !counted.insert(Rc::as_ptr(proto) as usize) {
+        return 0;
+    }
+
+    let own = 120
+        + 4 * proto.code.len()
+        + 16 * proto.constants.len()
+        + 4 * proto.lines.len()
+        + 24 * proto.locals.len();
+
+    own + proto.protos.iter().map(|child| proto_size(child, counted)).sum::<usize>()
+}
@@ -0,0 +1,309 @@
+//! limits on what a script is allowed to do, for running code that can't
+//! be trusted, like mods. a script can be given a number of instructions
+//! to run, an amount of memory to use and how deep it can call, and it
+//! can be cancelled from outside, from another thread even.
+//!
+//! going over a limit is a `LimitError`. the script can't catch it with
+//! `pcall`, so it stops and the error comes back out to rust. the limits
+//! are only looked at every so often, so a script can go a little over
+//! before it gets stopped.
+
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::Value;
+use crate::error::runtime::LimitError;
+
+/// how many instructions are run between looking at the limits
+const CHECK_INTERVAL : u64 = 1000;
+
+pub(crate) struct Limits {
+    instructions : Cell<Option<u64>>,
+    // the instructions run since the limit was set, up to the last check
+    executed : Cell<u64>,
+    // the instructions left until the next check, and how many there
+    // were when it started
+    countdown : Cell<u64>,
+    interval : Cell<u64>,
+    memory : Cell<Option<usize>>,
+    call_depth : Cell<Option<usize>>,
+    cancelled : Arc<AtomicBool>,
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Limits {
+            instructions : Cell::new(None),
+            executed : Cell::new(0),
+            countdown : Cell::new(CHECK_INTERVAL),
+            interval : Cell::new(CHECK_INTERVAL),
+            memory : Cell::new(None),
+            call_depth : Cell::new(None),
+            cancelled : Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn tick(&self) -> bool {
+        //! counts an instruction, true when it is time to check the limits
+
+        let countdown = self.countdown.get() - 1;
+        self.countdown.set(countdown);
+        countdown == 0
+    }
+
+    fn restart_countdown(&self) {
+        //! the next check is when the instruction limit would be reached,
+        //! if that is sooner than the usual interval
+
+        let interval = match self.instructions.get() {
+            Some(limit) => limit.saturating_sub(self.executed.get()).clamp(1, CHECK_INTERVAL),
+            None => CHECK_INTERVAL,
+        };
+        self.countdown.set(interval);
+        self.interval.set(interval);
+    }
+
+    fn counted(&self) -> u64 {
+        //! the instructions run since the last check
+
+        self.interval.get() - self.countdown.get()
+    }
+}
+
+impl Interpreter {
+    pub fn set_instruction_limit(&self, limit : Option<u64>) {
+        //! how many more instructions can be run, `None` for no limit.
+        //! the count starts over every time this is set, so it can be
+        //! set before each call to give each one the same budget.
+
+        self.limits.instructions.set(limit);
+        self.limits.executed.set(0);
+        self.limits.restart_countdown();
+    }
+
+    pub fn instructions_executed(&self) -> u64 {
+        //! how many instructions have been run since the limit was set
+
+        self.limits.executed.get() + self.limits.counted()
+    }
+
+    pub fn set_memory_limit(&self, limit : Option<usize>) {
+        //! how many bytes the lua values can use, as counted by
+        //! `collectgarbage("count")`. the garbage is collected before
+        //! it is decided that the script went over.
+
+        self.limits.memory.set(limit);
+    }
+
+    pub fn set_call_depth_limit(&self, limit : Option<usize>) {
+        //! how many lua functions can be called inside of each other,
+        //! it can only be lower than the stack overflow lua already has
+
+        self.limits.call_depth.set(limit);
+    }
+
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        //! setting the flag stops whatever is running, it can be set from
+        //! any thread. it has to be set back to `false` to run anything
+        //! again.
+
+        self.limits.cancelled.clone()
+    }
+
+    pub(crate) fn check_limits(&self) -> Result<(),Error> {
+        //! done every so often while running, which is also when the
+        //! collector looks at what was made without being tracked
+
+        let limits = &self.limits;
+        limits.executed.set(limits.executed.get() + limits.counted());
+        limits.restart_countdown();
+
+        self.check_cancelled()?;
+
+        if let Some(limit) = limits.instructions.get() {
+            if limits.executed.get() >= limit {
+                return Err(LimitError::Instructions(limit).into());
+            }
+        }
+
+        self.check_garbage()
+    }
+
+    pub(crate) fn check_cancelled(&self) -> Result<(),Error> {
+        //! done before anything starts running too, so a cancelled
+        //! interpreter doesn't run a single instruction
+
+        match self.limits.cancelled.load(Ordering::Relaxed) {
+            true => Err(LimitError::Cancelled.into()),
+            false => Ok(()),
+        }
+    }
+
+    pub(crate) fn count_step(&self) -> Result<(),Error> {
+        //! counts a step of a library function's work like an instruction,
+        //! so the limits and the cancel flag can stop long loops in rust
+        //! that don't run any lua, like a pattern that backtracks a lot
+
+        match self.limits.tick() {
+            true => self.check_limits(),
+            false => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_memory(&self) -> Result<(),Error> {
+        self.check_allocation(0)
+    }
+
+    pub(crate) fn check_allocation(&self, bytes : usize) -> Result<(),Error> {
+        //! if there is room for `bytes` more, for checking before something
+        //! big is made all at once
+
+        let limit = match self.limits.memory.get() {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        if self.memory_in_use().saturating_add(bytes) <= limit || self.is_collecting() {
+            return Ok(());
+        }
+
+        self.collect_garbage()?;
+        match self.memory_in_use().saturating_add(bytes) <= limit {
+            true => Ok(()),
+            false => Err(LimitError::Memory(limit).into()),
+        }
+    }
+
+    pub(crate) fn check_call_depth(&self, depth : usize) -> Result<(),Error> {
+        match self.limits.call_depth.get() {
+            Some(limit) if depth >= limit => Err(LimitError::CallDepth(limit).into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn catch(&self, error : Error) -> Result<Value,Error> {
+        //! the value of an error `pcall` caught, going over a limit can't
+        //! be caught so it keeps going
+
+        match error.downcast_ref::<LimitError>() {
+            Some(_) => Err(error),
+            None => Ok(self.error_value(&error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::atomic::Ordering;
+
+    use crate::interpreter::Interpreter;
+    use crate::error::runtime::LimitError;
+
+    fn limit_error(interpreter : &Interpreter, code : &str) -> LimitError {
+        match interpreter.run(code, None) {
+            Ok(_) => panic!("ran without reaching the limit"),
+            Err(error) => error.downcast::<LimitError>().unwrap(),
+        }
+    }
+
+    #[test]
+    pub fn execution_limits() {
+        let interpreter = Interpreter::new();
+
+        interpreter.set_instruction_limit(Some(10_000));
+        match limit_error(&interpreter, "while true do end") {
+            LimitError::Instructions(10_000) => { },
+            error => panic!("{:?}", error),
+        }
+        assert!(interpreter.instructions_executed() >= 10_000);
+
+        // pcall can't catch it, even with a new budget
+        interpreter.set_instruction_limit(Some(10_000));
+        match limit_error(&interpreter, "pcall(function() while true do end end) return 'caught'") {
+            LimitError::Instructions(_) => { },
+            error => panic!("{:?}", error),
+        }
+
+        interpreter.set_instruction_limit(Some(10_000));
+        assert!(interpreter.run("local x = 0 for i = 1, 100 do x = x + i end", None).is_ok());
+
+        // or `load` while it is reading the code
+        interpreter.set_instruction_limit(Some(10_000));
+        match limit_error(&interpreter, "return load(function() while true do end end)") {
+            LimitError::Instructions(_) => { },
+            error => panic!("{:?}", error),
+        }
+
+        // the work the libraries do counts too
+        interpreter.set_instruction_limit(Some(10_000));
+        match limit_error(&interpreter, "return string.rep('a', 3000):find('.-.-.-b')") {
+            LimitError::Instructions(10_000) => { },
+            error => panic!("{:?}", error),
+        }
+        interpreter.set_instruction_limit(Some(10_000));
+        assert!(interpreter.run("return string.rep('', 1e15)", None).is_ok());
+        interpreter.set_instruction_limit(None);
+
+        interpreter.cancel_flag().store(true, Ordering::Relaxed);
+        match limit_error(&interpreter, "return string.rep('a', 3000):find('.-.-.-b')") {
+            LimitError::Cancelled => { },
+            error => panic!("{:?}", error),
+        }
+        interpreter.cancel_flag().store(false, Ordering::Relaxed);
+
+        interpreter.set_memory_limit(Some(1024 * 1024));
+        match limit_error(&interpreter, "local t = {} for i = 1, 1e7 do t[i] = i end") {
+            LimitError::Memory(_) => { },
+            error => panic!("{:?}", error),
+        }
+        match limit_error(&interpreter, "local s = 'x' while true do s = s .. s end") {
+            LimitError::Memory(_) => { },
+            error => panic!("{:?}", error),
+        }
+        match limit_error(&interpreter, "return string.rep('x', 1e12)") {
+            LimitError::Memory(_) => { },
+            error => panic!("{:?}", error),
+        }
+        // garbage doesn't count
+        assert!(interpreter.run("for i = 1, 1e5 do local t = { i, i, i } end", None).is_ok());
+
+        // and neither does what another interpreter is using
+        let other = Interpreter::new();
+        let before = interpreter.memory_in_use();
+        other.run("big = string.rep('x', 4 * 1024 * 1024) t = {} for i = 1, 1e5 do t[i] = i end", None).unwrap();
+        assert_eq!(interpreter.memory_in_use(), before);
+        assert!(interpreter.run("return 1", None).is_ok());
+        assert!(other.memory_in_use() > 4 * 1024 * 1024);
+        interpreter.set_memory_limit(None);
+
+        interpreter.set_call_depth_limit(Some(100));
+        match limit_error(&interpreter, "local function f(n) return 1 + f(n + 1) end f(1)") {
+            LimitError::CallDepth(100) => { },
+            error => panic!("{:?}", error),
+        }
+        interpreter.set_call_depth_limit(None);
+
+        interpreter.cancel_flag().store(true, Ordering::Relaxed);
+        match limit_error(&interpreter, "local co = coroutine.wrap(function() while true do end end) co()") {
+            LimitError::Cancelled => { },
+            error => panic!("{:?}", error),
+        }
+
+        // nothing new is started once it is cancelled, however short
+        match limit_error(&interpreter, "return 1") {
+            LimitError::Cancelled => { },
+            error => panic!("{:?}", error),
+        }
+        let function = interpreter.load(b"return 1", "=short").unwrap();
+        assert!(interpreter.call(&function, Vec::new()).unwrap_err().downcast::<LimitError>().is_ok());
+        interpreter.cancel_flag().store(false, Ordering::Relaxed);
+
+        // the interpreter can still be used after
+        assert!(interpreter.run("return 1", None).is_ok());
+    }
+}
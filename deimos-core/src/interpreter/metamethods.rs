@@ -0,0 +1,360 @@
+//! the operations that can be changed with metatables, per the manual
+//! https://www.lua.org/manual/5.1/manual.html#2.8
+//!
+//! each operation first tries to do the normal thing and only looks for
+//! a metamethod when it can't. the `_with` versions take a function that
+//! makes the error when a value is the wrong type, so the evaluator can
+//! say where the value came from (`attempt to index global 'x'`).
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, Table};
+use crate::error::runtime::RuntimeError;
+
+/// how many `__index` or `__newindex` tables we will go through before
+/// deciding that there is a loop.
+const MAX_META_LOOP : usize = 100;
+
+/// the arithmetic operations, and the metamethods that go with them.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Arithmetic {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Unm,
+}
+
+impl Arithmetic {
+    pub fn event(self) -> &'static str {
+        match self {
+            Arithmetic::Add => "__add",
+            Arithmetic::Sub => "__sub",
+            Arithmetic::Mul => "__mul",
+            Arithmetic::Div => "__div",
+            Arithmetic::Mod => "__mod",
+            Arithmetic::Pow => "__pow",
+            Arithmetic::Unm => "__unm",
+        }
+    }
+
+    pub fn apply(self, a : f64, b : f64) -> f64 {
+        match self {
+            Arithmetic::Add => a + b,
+            Arithmetic::Sub => a - b,
+            Arithmetic::Mul => a * b,
+            Arithmetic::Div => a / b,
+            Arithmetic::Mod => a - (a / b).floor() * b,
+            Arithmetic::Pow => a.powf(b),
+            Arithmetic::Unm => -a,
+        }
+    }
+}
+
+impl Interpreter {
+    pub fn get_metatable(&self, value : &Value) -> Option<Rc<RefCell<Table>>> {
+        //! the metatable of the value, without looking at `__metatable`
+
+        match value {
+            Value::Table(table) => table.borrow().metatable(),
+            Value::UserData(data) => data.metatable(),
+            value => self.type_metatables.borrow().get(value.type_name()).cloned(),
+        }
+    }
+
+    pub fn set_type_metatable(&self, type_name : &'static str, metatable : Option<Rc<RefCell<Table>>>) {
+        //! sets the metatable that all values of the type share, tables
+        //! and userdata have their own so this doesn't change them
+
+        let mut metatables = self.type_metatables.borrow_mut();
+        match metatable {
+            Some(metatable) => metatables.insert(type_name, metatable),
+            None => metatables.remove(type_name),
+        };
+    }
+
+    pub fn set_metatable(&self, value : &Value, metatable : Option<Rc<RefCell<Table>>>) -> Result<(),Error> {
+        //! sets the metatable of a table or userdata
+
+        match value {
+            Value::Table(table) => table.borrow_mut().set_metatable(metatable),
+            Value::UserData(data) => {
+                data.set_metatable(metatable);
+                self.watch_finalizer(data);
+            },
+            value => return Err(RuntimeError::general(&format!("cannot set the metatable of a {} value", value.type_name()))),
+        }
+
+        Ok(())
+    }
+
+    pub fn metamethod(&self, value : &Value, event : &str) -> Value {
+        //! the metamethod for the event, `nil` if there isn't one
+
+        match self.get_metatable(value) {
+            Some(metatable) => metatable.borrow().get_str(event),
+            None => Value::Nil,
+        }
+    }
+
+    pub fn index(&self, object : &Value, key : &Value) -> Result<Value,Error> {
+        //! `object[key]`, using `__index` if the key isn't there
+
+        self.index_with(object, key, &|value| RuntimeError::general(&format!("attempt to index a {} value", value.type_name())))
+    }
+
+    pub fn set_index(&self, object : &Value, key : Value, value : Value) -> Result<(),Error> {
+        //! `object[key] = value`, using `__newindex` if the key isn't there
+
+        self.set_index_with(object, key, value, &|value| RuntimeError::general(&format!("attempt to index a {} value", value.type_name())))
+    }
+
+    pub fn arithmetic(&self, op : Arithmetic, a : &Value, b : &Value) -> Result<Value,Error> {
+        //! `a op b`, for `unm` both values are the same
+
+        self.arithmetic_with(op, a, b, &|i| {
+            let value = if i == 0 { a } else { b };
+            RuntimeError::general(&format!("attempt to perform arithmetic on a {} value", value.type_name()))
+        })
+    }
+
+    pub fn concat(&self, a : &Value, b : &Value) -> Result<Value,Error> {
+        //! `a .. b`
+
+        self.concat_with(a, b, &|i| {
+            let value = if i == 0 { a } else { b };
+            RuntimeError::general(&format!("attempt to concatenate a {} value", value.type_name()))
+        })
+    }
+
+    pub fn length(&self, value : &Value) -> Result<Value,Error> {
+        //! `#value`, tables and strings always use their real length
+
+        self.length_with(value, &|value| RuntimeError::general(&format!("attempt to get length of a {} value", value.type_name())))
+    }
+
+    pub fn equals(&self, a : &Value, b : &Value) -> Result<bool,Error> {
+        //! `a == b`, only tables and userdata of the same type will
+        //! use `__eq`, and only if they have the same metamethod.
+
+        if a == b { return Ok(true); }
+
+        match (a, b) {
+            (Value::Table(_), Value::Table(_)) | (Value::UserData(_), Value::UserData(_)) => { },
+            _ => return Ok(false),
+        }
+
+        match self.comparison_metamethod(a, b, "__eq") {
+            Some(function) => Ok(self.call_first(&function, vec![a.clone(), b.clone()])?.is_truthy()),
+            None => Ok(false),
+        }
+    }
+
+    pub fn less_than(&self, a : &Value, b : &Value) -> Result<bool,Error> {
+        //! `a < b`
+
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => return Ok(a < b),
+            (Value::String(a), Value::String(b)) => return Ok(a < b),
+            _ => { },
+        }
+
+        if a.type_name() == b.type_name() {
+            if let Some(function) = self.comparison_metamethod(a, b, "__lt") {
+                return Ok(self.call_first(&function, vec![a.clone(), b.clone()])?.is_truthy());
+            }
+        }
+
+        Err(RuntimeError::general(&compare_error(a, b)))
+    }
+
+    pub fn less_equal(&self, a : &Value, b : &Value) -> Result<bool,Error> {
+        //! `a <= b`, if there isn't a `__le` then `not (b < a)` is tried
+
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => return Ok(a <= b),
+            (Value::String(a), Value::String(b)) => return Ok(a <= b),
+            _ => { },
+        }
+
+        if a.type_name() == b.type_name() {
+            if let Some(function) = self.comparison_metamethod(a, b, "__le") {
+                return Ok(self.call_first(&function, vec![a.clone(), b.clone()])?.is_truthy());
+            }
+
+            if let Some(function) = self.comparison_metamethod(b, a, "__lt") {
+                return Ok(!self.call_first(&function, vec![b.clone(), a.clone()])?.is_truthy());
+            }
+        }
+
+        Err(RuntimeError::general(&compare_error(a, b)))
+    }
+
+    pub fn tostring(&self, value : &Value) -> Result<Value,Error> {
+        //! the value as a string, using `__tostring` if it has one
+
+        match self.metamethod(value, "__tostring") {
+            Value::Nil => Ok(Value::String(value.tostring())),
+            function => self.call_first(&function, vec![value.clone()]),
+        }
+    }
+
+    // PRIVATE FUNCTIONS /////////////////////////////////////
+
+    pub(crate) fn index_with(&self, object : &Value, key : &Value, type_error : &dyn Fn(&Value) -> Error) -> Result<Value,Error> {
+        let mut object = object.clone();
+
+        for _ in 0 .. MAX_META_LOOP {
+            let handler = match object {
+                Value::Table(ref table) => {
+                    let value = table.borrow().get(key);
+                    if !value.is_nil() { return Ok(value); }
+
+                    match self.metamethod(&object, "__index") {
+                        Value::Nil => return Ok(Value::Nil),
+                        handler => handler,
+                    }
+                },
+                _ => match self.metamethod(&object, "__index") {
+                    Value::Nil => return Err(type_error(&object)),
+                    handler => handler,
+                },
+            };
+
+            match handler {
+                ref handler if handler.is_function() => return self.call_first(handler, vec![object, key.clone()]),
+                handler => object = handler,
+            }
+        }
+
+        Err(RuntimeError::general("loop in gettable"))
+    }
+
+    pub(crate) fn set_index_with(&self, object : &Value, key : Value, value : Value, type_error : &dyn Fn(&Value) -> Error) -> Result<(),Error> {
+        let mut object = object.clone();
+
+        for _ in 0 .. MAX_META_LOOP {
+            let handler = match object {
+                Value::Table(ref table) => {
+                    // `__newindex` is only used for keys that aren't there
+                    let exists = !table.borrow().get(&key).is_nil();
+                    match (exists, self.metamethod(&object, "__newindex")) {
+                        (true, _) | (_, Value::Nil) => return table.borrow_mut().set(key, value),
+                        (_, handler) => handler,
+                    }
+                },
+                _ => match self.metamethod(&object, "__newindex") {
+                    Value::Nil => return Err(type_error(&object)),
+                    handler => handler,
+                },
+            };
+
+            match handler {
+                ref handler if handler.is_function() => {
+                    self.call(handler, vec![object, key, value])?;
+                    return Ok(());
+                },
+                handler => object = handler,
+            }
+        }
+
+        Err(RuntimeError::general("loop in settable"))
+    }
+
+    pub(crate) fn arithmetic_with(&self, op : Arithmetic, a : &Value, b : &Value, type_error : &dyn Fn(usize) -> Error) -> Result<Value,Error> {
+        if let (Some(x), Some(y)) = (a.to_number(), b.to_number()) {
+            return Ok(Value::Number(op.apply(x, y)));
+        }
+
+        match self.binary_metamethod(a, b, op.event()) {
+            Some(function) => self.call_first(&function, vec![a.clone(), b.clone()]),
+            // lua blames the first value unless it could be a number
+            None => Err(type_error(if a.to_number().is_none() { 0 } else { 1 })),
+        }
+    }
+
+    pub(crate) fn concat_with(&self, a : &Value, b : &Value, type_error : &dyn Fn(usize) -> Error) -> Result<Value,Error> {
+        if let (Some(x), Some(y)) = (a.to_lua_string(), b.to_lua_string()) {
+            return Ok(Value::String(x.concat(&y)));
+        }
+
+        match self.binary_metamethod(a, b, "__concat") {
+            Some(function) => self.call_first(&function, vec![a.clone(), b.clone()]),
+            None => Err(type_error(if a.to_lua_string().is_none() { 0 } else { 1 })),
+        }
+    }
+
+    pub(crate) fn length_with(&self, value : &Value, type_error : &dyn Fn(&Value) -> Error) -> Result<Value,Error> {
+        match value {
+            Value::String(string) => Ok(Value::Number(string.len() as f64)),
+            Value::Table(table) => Ok(Value::Number(table.borrow().len() as f64)),
+            _ => match self.metamethod(value, "__len") {
+                Value::Nil => Err(type_error(value)),
+                function => self.call_first(&function, vec![value.clone(), Value::Nil]),
+            },
+        }
+    }
+
+    pub(crate) fn call_metamethod(&self, function : &Value, args : Vec<Value>) -> Result<Option<Vec<Value>>,Error> {
+        //! calls the value using its `__call`, `None` if it doesn't have one
+
+        match self.metamethod(function, "__call") {
+            handler if handler.is_function() => {
+                let mut handler_args = Vec::with_capacity(args.len() + 1);
+                handler_args.push(function.clone());
+                handler_args.extend(args);
+                Ok(Some(self.call(&handler, handler_args)?))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn call_first(&self, function : &Value, args : Vec<Value>) -> Result<Value,Error> {
+        //! calls the function and only keeps the first result
+
+        Ok(self.call(function, args)?.into_iter().next().unwrap_or(Value::Nil))
+    }
+
+    fn binary_metamethod(&self, a : &Value, b : &Value, event : &str) -> Option<Value> {
+        //! the metamethod of the first value, or of the second if the
+        //! first doesn't have one
+
+        match self.metamethod(a, event) {
+            Value::Nil => match self.metamethod(b, event) {
+                Value::Nil => None,
+                function => Some(function),
+            },
+            function => Some(function),
+        }
+    }
+
+    fn comparison_metamethod(&self, a : &Value, b : &Value, event : &str) -> Option<Value> {
+        //! comparisons are only done if both values have the same metamethod
+
+        let first = self.metamethod(a, event);
+        if first.is_nil() { return None; }
+
+        let second = self.metamethod(b, event);
+        match first == second {
+            true => Some(first),
+            false => None,
+        }
+    }
+}
+
+fn compare_error(left : &Value, right : &Value) -> String {
+    //! the error message when two values can't be compared
+
+    let (a, b) = (left.type_name(), right.type_name());
+
+    match a == b {
+        true => format!("attempt to compare two {} values", a),
+        false => format!("attempt to compare {} with {}", a, b),
+    }
+}
@@ -0,0 +1,1070 @@
+//! the interpreter is the thing that actually runs the code, it compiles
+//! the parsed tree of a `Chunk` into bytecode and runs that on its virtual
+//! machine.
+//!
+//! the interpreter is cheap to clone, all the clones share the same state
+//! so it can be handed to the functions that are called from lua.
+
+mod metamethods;
+mod stack;
+mod coroutine;
+mod gc;
+mod native;
+mod limits;
+mod function;
+mod vm;
+
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+
+use failure::Error;
+
+use crate::chunk::Chunk;
+use crate::compiler;
+use crate::bytecode::Proto;
+use crate::bytecode::undump;
+use crate::scanner;
+use crate::value::{Value, ReturnValues, LuaFunction, Table, Thread};
+use crate::error::runtime::RuntimeError;
+use crate::stdlib;
+use crate::host::{Host, SystemHost};
+
+pub use crate::interpreter::metamethods::Arithmetic;
+pub use crate::interpreter::native::{Args, Library};
+pub use crate::interpreter::function::Function;
+pub(crate) use crate::interpreter::native::native_function;
+pub(crate) use crate::interpreter::stack::CallInfo;
+use crate::interpreter::gc::Heap;
+use crate::interpreter::limits::Limits;
+use crate::interpreter::coroutine::Handle;
+pub(crate) use crate::interpreter::coroutine::Handles;
+
+/// how many functions can be called inside of each other before we
+/// give up and call it a stack overflow, the same limit lua has.
+const MAX_CALL_DEPTH : usize = 4000;
+
+/// the metatables that are shared by every value of a type, by type name
+type TypeMetatables = HashMap<&'static str, Rc<RefCell<Table>>>;
+
+#[derive(Clone)]
+pub struct Interpreter {
+    // the environment of the main thread, what new chunks get as their
+    // globals. the other threads each have their own
+    globals : Rc<RefCell<Rc<RefCell<Table>>>>,
+    call_depth : Rc<Cell<usize>>,
+    // the lowest address the running stack goes down to
+    stack_limit : Rc<Cell<usize>>,
+    // everything the garbage collector knows about
+    heap : Rc<RefCell<Heap>>,
+    // the functions that are running
+    stack : Rc<RefCell<Vec<CallInfo>>>,
+    // the last value given to `error`, and which one it was
+    thrown : Rc<RefCell<Option<(usize, Value)>>>,
+    thrown_count : Rc<Cell<usize>>,
+    // the threads that are running, each one resumed the next
+    threads : Rc<RefCell<Vec<Rc<Thread>>>>,
+    // the metatables shared by all the values of a type, like strings
+    type_metatables : Rc<RefCell<TypeMetatables>>,
+    // the metatables made for the rust types given to lua as userdata
+    userdata_metatables : Rc<RefCell<HashMap<TypeId, Rc<RefCell<Table>>>>>,
+    // how far the scripts are allowed to go
+    limits : Rc<Limits>,
+    // what `io` and `os` use to get to the files and the clock
+    host : Rc<dyn Host>,
+    // which of the interpreter's clones this is
+    handle : Handle,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        //! creates a new interpreter with the standard library loaded.
+
+        Interpreter::with_host(Rc::new(SystemHost))
+    }
+
+    pub fn with_host(host : Rc<dyn Host>) -> Interpreter {
+        //! creates a new interpreter where the libraries get to the files,
+        //! the environment and the clock through the host.
+
+        let interpreter = Interpreter {
+            globals : Rc::new(RefCell::new(Rc::new(RefCell::new(Table::new())))),
+            call_depth : Rc::new(Cell::new(0)),
+            stack_limit : Rc::new(Cell::new(stack::main_stack_limit())),
+            heap : Rc::new(RefCell::new(Heap::new())),
+            stack : Rc::new(RefCell::new(Vec::new())),
+            thrown : Rc::new(RefCell::new(None)),
+            thrown_count : Rc::new(Cell::new(0)),
+            threads : Rc::new(RefCell::new(Vec::new())),
+            type_metatables : Rc::new(RefCell::new(HashMap::new())),
+            userdata_metatables : Rc::new(RefCell::new(HashMap::new())),
+            limits : Rc::new(Limits::new()),
+            host,
+            handle : Handle::new(),
+        };
+
+        interpreter.set_global("_G", Value::Table(interpreter.globals()));
+
+        stdlib::load(&interpreter);
+
+        interpreter
+    }
+
+    pub fn run(&self, code : &str, file_name : Option<&str>) -> Result<ReturnValues,Error> {
+        //! runs the code as a chunk, giving back whatever the chunk
+        //! returned.
+
+        self.counting(|| {
+            let proto = compiler::compile(Chunk::from_str(code, file_name)?)?;
+            self.run_proto(proto)
+        })
+    }
+
+    pub fn load(&self, code : &[u8], chunk_name : &str) -> Result<Value,Error> {
+        //! compiles the code into a function without running it, the
+        //! chunk name is what errors and tracebacks call it. the function
+        //! gets the globals as its environment. the code can also be a
+        //! binary chunk from `luac` or `string.dump`.
+
+        self.counting(|| {
+            let proto = compiler::load(code, chunk_name)?;
+            let function = Value::Function(Rc::new(LuaFunction::main(proto, self.globals())));
+            self.track(&function)?;
+
+            Ok(function)
+        })
+    }
+
+    pub fn run_bytes(&self, code : &[u8], file_name : Option<&str>) -> Result<ReturnValues,Error> {
+        //! runs code that might not be UTF-8, like a file saved as latin-1.
+        //! the strings in it keep the bytes the file had. binary chunks
+        //! are run too.
+
+        match undump::is_binary(code) {
+            true => self.counting(|| self.run_proto(undump::undump(code, file_name.unwrap_or("?"))?)),
+            false => self.run(&scanner::decode_source(code), file_name),
+        }
+    }
+
+    pub fn globals(&self) -> Rc<RefCell<Table>> {
+        //! the table that has all the global variables, `_G`, or the
+        //! environment of the thread that is running
+
+        match self.running() {
+            Some(thread) => thread.environment(),
+            None => self.globals.borrow().clone(),
+        }
+    }
+
+    pub fn set_globals(&self, globals : Rc<RefCell<Table>>) {
+        //! changes the environment that new chunks get, the functions that
+        //! already exist keep the one they have. like `setfenv(0, table)`,
+        //! it is only changed for the thread that is running
+
+        match self.running() {
+            Some(thread) => thread.set_environment(globals),
+            None => *self.globals.borrow_mut() = globals,
+        }
+    }
+
+    pub fn get_global(&self, name : &str) -> Value {
+        self.globals().borrow().get_str(name)
+    }
+
+    pub fn set_global(&self, name : &str, value : Value) {
+        self.globals().borrow_mut().set_str(name, value);
+    }
+
+    pub fn host(&self) -> Rc<dyn Host> {
+        self.host.clone()
+    }
+
+    pub fn call(&self, function : &Value, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+        //! calls the value with the given arguements, anything that isn't
+        //! a function can be called if it has a `__call` metamethod.
+
+        self.check_cancelled()?;
+        match self.counting(|| self.call_value(function, args))? {
+            Some(values) => Ok(values),
+            None => Err(RuntimeError::general(&format!("attempt to call a {} value", function.type_name()))),
+        }
+    }
+
+    // PRIVATE FUNCTIONS /////////////////////////////////////
+    //////////////////////////////////////////////////////////
+    //////////////////////////////////////////////////////////
+    //////////////////////////////////////////////////////////
+
+    fn run_proto(&self, proto : Rc<Proto>) -> Result<ReturnValues,Error> {
+        let function = Value::Function(Rc::new(LuaFunction::main(proto, self.globals())));
+        self.track(&function)?;
+        let values = self.call(&function, Vec::new())?;
+
+        self.collect_garbage()?;
+
+        Ok(ReturnValues::new(values))
+    }
+
+    fn call_value(&self, function : &Value, args : Vec<Value>) -> Result<Option<Vec<Value>>,Error> {
+        //! calls the function as a new level of the stack, gives `None` if
+        //! the value can't be called.
+
+        match function {
+            Value::NativeFunction(native) => self.with_call(CallInfo::native(), || native(self, args)).map(Some),
+            Value::NativeClosure(native) => self.with_call(CallInfo::native(), || native.call(self, args)).map(Some),
+            Value::Function(function) => self.with_call(CallInfo::lua(function), || self.call_function(function, args)).map(Some),
+            value => self.call_metamethod(value, args),
+        }
+    }
+
+    fn call_function(&self, function : &Rc<LuaFunction>, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+        //! runs a lua function on the virtual machine
+
+        if self.call_depth.get() >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::general("stack overflow"));
+        }
+        self.check_call_depth(self.call_depth.get())?;
+
+        // every lua call is a few rust calls, so the stack is grown as
+        // needed instead of overflowing before lua would.
+        self.call_depth.set(self.call_depth.get() + 1);
+        let result = self.with_stack_space(|| self.execute(function, args));
+        self.call_depth.set(self.call_depth.get() - 1);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::rc::Rc;
+
+    use crate::interpreter::Interpreter;
+    use crate::host::SandboxHost;
+    use crate::value::Value;
+
+    fn run(code : &str) -> Vec<Value> {
+        match Interpreter::new().run(code, Some("testfile.lua")) {
+            Ok(values) => values.into_values(),
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    #[test]
+    pub fn control_flow() {
+        assert_eq!(run("local x = 0; for i = 1, 10 do x = x + i end; return x"), vec![Value::Number(55.0)]);
+        assert_eq!(run("local x = 0; for i = 10, 1, -2 do x = x + i end; return x"), vec![Value::Number(30.0)]);
+        assert_eq!(run("local x = 0; while true do x = x + 1; if x > 5 then break end end; return x"), vec![Value::Number(6.0)]);
+        assert_eq!(run("local x = 0; repeat local y = x; x = x + 1 until y >= 3; return x"), vec![Value::Number(4.0)]);
+        assert_eq!(run("local x = 3; if x == 1 then return 'a' elseif x == 3 then return 'c' else return 'z' end"), vec![Value::from("c")]);
+        assert_eq!(run("if false then return 1 end; do local y = 2 end; return y"), vec![Value::Nil]);
+    }
+
+    #[test]
+    pub fn locals_and_functions() {
+        assert_eq!(run("x = 1; local x = 2; do local x = 3 end; return x, y"), vec![Value::Number(2.0), Value::Nil]);
+        assert_eq!(run("function fib(n) if n < 2 then return n end return fib(n-1) + fib(n-2) end return fib(15)"), vec![Value::Number(610.0)]);
+        assert_eq!(run("local function f(a, b) return b, a end; return f(1, 2)"), vec![Value::Number(2.0), Value::Number(1.0)]);
+        assert_eq!(run("local f = function(a) return a end; return f(1, 2), (f(3, 4)), f(5)"), vec![Value::Number(1.0), Value::Number(3.0), Value::Number(5.0)]);
+        assert_eq!(run("local function f() return end; return f()"), vec![]);
+    }
+
+    #[test]
+    pub fn tables() {
+        assert_eq!(run("local t = {1, 2, 3, n = 'x', [10] = 'y'}; return #t, t.n, t[10], t[4]"), vec![Value::Number(3.0), Value::from("x"), Value::from("y"), Value::Nil]);
+        assert_eq!(run("local function f() return 1, 2, 3 end; local t = {f(), f()}; return #t"), vec![Value::Number(4.0)]);
+        assert_eq!(run("local t = {}; t.a = {}; t.a.b = 1; t['c'] = t.a.b + 1; return t.a.b, t.c"), vec![Value::Number(1.0), Value::Number(2.0)]);
+        assert_eq!(run("local t = {}; for i = 1, 100 do t[i] = i end; t[100] = nil; return #t, _G._G == _G"), vec![Value::Number(99.0), Value::Boolean(true)]);
+        assert_eq!(run("a = {}; function a.b(x) return x end; function a:c(x) return self == a, x end; return a.b(1), a:c(2)"),
+            vec![Value::Number(1.0), Value::Boolean(true), Value::Number(2.0)]);
+        assert_eq!(run("local i, t = 1, {}; i, t[i] = i + 1, 20; return i, t[1], t[2]"), vec![Value::Number(2.0), Value::Number(20.0), Value::Nil]);
+        assert_eq!(run("local n = 0; for k, v in pairs({1, 2, a = 3}) do n = n + v end; for i, v in ipairs({1, 2, nil, 4}) do n = n + i end; return n"),
+            vec![Value::Number(9.0)]);
+
+        // the nils in a constructor or `...` are inside the array, so the
+        // border is the same as lua 5.1's
+        let code = r#"
+            local function count(...) return #{...} end
+            return #{1, 2, nil, 4}, #{nil, nil, 3}, count(nil, 2), count(1, nil, 3), #{nil, nil}
+        "#;
+        assert_eq!(run(code), vec![Value::Number(4.0), Value::Number(3.0), Value::Number(2.0), Value::Number(3.0), Value::Number(0.0)]);
+    }
+
+    #[test]
+    pub fn metatables() {
+        let code = r#"
+            Point = {}
+            Point.__index = Point
+            Point.__add = function(a, b) return Point.new(a.x + b.x, a.y + b.y) end
+            Point.__eq = function(a, b) return a.x == b.x and a.y == b.y end
+            Point.__lt = function(a, b) return a.x < b.x end
+            Point.__tostring = function(p) return "(" .. p.x .. ", " .. p.y .. ")" end
+            Point.__call = function(p, scale) return p.x * scale end
+            function Point.new(x, y) return setmetatable({x = x, y = y}, Point) end
+            function Point:length() return self.x + self.y end
+
+            local a, b = Point.new(1, 2), Point.new(3, 4)
+            return (a + b):length(), tostring(a + b), a + b == Point.new(4, 6), a <= b, b(10), getmetatable(a) == Point
+        "#;
+
+        assert_eq!(run(code), vec![Value::Number(10.0), Value::from("(4, 6)"), Value::Boolean(true), Value::Boolean(true),
+            Value::Number(30.0), Value::Boolean(true)]);
+
+        let code = r#"
+            log = {}
+            local t = setmetatable({}, {__newindex = function(t, k, v) rawset(t, k, v * 2); log[k] = true end, __metatable = false})
+            t.a = 1; t.a = 5
+            local d = setmetatable({}, {__index = setmetatable({x = 1}, {__index = function(t, k) return k .. "!" end})})
+            return t.a, log.a, getmetatable(t), d.x, d.y
+        "#;
+
+        assert_eq!(run(code), vec![Value::Number(5.0), Value::Boolean(true), Value::Boolean(false), Value::Number(1.0), Value::from("y!")]);
+
+        let interpreter = Interpreter::new();
+        interpreter.run("do local p = newproxy(true); getmetatable(p).__gc = function(p) collected = true end end", None).unwrap();
+        assert_eq!(interpreter.get_global("collected"), Value::Boolean(true));
+
+        for code in [
+            "local t = setmetatable({}, {__metatable = 1}); setmetatable(t, {})",
+            "local m = {}; m.__index = m; setmetatable(m, m); return m.x",
+            "return setmetatable({}, {}) < setmetatable({}, {})",
+            "local t = setmetatable({}, {__index = function(t, k) return t[k] end}); return t.x",
+        ].iter() {
+            assert!(Interpreter::new().run(code, None).is_err(), "{} should fail", code);
+        }
+    }
+
+    #[test]
+    pub fn closures() {
+        // sibling closures share the same upvalue
+        let code = r#"
+            local function counter()
+                local n = 0
+                return function() n = n + 1; return n end, function() return n end
+            end
+            local inc, get = counter()
+            inc(); inc()
+            local other = counter()
+            other()
+            return get()
+        "#;
+        assert_eq!(run(code), vec![Value::Number(2.0)]);
+
+        // each loop around gets its own variables
+        let code = r#"
+            local a = {}
+            for i = 1, 10 do
+                local y = i * 10
+                a[i] = {set = function(x) i = x end, get = function() return i, y end}
+                if i == 3 then break end
+            end
+            a[1].set(10)
+            local b = {}
+            for k, v in pairs({'a', 'b'}) do b[k] = function() return v end end
+            local i1, y1 = a[1].get()
+            local i2, y2 = a[2].get()
+            return i1, y1, i2, y2, a[4], b[1](), b[2]()
+        "#;
+        assert_eq!(run(code), vec![Value::Number(10.0), Value::Number(10.0), Value::Number(2.0), Value::Number(20.0), Value::Nil,
+            Value::from("a"), Value::from("b")]);
+
+        // many levels deep, and locals declared after the function are still seen
+        let code = r#"
+            local w
+            local function f(x)
+                return function(y)
+                    return function(z) return w + x + y + z end
+                end
+            end
+            local y = f(10)
+            w = 1
+            local a, i = {}, 1
+            repeat
+                local x = i
+                a[i] = function() i = x + 1; return x end
+            until i > 10 or a[i]() ~= x
+            return y(20)(30), i, a[3](), i
+        "#;
+        assert_eq!(run(code), vec![Value::Number(61.0), Value::Number(11.0), Value::Number(3.0), Value::Number(4.0)]);
+    }
+
+    #[test]
+    pub fn varargs() {
+        let code = r#"
+            local function pack(...) return {n = select('#', ...), ...} end
+            local function first(...) return (...) end
+            local function rest(a, ...) return ... end
+            local t = pack(1, nil, 3, nil)
+            return t.n, #pack(rest(1, 2, 3)), first(4, 5), select(2, 'a', 'b', 'c'), select(-1, 'x', 'y')
+        "#;
+        assert_eq!(run(code), vec![Value::Number(4.0), Value::Number(2.0), Value::Number(4.0), Value::from("b"),
+            Value::from("y")]);
+
+        // without using `...` the extra arguements go into `arg`
+        let code = r#"
+            local function old(a, ...) return arg.n, arg[2] end
+            local function new(...) return arg end
+            local a, b, c = unpack({1, 2, 3})
+            local n, second = old(1, 2, 3)
+            return n, second, new(1).n, a + b + c, unpack({1, 2, 3}, 2)
+        "#;
+        assert_eq!(run(code), vec![Value::Number(2.0), Value::Number(3.0), Value::Number(1.0), Value::Number(6.0), Value::Number(2.0),
+            Value::Number(3.0)]);
+
+        assert!(Interpreter::new().run("select(0, 1)", None).is_err());
+    }
+
+    #[test]
+    pub fn errors() {
+        let code = r#"
+            local function f(level) error('a', level) end
+            local function g(level) f(level) end
+            local results = {}
+            for level = 0, 2 do
+                local ok, message = pcall(g, level)
+                results[#results + 1] = message
+            end
+            return unpack(results)
+        "#;
+        assert_eq!(run(code), vec![Value::from("a"), Value::from("testfile.lua:2: a"), Value::from("testfile.lua:3: a")]);
+
+        let code = r#"
+            local t = {}
+            local ok, e = pcall(error, t)
+            local ok2, e2 = pcall(function() local x = nil; return x.y end)
+            local ok3, e3 = xpcall(function() error({msg = 'x'}) end, function(e) return e.msg .. 'y' end)
+            return e == t, e2, pcall(error), e3, pcall(error, 'no position')
+        "#;
+        assert_eq!(run(code), vec![Value::Boolean(true), Value::from("testfile.lua:4: attempt to index local 'x' (a nil value)"),
+            Value::Boolean(false), Value::from("xy"), Value::Boolean(false), Value::from("no position")]);
+
+        // errors that get out keep where they came from
+        let error = match Interpreter::new().run("local function f()\n error('oops')\n end\n f()", Some("testfile.lua")) {
+            Err(error) => error,
+            Ok(_) => panic!("should have failed"),
+        };
+        let error = error.downcast_ref::<crate::RuntimeError>().unwrap();
+        assert_eq!(error.message(), "testfile.lua:2: oops");
+        assert_eq!(error.traceback(), Some("stack traceback:\n\t[C]: in function 'error'\n\ttestfile.lua:2: in function 'f'\n\ttestfile.lua:4: in main chunk"));
+    }
+
+    #[test]
+    pub fn syntax_levels() {
+        // code nested too deep is an error that can be caught, not a crash
+        let code = r#"
+            local deep = {
+                "return " .. ("("):rep(1e5) .. "1" .. (")"):rep(1e5),
+                "return " .. ("{"):rep(1e5) .. ("}"):rep(1e5),
+                ("do "):rep(1e5) .. (" end"):rep(1e5),
+                "return 'a'" .. (" .. 'a'"):rep(1e5),
+                "return a" .. (".b"):rep(1e5),
+                "f" .. ("()"):rep(1e5),
+            }
+            local messages = {}
+            for i, code in ipairs(deep) do
+                local ok, f, message = pcall(loadstring, code, "=deep")
+                messages[i] = tostring(ok and f == nil and message)
+            end
+            return unpack(messages)
+        "#;
+        assert_eq!(run(code), vec![Value::from("deep:1: chunk has too many syntax levels"); 6]);
+    }
+
+    #[test]
+    pub fn tail_calls() {
+        // they don't use up the stack
+        let code = r#"
+            local function count(n, total) if n == 0 then return total end return count(n - 1, total + 1) end
+            return count(100000, 0)
+        "#;
+        assert_eq!(run(code), vec![Value::Number(100000.0)]);
+
+        // but the traceback shows where one happened
+        let error = match Interpreter::new().run("local function f()\n error('oops')\n end\n local function g() return f() end\n g()", Some("testfile.lua")) {
+            Err(error) => error,
+            Ok(_) => panic!("should have failed"),
+        };
+        let error = error.downcast_ref::<crate::RuntimeError>().unwrap();
+        assert_eq!(error.traceback(), Some("stack traceback:\n\t[C]: in function 'error'\n\ttestfile.lua:2: in function <testfile.lua:1>\n\t(tail call): ?\n\ttestfile.lua:5: in main chunk"));
+    }
+
+    #[test]
+    pub fn coroutines() {
+        // yielding from inside nested calls, and passing values both ways
+        let code = r#"
+            local function deep(n, x) if n == 0 then return coroutine.yield(x) end return deep(n - 1, x) end
+            local co = coroutine.create(function(a, b)
+                local c = deep(10, a + b)
+                local d, e = coroutine.yield(c * 2)
+                return d + e
+            end)
+            local _, first = coroutine.resume(co, 1, 2)
+            local _, second = coroutine.resume(co, 10)
+            local status = coroutine.status(co)
+            local _, third = coroutine.resume(co, 3, 4)
+            return first, second, status, third, coroutine.status(co), coroutine.resume(co)
+        "#;
+        assert_eq!(run(code), vec![Value::Number(3.0), Value::Number(20.0), Value::from("suspended"), Value::Number(7.0),
+            Value::from("dead"), Value::Boolean(false), Value::from("cannot resume dead coroutine")]);
+
+        let code = r#"
+            local sum = 0
+            for i in coroutine.wrap(function() for i = 1, 4 do coroutine.yield(i) end end) do sum = sum + i end
+
+            local outer
+            outer = coroutine.create(function()
+                local inner = coroutine.create(function() coroutine.yield(coroutine.status(outer)) end)
+                local _, status = coroutine.resume(inner)
+                coroutine.yield(status, coroutine.status(coroutine.running()))
+            end)
+            local _, a, b = coroutine.resume(outer)
+            return sum, a, b, coroutine.running()
+        "#;
+        assert_eq!(run(code), vec![Value::Number(10.0), Value::from("normal"), Value::from("running"), Value::Nil]);
+
+        // errors end the coroutine and come out of `resume`
+        let code = r#"
+            local co = coroutine.create(function() error('boom') end)
+            local ok, message = coroutine.resume(co)
+            local yielded, outside = pcall(coroutine.yield)
+            return ok, message, coroutine.status(co), yielded, outside
+        "#;
+        assert_eq!(run(code), vec![Value::Boolean(false), Value::from("testfile.lua:2: boom"), Value::from("dead"),
+            Value::Boolean(false), Value::from("attempt to yield from outside a coroutine")]);
+
+        // suspended coroutines that nothing uses are collected, and so are
+        // ones that haven't started and point at themselves
+        let code = r#"
+            local threads = setmetatable({}, {__mode = "v"})
+            for i = 1, 5000 do
+                local co = coroutine.create(function(x) coroutine.yield(x) end)
+                coroutine.resume(co, {})
+                threads[i] = co
+            end
+            for i = 1, 10 do
+                local co
+                co = coroutine.create(function() return co end)
+                threads[#threads + 1] = co
+            end
+            collectgarbage()
+            return next(threads)
+        "#;
+        assert_eq!(run(code), vec![Value::Nil]);
+
+        // and so are ones that have started and point back at themselves
+        // from their stack, like an entity running its own script
+        let code = r#"
+            local entities = setmetatable({}, {__mode = "k"})
+            local function spawn()
+                local e = {}
+                e.co = coroutine.create(function()
+                    while true do
+                        e.x = (e.x or 0) + 1
+                        coroutine.yield()
+                    end
+                end)
+                coroutine.resume(e.co)
+                entities[e] = true
+            end
+            for i = 1, 100 do spawn() end
+            collectgarbage()
+            return next(entities)
+        "#;
+        assert_eq!(run(code), vec![Value::Nil]);
+
+        // a thread can only be resumed by the interpreter it was made in,
+        // and is closed once that interpreter is dropped
+        let owner = Interpreter::new();
+        let thread = owner.run("local co = coroutine.create(function(x) coroutine.yield(x) return 'done' end)
+            coroutine.resume(co, {}) return co", None).unwrap().into_values().remove(0);
+        let thread = match thread { Value::Thread(thread) => thread, _ => panic!("not a thread") };
+        let other = Interpreter::new();
+        assert!(other.resume(&thread, vec![]).unwrap_err().to_string().contains("cannot resume coroutine from another interpreter"));
+        assert_eq!(owner.resume(&thread, vec![]).unwrap(), vec![Value::from("done")]);
+
+        let thread = owner.create_thread(owner.get_global("coroutine")).unwrap();
+        let suspended = owner.run("local co = coroutine.create(function() coroutine.yield() end)
+            coroutine.resume(co) return co", None).unwrap().into_values().remove(0);
+        drop(owner);
+        assert_eq!(thread.status(), crate::ThreadStatus::Dead);
+        match suspended {
+            Value::Thread(suspended) => {
+                assert_eq!(suspended.status(), crate::ThreadStatus::Dead);
+                assert!(Interpreter::new().resume(&suspended, vec![]).is_err());
+            },
+            _ => panic!("not a thread"),
+        }
+    }
+
+    #[test]
+    pub fn strings() {
+        let code = r#"
+            local s = "hello world"
+            return s:upper(), s:sub(-5), s:rep(2, nil):len(), s:byte(1), string.char(72, 105), ("abc"):reverse()
+        "#;
+        assert_eq!(run(code), vec![Value::from("HELLO WORLD"), Value::from("world"), Value::Number(22.0), Value::Number(104.0),
+            Value::from("Hi"), Value::from("cba")]);
+
+        let code = r#"
+            local words = {}
+            for k, v in string.gmatch("a=1, b=2", "(%w+)=(%w+)") do words[#words + 1] = k .. v end
+            local key, value = string.match("  key = val ", "^%s*(%w+)%s*=%s*(%w+)")
+            return key, value, words[2], (string.gsub("hello", "l", {l = "L"})), string.gsub("abc", "%w", "%0%0", 2)
+        "#;
+        assert_eq!(run(code), vec![Value::from("key"), Value::from("val"), Value::from("b2"), Value::from("heLLo"), Value::from("aabbc"),
+            Value::Number(2.0)]);
+        assert_eq!(run("return string.find('x(a(b)c)y', '%b()')"), vec![Value::Number(2.0), Value::Number(8.0)]);
+
+        let code = r#"
+            return string.format("%5.2f|%-3d|%x|%s|%g", 3.14159, 7, 255, 1, 1e20), string.format("%q", 'a"\n') == '"a\\"\\\n"'
+        "#;
+        assert_eq!(run(code), vec![Value::from(" 3.14|7  |ff|1|1e+20"), Value::Boolean(true)]);
+
+        for code in [
+            "string.rep()",
+            "string.find('a', '[a')",
+            "string.format('%d', 'x')",
+            "string.find('a', '(')",
+        ].iter() {
+            assert!(Interpreter::new().run(code, None).is_err(), "{} should fail", code);
+        }
+
+        // too big to make is an error lua can catch
+        assert_eq!(run("return pcall(string.rep, 'ab', 1e10)"), vec![Value::Boolean(false), Value::from("resulting string too large")]);
+    }
+
+    #[test]
+    pub fn numbers() {
+        let code = r#"
+            local whole, fraction = math.modf(-3.5)
+            return tostring(0.1), 1e300 .. "", tostring(2^53), math.floor(-4.5), math.max(3, 9, 1), whole, fraction, math.fmod(-7, 3)
+        "#;
+        assert_eq!(run(code), vec![Value::from("0.1"), Value::from("1e+300"), Value::from("9.007199254741e+15"), Value::Number(-5.0),
+            Value::Number(9.0), Value::Number(-3.0), Value::Number(-0.5), Value::Number(-1.0)]);
+
+        let code = r#"
+            math.randomseed(7)
+            local a = {math.random(), math.random(10), math.random(-3, 3)}
+            math.randomseed(7)
+            local b = {math.random(), math.random(10), math.random(-3, 3)}
+            return a[1] == b[1] and a[2] == b[2] and a[3] == b[3], a[2] >= 1 and a[2] <= 10, tonumber("0x10"), tonumber("z", 36)
+        "#;
+        assert_eq!(run(code), vec![Value::Boolean(true), Value::Boolean(true), Value::Number(16.0), Value::Number(35.0)]);
+
+        assert!(Interpreter::new().run("math.random(2, 1)", None).is_err());
+    }
+
+    #[test]
+    pub fn table_library() {
+        let code = r#"
+            local t = {5, 2, 8, 1}
+            table.insert(t, 3)
+            table.insert(t, 1, 9)
+            local removed = table.remove(t, 2)
+            table.sort(t)
+            local sorted = table.concat(t, ",")
+            table.sort(t, function(a, b) return a > b end)
+            return sorted, table.concat(t, "", 2, 3), removed, table.maxn({1, [10] = 2, [2.5] = 3}), table.remove({})
+        "#;
+        assert_eq!(run(code), vec![Value::from("1,2,3,8,9"), Value::from("83"), Value::Number(5.0), Value::Number(10.0)]);
+
+        let code = r#"
+            local t = {}
+            for i = 1, 100 do t[i] = (i * 37) % 101 end
+            local ok, message = pcall(table.sort, t, function(a, b) return true end)
+            return ok, message, pcall(table.concat, {1, {}, 3})
+        "#;
+        assert_eq!(run(code), vec![Value::Boolean(false), Value::from("invalid order function for sorting"), Value::Boolean(false),
+            Value::from("invalid value (at index 2) in table for 'concat'")]);
+    }
+
+    #[test]
+    pub fn io_and_os() {
+        let code = r#"
+            local name = os.tmpname()
+            local file = assert(io.open(name, "w"))
+            file:write("10 20.5\n", "second line\n", 3)
+            file:close()
+
+            local file = io.open(name)
+            local a, b, rest = file:read("*n", "*n", "*l")
+            local lines = {}
+            for line in file:lines() do lines[#lines + 1] = line end
+            file:close()
+
+            local closed = tostring(file)
+            local missing, message = io.open(name .. ".missing")
+            assert(os.remove(name))
+            return a + b, rest, lines[1], lines[2], closed, missing, io.type(file), os.date("!%Y-%m-%d %H", 86400)
+        "#;
+
+        assert_eq!(run(code), vec![Value::Number(30.5), Value::from(""), Value::from("second line"), Value::from("3"),
+            Value::from("file (closed)"), Value::Nil, Value::from("closed file"), Value::from("1970-01-02 00")]);
+        assert_eq!(run("return os.time{year=2000, month=1, day=1, hour=12} - os.time{year=1999, month=13, day=0, hour=12}"), vec![Value::Number(86400.0)]);
+
+        // files are closed by their `__gc`, but not the standard ones
+        let code = r#"
+            local name = os.tmpname()
+            do
+                local file = assert(io.open(name, "w"))
+                file:write("written")
+            end
+            collectgarbage()
+            local file = io.open(name)
+            local text = file:read("*a")
+            getmetatable(file).__gc(file)
+            getmetatable(io.stdout).__gc(io.stdout)
+            local _, message = pcall(getmetatable(io.stdin).__gc)
+            assert(os.remove(name))
+            return text, io.type(file), io.type(io.stdout), message
+        "#;
+
+        assert_eq!(run(code), vec![Value::from("written"), Value::from("closed file"), Value::from("file"),
+            Value::from("bad argument #1 to '__gc' (file expected, got no value)")]);
+    }
+
+    #[test]
+    pub fn sandboxed_host() {
+        let interpreter = Interpreter::with_host(Rc::new(SandboxHost));
+        let code = r#"
+            local file, message = io.open('Cargo.toml')
+            return file, message, os.getenv('HOME'), (pcall(os.exit)), type(os.time())
+        "#;
+
+        assert_eq!(interpreter.run(code, None).unwrap().into_values(), vec![Value::Nil, Value::from("Cargo.toml: Permission denied"),
+            Value::Nil, Value::Boolean(false), Value::from("number")]);
+    }
+
+    #[test]
+    pub fn loading_code() {
+        let code = r#"
+            local add = loadstring("local a, b = ... return a + b")
+            local _, message = loadstring("x = = 1")
+            local _, named = loadstring("return 1 +", "=adder")
+            local _, thrown = pcall(loadstring("error('boom')", "@boom.lua"))
+
+            local parts, i = { "return ", "'pie", "ces'" }, 0
+            local pieces = load(function() i = i + 1 return parts[i] end)
+
+            -- errors while reading or scanning are given back, not thrown
+            local ok, nothing, unknown = pcall(loadstring, "x = abc\195\169")
+            local _, reader = load(function() error('no more') end)
+            local _, wrong = load(function() return {} end)
+
+            return add(2, 3), message, named, thrown, pieces(), ok, nothing, unknown, reader, wrong
+        "#;
+
+        let values = run(code);
+        assert_eq!(values[0], Value::Number(5.0));
+        assert!(values[1].tostring().to_string_lossy().starts_with("[string \"x = = 1\"]:1:"));
+        assert!(values[2].tostring().to_string_lossy().starts_with("adder:1:"));
+        assert_eq!(values[3], Value::from("boom.lua:1: boom"));
+        assert_eq!(values[4], Value::from("pieces"));
+        assert_eq!(&values[5 .. 7], &[Value::Boolean(true), Value::Nil]);
+        assert!(values[7].tostring().to_string_lossy().contains("unknown character"));
+        assert_eq!(values[8], Value::from("testfile.lua:12: no more"));
+        assert_eq!(values[9], Value::from("reader function must return a string"));
+
+        let interpreter = Interpreter::new();
+        let function = interpreter.load(b"return select('#', ...)", "=loaded").unwrap();
+        assert_eq!(interpreter.call(&function, vec![Value::Nil, Value::Nil]).unwrap(), vec![Value::Number(2.0)]);
+    }
+
+    #[test]
+    pub fn binary_chunks() {
+        let code = r#"
+            local function counter(start, ...)
+                local count = start or select('#', ...)
+                local t = { "a", n = 1.5, [true] = false }
+                return function() count = count + 1 return count, t.n, #t end
+            end
+
+            local copy = loadstring(string.dump(counter))
+            local next = copy(10)
+            next()
+
+            local fails = loadstring(string.dump(function() local x return x.y end))
+            local _, message = pcall(fails)
+            local _, native = pcall(string.dump, print)
+            local _, broken = loadstring(string.dump(counter):sub(1, 40), "=broken")
+
+            return message, native, broken, next()
+        "#;
+
+        let values = run(code);
+        assert_eq!(values[0], Value::from("testfile.lua:12: attempt to index local 'x' (a nil value)"));
+        assert_eq!(values[1], Value::from("unable to dump given function"));
+        assert_eq!(values[2], Value::from("broken: unexpected end in precompiled chunk"));
+        assert_eq!(values[3 ..].to_vec(), vec![Value::Number(12.0), Value::Number(1.5), Value::Number(1.0)]);
+
+        // hand made chunks that pass the checks but would do something
+        // the compiler never does
+        let code = r#"
+            local dump = string.dump(function() local t = {} return t end)
+            local i = dump:find("\10\0\0\0", 1, true)
+            local huge = loadstring(dump:sub(1, i - 1) .. "\10\0\255\255" .. dump:sub(i + 4))
+
+            -- every `FORPREP` it could be is turned into a `JMP`
+            local dump = string.dump(function() for i = 'a', 'b' do end end)
+            local messages = {}
+            for i = 1, #dump do
+                if dump:byte(i) % 64 == 32 then
+                    local jump = loadstring(dump:sub(1, i - 1) .. string.char(dump:byte(i) - 10) .. dump:sub(i + 1))
+                    if jump then messages[(select(2, pcall(jump)):gsub("^.-:%d+: ", ""))] = true end
+                end
+            end
+
+            return type(huge()), messages["'for' values must be numbers"]
+        "#;
+        assert_eq!(run(code), vec![Value::from("table"), Value::Boolean(true)]);
+    }
+
+    #[test]
+    pub fn garbage_collection() {
+        let code = r#"
+            local order = {}
+            local weak = setmetatable({}, {__mode = "k"})
+            do
+                local a, b = {}, {}
+                a.other, b.other = b, a
+                weak[a] = true
+                for i = 1, 3 do
+                    local p = newproxy(true)
+                    getmetatable(p).__gc = function() order[#order + 1] = i end
+                    a[i] = p
+                end
+            end
+
+            local kept = {}
+            weak[kept] = true
+            collectgarbage()
+
+            local count = 0
+            for k in pairs(weak) do count = count + 1 end
+
+            collectgarbage("stop")
+            local before = collectgarbage("count")
+            for i = 1, 100 do local t = {{}, {}} end
+            local grew = collectgarbage("count") > before
+            collectgarbage("restart")
+
+            local _, invalid = pcall(collectgarbage, "nope")
+            return count, table.concat(order, " "), grew, collectgarbage("setpause", 150), collectgarbage("step", 10000), type(gcinfo()), invalid
+        "#;
+
+        assert_eq!(run(code), vec![Value::Number(1.0), Value::from("3 2 1"), Value::Boolean(true), Value::Number(200.0), Value::Boolean(true),
+            Value::from("number"), Value::from("bad argument #1 to 'collectgarbage' (invalid option 'nope')")]);
+
+        // making only strings is enough to get a collection going
+        let code = r#"
+            local x = setmetatable({{}}, {__mode = "v"})
+            local A = 0
+            while x[1] do
+                local a = A .. A .. A .. A
+                A = A + 1
+            end
+            return A > 0
+        "#;
+        assert_eq!(run(code), vec![Value::Boolean(true)]);
+
+        // a collection in the middle of going through a weak table doesn't
+        // stop `next` from finding where it was
+        let code = r#"
+            local values = setmetatable({}, {__mode = "v"})
+            for i = 1, 100 do values["k" .. i] = {} end
+            local seen = 0
+            for k, v in pairs(values) do
+                seen = seen + 1
+                v = nil
+                collectgarbage()
+            end
+
+            local keys = setmetatable({}, {__mode = "k"})
+            for i = 1, 100 do keys[{}] = i end
+            for k in pairs(keys) do seen = seen + 1 collectgarbage() end
+
+            values.new = true
+            return seen, next(values)
+        "#;
+        assert_eq!(run(code), vec![Value::Number(2.0), Value::from("new"), Value::Boolean(true)]);
+
+        // the thread behind a wrapped coroutine is found through the
+        // function, so one that refers to itself can still be collected
+        let code = r#"
+            local weak = setmetatable({}, {__mode = "v"})
+            do
+                local gen
+                gen = coroutine.wrap(function() return gen end)
+                weak[1] = gen
+            end
+            collectgarbage()
+            return weak[1]
+        "#;
+        assert_eq!(run(code), vec![Value::Nil]);
+
+        // small steps say false until one of them finishes the cycle
+        let code = r#"
+            collectgarbage()
+            local weak = setmetatable({}, {__mode = "k"})
+            weak[{}] = true
+            local steps = 1
+            while not collectgarbage("step") do steps = steps + 1 end
+            return steps > 1, next(weak), collectgarbage("step")
+        "#;
+        assert_eq!(run(code), vec![Value::Boolean(true), Value::Nil, Value::Boolean(false)]);
+    }
+
+    #[test]
+    pub fn require() {
+        let code = r#"
+            local loads = 0
+            package.preload.counter = function(name)
+                loads = loads + 1
+                return { name = name }
+            end
+
+            local first, second = require "counter", require "counter"
+            local found, message = pcall(require, "missing.module")
+            return first == second, loads, first.name, require "string" == string, message
+        "#;
+
+        let values = run(code);
+        assert_eq!(&values[.. 4], &[Value::Boolean(true), Value::Number(1.0), Value::from("counter"), Value::Boolean(true)]);
+
+        let message = values[4].tostring().to_string_lossy();
+        assert!(message.starts_with("module 'missing.module' not found:\n\tno field package.preload['missing.module']"));
+        assert!(message.contains("no file './missing/module.lua'"));
+    }
+
+    #[test]
+    pub fn environments() {
+        let code = r#"
+            x = "global"
+            local function read() return x end
+            local function maker() return function() return x end end
+
+            setfenv(read, { x = "set" })
+            setfenv(maker, { x = "inherited" })
+
+            local function own()
+                setfenv(1, { x = "own" })
+                return x
+            end
+
+            local fixed = pcall(setfenv, print, {})
+            return read(), maker()(), own(), loadstring("return x")(), getfenv(0) == _G, fixed
+        "#;
+
+        assert_eq!(run(code), vec![
+            Value::from("set"), Value::from("inherited"), Value::from("own"),
+            Value::from("global"), Value::Boolean(true), Value::Boolean(false),
+        ]);
+
+        // each thread has its own environment, starting with the one of
+        // the thread that made it
+        let interpreter = Interpreter::new();
+        let code = r#"
+            marker = "main"
+            local co = coroutine.create(function()
+                setfenv(0, { marker = "thread", coroutine = coroutine, loadstring = loadstring })
+                local inner = coroutine.wrap(function() return loadstring("return marker")() end)
+                coroutine.yield(loadstring("return marker")(), inner())
+            end)
+            local _, seen, inherited = coroutine.resume(co)
+            return seen, inherited, getfenv(0) == _G, loadstring("return marker")()
+        "#;
+        let values = interpreter.run(code, None).unwrap().into_values();
+        assert_eq!(values, vec![Value::from("thread"), Value::from("thread"), Value::Boolean(true), Value::from("main")]);
+        assert_eq!(interpreter.get_global("marker"), Value::from("main"));
+    }
+
+    #[test]
+    pub fn modules() {
+        let code = r#"
+            package.preload["shapes.square"] = function(...)
+                module(..., package.seeall)
+                function area(side) return side * side end
+                return _M
+            end
+
+            local square = require "shapes.square"
+            return square == shapes.square, square.area(3), square._NAME, square._PACKAGE, area
+        "#;
+
+        assert_eq!(run(code), vec![
+            Value::Boolean(true), Value::Number(9.0), Value::from("shapes.square"), Value::from("shapes."), Value::Nil,
+        ]);
+    }
+
+    #[test]
+    pub fn generic_for() {
+        let code = r#"
+            function iter(limit, last)
+                if last < limit then return last + 1, last * 2 end
+            end
+
+            local total = 0
+            for i, double in iter, 4, 0 do
+                total = total + i + double
+            end
+            return total
+        "#;
+
+        assert_eq!(run(code), vec![Value::Number(22.0)]);
+    }
+
+    #[test]
+    pub fn runtime_errors() {
+        let interpreter = Interpreter::new();
+
+        for code in [
+            "x = nil + 1",
+            "local x; x()",
+            "return 1 < 'x'",
+            "for i = 'a', 2 do end",
+            "function f() return 1 + f() end f()",
+            "local t = {}; t.x.y = 1",
+            "local t = {}; t[nil] = 1",
+            "local t = {}; t:m()",
+        ].iter() {
+            assert!(interpreter.run(code, None).is_err(), "{} should fail", code);
+        }
+    }
+
+    #[test]
+    pub fn run_lua_test_suite() {
+        // the parts of lua's own tests that the libraries were checked
+        // against, each one asserts as it goes so finishing is passing
+        let file_names = vec![
+            "vararg.lua",
+            "pm.lua",
+            "strings.lua",
+            "math.lua",
+            "sort.lua",
+            "gc.lua",
+            "files.lua",
+            "nextvar.lua",
+        ];
+
+        for file_name in file_names {
+            let path = format!("../lua/lua-test-suite/{}", file_name);
+            let code = std::fs::read(&path).unwrap_or_else(|_| panic!("{}: can't read file", file_name));
+
+            // the tests `require` their helpers from the same folder
+            let interpreter = Interpreter::new();
+            interpreter.run("package.path = '../lua/lua-test-suite/?.lua;' .. package.path", None).unwrap();
+
+            if let Err(error) = interpreter.run_bytes(&code, Some(&path)) {
+                panic!("{}: {}", file_name, error);
+            }
+        }
+    }
+}
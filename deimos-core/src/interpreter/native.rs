@@ -0,0 +1,423 @@
+//! what a program that embeds the interpreter uses to give its own rust
+//! functions to lua, as globals or grouped into library tables like the
+//! standard library's, lua's `lua_register` and `luaL_register`.
+//!
+//! the functions get their arguements as `Args`, which turns them into
+//! rust values with the same errors the standard library gives, and they
+//! give back any number of values. an error they give back is a lua error
+//! that `pcall` can catch, a `RuntimeError::general` gets where it was
+//! called from in front like the library's do, and `Interpreter::throw`
+//! raises any lua value.
+
+use std::any::TypeId;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, Table, LuaString, AnyUserData, UserData, UserDataMethods};
+use crate::error::runtime::RuntimeError;
+use crate::stdlib;
+
+/// the arguements a rust function was called with, counted from `1` like
+/// lua does. the errors name the function they were given to.
+pub struct Args {
+    function : Rc<str>,
+    values : Vec<Value>,
+}
+
+impl Args {
+    pub fn new(function : &str, values : Vec<Value>) -> Args {
+        Args { function : Rc::from(function), values }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn get(&self, n : usize) -> Value {
+        //! the `n`th arguement, the missing ones are `nil`
+
+        stdlib::arg(&self.values, n)
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+
+    pub fn any(&self, n : usize) -> Result<Value,Error> {
+        //! the arguement, which can be anything but has to be there
+
+        stdlib::check_any(&self.values, n, &self.function)
+    }
+
+    pub fn number(&self, n : usize) -> Result<f64,Error> {
+        //! strings that look like numbers are numbers too
+
+        stdlib::check_number(&self.values, n, &self.function)
+    }
+
+    pub fn integer(&self, n : usize) -> Result<i64,Error> {
+        //! the number cut down to a whole number
+
+        stdlib::check_integer(&self.values, n, &self.function)
+    }
+
+    pub fn string(&self, n : usize) -> Result<LuaString,Error> {
+        //! numbers are turned into strings
+
+        stdlib::check_string(&self.values, n, &self.function)
+    }
+
+    pub fn boolean(&self, n : usize) -> bool {
+        //! anything but `nil` and `false` is true, so it can't fail
+
+        self.get(n).is_truthy()
+    }
+
+    pub fn table(&self, n : usize) -> Result<Rc<RefCell<Table>>,Error> {
+        stdlib::check_table(&self.values, n, &self.function)
+    }
+
+    pub fn function(&self, n : usize) -> Result<Value,Error> {
+        //! anything that is a function, written in lua or in rust
+
+        match self.values.get(n - 1) {
+            Some(value) if value.is_function() => Ok(value.clone()),
+            _ => Err(self.type_error(n, "function")),
+        }
+    }
+
+    pub fn userdata(&self, n : usize) -> Result<Rc<AnyUserData>,Error> {
+        match self.values.get(n - 1) {
+            Some(Value::UserData(data)) => Ok(data.clone()),
+            _ => Err(self.type_error(n, "userdata")),
+        }
+    }
+
+    pub fn opt_number(&self, n : usize, default : f64) -> Result<f64,Error> {
+        //! `default` if the arguement is `nil` or missing
+
+        match self.get(n) {
+            Value::Nil => Ok(default),
+            _ => self.number(n),
+        }
+    }
+
+    pub fn opt_integer(&self, n : usize, default : i64) -> Result<i64,Error> {
+        stdlib::opt_integer(&self.values, n, &self.function, default)
+    }
+
+    pub fn opt_string(&self, n : usize, default : &str) -> Result<LuaString,Error> {
+        match self.get(n) {
+            Value::Nil => Ok(LuaString::from(default)),
+            _ => self.string(n),
+        }
+    }
+
+    pub fn error(&self, n : usize, message : &str) -> Error {
+        //! an error about the arguement, like
+        //! `bad argument #1 to 'f' (out of range)`
+
+        stdlib::arg_error(n, &self.function, message)
+    }
+
+    pub fn type_error(&self, n : usize, expected : &str) -> Error {
+        //! the arguement isn't the type it should be, like
+        //! `bad argument #1 to 'f' (number expected, got nil)`
+
+        stdlib::type_error(&self.values, n, &self.function, expected)
+    }
+
+    pub(crate) fn split_self(mut self) -> (Value, Args) {
+        //! takes the object off the front of a method's arguements, so
+        //! the rest are counted from `1`
+
+        let object = match self.values.is_empty() {
+            true => Value::Nil,
+            false => self.values.remove(0),
+        };
+        (object, self)
+    }
+}
+
+/// a table of rust functions, made by `Interpreter::register_library`
+pub struct Library {
+    table : Rc<RefCell<Table>>,
+    // what the functions in it are called in errors, `name.function`
+    name : String,
+}
+
+impl Library {
+    pub fn function<F>(&self, name : &str, function : F) -> &Library
+        where F : Fn(&Interpreter, Args) -> Result<Vec<Value>,Error> + 'static {
+        //! adds a function to the library
+
+        let full_name = format!("{}.{}", self.name, name);
+        self.table.borrow_mut().set_str(name, native_function(&full_name, function));
+        self
+    }
+
+    pub fn set(&self, name : &str, value : Value) -> &Library {
+        //! adds anything else to the library, like a constant
+
+        self.table.borrow_mut().set_str(name, value);
+        self
+    }
+
+    pub fn table(&self) -> Rc<RefCell<Table>> {
+        self.table.clone()
+    }
+}
+
+impl Interpreter {
+    pub fn register<F>(&self, name : &str, function : F)
+        where F : Fn(&Interpreter, Args) -> Result<Vec<Value>,Error> + 'static {
+        //! makes the rust function a global
+
+        self.set_global(name, native_function(name, function));
+    }
+
+    pub fn register_library(&self, name : &str) -> Result<Library,Error> {
+        //! the library table with the name, where functions can be added.
+        //! if there is already a module with the name it gets added to,
+        //! otherwise a new table is made where the dotted name says and
+        //! it can be `require`d like any other module.
+
+        let key = Value::from(name);
+        let loaded = match self.get_global("package") {
+            Value::Table(package) => match package.borrow().get_str("loaded") {
+                Value::Table(loaded) => Some(loaded),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let existing = loaded.as_ref().map(|loaded| loaded.borrow().get(&key));
+        let table = match existing {
+            Some(Value::Table(table)) => table,
+            _ => {
+                let table = stdlib::package::find_table(&self.globals(), &LuaString::from(name))
+                    .ok_or_else(|| RuntimeError::general(&format!("name conflict for module '{}'", name)))?;
+                if let Some(loaded) = loaded {
+                    loaded.borrow_mut().set(key, Value::Table(table.clone()))?;
+                }
+                table
+            },
+        };
+
+        Ok(Library { table, name : name.to_string() })
+    }
+
+    pub fn create_userdata<T : UserData>(&self, data : T) -> Result<Value,Error> {
+        //! gives the rust value to lua, with the methods and metamethods
+        //! its type adds. the metatable is made the first time and shared
+        //! by all the values of the type after that.
+
+        let metatable = self.userdata_metatable::<T>()?;
+        let data = Rc::new(AnyUserData::from_userdata(data));
+        self.set_metatable(&Value::UserData(data.clone()), Some(metatable))?;
+
+        let value = Value::UserData(data);
+        self.track(&value)?;
+        Ok(value)
+    }
+
+    fn userdata_metatable<T : UserData>(&self) -> Result<Rc<RefCell<Table>>,Error> {
+        if let Some(metatable) = self.userdata_metatables.borrow().get(&TypeId::of::<T>()) {
+            return Ok(metatable.clone());
+        }
+
+        let mut methods = UserDataMethods::new();
+        T::add_methods(&mut methods);
+        let metatable = Rc::new(RefCell::new(methods.into_metatable()));
+        self.track(&Value::Table(metatable.clone()))?;
+
+        self.userdata_metatables.borrow_mut().insert(TypeId::of::<T>(), metatable.clone());
+        Ok(metatable)
+    }
+}
+
+pub(crate) fn native_function<F>(name : &str, function : F) -> Value
+    where F : Fn(&Interpreter, Args) -> Result<Vec<Value>,Error> + 'static {
+    let name : Rc<str> = Rc::from(name);
+
+    Value::NativeClosure(Rc::new(move |interpreter : &Interpreter, values : Vec<Value>| {
+        function(interpreter, Args { function : name.clone(), values })
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::interpreter::Interpreter;
+    use crate::value::{Value, UserData, UserDataMethods};
+    use crate::error::runtime::RuntimeError;
+
+    struct Vector {
+        x : f64,
+        y : f64,
+    }
+
+    impl UserData for Vector {
+        fn add_methods(methods : &mut UserDataMethods<Vector>) {
+            methods.method("length", |_, vector, _| {
+                Ok(vec![Value::Number(vector.x.hypot(vector.y))])
+            });
+            methods.method_mut("scale", |_, vector, args| {
+                let n = args.number(1)?;
+                vector.x *= n;
+                vector.y *= n;
+                Ok(Vec::new())
+            });
+            methods.method_mut("each", |interpreter, vector, args| {
+                let function = args.function(1)?;
+                interpreter.call(&function, vec![Value::Number(vector.x), Value::Number(vector.y)])
+            });
+            methods.field_getter("x", |_, vector| Ok(Value::Number(vector.x)));
+            methods.field_setter("x", |_, vector, value| {
+                vector.x = value.to_number().ok_or_else(|| RuntimeError::general("x has to be a number"))?;
+                Ok(())
+            });
+
+            methods.meta_function("__add", |interpreter, args| {
+                let (a, b) = (args.userdata(1)?, args.userdata(2)?);
+                let (a, b) = (a.borrow::<Vector>()?, b.borrow::<Vector>()?);
+                Ok(vec![interpreter.create_userdata(Vector { x : a.x + b.x, y : a.y + b.y })?])
+            });
+            methods.meta_method("__tostring", |_, vector, _| {
+                Ok(vec![Value::from(format!("({}, {})", vector.x, vector.y).as_str())])
+            });
+        }
+    }
+
+    struct Callback {
+        function : Value,
+    }
+
+    impl UserData for Callback {
+        fn trace(&self, visit : &mut dyn FnMut(&Value)) {
+            visit(&self.function);
+        }
+    }
+
+    struct Pair<T> {
+        _items : (T, T),
+    }
+
+    impl<T : 'static> UserData for Pair<T> { }
+
+    #[test]
+    pub fn rust_functions() {
+        let interpreter = Interpreter::new();
+
+        interpreter.register("divide", |_, args| {
+            let (a, b) = (args.number(1)?, args.number(2)?);
+            if b == 0.0 {
+                return Err(RuntimeError::general("division by zero"));
+            }
+            Ok(vec![Value::Number((a / b).floor()), Value::Number(a % b)])
+        });
+
+        let engine = interpreter.register_library("engine.util").unwrap();
+        engine.set("version", Value::from("1.0"))
+            .function("greet", |_, args| {
+                let name = args.opt_string(1, "world")?;
+                Ok(vec![Value::from(format!("hello {}", name).as_str())])
+            });
+
+        let code = r#"
+            local q, r = divide(7, 2)
+            local _, zero = pcall(function() return divide(1, 0) end)
+            local _, bad = pcall(divide, "x")
+            local _, missing = pcall(engine.util.greet, {})
+            return q, r, zero, bad, engine.util.greet(), require("engine.util").version, missing
+        "#;
+
+        let values = interpreter.run(code, Some("testfile.lua")).unwrap().into_values();
+        assert_eq!(values, vec![
+            Value::Number(3.0),
+            Value::Number(1.0),
+            Value::from("testfile.lua:3: division by zero"),
+            Value::from("bad argument #1 to 'divide' (number expected, got string)"),
+            Value::from("hello world"),
+            Value::from("1.0"),
+            Value::from("bad argument #1 to 'engine.util.greet' (string expected, got table)"),
+        ]);
+
+        interpreter.set_global("taken", Value::Number(1.0));
+        assert!(interpreter.register_library("taken").is_err());
+    }
+
+    #[test]
+    pub fn rust_userdata() {
+        let interpreter = Interpreter::new();
+
+        interpreter.register("vector", |interpreter, args| {
+            let vector = Vector { x : args.number(1)?, y : args.number(2)? };
+            Ok(vec![interpreter.create_userdata(vector)?])
+        });
+
+        let code = r#"
+            local v = vector(3, 4)
+            v:scale(2)
+            local length = v:length()
+            v.x = 0
+            local w = v + vector(1, 1)
+            local _, unknown = pcall(function() v.z = 1 end)
+            local _, borrowed = pcall(v.each, v, function() return v:length() end)
+            local _, bad = pcall(v.length, 1)
+            return length, v.x, v.y, tostring(w), unknown, borrowed, bad, getmetatable(v) == getmetatable(w)
+        "#;
+
+        let values = interpreter.run(code, Some("testfile.lua")).unwrap().into_values();
+        assert_eq!(values, vec![
+            Value::Number(10.0),
+            Value::Number(0.0),
+            Value::Nil,
+            Value::from("(1, 9)"),
+            Value::from("testfile.lua:7: attempt to set unknown field 'z' of 'Vector'"),
+            Value::from("testfile.lua:8: 'Vector' is already mutably borrowed"),
+            Value::from("calling 'length' on bad self (Vector expected, got number)"),
+            Value::Boolean(true),
+        ]);
+
+        assert_eq!(<Pair<Vector> as UserData>::type_name(), "Pair");
+        assert_eq!(<Pair<Pair<u8>> as UserData>::type_name(), "Pair");
+    }
+
+    #[test]
+    pub fn userdata_tracing() {
+        let interpreter = Interpreter::new();
+
+        interpreter.register("callback", |interpreter, args| {
+            let callback = Callback { function : args.function(1)? };
+            Ok(vec![interpreter.create_userdata(callback)?])
+        });
+
+        // the userdata holds the function and the function holds the
+        // userdata, which is only collected if the data is traced
+        let code = r#"
+            local weak = setmetatable({}, {__mode = "v"})
+            do
+                local c
+                c = callback(function() return c end)
+                weak[1] = c
+            end
+            collectgarbage()
+            return weak[1]
+        "#;
+
+        let values = interpreter.run(code, Some("testfile.lua")).unwrap().into_values();
+        assert_eq!(values, vec![Value::Nil]);
+    }
+}
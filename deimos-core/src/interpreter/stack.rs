@@ -0,0 +1,281 @@
+//! the functions that are running right now, kept so errors can say where
+//! they happened (`error('x', 2)`) and so uncaught errors can show a
+//! traceback like lua does.
+//!
+//! also makes sure there is enough of rust's stack to call deeper, each
+//! lua function call is a few rust calls so deep code would overflow it.
+
+use std::rc::Rc;
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::interpreter::vm::Frame;
+use crate::value::{Value, LuaFunction, Upvalue};
+use crate::error::runtime::{RuntimeError, ErrorValue};
+use crate::error::scanner::ScannerError;
+use crate::error::parser::ParserError;
+
+/// how much stack a lua function needs to have left when it is called
+const RED_ZONE : usize = 128 * 1024;
+
+/// how much more stack we get when there isn't enough
+const STACK_SEGMENT : usize = 1024 * 1024;
+
+/// a function that is running
+pub(crate) struct CallInfo {
+    // the function, if it was written in lua
+    function : Option<Rc<LuaFunction>>,
+    // the instruction it is on, it is updated whenever it calls out
+    pc : usize,
+    // if it replaced the function that called it, which is gone now
+    tail_call : bool,
+    // the registers, once a lua function has started running
+    frame : Option<Rc<Frame>>,
+}
+
+impl CallInfo {
+    pub fn lua(function : &Rc<LuaFunction>) -> CallInfo {
+        CallInfo { function : Some(function.clone()), pc : 0, tail_call : false, frame : None }
+    }
+
+    pub fn tail(function : &Rc<LuaFunction>) -> CallInfo {
+        CallInfo { function : Some(function.clone()), pc : 0, tail_call : true, frame : None }
+    }
+
+    pub fn native() -> CallInfo {
+        CallInfo { function : None, pc : 0, tail_call : false, frame : None }
+    }
+
+    pub(crate) fn trace(&self, visit : &mut dyn FnMut(&Value), visit_upvalue : &mut dyn FnMut(&Upvalue)) -> Option<()> {
+        //! goes through everything the call is holding on to, `None` if
+        //! some of it can't be looked at right now
+
+        if let Some(function) = &self.function {
+            visit(&Value::Function(function.clone()));
+        }
+
+        match &self.frame {
+            Some(frame) => frame.trace(visit, visit_upvalue),
+            None => Some(()),
+        }
+    }
+
+    fn line(&self) -> usize {
+        self.function.as_ref().map(|function| function.proto().line(self.pc)).unwrap_or(0)
+    }
+
+    fn describe(&self, line : usize, name : Option<String>) -> String {
+        //! the line in the traceback for this function
+
+        let proto = self.function.as_ref().map(|function| function.proto());
+
+        let place = match proto {
+            Some(proto) => format!("{}:{}:", proto.source.name, line),
+            None => String::from("[C]:"),
+        };
+
+        match (name, proto) {
+            (Some(name), _) => format!("{} in function '{}'", place, name),
+            (None, None) => format!("{} ?", place),
+            (None, Some(proto)) if proto.is_main() => format!("{} in main chunk", place),
+            (None, Some(proto)) => format!("{} in function <{}:{}>", place, proto.source.name, proto.line_defined),
+        }
+    }
+}
+
+impl Interpreter {
+    pub fn throw(&self, value : Value) -> Error {
+        //! makes an error out of any lua value, like `error` does. the
+        //! value is given back when the error is caught by `pcall`.
+
+        let message = match value {
+            Value::String(ref string) => string.to_string_lossy(),
+            Value::Number(_) => format!("{}", value),
+            Value::Nil => String::from("nil"),
+            ref value => format!("(error object is a {} value)", value.type_name()),
+        };
+
+        let id = self.thrown_count.get() + 1;
+        self.thrown_count.set(id);
+        *self.thrown.borrow_mut() = Some((id, value));
+
+        RuntimeError::VALUE(ErrorValue { message, id }).into()
+    }
+
+    pub fn error_value(&self, error : &Error) -> Value {
+        //! the lua value of the error, the message for errors that come
+        //! from reading or running the code or the value if it came
+        //! from `error`
+
+        match error.downcast_ref::<RuntimeError>().map(|error| error.inner()) {
+            Some(RuntimeError::VALUE(value)) => {
+                let mut thrown = self.thrown.borrow_mut();
+                match *thrown {
+                    Some((id, _)) if id == value.id => thrown.take().map(|(_, value)| value).unwrap_or(Value::Nil),
+                    _ => Value::from(value.message.as_str()),
+                }
+            },
+            Some(error) => Value::from(error.message().as_str()),
+            None => match (error.downcast_ref::<ScannerError>(), error.downcast_ref::<ParserError>()) {
+                (Some(error), _) => Value::from(error.message().as_str()),
+                (_, Some(error)) => Value::from(error.message().as_str()),
+                _ => Value::from(format!("{}", error).as_str()),
+            },
+        }
+    }
+
+    pub fn position(&self, level : usize) -> Option<String> {
+        //! where the function `level` calls up is in the code, like
+        //! `file.lua:10:`. level `1` is whatever called the rust function
+        //! that is running now, functions written in rust don't have a
+        //! position.
+
+        let stack = self.stack.borrow();
+        let info = stack.len().checked_sub(level + 1).and_then(|i| stack.get(i))?;
+
+        info.function.as_ref().map(|function| format!("{}:{}:", function.proto().source.name, info.line()))
+    }
+
+    pub fn function_at(&self, level : usize) -> Option<Option<Rc<LuaFunction>>> {
+        //! the lua function `level` calls up, counted the same way as
+        //! `position`. gives `Some(None)` if the function there was
+        //! written in rust, and `None` if the stack isn't that deep.
+
+        let stack = self.stack.borrow();
+        let info = stack.len().checked_sub(level + 1).and_then(|i| stack.get(i))?;
+
+        Some(info.function.clone())
+    }
+
+    pub fn traceback(&self) -> String {
+        //! the functions that are running, from the newest to the oldest
+
+        let stack = self.stack.borrow();
+        traceback(&stack, None)
+    }
+
+    // PRIVATE FUNCTIONS /////////////////////////////////////
+
+    pub(crate) fn save_pc(&self, pc : usize) {
+        //! the instruction the running function is on, it is only kept
+        //! up to date when the function calls out
+
+        if let Some(info) = self.stack.borrow_mut().last_mut() {
+            info.pc = pc;
+        }
+    }
+
+    pub(crate) fn set_frame(&self, frame : &Rc<Frame>) {
+        //! the registers of the lua function that is running
+
+        if let Some(info) = self.stack.borrow_mut().last_mut() {
+            info.frame = Some(frame.clone());
+        }
+    }
+
+    pub(crate) fn replace_call(&self, info : CallInfo) {
+        //! a tail call, the function that is running is replaced
+
+        if let Some(last) = self.stack.borrow_mut().last_mut() {
+            *last = info;
+        }
+    }
+
+    pub(crate) fn with_call<T, F : FnOnce() -> Result<T,Error>>(&self, info : CallInfo, function : F) -> Result<T,Error> {
+        //! runs the function as a new level of the stack. an error that
+        //! comes out gets the traceback of where it happened.
+
+        self.stack.borrow_mut().push(info);
+        let result = function();
+
+        let result = match result {
+            Err(error) => Err(self.add_traceback(error)),
+            result => result,
+        };
+
+        self.stack.borrow_mut().pop();
+        result
+    }
+
+    pub(crate) fn with_stack_space<T, F : FnOnce() -> T>(&self, function : F) -> T {
+        //! runs the function, on a new piece of stack if we are close to
+        //! running out. each thread has its own stack so we keep track of
+        //! where it ends ourselves.
+
+        if stack_position().saturating_sub(self.stack_limit.get()) > RED_ZONE {
+            return function();
+        }
+
+        stacker::grow(STACK_SEGMENT, || {
+            let limit = self.stack_limit.replace(stack_position().saturating_sub(STACK_SEGMENT));
+            let result = function();
+            self.stack_limit.set(limit);
+            result
+        })
+    }
+
+    fn add_traceback(&self, mut error : Error) -> Error {
+        //! the error is changed where it is, making a new one would get a
+        //! new rust backtrace at every level it goes through
+
+        if let Some(runtime) = error.downcast_mut::<RuntimeError>() {
+            if let RuntimeError::TRACE(..) = runtime {
+                return error;
+            }
+
+            // runtime errors know what line they happened on
+            let line = match runtime {
+                RuntimeError::EXEC(ref info) => Some(info.line_number),
+                _ => None,
+            };
+
+            let traceback = traceback(&self.stack.borrow(), line);
+            let inner = std::mem::replace(runtime, RuntimeError::GEN(String::new()));
+            *runtime = RuntimeError::TRACE(Box::new(inner), traceback);
+        }
+
+        error
+    }
+}
+
+pub(crate) fn main_stack_limit() -> usize {
+    //! where the stack of the thread we are on ends, if we can't tell
+    //! then we say there isn't any left so it gets more right away
+
+    let position = stack_position();
+    position.saturating_sub(stacker::remaining_stack().unwrap_or(0))
+}
+
+fn stack_position() -> usize {
+    //! about where the top of the stack is right now
+
+    let here = 0u8;
+    &here as *const u8 as usize
+}
+
+fn traceback(stack : &[CallInfo], line : Option<usize>) -> String {
+    let mut traceback = String::from("stack traceback:");
+
+    for (i, info) in stack.iter().enumerate().rev() {
+        let line = match (i + 1 == stack.len(), line) {
+            (true, Some(line)) if info.function.is_some() => line,
+            _ => info.line(),
+        };
+
+        // the name is what the function that called it calls it
+        let name = match (info.tail_call, i.checked_sub(1).and_then(|caller| stack.get(caller))) {
+            (false, Some(CallInfo { function : Some(caller), pc, .. })) => caller.proto().function_name(*pc).map(|(_, name)| name),
+            _ => None,
+        };
+
+        traceback.push_str("\n\t");
+        traceback.push_str(&info.describe(line, name));
+
+        if info.tail_call {
+            traceback.push_str("\n\t(tail call): ?");
+        }
+    }
+
+    traceback
+}
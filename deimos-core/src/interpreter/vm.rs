@@ -0,0 +1,721 @@
+//! the virtual machine that runs the compiled functions. it works like
+//! lua's own, each function call gets a frame of registers that the
+//! instructions read from and write to.
+//!
+//! a register that a function captured is shared with it until the block
+//! that declared the local ends, then the function keeps the value and the
+//! register goes back to being a plain value.
+
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+
+use failure::Error;
+
+use crate::interpreter::{Interpreter, Arithmetic};
+use crate::interpreter::stack::CallInfo;
+use crate::value::{Value, LuaFunction, LuaString, Table, Upvalue};
+use crate::error::runtime::RuntimeError;
+use crate::bytecode::{Proto, VARARG_ISVARARG, VARARG_NEEDSARG};
+use crate::bytecode::instruction::{OpCode, FIELDS_PER_FLUSH, is_constant, constant_index, fb_to_int};
+
+/// the most room `NEWTABLE` makes ahead of time, bigger tables grow
+const MAX_PRESIZE : usize = 1 << 16;
+
+#[derive(Clone)]
+enum Register {
+    Value(Value),
+    // a local that a function captured
+    Captured(Upvalue),
+}
+
+/// a function that is running, its registers are shared with its
+/// `CallInfo` so the collector can see them while the thread it is
+/// running on is suspended
+pub(crate) struct Frame {
+    function : Rc<LuaFunction>,
+    registers : RefCell<Vec<Register>>,
+    // the extra arguements, `...`
+    varargs : Vec<Value>,
+    // the end of the values left by the last call or `...` that gave
+    // all of its values
+    top : Cell<usize>,
+    // the instruction that is running
+    pc : Cell<usize>,
+    // the register of the function it is calling, the call has its own
+    // copy of it
+    calling : Cell<Option<usize>>,
+}
+
+/// how a function stopped running
+enum Exit {
+    Return(Vec<Value>),
+    // it gives back whatever the function gives back
+    TailCall(Rc<LuaFunction>, Vec<Value>),
+}
+
+impl Frame {
+    fn new(function : Rc<LuaFunction>, args : Vec<Value>) -> Frame {
+        //! the arguements are the first registers, anything after them is
+        //! `...` or the `arg` table
+
+        let proto = function.proto();
+        let mut registers = vec![Register::Value(Value::Nil); proto.max_stack.max(proto.parameters + 1)];
+        let mut args = args.into_iter();
+
+        for register in registers.iter_mut().take(proto.parameters) {
+            *register = Register::Value(args.next().unwrap_or(Value::Nil));
+        }
+
+        let varargs : Vec<Value> = match proto.is_vararg & VARARG_ISVARARG {
+            0 => Vec::new(),
+            _ => args.collect(),
+        };
+
+        // the old way of getting the extra arguements
+        if proto.is_vararg & VARARG_NEEDSARG != 0 {
+            let mut arg = Table::from_values(varargs.clone());
+            arg.set_str("n", Value::Number(varargs.len() as f64));
+            registers[proto.parameters] = Register::Value(Value::from(arg));
+        }
+
+        Frame {
+            function,
+            registers : RefCell::new(registers),
+            varargs,
+            top : Cell::new(0),
+            pc : Cell::new(0),
+            calling : Cell::new(None),
+        }
+    }
+
+    pub(crate) fn trace(&self, visit : &mut dyn FnMut(&Value), visit_upvalue : &mut dyn FnMut(&Upvalue)) -> Option<()> {
+        //! goes through everything the frame is holding on to, the
+        //! function, the registers and the extra arguements. `None` if
+        //! the registers are being changed and can't be looked at
+
+        let registers = self.registers.try_borrow().ok()?;
+
+        visit(&Value::Function(self.function.clone()));
+        for register in registers.iter() {
+            match register {
+                Register::Value(value) => visit(value),
+                Register::Captured(upvalue) => visit_upvalue(upvalue),
+            }
+        }
+        for value in self.varargs.iter() {
+            visit(value);
+        }
+
+        // a function that is being called from a register is held by the
+        // call too, unless it is a local a function captured which could
+        // have been changed since
+        if let Some(Register::Value(value)) = self.calling.get().and_then(|register| registers.get(register)) {
+            visit(value);
+        }
+
+        Some(())
+    }
+
+    fn get(&self, register : usize) -> Value {
+        match &self.registers.borrow()[register] {
+            Register::Value(value) => value.clone(),
+            Register::Captured(upvalue) => upvalue.borrow().clone(),
+        }
+    }
+
+    fn set(&self, register : usize, value : Value) {
+        let mut registers = self.registers.borrow_mut();
+        if register >= registers.len() {
+            registers.resize(register + 1, Register::Value(Value::Nil));
+        }
+
+        match &mut registers[register] {
+            Register::Value(old) => *old = value,
+            Register::Captured(upvalue) => *upvalue.borrow_mut() = value,
+        }
+    }
+
+    fn values(&self, from : usize, to : usize) -> Vec<Value> {
+        (from .. to).map(|register| self.get(register)).collect()
+    }
+
+    fn clear(&self, from : usize) {
+        //! in lua the function being called uses the registers after its
+        //! arguements, so nothing in them is kept alive by the caller
+
+        for register in self.registers.borrow_mut().iter_mut().skip(from) {
+            if let Register::Value(value) = register {
+                *value = Value::Nil;
+            }
+        }
+    }
+
+    fn capture(&self, register : usize) -> Upvalue {
+        //! shares the register with a function
+
+        let mut registers = self.registers.borrow_mut();
+        let register = &mut registers[register];
+        if let Register::Captured(upvalue) = register {
+            return upvalue.clone();
+        }
+
+        let value = match register {
+            Register::Value(value) => std::mem::replace(value, Value::Nil),
+            Register::Captured(_) => Value::Nil,
+        };
+
+        let upvalue = Rc::new(RefCell::new(value));
+        *register = Register::Captured(upvalue.clone());
+        upvalue
+    }
+
+    fn close(&self, from : usize) {
+        //! the locals from the register on have gone out of scope, the
+        //! functions that captured them keep them to themselves
+
+        for register in self.registers.borrow_mut().iter_mut().skip(from) {
+            if let Register::Captured(upvalue) = register {
+                let value = upvalue.borrow().clone();
+                *register = Register::Value(value);
+            }
+        }
+    }
+
+    fn call<T, F : FnOnce() -> T>(&self, register : usize, function : F) -> T {
+        //! calls the function in the register, the collector counts the
+        //! copy of it the call has while it is running
+
+        self.calling.set(Some(register));
+        let result = function();
+        self.calling.set(None);
+        result
+    }
+}
+
+impl Interpreter {
+    pub(crate) fn execute(&self, function : &Rc<LuaFunction>, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+        //! runs the lua function. a tail call runs the next function in
+        //! its place, so they can go on forever.
+
+        let mut frame = Rc::new(Frame::new(function.clone(), args));
+
+        loop {
+            self.set_frame(&frame);
+
+            match self.run_frame(&frame) {
+                Ok(Exit::Return(values)) => return Ok(values),
+                Ok(Exit::TailCall(callee, callee_args)) => {
+                    self.replace_call(CallInfo::tail(&callee));
+                    frame = Rc::new(Frame::new(callee, callee_args));
+                },
+                Err(error) => return Err(locate(frame.function.proto(), frame.pc.get(), error)),
+            }
+        }
+    }
+
+    // PRIVATE FUNCTIONS /////////////////////////////////////
+
+    fn run_frame(&self, frame : &Frame) -> Result<Exit,Error> {
+        //! the instructions of the function, one after the other
+
+        let function = &frame.function;
+        let proto = function.proto();
+        let code = &proto.code;
+        let constants = &proto.constants;
+
+        let rk = |frame : &Frame, rk : usize| match is_constant(rk) {
+            true => constants[constant_index(rk)].clone(),
+            false => frame.get(rk),
+        };
+
+        let mut pc = 0;
+
+        loop {
+            let instruction = code[pc];
+            frame.pc.set(pc);
+            pc += 1;
+
+            if self.limits.tick() {
+                self.check_limits()?;
+            }
+
+            let a = instruction.a();
+
+            match instruction.opcode() {
+                OpCode::Move => {
+                    let value = frame.get(instruction.b());
+                    frame.set(a, value);
+                },
+
+                OpCode::LoadK => frame.set(a, constants[instruction.bx()].clone()),
+
+                OpCode::LoadBool => {
+                    frame.set(a, Value::Boolean(instruction.b() != 0));
+                    if instruction.c() != 0 {
+                        pc += 1;
+                    }
+                },
+
+                OpCode::LoadNil => for register in a ..= instruction.b() {
+                    frame.set(register, Value::Nil);
+                },
+
+                OpCode::GetUpval => {
+                    let value = function.upvalues()[instruction.b()].borrow().clone();
+                    frame.set(a, value);
+                },
+
+                OpCode::GetGlobal => {
+                    let environment = Value::Table(function.environment());
+                    let value = self.get_index(proto, frame.pc.get(), &environment, &constants[instruction.bx()], None)?;
+                    frame.set(a, value);
+                },
+
+                OpCode::GetTable => {
+                    let object = frame.get(instruction.b());
+                    let key = rk(frame, instruction.c());
+                    let value = self.get_index(proto, frame.pc.get(), &object, &key, Some(instruction.b()))?;
+                    frame.set(a, value);
+                },
+
+                OpCode::SetGlobal => {
+                    let environment = Value::Table(function.environment());
+                    self.save_pc(frame.pc.get());
+                    self.set_index_with(&environment, constants[instruction.bx()].clone(), frame.get(a), &|value| type_error(proto, frame.pc.get(), None, value, "index"))?;
+                },
+
+                OpCode::SetUpval => *function.upvalues()[instruction.b()].borrow_mut() = frame.get(a),
+
+                OpCode::SetTable => {
+                    let object = frame.get(a);
+                    let key = rk(frame, instruction.b());
+                    let value = rk(frame, instruction.c());
+
+                    match object {
+                        // no metatable, so nothing else can happen
+                        Value::Table(ref table) if table.borrow().metatable().is_none() => table.borrow_mut().set(key, value)?,
+                        _ => {
+                            self.save_pc(frame.pc.get());
+                            self.set_index_with(&object, key, value, &|value| type_error(proto, frame.pc.get(), Some(a), value, "index"))?;
+                        },
+                    }
+                },
+
+                OpCode::NewTable => {
+                    // the sizes are only a hint, a loaded chunk could ask for anything
+                    let (array, hash) = (fb_to_int(instruction.b()).min(MAX_PRESIZE), fb_to_int(instruction.c()).min(MAX_PRESIZE));
+                    let table = Value::from(Table::with_capacity(array, hash));
+                    self.save_pc(frame.pc.get());
+                    self.track(&table)?;
+                    frame.set(a, table);
+                },
+
+                OpCode::SelfOp => {
+                    let object = frame.get(instruction.b());
+                    let key = rk(frame, instruction.c());
+                    frame.set(a + 1, object.clone());
+                    let method = self.get_index(proto, frame.pc.get(), &object, &key, Some(instruction.b()))?;
+                    frame.set(a, method);
+                },
+
+                op @ OpCode::Add | op @ OpCode::Sub | op @ OpCode::Mul | op @ OpCode::Div | op @ OpCode::Mod | op @ OpCode::Pow => {
+                    let left = rk(frame, instruction.b());
+                    let right = rk(frame, instruction.c());
+
+                    let arithmetic = match op {
+                        OpCode::Add => Arithmetic::Add,
+                        OpCode::Sub => Arithmetic::Sub,
+                        OpCode::Mul => Arithmetic::Mul,
+                        OpCode::Div => Arithmetic::Div,
+                        OpCode::Mod => Arithmetic::Mod,
+                        _ => Arithmetic::Pow,
+                    };
+
+                    let value = match (&left, &right) {
+                        (Value::Number(x), Value::Number(y)) => Value::Number(arithmetic.apply(*x, *y)),
+                        _ => {
+                            self.save_pc(frame.pc.get());
+                            self.arithmetic_with(arithmetic, &left, &right, &|i| match i {
+                                0 => type_error(proto, frame.pc.get(), register(instruction.b()), &left, "perform arithmetic on"),
+                                _ => type_error(proto, frame.pc.get(), register(instruction.c()), &right, "perform arithmetic on"),
+                            })?
+                        },
+                    };
+
+                    frame.set(a, value);
+                },
+
+                OpCode::Unm => {
+                    let value = match frame.get(instruction.b()) {
+                        Value::Number(number) => Value::Number(-number),
+                        value => {
+                            self.save_pc(frame.pc.get());
+                            self.arithmetic_with(Arithmetic::Unm, &value, &value,
+                                &|_| type_error(proto, frame.pc.get(), Some(instruction.b()), &value, "perform arithmetic on"))?
+                        },
+                    };
+
+                    frame.set(a, value);
+                },
+
+                OpCode::Not => {
+                    let value = frame.get(instruction.b());
+                    frame.set(a, Value::Boolean(!value.is_truthy()));
+                },
+
+                OpCode::Len => {
+                    let value = frame.get(instruction.b());
+                    self.save_pc(frame.pc.get());
+                    let length = self.length_with(&value, &|value| type_error(proto, frame.pc.get(), Some(instruction.b()), value, "get length of"))?;
+                    frame.set(a, length);
+                },
+
+                OpCode::Concat => {
+                    self.save_pc(frame.pc.get());
+                    self.concat_registers(proto, frame, instruction.b(), instruction.c())?;
+                    let value = frame.get(instruction.b());
+                    frame.set(a, value);
+                    self.check_garbage()?;
+                },
+
+                OpCode::Jmp => pc = jump(pc, instruction.sbx()),
+
+                op @ OpCode::Eq | op @ OpCode::Lt | op @ OpCode::Le => {
+                    let left = rk(frame, instruction.b());
+                    let right = rk(frame, instruction.c());
+
+                    let result = match (op, &left, &right) {
+                        (OpCode::Eq, Value::Number(x), Value::Number(y)) => x == y,
+                        (OpCode::Lt, Value::Number(x), Value::Number(y)) => x < y,
+                        (OpCode::Le, Value::Number(x), Value::Number(y)) => x <= y,
+                        _ => {
+                            self.save_pc(frame.pc.get());
+                            match op {
+                                OpCode::Eq => self.equals(&left, &right)?,
+                                OpCode::Lt => self.less_than(&left, &right)?,
+                                _ => self.less_equal(&left, &right)?,
+                            }
+                        },
+                    };
+
+                    // the next instruction is the jump taken when it is
+                    // what we are testing for
+                    pc = match result == (a != 0) {
+                        true => jump(pc + 1, code[pc].sbx()),
+                        false => pc + 1,
+                    };
+                },
+
+                OpCode::Test => {
+                    pc = match frame.get(a).is_truthy() == (instruction.c() != 0) {
+                        true => jump(pc + 1, code[pc].sbx()),
+                        false => pc + 1,
+                    };
+                },
+
+                OpCode::TestSet => {
+                    let value = frame.get(instruction.b());
+                    pc = match value.is_truthy() == (instruction.c() != 0) {
+                        true => {
+                            frame.set(a, value);
+                            jump(pc + 1, code[pc].sbx())
+                        },
+                        false => pc + 1,
+                    };
+                },
+
+                OpCode::Call => {
+                    let end = match instruction.b() {
+                        0 => frame.top.get(),
+                        b => a + b,
+                    };
+
+                    let callee = frame.get(a);
+                    let args = frame.values(a + 1, end);
+                    frame.clear(end);
+
+                    self.save_pc(frame.pc.get());
+                    let values = match frame.call(a, || self.call_value(&callee, args))? {
+                        Some(values) => values,
+                        None => return Err(type_error(proto, frame.pc.get(), Some(a), &callee, "call")),
+                    };
+
+                    match instruction.c() {
+                        // all of them
+                        0 => {
+                            frame.top.set(a + values.len());
+                            for (i, value) in values.into_iter().enumerate() {
+                                frame.set(a + i, value);
+                            }
+                        },
+                        c => {
+                            let mut values = values.into_iter();
+                            for i in 0 .. c - 1 {
+                                frame.set(a + i, values.next().unwrap_or(Value::Nil));
+                            }
+                        },
+                    }
+                },
+
+                OpCode::TailCall => {
+                    let end = match instruction.b() {
+                        0 => frame.top.get(),
+                        b => a + b,
+                    };
+
+                    let callee = frame.get(a);
+                    let args = frame.values(a + 1, end);
+
+                    if let Value::Function(callee) = callee {
+                        return Ok(Exit::TailCall(callee, args));
+                    }
+
+                    // rust functions don't have a frame to replace
+                    self.save_pc(frame.pc.get());
+                    return match frame.call(a, || self.call_value(&callee, args))? {
+                        Some(values) => Ok(Exit::Return(values)),
+                        None => Err(type_error(proto, frame.pc.get(), Some(a), &callee, "call")),
+                    };
+                },
+
+                OpCode::Return => {
+                    let end = match instruction.b() {
+                        0 => frame.top.get(),
+                        b => a + b - 1,
+                    };
+
+                    return Ok(Exit::Return(frame.values(a, end)));
+                },
+
+                OpCode::ForLoop => {
+                    let (index, limit, step) = match (frame.get(a), frame.get(a + 1), frame.get(a + 2)) {
+                        (Value::Number(index), Value::Number(limit), Value::Number(step)) => (index + step, limit, step),
+                        // only a loaded chunk could get here without going through `FORPREP`
+                        _ => return Err(RuntimeError::general("'for' values must be numbers")),
+                    };
+
+                    let keep_going = match step > 0.0 {
+                        true => index <= limit,
+                        false => limit <= index,
+                    };
+
+                    if keep_going {
+                        pc = jump(pc, instruction.sbx());
+                        frame.set(a, Value::Number(index));
+                        frame.set(a + 3, Value::Number(index));
+                    }
+                },
+
+                OpCode::ForPrep => {
+                    let mut numbers = [0.0; 3];
+                    for (i, what) in ["initial value", "limit", "step"].iter().enumerate() {
+                        match frame.get(a + i).to_number() {
+                            Some(number) => numbers[i] = number,
+                            None => return Err(RuntimeError::general(&format!("'for' {} must be a number", what))),
+                        }
+                    }
+
+                    frame.set(a, Value::Number(numbers[0] - numbers[2]));
+                    frame.set(a + 1, Value::Number(numbers[1]));
+                    frame.set(a + 2, Value::Number(numbers[2]));
+                    pc = jump(pc, instruction.sbx());
+                },
+
+                OpCode::TForLoop => {
+                    let callee = frame.get(a);
+                    let args = vec![frame.get(a + 1), frame.get(a + 2)];
+
+                    self.save_pc(frame.pc.get());
+                    let values = match frame.call(a, || self.call_value(&callee, args))? {
+                        Some(values) => values,
+                        None => return Err(type_error(proto, frame.pc.get(), None, &callee, "call")),
+                    };
+
+                    let mut values = values.into_iter();
+                    for i in 0 .. instruction.c() {
+                        frame.set(a + 3 + i, values.next().unwrap_or(Value::Nil));
+                    }
+
+                    // the loop goes on until the first value is `nil`
+                    pc = match frame.get(a + 3) {
+                        Value::Nil => pc + 1,
+                        control => {
+                            frame.set(a + 2, control);
+                            jump(pc + 1, code[pc].sbx())
+                        },
+                    };
+                },
+
+                OpCode::SetList => {
+                    let count = match instruction.b() {
+                        0 => frame.top.get().saturating_sub(a + 1),
+                        b => b,
+                    };
+
+                    // a big `C` is in the next instruction
+                    let group = match instruction.c() {
+                        0 => {
+                            pc += 1;
+                            code[pc - 1].0 as usize
+                        },
+                        c => c,
+                    };
+
+                    if let Value::Table(table) = frame.get(a) {
+                        let mut table = table.borrow_mut();
+                        let offset = (group - 1) * FIELDS_PER_FLUSH;
+                        // the nils in the list are kept in the array, so
+                        // `#` sees the same border lua does
+                        table.resize_array(offset + count);
+                        for i in 1 ..= count {
+                            table.set(Value::Number((offset + i) as f64), frame.get(a + i))?;
+                        }
+                    }
+                },
+
+                OpCode::Close => frame.close(a),
+
+                OpCode::Closure => {
+                    let child = proto.protos[instruction.bx()].clone();
+
+                    // the instructions after it say what it captures
+                    let mut upvalues = Vec::with_capacity(child.upvalues);
+                    for _ in 0 .. child.upvalues {
+                        let pseudo = code[pc];
+                        pc += 1;
+
+                        upvalues.push(match pseudo.opcode() {
+                            OpCode::Move => frame.capture(pseudo.b()),
+                            _ => function.upvalues()[pseudo.b()].clone(),
+                        });
+                    }
+
+                    let closure = Value::Function(Rc::new(LuaFunction::new(child, upvalues, function.environment())));
+                    self.save_pc(frame.pc.get());
+                    self.track(&closure)?;
+                    frame.set(a, closure);
+                },
+
+                OpCode::VarArg => match instruction.b() {
+                    0 => {
+                        frame.top.set(a + frame.varargs.len());
+                        for i in 0 .. frame.varargs.len() {
+                            let value = frame.varargs[i].clone();
+                            frame.set(a + i, value);
+                        }
+                    },
+                    b => for i in 0 .. b - 1 {
+                        let value = frame.varargs.get(i).cloned().unwrap_or(Value::Nil);
+                        frame.set(a + i, value);
+                    },
+                },
+            }
+        }
+    }
+
+    fn get_index(&self, proto : &Proto, pc : usize, object : &Value, key : &Value, register : Option<usize>) -> Result<Value,Error> {
+        //! `object[key]`, the register is where the object came from
+
+        if let Value::Table(table) = object {
+            let value = table.borrow().get(key);
+            if !value.is_nil() {
+                return Ok(value);
+            }
+        }
+
+        self.save_pc(pc);
+        self.index_with(object, key, &|value| type_error(proto, pc, register, value, "index"))
+    }
+
+    fn concat_registers(&self, proto : &Proto, frame : &Frame, first : usize, last : usize) -> Result<(),Error> {
+        //! joins the values from the first register to the last one, from
+        //! the end so the metamethods see them in the order lua does. the
+        //! result is left in the first register.
+
+        let mut top = last + 1;
+        let mut total = last - first + 1;
+
+        while total > 1 {
+            let left = frame.get(top - 2);
+            let right = frame.get(top - 1);
+
+            let joined = match (left.to_lua_string(), right.to_lua_string()) {
+                (Some(_), Some(_)) => {
+                    // as many strings as there are in a row, all at once
+                    let mut count = 2;
+                    while count < total && frame.get(top - count - 1).to_lua_string().is_some() {
+                        count += 1;
+                    }
+
+                    let mut bytes : Vec<u8> = Vec::new();
+                    for register in top - count .. top {
+                        if let Some(string) = frame.get(register).to_lua_string() {
+                            bytes.extend_from_slice(string.as_bytes());
+                        }
+                    }
+
+                    frame.set(top - count, Value::String(LuaString::from(bytes)));
+                    count
+                },
+                _ => {
+                    let value = self.concat_with(&left, &right, &|i| match i {
+                        0 => type_error(proto, frame.pc.get(), Some(top - 2), &left, "concatenate"),
+                        _ => type_error(proto, frame.pc.get(), Some(top - 1), &right, "concatenate"),
+                    })?;
+                    frame.set(top - 2, value);
+                    2
+                },
+            };
+
+            total -= joined - 1;
+            top -= joined - 1;
+        }
+
+        Ok(())
+    }
+}
+
+fn jump(pc : usize, offset : i32) -> usize {
+    (pc as i64 + offset as i64) as usize
+}
+
+fn register(rk : usize) -> Option<usize> {
+    //! the register of a `B` or `C` arguement, if it isn't a constant
+
+    match is_constant(rk) {
+        true => None,
+        false => Some(rk),
+    }
+}
+
+fn type_error(proto : &Proto, pc : usize, register : Option<usize>, value : &Value, action : &str) -> Error {
+    //! the error when a value of the wrong type is used, names the variable
+    //! if the value came from one, like lua does.
+
+    let description = match register.and_then(|register| proto.variable_name(pc, register)) {
+        Some((kind, name)) => format!("attempt to {} {} '{}' (a {} value)", action, kind, name, value.type_name()),
+        None => format!("attempt to {} a {} value", action, value.type_name()),
+    };
+
+    RuntimeError::general(&description)
+}
+
+fn locate(proto : &Proto, pc : usize, mut error : Error) -> Error {
+    //! gives a general error a place in the code, the operations don't
+    //! know where they are being done so this is done after. the error is
+    //! changed where it is so it isn't boxed again at every level.
+
+    let inner = match error.downcast_mut::<RuntimeError>() {
+        Some(RuntimeError::TRACE(inner, _)) => &mut **inner,
+        Some(inner) => inner,
+        None => return error,
+    };
+
+    if let RuntimeError::GEN(ref description) = inner {
+        if let Ok(located) = proto.error(pc, description).downcast::<RuntimeError>() {
+            *inner = located;
+        }
+    }
+
+    error
+}
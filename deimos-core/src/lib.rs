@@ -1,6 +1,53 @@
+// these were written before clippy was part of the workflow, they're
+// left the way they are and the lints they trip over are allowed
+#[allow(clippy::len_zero, clippy::needless_borrow, clippy::op_ref, clippy::redundant_field_names,
+    clippy::expect_fun_call, clippy::needless_borrows_for_generic_args, clippy::assertions_on_constants)]
 mod scanner;
+#[allow(clippy::needless_borrow, clippy::match_like_matches_macro, clippy::op_ref, clippy::upper_case_acronyms,
+    clippy::needless_bool, clippy::bool_comparison)]
 mod token;
+#[allow(clippy::wrong_self_convention, clippy::assign_op_pattern, clippy::len_zero, clippy::format_in_format_args,
+    clippy::upper_case_acronyms, clippy::needless_borrow, clippy::doc_overindented_list_items, non_local_definitions)]
 mod error;
+#[allow(clippy::needless_lifetimes)]
 mod coderef;
 mod parser;
+#[allow(clippy::collapsible_if, clippy::needless_range_loop, clippy::len_zero, clippy::needless_lifetimes,
+    clippy::needless_bool, clippy::tabs_in_doc_comments, clippy::ptr_arg, clippy::single_match,
+    clippy::needless_borrow, clippy::needless_return, clippy::vec_box)]
 mod element;
+mod chunk;
+mod bytecode;
+mod compiler;
+mod value;
+mod interpreter;
+mod stdlib;
+mod host;
+mod repl;
+
+pub use crate::interpreter::{Interpreter, Arithmetic, Args, Library, Function};
+pub use crate::repl::Repl;
+pub use crate::host::{Host, HostFile, OpenMode, Buffering, Timezone, SystemHost, SystemFile, SandboxHost};
+pub use crate::value::{Value, ReturnValues, NativeFunction, NativeClosure, LuaString, Table, AnyUserData, UserData, UserDataMethods, Thread, ThreadStatus};
+pub use crate::value::{IntoLua, FromLua, IntoLuaMulti, FromLuaMulti};
+#[cfg(feature = "serde")]
+pub use crate::value::{Serializer, Deserializer, SerdeError};
+pub use crate::error::runtime::{RuntimeError, ErrorValue, LimitError};
+
+use failure::Error;
+
+pub fn run(code : &str) -> Result<ReturnValues,Error> {
+    //! runs the code in a new interpreter, scanner => parser => interpreter,
+    //! and gives back whatever the code returned.
+
+    Interpreter::new().run(code, None)
+}
+
+pub fn listing(code : &[u8], chunk_name : &str, full : bool) -> Result<String,Error> {
+    //! compiles the code without running it and lists the bytecode the way
+    //! `luac -l` does, `full` also lists the constants, locals and upvalues
+    //! like `luac -l -l`. the code can be a binary chunk too.
+
+    let proto = compiler::load(code, chunk_name)?;
+    Ok(bytecode::listing::listing(&proto, full))
+}
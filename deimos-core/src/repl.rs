@@ -0,0 +1,146 @@
+//! the repl keeps an interpreter around between inputs, so that anything
+//! defined in one input can be used in the next one.
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::scanner::Scanner;
+use crate::chunk::Chunk;
+use crate::token::Token;
+use crate::value::ReturnValues;
+use crate::error::scanner::ScannerError;
+use crate::error::parser::ParserError;
+
+pub struct Repl {
+    interpreter : Interpreter,
+}
+
+impl Default for Repl {
+    fn default() -> Repl {
+        Repl::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl {
+            interpreter : Interpreter::new(),
+        }
+    }
+
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+
+    pub fn add(&mut self, input : &str) -> Result<ReturnValues,Error> {
+        //! runs the input in the repl's session. like the lua repl, starting
+        //! the line with a `=` is the same as `return`, so `=x` will give 
+        //! back the value of `x`.
+
+        self.interpreter.run(&Repl::code(input), Some("stdin"))
+    }
+
+    pub fn check_for_complete_statement(input : &str) -> Result<bool,Error> {
+        //! checks if the input is a complete statement, or if we need
+        //! more lines before it can be run. `while x do` is not complete
+        //! but `while x do end` is, and neither is `x = 1 +` until the
+        //! rest of the expression comes.
+        //!
+        //! if the statement is broken in a way that more input won't fix
+        //! we say its complete so that it gets run and the error is shown.
+
+        let scanner = match Scanner::from_str(input, Some("stdin")) {
+            Ok(scanner) => scanner,
+            Err(error) => return match error.downcast_ref::<ScannerError>() {
+                // unfinished strings and comments can be finished on the next line
+                Some(ScannerError::UCS(_)) => Ok(false),
+                _ => Err(error),
+            },
+        };
+
+        let mut nesting_stack : Vec<Token> = Vec::new();
+
+        for token in scanner.tokens {
+            let token = token.unwrap();
+
+            // the same as the parser, if this token closes the last level then
+            // we don't check if it opens a new one, so the `do` in `while .. do .. end`
+            // doesn't make us look for two `end`s
+            if let Some(expected) = nesting_stack.last() {
+                if *expected == token {
+                    nesting_stack.pop();
+                    continue;
+                }
+            }
+
+            match token.matching_set() {
+                Some(mut ending) => nesting_stack.append(&mut ending),
+                None => if token == Token::End || token == Token::Until || token == Token::RightMoustache {
+                    // closing something that was never opened.
+                    return Ok(true);
+                },
+            }
+        }
+
+        if !nesting_stack.is_empty() {
+            return Ok(false);
+        }
+
+        // like the lua repl, if the parser ran out of input before it
+        // was done then the rest might be on the next line
+        match Chunk::from_str(&Repl::code(input), Some("stdin")) {
+            Err(error) => match error.downcast_ref::<ParserError>() {
+                Some(error) => Ok(!error.message().ends_with("near '<eof>'")),
+                None => Ok(true),
+            },
+            Ok(_) => Ok(true),
+        }
+    }
+
+    // PRIVATE FUNCTIONS /////////////////////////////////////
+
+    fn code(input : &str) -> String {
+        //! the input as a chunk, with a starting `=` made into `return`
+
+        let trimmed = input.trim_start();
+
+        match trimmed.starts_with('=') {
+            true => format!("return {}", &trimmed[1 ..]),
+            false => input.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::repl::Repl;
+
+    #[test]
+    pub fn complete_statements() {
+        assert!(Repl::check_for_complete_statement("x = 1\n").unwrap());
+        assert!(!Repl::check_for_complete_statement("while x do\n").unwrap());
+        assert!(Repl::check_for_complete_statement("while x do\nend\n").unwrap());
+        assert!(!Repl::check_for_complete_statement("for i = 1, 2 do if i then\n").unwrap());
+        assert!(!Repl::check_for_complete_statement("x = [[\nstill going").unwrap());
+        assert!(Repl::check_for_complete_statement("end\n").unwrap());
+
+        // expressions that are cut off wait for the rest of them
+        assert!(!Repl::check_for_complete_statement("= \"a\" ..\n").unwrap());
+        assert!(Repl::check_for_complete_statement("= \"a\" ..\n\"b\"\n").unwrap());
+        assert!(!Repl::check_for_complete_statement("x = f(1,\n").unwrap());
+        assert!(!Repl::check_for_complete_statement("local x =\n").unwrap());
+        assert!(Repl::check_for_complete_statement("x = = 1\n").unwrap());
+        assert!(Repl::check_for_complete_statement("return 1 x = 2\n").unwrap());
+    }
+
+    #[test]
+    pub fn keeps_session_state() {
+        let mut repl = Repl::new();
+
+        assert!(repl.add("x = 1 + 2").unwrap().is_empty());
+        assert_eq!(repl.add("=x").unwrap().as_user_output(), Some("3".to_string()));
+        assert_eq!(repl.add("return x .. 'a', x > 2").unwrap().as_user_output(), Some("3a\ttrue".to_string()));
+        assert_eq!(repl.add("= \"a\" ..\n\"b\"\n").unwrap().as_user_output(), Some("ab".to_string()));
+    }
+}
@@ -1,9 +1,69 @@
+use std::borrow::Cow;
+
 use failure::Error;
 use crate::token::{CodeToken, Token};
 use crate::error::{
     codeinfo::CodeInformation,
     scanner::ScannerError,};
 use crate::coderef::CodeRef::CodeRef;
+use crate::value::LuaString;
+
+/// bytes in the source that aren't valid UTF-8 are turned into characters
+/// starting here (in the private use area), so strings can get the same
+/// byte back when they are scanned.
+const RAW_BYTE_BASE : u32 = 0xF700;
+
+pub fn decode_source(bytes : &[u8]) -> Cow<'_, str> {
+    //! makes code that isn't valid UTF-8 into something the scanner can
+    //! read, like the lua test suite which is latin-1. the bytes that
+    //! aren't UTF-8 are kept so `source_bytes` can give them back.
+
+    if let Ok(code) = std::str::from_utf8(bytes) {
+        return Cow::Borrowed(code);
+    }
+
+    let mut code = String::with_capacity(bytes.len());
+    let mut rest = bytes;
+
+    while !rest.is_empty() {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                code.push_str(valid);
+                break;
+            },
+            Err(error) => {
+                let (valid, invalid) = rest.split_at(error.valid_up_to());
+                code.push_str(std::str::from_utf8(valid).unwrap_or(""));
+
+                let length = error.error_len().unwrap_or(invalid.len());
+                for byte in &invalid[.. length] {
+                    code.push(std::char::from_u32(RAW_BYTE_BASE + u32::from(*byte)).unwrap_or('?'));
+                }
+                rest = &invalid[length ..];
+            },
+        }
+    }
+
+    Cow::Owned(code)
+}
+
+pub fn source_bytes(code : &str) -> Vec<u8> {
+    //! the bytes of a piece of code the way they were before
+    //! `decode_source`
+
+    let mut bytes = Vec::with_capacity(code.len());
+    for char in code.chars() {
+        push_source_char(&mut bytes, char);
+    }
+    bytes
+}
+
+fn push_source_char(bytes : &mut Vec<u8>, char : char) {
+    match (char as u32).checked_sub(RAW_BYTE_BASE) {
+        Some(byte @ 0x80 ..= 0xFF) => bytes.push(byte as u8),
+        _ => bytes.extend_from_slice(char.encode_utf8(&mut [0; 4]).as_bytes()),
+    }
+}
 
 pub struct Scanner<'a> {
     pub file_name : String,
@@ -66,6 +126,19 @@ impl<'a> Scanner<'a> {
             return Err(ScannerError::general("can't run scan more than once."));
         }
 
+        // the first line of a file can be a `#` line (like `#!/usr/bin/lua`)
+        // which isn't lua code, so we treat it like a comment.
+        if self.raw_code.starts_with('#') {
+            let end = self.raw_code.find(['\n', '\r']).unwrap_or(self.raw_code.len());
+            self.tokens.push(CodeRef {
+                item : Token::Comment(self.raw_code[1 .. end].to_string()),
+                code_start : 0,
+                code_end : end,
+                line_number : self.line_number,
+            });
+            self.cursor_pos = end;
+        }
+
         loop {
             // requests the next token from the stream
             match self.get_next_token()? {
@@ -75,7 +148,13 @@ impl<'a> Scanner<'a> {
                 // if we get a token we can just add it to the 
                 // list of tokens
                 Some(token) => {
-                    if token.item() == Token::EOL { self.line_number += 1; }
+                    // strings and comments can span more than one line, so we count
+                    // the lines inside of them too.
+                    match token.item() {
+                        Token::EOL => self.line_number += 1,
+                        _ => self.line_number += self.raw_code[token.code_start() .. token.code_end()]
+                            .matches('\n').count(),
+                    }
                     self.tokens.push(token);
                 }
             }
@@ -97,10 +176,13 @@ impl<'a> Scanner<'a> {
         // the starting position of the token, so we can
         // place it inside the token when finished
         let code_start : usize = self.cursor_pos;
-        let mut code_end : Option<usize> = None;
 
-         // gets a slice of the next character
-        let character = &self.raw_code[self.cursor_pos .. self.cursor_pos + 1];
+         // gets a slice of the next character, anything that isn't a single
+         // byte character can't be lua code outside of a string.
+        let character = match self.char_at(self.cursor_pos) {
+            Some(character) => character,
+            None => return Err(ScannerError::illegal_character(self,None)),
+        };
         self.cursor_pos += 1;
 
         // determines what the token could possibly be
@@ -138,9 +220,11 @@ impl<'a> Scanner<'a> {
             "\"" => self.scan_token_string("\"")?,
             "'" => self.scan_token_string("'")?,
 
-            " " => { code_end = Some(self.scan_peek_all(" ") + 1); Token::WhiteSpace },
+            "\r" => { self.scan_peek("\n"); Token::EOL },
+            "\n" => { self.scan_peek("\r"); Token::EOL },
 
-            character => if Token::is_eol(character) { Token::EOL } 
+            character => if Token::is_whitespace(character) { self.scan_peek_whitespace(); Token::WhiteSpace }
+                    else if Token::is_eol(character) { Token::EOL } 
                     else { 
                         // the catch all part, this needs to check if its a number, string, or identifier
                         match self.scan_peek_token_keyword(character) {
@@ -153,11 +237,12 @@ impl<'a> Scanner<'a> {
                     },
         };
 
-        let token_length = if let Some(length) = code_end { length } else { token.len() };
+        // the token might not be the same length as the code it came from
+        // (like escaped characters in a string) so we use where the cursor is
         let code_token = CodeRef { 
             item : token, 
             code_start, 
-            code_end : token_length + code_start,
+            code_end : self.cursor_pos,
             line_number : self.line_number 
         };
         Ok(Some(code_token))
@@ -183,7 +268,7 @@ impl<'a> Scanner<'a> {
         // most of the time this will probably be `1`
         //for i in 0 .. length {
             // get the next character slice
-            let char = &self.raw_code[self.cursor_pos .. self.cursor_pos + length];
+            let char = &self.raw_code.as_bytes()[self.cursor_pos .. self.cursor_pos + length];
 
             // checks if its what we expect so far we will keep doing 
             // this until we hit a point where it doesn't match, 
@@ -191,7 +276,7 @@ impl<'a> Scanner<'a> {
             //if char != &chars[i .. i + 1] {
             //    return false;
             //}
-            if char != chars { return false; }
+            if char != chars.as_bytes() { return false; }
         //}
 
         // we found what we were looking for, so move the cursor 
@@ -202,15 +287,33 @@ impl<'a> Scanner<'a> {
         true
     }
 
-    fn scan_peek_all(&mut self, char : &str) -> usize {
-        //! a peek that will consume all of the characters, use this 
-        //! in places where it doesn't matter how many of these characters
-        //! exist, like WHITESPACE or EOL
-        
-        let mut count_found : usize = 0;
-        while self.scan_peek(char) { count_found += 1; }
+    fn scan_peek_whitespace(&mut self) {
+        //! consumes all of the whitespace characters, doesn't matter
+        //! how many or what kind (spaces or tabs) they are.
 
-        count_found
+        while self.char_at(self.cursor_pos).map(Token::is_whitespace).unwrap_or(false) {
+            self.cursor_pos += 1;
+        }
+    }
+
+    fn char_at(&self, pos : usize) -> Option<&'a str> {
+        //! the character at the position if it is a single byte, the only
+        //! ones that can be lua code outside of a string. `None` if it
+        //! isn't or we are past the end of the code.
+
+        match self.raw_code.as_bytes().get(pos) {
+            Some(byte) if byte.is_ascii() => Some(&self.raw_code[pos .. pos + 1]),
+            _ => None,
+        }
+    }
+
+    fn scan_next_char(&mut self) -> Option<char> {
+        //! gets the next full character, so we don't cut a multi-byte
+        //! character in half when we are inside a string or comment.
+
+        let character = self.raw_code[self.cursor_pos ..].chars().next()?;
+        self.cursor_pos += character.len_utf8();
+        Some(character)
     }
 
     fn scan_peek_token_keyword(&mut self, first : &str) -> Option<Token> {
@@ -222,17 +325,9 @@ impl<'a> Scanner<'a> {
         let mut pos = self.cursor_pos;
         let mut word : String = first.to_string();
 
-        loop {
-            // checks we are trying to look past whats left in the 
-            // raw code, if so then we won't find it because nothing
-            // exists in the void after our code, so lets just return
-            // false
-            if self.raw_code.len() - 1 < pos {
-                break;
-            }
-
-            let char = &self.raw_code[pos .. pos + 1];
-
+        // stops at the end of the code, or at a character that can't
+        // be part of it
+        while let Some(char) = self.char_at(pos) {
             match Token::is_valid_word_char(char,false) {
                 false => break,
                 true => {
@@ -246,7 +341,7 @@ impl<'a> Scanner<'a> {
 
         let token : Token = match Token::match_keyword(&word) {
             Some(token) => token,
-            None => Token::Identifier(word.to_string()),
+            None => Token::Identifier(LuaString::from(word)),
         };
 
         Some(token)
@@ -258,6 +353,28 @@ impl<'a> Scanner<'a> {
         
         if !Token::is_valid_number_char(&first) { return Ok(None); }
 
+        // hex numbers, `0xff`
+        if first == "0" && (self.scan_peek("x") || self.scan_peek("X")) {
+            let digits : String = self.raw_code[self.cursor_pos ..].chars()
+                .take_while(|c| c.is_ascii_hexdigit()).collect();
+
+            if digits.is_empty() {
+                return Err(ScannerError::number_parsing(self,2,"can't parse as a hex number"));
+            }
+
+            // added up as a float so that more than 16 digits still makes
+            // a (rounded) number instead of overflowing
+            let num = digits.chars()
+                .fold(0.0, |num, c| num * 16.0 + c.to_digit(16).unwrap_or(0) as f64);
+            self.cursor_pos += digits.len();
+            return Ok(Some(Token::Number(num)));
+        }
+
+        // a `.` is only a number if a digit comes right after it
+        if first == "." && !self.raw_code.as_bytes().get(self.cursor_pos).map(|c| c.is_ascii_digit()).unwrap_or(false) {
+            return Ok(None);
+        }
+
         let mut pos = self.cursor_pos;
         let mut number : String = first.to_string();
         // need to do this because rust will have a stack overflow if 
@@ -266,25 +383,21 @@ impl<'a> Scanner<'a> {
         // to check if we found it in exponential formatting.
         let mut exponent_format = false;  
 
-        loop {
-
-            // checks we are trying to look past whats left in the 
-            // raw code, if so then we won't find it because nothing
-            // exists in the void after our code, so lets just return
-            // false
-            if self.raw_code.len() - 1 < pos {
-                break;
+        // stops at the end of the code, or at a character that can't
+        // be part of it
+        while let Some(char) = self.char_at(pos) {
+            if char == "." {
+                // the exponent can't have a decimal
+                if exponent_format { break; }
+                decimal_number += 1;
             }
 
-            let char = &self.raw_code[pos .. pos + 1];
-            if char == "." { decimal_number += 1; }
-
             match Token::is_valid_number_char(char) {
                 false => match char {
                     "e" | "E" => { 
                         // if we find an e in the number, it might be a number still, we just can
-                        // only have 1 and can't have any decimal places anymore.
-                        if !exponent_format && decimal_number == 0 {
+                        // only have 1.
+                        if !exponent_format {
                             pos += 1;
                             number = format!("{}{}",number,char);
                             exponent_format = true;
@@ -292,8 +405,8 @@ impl<'a> Scanner<'a> {
                             break
                         }
                     },
-                    "-" => {
-                        if exponent_format {
+                    "-" | "+" => {
+                        if exponent_format && number.ends_with(['e', 'E']) {
                             pos += 1;
                             number = format!("{}{}",number,char);
                         } else { break; }
@@ -313,14 +426,9 @@ impl<'a> Scanner<'a> {
                 &format!("a number can't have more than 1 decimal point, found {}",decimal_number)));
         }
 
-        if decimal_number == 1 && exponent_format {
-            return Err(ScannerError::number_parsing(self,number.len(),
-                &format!("a number can't have more a decimal and be in exp format")));
-        }
-
         if number == "." { return Ok(None); }
 
-        match number.parse::<f32>() {
+        match number.parse::<f64>() {
             Err(_) => { 
                 #[cfg(feature = "dev-testing")]
                 {
@@ -369,22 +477,16 @@ impl<'a> Scanner<'a> {
         // will check if the previous character is the first '[' or if the
         // current character is the first '[', then moves so the cursor is 
         // currently right after the first '['
-        if &self.raw_code[working_pos - 1 .. working_pos] != "[" {
+        if self.char_at(working_pos - 1) != Some("[") {
+            if self.raw_code.as_bytes().get(working_pos) != Some(&b'[') { return None; }
             working_pos += 1;
         }
 
         loop {
 
-            // checks we are trying to look past whats left in the 
-            // raw code, if so then we won't find it because nothing
-            // exists in the void after our code, so lets just return
-            // false
-            if self.raw_code.len() - 1< working_pos {
-                return None;
-            }
-
-            // gets the next character
-            let char = &self.raw_code[working_pos .. working_pos + 1];
+            // gets the next character, it isn't a long bracket if the
+            // code ends first
+            let char = self.char_at(working_pos)?;
             working_pos += 1;
 
             // the only 2 valid characters are 
@@ -407,27 +509,76 @@ impl<'a> Scanner<'a> {
         //! of that string. doesn't do checking to make sure we are in 
         //! a string but will error if it can't find the end
         //! 
-        //! handles ' and " strings currently
+        //! handles ' and " strings currently, and the escape sequences
+        //! from the manual https://www.lua.org/manual/5.1/manual.html#2.1
         //! 
         //! - starter : expecting a string of len 1, will not work otherwise
+        //!
+        //! lua strings are bytes, so the escape sequences can make strings
+        //! that aren't valid UTF-8.
 
-        let mut string : String = String::new();
+        let mut string : Vec<u8> = Vec::new();
+        let start = self.cursor_pos;
 
         loop {
-            // checks if we reached the end of the code without the comment close
-            if self.cursor_pos == self.raw_code.len() {
-                return Err(ScannerError::unterminated_code_segment(self,1,1,"string not terminated"));  
+            // checks if we reached the end of the code without the string close
+            let char = match self.scan_next_char() {
+                Some(char) => char,
+                None => return Err(ScannerError::unterminated_code_segment(self,self.cursor_pos - start + 1,1,"string not terminated")),
+            };
+
+            match char {
+                '\\' => string.push(self.scan_escape_sequence()?),
+                '\n' | '\r' => return Err(ScannerError::unterminated_code_segment(self,self.cursor_pos - start + 1,1,"string not terminated")),
+                char if char.to_string() == starter => return Ok(Token::String(LuaString::from(string))),
+                char => push_source_char(&mut string, char),
             }
+        }
+    }
 
-            // the next character
-            let char = &self.raw_code[self.cursor_pos .. self.cursor_pos + 1];
-            self.cursor_pos += 1;
+    fn scan_escape_sequence(&mut self) -> Result<u8,Error> {
+        //! reads the character after a `\` and gives the byte that
+        //! it means.
 
-            match char == starter {
-                false => string = format!("{}{}",string,char),
-                true => return Ok(Token::String(string)),
-            } 
-        }
+        let char = match self.scan_next_char() {
+            Some(char) => char,
+            None => return Err(ScannerError::unterminated_code_segment(self,1,1,"string not terminated")),
+        };
+
+        let escaped = match char {
+            'a' => 0x07,
+            'b' => 0x08,
+            'f' => 0x0C,
+            'n' => b'\n',
+            'r' => b'\r',
+            't' => b'\t',
+            'v' => 0x0B,
+            '\\' | '"' | '\'' | '[' | ']' => char as u8,
+            '\n' => { self.scan_peek("\r"); b'\n' },
+            '\r' => { self.scan_peek("\n"); b'\n' },
+            '0' ..= '9' => {
+                // `\ddd` is a decimal character code, up to 3 digits
+                let mut code = char.to_digit(10).unwrap_or(0);
+                for _ in 0 .. 2 {
+                    match self.raw_code[self.cursor_pos ..].chars().next() {
+                        Some(digit) if digit.is_ascii_digit() => {
+                            code = code * 10 + digit.to_digit(10).unwrap_or(0);
+                            self.cursor_pos += 1;
+                        },
+                        _ => break,
+                    }
+                }
+
+                if code > 255 {
+                    return Err(ScannerError::illegal_character(self,Some("escape sequence too large")));
+                }
+
+                code as u8
+            },
+            _ => return Err(ScannerError::illegal_character(self,Some("invalid escape sequence"))),
+        };
+
+        Ok(escaped)
     }
 
     fn scan_token_multiline_string(&mut self, level : usize) -> Result<Token,Error> {
@@ -435,7 +586,7 @@ impl<'a> Scanner<'a> {
         
         match self.scan_token_multiline(level) {
             Err(error) => Err(error),
-            Ok(string) => Ok(Token::MultiLineString(string)),
+            Ok(string) => Ok(Token::MultiLineString(LuaString::from(source_bytes(&string)))),
         }
     }
 
@@ -458,21 +609,26 @@ impl<'a> Scanner<'a> {
         };
 
         let mut string : String = String::new();
+        let start = self.cursor_pos;
+
+        // a line break right after the opening brackets is skipped
+        if !self.scan_peek("\r\n") && !self.scan_peek("\n") { self.scan_peek("\r"); }
 
         loop {
 
             // checks if we reached the end of the code without the comment close
-            if self.cursor_pos == self.raw_code.len() {
-                return Err(ScannerError::unterminated_code_segment(self,level+2,level+2,"multiline comment has no end, starts here"));  
-            }
-
-            let char = &self.raw_code[self.cursor_pos .. self.cursor_pos + 1];
-            self.cursor_pos += 1;
+            let char = match self.scan_next_char() {
+                Some(char) => char,
+                None => {
+                    self.cursor_pos = start;
+                    return Err(ScannerError::unterminated_code_segment(self,level+2,level+2,"multiline comment has no end, starts here"));
+                },
+            };
 
             match char {
-                "]" => if self.scan_peek(&ending_chars) { break; } 
-                       else { string = format!("{}{}",string,char); },
-                _ => string = format!("{}{}",string,char)
+                ']' => if self.scan_peek(&ending_chars) { break; } 
+                       else { string.push(char); },
+                _ => string.push(char),
             }
         }
 
@@ -502,18 +658,12 @@ impl<'a> Scanner<'a> {
         } else {
             // we have the simple comment, which terminates at the end of the line
             
-            loop {
-                // check if we are at the end of the code
-                if self.cursor_pos == self.raw_code.len() { break; }
-
-                let char = &self.raw_code[self.cursor_pos .. self.cursor_pos + 1];
-                match Token::is_eol(char) {
-                    true => break, // we don't want to consume an EOL token in a simple comment
-                    false => {
-                        self.cursor_pos += 1;
-                        string = format!("{}{}",string,char);
-                    }
-                }
+            // we don't want to consume an EOL token in a simple comment
+            while let Some(char) = self.raw_code[self.cursor_pos ..].chars().next() {
+                if char == '\n' || char == '\r' { break; }
+
+                self.cursor_pos += char.len_utf8();
+                string.push(char);
             }
         }
 
@@ -524,16 +674,15 @@ impl<'a> Scanner<'a> {
 #[cfg(test)]
 mod tests {
 
-    use crate::scanner::Scanner;
+    use crate::scanner::{Scanner, decode_source, source_bytes};
 
     #[test]
     pub fn scan_lua_test_suite() {
         use std::fs::File;
         use std::io::Read;
-        use std::str;
 
         let file_names = vec![
-            // "all.lua", // fails because of #! is invalid rust, TODO : figure out what to do, if anything
+            "all.lua",
             "api.lua",
             "attrib.lua",
             "big.lua",
@@ -542,19 +691,19 @@ mod tests {
             "closure.lua",
             "code.lua",
             "constructs.lua",
-            // "db.lua", // fails because not UTF-8, has unicode? TODO : unicode support
+            "db.lua",
             "errors.lua",
             "events.lua",
-            // "files.lua", // fails because not UTF-8, has unicode? TODO : unicode support
+            "files.lua",
             "gc.lua",
-            // "literals.lua", // fails because not UTF-8, has unicode? TODO : unicode support
+            "literals.lua",
             "locals.lua",
             "main.lua",
             "math.lua",
             "nextvar.lua",
-            // "pm.lua", // fails because not UTF-8, has unicode? TODO : unicode support
-            // "sort.lua", // fails because not UTF-8, has unicode? TODO : unicode support
-            // "strings.lua", // fails because not UTF-8, has unicode? TODO : unicode support
+            "pm.lua",
+            "sort.lua",
+            "strings.lua",
             "vararg.lua",
             "verybig.lua",
         ];
@@ -572,10 +721,8 @@ mod tests {
 
             };
 
-            let code = match str::from_utf8(&code_stream) {
-                Ok(c) => c,
-                Err(error) =>  { println!("{}: {}",file_name,error); assert!(false); "" },
-            };
+            // some of the files are latin-1
+            let code = decode_source(&code_stream);
 
             match Scanner::from_str(&code,Some(file_name)) {
                 Err(error) => { println!("{}: {}",file_name,error); assert!(false); }
@@ -584,4 +731,43 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn hex_numbers() {
+        use crate::token::Token;
+
+        let number = |code : &str, expected : f64| {
+            let scanner = Scanner::from_str(code, None).unwrap();
+            assert!(scanner.tokens.iter().any(|token| token.item() == &Token::Number(expected)), "{}", code);
+        };
+        number("x = 0xff", 255.0);
+        number("x = 0XfFfFfFfFfFfFfFfF", u64::MAX as f64);
+        number("x = 0x7fffffffffffffffff", 2f64.powi(71));
+        number("x = 0x10000000000000000000000000000000", 2f64.powi(124));
+        assert!(Scanner::from_str("x = 0x", None).is_err());
+    }
+
+    #[test]
+    pub fn raw_bytes() {
+        // latin-1 bytes come back out of strings the way they went in
+        let code = decode_source(b"x = '\xe1lo' -- ol\xe1");
+        let scanner = Scanner::from_str(&code, None).unwrap();
+        assert!(scanner.tokens.iter().any(|token| token.item() == &crate::token::Token::String(crate::value::LuaString::from(&[0xE1, b'l', b'o'][..]))));
+        assert_eq!(source_bytes(&code), b"x = '\xe1lo' -- ol\xe1");
+        assert_eq!(source_bytes("\u{e1}"), "\u{e1}".as_bytes());
+    }
+
+    #[test]
+    pub fn non_ascii_outside_strings() {
+        // characters longer than a byte are errors outside of strings and
+        // comments, wherever they come up in a token
+        for code in ["x = 1\u{e9}", "x\u{e9} = 1", "x = \u{e9}", "x =\t\u{e9}", "x = [\u{e9}", "x = .5\u{e9}", "x = \"\u{e9}\" .. \u{e9}"] {
+            assert!(Scanner::from_str(code, None).is_err(), "{}", code);
+        }
+
+        // a latin-1 file is made into private use characters
+        let code = decode_source(b"x = abc\xe9");
+        assert!(Scanner::from_str(&code, None).is_err());
+        assert!(Scanner::from_str("x = '\u{e9}' -- \u{e9}", None).is_ok());
+    }
+
 }
\ No newline at end of file
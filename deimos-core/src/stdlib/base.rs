@@ -0,0 +1,543 @@
+//! the basic functions, https://www.lua.org/manual/5.1/manual.html#5.1
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, Table, AnyUserData, LuaString, LuaFunction};
+use crate::error::runtime::RuntimeError;
+use crate::stdlib::{arg, arg_error, check_any, check_string, check_table, check_integer, opt_integer, load_file};
+use crate::chunk::chunk_id;
+
+pub fn load(interpreter : &Interpreter) {
+    interpreter.set_global("_VERSION", Value::from("Lua 5.1"));
+    interpreter.set_global("print", Value::NativeFunction(print));
+    interpreter.set_global("tostring", Value::NativeFunction(tostring));
+    interpreter.set_global("tonumber", Value::NativeFunction(tonumber));
+    interpreter.set_global("type", Value::NativeFunction(type_name));
+    interpreter.set_global("next", Value::NativeFunction(next));
+    interpreter.set_global("pairs", Value::NativeFunction(pairs));
+    interpreter.set_global("ipairs", Value::NativeFunction(ipairs));
+    interpreter.set_global("rawget", Value::NativeFunction(rawget));
+    interpreter.set_global("rawset", Value::NativeFunction(rawset));
+    interpreter.set_global("rawequal", Value::NativeFunction(rawequal));
+    interpreter.set_global("getmetatable", Value::NativeFunction(getmetatable));
+    interpreter.set_global("setmetatable", Value::NativeFunction(setmetatable));
+    interpreter.set_global("newproxy", Value::NativeFunction(newproxy));
+    interpreter.set_global("collectgarbage", Value::NativeFunction(collectgarbage));
+    interpreter.set_global("gcinfo", Value::NativeFunction(gcinfo));
+    interpreter.set_global("select", Value::NativeFunction(select));
+    interpreter.set_global("unpack", Value::NativeFunction(unpack));
+    interpreter.set_global("assert", Value::NativeFunction(assert));
+    interpreter.set_global("error", Value::NativeFunction(error));
+    interpreter.set_global("pcall", Value::NativeFunction(pcall));
+    interpreter.set_global("xpcall", Value::NativeFunction(xpcall));
+    interpreter.set_global("load", Value::NativeFunction(load_chunk));
+    interpreter.set_global("loadstring", Value::NativeFunction(loadstring));
+    interpreter.set_global("loadfile", Value::NativeFunction(loadfile));
+    interpreter.set_global("dofile", Value::NativeFunction(dofile));
+    interpreter.set_global("getfenv", Value::NativeFunction(getfenv));
+    interpreter.set_global("setfenv", Value::NativeFunction(setfenv));
+}
+
+fn print(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! print (···)
+
+    // lua strings don't have to be UTF-8, so we write the bytes
+    let mut line : Vec<u8> = Vec::new();
+    for (i, value) in args.iter().enumerate() {
+        if i > 0 { line.push(b'\t'); }
+        match interpreter.tostring(value)? {
+            Value::String(string) => line.extend_from_slice(string.as_bytes()),
+            _ => return Err(RuntimeError::general("'tostring' must return a string to 'print'")),
+        }
+    }
+    line.push(b'\n');
+
+    let mut stdout = interpreter.host().stdout();
+    stdout.write(&line)?;
+    stdout.flush()?;
+
+    Ok(Vec::new())
+}
+
+fn tostring(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! tostring (e)
+
+    let value = check_any(&args, 1, "tostring")?;
+    Ok(vec![interpreter.tostring(&value)?])
+}
+
+fn tonumber(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! tonumber (e [, base])
+
+    let value = check_any(&args, 1, "tonumber")?;
+    let base = opt_integer(&args, 2, "tonumber", 10)?;
+
+    if base == 10 {
+        return Ok(vec![value.to_number().map(Value::Number).unwrap_or(Value::Nil)]);
+    }
+
+    if !(2 ..= 36).contains(&base) {
+        return Err(arg_error(2, "tonumber", "base out of range"));
+    }
+
+    let string = check_string(&args, 1, "tonumber")?;
+    let text = match std::str::from_utf8(string.as_bytes()) {
+        Ok(text) => text.trim(),
+        Err(_) => return Ok(vec![Value::Nil]),
+    };
+
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    if digits.is_empty() {
+        return Ok(vec![Value::Nil]);
+    }
+
+    let mut number = 0.0;
+    for c in digits.chars() {
+        match c.to_digit(base as u32) {
+            Some(digit) => number = number * base as f64 + f64::from(digit),
+            None => return Ok(vec![Value::Nil]),
+        }
+    }
+
+    Ok(vec![Value::Number(if negative { -number } else { number })])
+}
+
+fn type_name(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! type (v)
+
+    let value = check_any(&args, 1, "type")?;
+    Ok(vec![Value::from(value.type_name())])
+}
+
+fn next(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! next (table [, index])
+
+    let table = check_table(&args, 1, "next")?;
+    let next = table.borrow().next(&arg(&args, 2))?;
+
+    match next {
+        Some((key, value)) => Ok(vec![key, value]),
+        None => Ok(vec![Value::Nil]),
+    }
+}
+
+fn pairs(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! pairs (t)
+
+    let table = check_table(&args, 1, "pairs")?;
+    Ok(vec![Value::NativeFunction(next), Value::Table(table), Value::Nil])
+}
+
+fn ipairs(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! ipairs (t)
+
+    let table = check_table(&args, 1, "ipairs")?;
+    Ok(vec![Value::NativeFunction(ipairs_next), Value::Table(table), Value::Number(0.0)])
+}
+
+fn ipairs_next(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! the iterator that `ipairs` gives back, stops at the first `nil`
+
+    let table = check_table(&args, 1, "ipairs")?;
+    let i = arg(&args, 2).to_number().unwrap_or(0.0) + 1.0;
+
+    let value = table.borrow().get(&Value::Number(i));
+    match value {
+        Value::Nil => Ok(vec![Value::Nil]),
+        value => Ok(vec![Value::Number(i), value]),
+    }
+}
+
+fn rawget(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! rawget (table, index)
+
+    let table = check_table(&args, 1, "rawget")?;
+    let key = check_any(&args, 2, "rawget")?;
+    let value = table.borrow().get(&key);
+    Ok(vec![value])
+}
+
+fn rawset(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! rawset (table, index, value)
+
+    let table = check_table(&args, 1, "rawset")?;
+    let key = check_any(&args, 2, "rawset")?;
+    let value = check_any(&args, 3, "rawset")?;
+    table.borrow_mut().set(key, value)?;
+    Ok(vec![Value::Table(table)])
+}
+
+fn rawequal(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! rawequal (v1, v2)
+
+    let a = check_any(&args, 1, "rawequal")?;
+    let b = check_any(&args, 2, "rawequal")?;
+    Ok(vec![Value::Boolean(a == b)])
+}
+
+fn getmetatable(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! getmetatable (object)
+    //!
+    //! if the metatable has a `__metatable` field then that is given
+    //! instead, so the real metatable can be hidden.
+
+    let value = check_any(&args, 1, "getmetatable")?;
+
+    match interpreter.get_metatable(&value) {
+        None => Ok(vec![Value::Nil]),
+        Some(metatable) => match metatable.borrow().get_str("__metatable") {
+            Value::Nil => Ok(vec![Value::Table(metatable.clone())]),
+            protected => Ok(vec![protected]),
+        },
+    }
+}
+
+fn setmetatable(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! setmetatable (table, metatable)
+
+    let table = check_table(&args, 1, "setmetatable")?;
+    let metatable = match arg(&args, 2) {
+        Value::Nil => None,
+        Value::Table(metatable) => Some(metatable),
+        _ => return Err(arg_error(2, "setmetatable", "nil or table expected")),
+    };
+
+    let table = Value::Table(table);
+    if !interpreter.metamethod(&table, "__metatable").is_nil() {
+        return Err(RuntimeError::general("cannot change a protected metatable"));
+    }
+
+    interpreter.set_metatable(&table, metatable)?;
+    Ok(vec![table])
+}
+
+fn newproxy(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! newproxy ([boolean | proxy])
+    //!
+    //! makes an empty userdata, with a new metatable if given `true` or
+    //! sharing the metatable of another proxy.
+
+    let metatable = match arg(&args, 1) {
+        Value::Nil | Value::Boolean(false) => None,
+        Value::Boolean(true) => Some(Rc::new(RefCell::new(Table::new()))),
+        Value::UserData(ref proxy) if proxy.is::<Proxy>() => proxy.metatable(),
+        _ => return Err(arg_error(1, "newproxy", "boolean or proxy expected")),
+    };
+
+    let proxy = Value::UserData(Rc::new(AnyUserData::new(Proxy)));
+    if metatable.is_some() {
+        interpreter.set_metatable(&proxy, metatable)?;
+    }
+
+    Ok(vec![proxy])
+}
+
+fn collectgarbage(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! collectgarbage ([opt [, arg]])
+
+    let option = match arg(&args, 1) {
+        Value::Nil => LuaString::from("collect"),
+        _ => check_string(&args, 1, "collectgarbage")?,
+    };
+    let size = opt_integer(&args, 2, "collectgarbage", 0)?.max(0) as usize;
+
+    let result = match option.as_bytes() {
+        b"collect" => { interpreter.collect_garbage()?; Value::Number(0.0) },
+        b"count" => Value::Number(interpreter.memory_in_use() as f64 / 1024.0),
+        b"step" => Value::Boolean(interpreter.step_garbage(size)?),
+        b"stop" => { interpreter.set_collecting(false); Value::Number(0.0) },
+        b"restart" => { interpreter.set_collecting(true); Value::Number(0.0) },
+        b"setpause" => Value::Number(interpreter.set_collector_pause(size) as f64),
+        b"setstepmul" => Value::Number(interpreter.set_collector_step_multiplier(size) as f64),
+        _ => return Err(arg_error(1, "collectgarbage", &format!("invalid option '{}'", option))),
+    };
+
+    Ok(vec![result])
+}
+
+fn gcinfo(interpreter : &Interpreter, _ : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! gcinfo ()
+    //!
+    //! the kilobytes in use, what `collectgarbage("count")` was before 5.1
+
+    Ok(vec![Value::Number((interpreter.memory_in_use() / 1024) as f64)])
+}
+
+fn select(_ : &Interpreter, mut args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! select (index, ···)
+
+    let count = args.len().saturating_sub(1) as i64;
+
+    if let Some(Value::String(string)) = args.first() {
+        if string.as_bytes().first() == Some(&b'#') {
+            return Ok(vec![Value::Number(count as f64)]);
+        }
+    }
+
+    // negative numbers count back from the end
+    let i = match check_integer(&args, 1, "select")? {
+        i if i < 0 => count + 1 + i,
+        i => i.min(count + 1),
+    };
+
+    if i < 1 {
+        return Err(arg_error(1, "select", "index out of range"));
+    }
+
+    Ok(args.split_off(i as usize))
+}
+
+fn unpack(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! unpack (list [, i [, j]])
+
+    let table = check_table(&args, 1, "unpack")?;
+    let table = table.borrow();
+
+    let i = opt_integer(&args, 2, "unpack", 1)?;
+    let j = match arg(&args, 3) {
+        Value::Nil => table.len() as i64,
+        _ => check_integer(&args, 3, "unpack")?,
+    };
+
+    if i > j { return Ok(Vec::new()); }
+    if j - i >= 8_000_000 {
+        return Err(RuntimeError::general("too many results to unpack"));
+    }
+
+    Ok((i ..= j).map(|i| table.get(&Value::Number(i as f64))).collect())
+}
+
+fn assert(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! assert (v [, message])
+
+    match check_any(&args, 1, "assert")?.is_truthy() {
+        true => Ok(args),
+        false => match arg(&args, 2) {
+            Value::Nil => Err(RuntimeError::general("assertion failed!")),
+            message => Err(RuntimeError::general(&message.tostring().to_string_lossy())),
+        },
+    }
+}
+
+fn error(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! error (message [, level])
+    //!
+    //! strings get the position of the function at `level` put in front,
+    //! `1` (the default) is the function that called `error`.
+
+    let level = opt_integer(&args, 2, "error", 1)?;
+    let value = match arg(&args, 1) {
+        Value::String(message) if level > 0 => match interpreter.position(level as usize) {
+            Some(position) => Value::String(LuaString::from(format!("{} ", position)).concat(&message)),
+            None => Value::String(message),
+        },
+        value => value,
+    };
+
+    Err(interpreter.throw(value))
+}
+
+fn pcall(interpreter : &Interpreter, mut args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! pcall (f, arg1, ···)
+
+    let function = check_any(&args, 1, "pcall")?;
+    let args = args.split_off(1);
+
+    match interpreter.call(&function, args) {
+        Ok(mut values) => {
+            values.insert(0, Value::Boolean(true));
+            Ok(values)
+        },
+        Err(error) => Ok(vec![Value::Boolean(false), interpreter.catch(error)?]),
+    }
+}
+
+fn xpcall(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! xpcall (f, err)
+    //!
+    //! the message handler gets the error and whatever it gives back is
+    //! the error that `xpcall` gives.
+
+    let function = check_any(&args, 1, "xpcall")?;
+    let handler = arg(&args, 2);
+
+    match interpreter.call(&function, Vec::new()) {
+        Ok(mut values) => {
+            values.insert(0, Value::Boolean(true));
+            Ok(values)
+        },
+        Err(error) => {
+            let value = interpreter.catch(error)?;
+            let result = match interpreter.call(&handler, vec![value]) {
+                Ok(values) => values.into_iter().next().unwrap_or(Value::Nil),
+                Err(error) => {
+                    interpreter.catch(error)?;
+                    Value::from("error in error handling")
+                },
+            };
+
+            Ok(vec![Value::Boolean(false), result])
+        },
+    }
+}
+
+fn load_chunk(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! load (func [, chunkname])
+    //!
+    //! the function is called until it gives back nothing, all the
+    //! pieces it gives are the code. an error while reading is given
+    //! back like one in the code.
+
+    let function = check_any(&args, 1, "load")?;
+    let chunk_name = match arg(&args, 2) {
+        Value::Nil => LuaString::from("=(load)"),
+        _ => check_string(&args, 2, "load")?,
+    };
+
+    match read_chunk(interpreter, &function) {
+        Ok(code) => loaded(interpreter, &code, chunk_name.as_bytes()),
+        Err(error) => Ok(vec![Value::Nil, interpreter.catch(error)?]),
+    }
+}
+
+fn loadstring(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! loadstring (string [, chunkname])
+
+    let code = check_string(&args, 1, "loadstring")?;
+    let chunk_name = match arg(&args, 2) {
+        Value::Nil => code.clone(),
+        _ => check_string(&args, 2, "loadstring")?,
+    };
+
+    loaded(interpreter, code.as_bytes(), chunk_name.as_bytes())
+}
+
+fn loadfile(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! loadfile ([filename])
+
+    let path = match arg(&args, 1) {
+        Value::Nil => None,
+        _ => Some(check_string(&args, 1, "loadfile")?.to_string_lossy()),
+    };
+
+    match load_file(interpreter, path.as_deref()) {
+        Ok(function) => Ok(vec![function]),
+        Err(error) => Ok(vec![Value::Nil, interpreter.catch(error)?]),
+    }
+}
+
+fn dofile(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! dofile ([filename])
+
+    let path = match arg(&args, 1) {
+        Value::Nil => None,
+        _ => Some(check_string(&args, 1, "dofile")?.to_string_lossy()),
+    };
+
+    let function = load_file(interpreter, path.as_deref())?;
+    interpreter.call(&function, Vec::new())
+}
+
+fn getfenv(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! getfenv ([f])
+    //!
+    //! functions written in rust all use the globals.
+
+    let environment = match environment_target(interpreter, &args, "getfenv")? {
+        Target::Lua(function) => function.environment(),
+        Target::Thread | Target::Native => interpreter.globals(),
+    };
+
+    Ok(vec![Value::Table(environment)])
+}
+
+fn setfenv(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! setfenv (f, table)
+
+    let environment = check_table(&args, 2, "setfenv")?;
+
+    match environment_target(interpreter, &args, "setfenv")? {
+        Target::Thread => {
+            interpreter.set_globals(environment);
+            Ok(Vec::new())
+        },
+        Target::Lua(function) => {
+            function.set_environment(environment);
+            Ok(vec![Value::Function(function)])
+        },
+        Target::Native => Err(RuntimeError::general("'setfenv' cannot change environment of given object")),
+    }
+}
+
+/// what is inside of the userdata made by `newproxy`
+struct Proxy;
+
+// PRIVATE FUNCTIONS /////////////////////////////////////
+
+fn loaded(interpreter : &Interpreter, code : &[u8], chunk_name : &[u8]) -> Result<Vec<Value>,Error> {
+    //! the function for the code, or `nil` and the message. going over
+    //! a limit while loading isn't given back, it keeps going.
+
+    match interpreter.load(code, &chunk_id(chunk_name)) {
+        Ok(function) => Ok(vec![function]),
+        Err(error) => Ok(vec![Value::Nil, interpreter.catch(error)?]),
+    }
+}
+
+fn read_chunk(interpreter : &Interpreter, function : &Value) -> Result<Vec<u8>,Error> {
+    //! calls the reader function for the pieces of the code until it
+    //! gives back nothing
+
+    let mut code : Vec<u8> = Vec::new();
+    loop {
+        match interpreter.call(function, Vec::new())?.into_iter().next() {
+            None | Some(Value::Nil) => break,
+            Some(Value::String(piece)) if piece.is_empty() => break,
+            Some(Value::String(piece)) => code.extend_from_slice(piece.as_bytes()),
+            Some(_) => return Err(RuntimeError::general("reader function must return a string")),
+        }
+    }
+
+    Ok(code)
+}
+
+/// what `getfenv` and `setfenv` are looking at
+enum Target {
+    // the environment of the thread, level `0`
+    Thread,
+    Lua(Rc<LuaFunction>),
+    Native,
+}
+
+fn environment_target(interpreter : &Interpreter, args : &[Value], function : &str) -> Result<Target,Error> {
+    //! the first arguement is either a function or how many levels up
+    //! the stack the function is, `1` is the one that called us
+
+    let level = match arg(args, 1) {
+        Value::Function(lua) => return Ok(Target::Lua(lua)),
+        Value::NativeFunction(_) | Value::NativeClosure(_) => return Ok(Target::Native),
+        Value::Nil if function == "getfenv" => 1,
+        _ => check_integer(args, 1, function)?,
+    };
+
+    if level < 0 {
+        return Err(arg_error(1, function, "level must be non-negative"));
+    }
+    if level == 0 {
+        return Ok(Target::Thread);
+    }
+
+    match interpreter.function_at(level as usize) {
+        Some(Some(lua)) => Ok(Target::Lua(lua)),
+        Some(None) => Ok(Target::Native),
+        None => Err(arg_error(1, function, "invalid level")),
+    }
+}
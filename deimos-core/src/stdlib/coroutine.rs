@@ -0,0 +1,120 @@
+//! the coroutine library, https://www.lua.org/manual/5.1/manual.html#5.2
+
+use std::rc::Rc;
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, Table, Thread, NativeClosure};
+use crate::stdlib::arg_error;
+
+pub fn load(interpreter : &Interpreter) {
+    let mut coroutine = Table::new();
+
+    coroutine.set_str("create", Value::NativeFunction(create));
+    coroutine.set_str("resume", Value::NativeFunction(resume));
+    coroutine.set_str("yield", Value::NativeFunction(yield_values));
+    coroutine.set_str("status", Value::NativeFunction(status));
+    coroutine.set_str("wrap", Value::NativeFunction(wrap));
+    coroutine.set_str("running", Value::NativeFunction(running));
+
+    interpreter.set_global("coroutine", Value::from(coroutine));
+}
+
+fn create(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! coroutine.create (f)
+
+    let thread = new_thread(interpreter, &args, "create")?;
+    Ok(vec![Value::Thread(thread)])
+}
+
+fn resume(interpreter : &Interpreter, mut args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! coroutine.resume (co [, val1, ···])
+
+    let thread = check_thread(&args, 1, "resume")?;
+    let args = args.split_off(1);
+
+    match interpreter.resume(&thread, args) {
+        Ok(values) => {
+            let mut results = vec![Value::Boolean(true)];
+            results.extend(values);
+            Ok(results)
+        },
+        Err(error) => Ok(vec![Value::Boolean(false), interpreter.catch(error)?]),
+    }
+}
+
+fn yield_values(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! coroutine.yield (···)
+
+    interpreter.yield_values(args)
+}
+
+fn status(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! coroutine.status (co)
+
+    let thread = check_thread(&args, 1, "status")?;
+    Ok(vec![Value::from(format!("{}", thread.status()).as_str())])
+}
+
+fn wrap(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! coroutine.wrap (f)
+
+    let thread = new_thread(interpreter, &args, "wrap")?;
+
+    Ok(vec![Value::NativeClosure(Rc::new(Wrapped { thread }))])
+}
+
+fn running(interpreter : &Interpreter, _ : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! coroutine.running ()
+
+    match interpreter.running() {
+        Some(thread) => Ok(vec![Value::Thread(thread)]),
+        None => Ok(vec![Value::Nil]),
+    }
+}
+
+// PRIVATE FUNCTIONS /////////////////////////////////////
+
+fn new_thread(interpreter : &Interpreter, args : &[Value], function : &str) -> Result<Rc<Thread>,Error> {
+    match args.first() {
+        Some(value @ Value::Function(_)) => interpreter.create_thread(value.clone()),
+        _ => Err(arg_error(1, function, "Lua function expected")),
+    }
+}
+
+fn check_thread(args : &[Value], n : usize, function : &str) -> Result<Rc<Thread>,Error> {
+    match args.get(n - 1) {
+        Some(Value::Thread(thread)) => Ok(thread.clone()),
+        _ => Err(arg_error(n, function, "coroutine expected")),
+    }
+}
+
+struct Wrapped {
+    thread : Rc<Thread>,
+}
+
+impl NativeClosure for Wrapped {
+    fn call(&self, interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+        interpreter.resume(&self.thread, args).map_err(|error| {
+            // errors are passed on, with where the function was called
+            // added on to the front like lua does
+            match interpreter.catch(error) {
+                Err(error) => error,
+                Ok(value @ Value::String(_)) | Ok(value @ Value::Number(_)) => {
+                    let position = interpreter.position(1).unwrap_or_default();
+                    let message = format!("{}{}", position, value.tostring());
+                    interpreter.throw(Value::from(message.as_str()))
+                },
+                Ok(value) => interpreter.throw(value),
+            }
+        })
+    }
+
+    fn trace(&self, visit : &mut dyn FnMut(&Value)) {
+        //! the thread is only held here, so the collector has to be told
+        //! about it to find a wrapped function that refers to itself
+
+        visit(&Value::Thread(self.thread.clone()));
+    }
+}
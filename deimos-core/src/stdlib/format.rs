@@ -0,0 +1,229 @@
+//! the parts of C's `printf` that `string.format` uses. each conversion is
+//! given its flags, width and precision and writes out bytes, since lua
+//! strings don't need to be UTF-8.
+
+/// a single `%` conversion, like `%-10.3f`
+#[derive(Default)]
+pub struct Spec {
+    pub left : bool,
+    pub plus : bool,
+    pub space : bool,
+    pub alternate : bool,
+    pub zero : bool,
+    pub width : usize,
+    pub precision : Option<usize>,
+}
+
+impl Spec {
+    pub fn pad(&self, body : &[u8]) -> Vec<u8> {
+        //! pads the text with spaces out to the width
+
+        let fill = self.width.saturating_sub(body.len());
+        let mut padded = Vec::with_capacity(body.len() + fill);
+
+        if !self.left { padded.resize(fill, b' '); }
+        padded.extend_from_slice(body);
+        if self.left { padded.resize(fill + body.len(), b' '); }
+
+        padded
+    }
+
+    pub fn string(&self, bytes : &[u8]) -> Vec<u8> {
+        //! `%s`, the precision is how many bytes to use at most
+
+        let bytes = match self.precision {
+            Some(precision) if precision < bytes.len() => &bytes[.. precision],
+            _ => bytes,
+        };
+
+        self.pad(bytes)
+    }
+
+    pub fn integer(&self, number : i64) -> Vec<u8> {
+        //! `%d` and `%i`
+
+        let mut digits = number.unsigned_abs().to_string();
+        if let Some(precision) = self.precision {
+            digits = zero_extend(&digits, precision);
+            if precision == 0 && number == 0 { digits.clear(); }
+        }
+
+        self.number(self.sign(number < 0), "", &digits, self.precision.is_none())
+    }
+
+    pub fn unsigned(&self, number : u64, conversion : u8) -> Vec<u8> {
+        //! `%u`, `%o`, `%x` and `%X`
+
+        let mut digits = match conversion {
+            b'o' => format!("{:o}", number),
+            b'x' => format!("{:x}", number),
+            b'X' => format!("{:X}", number),
+            _ => number.to_string(),
+        };
+
+        if let Some(precision) = self.precision {
+            digits = zero_extend(&digits, precision);
+            if precision == 0 && number == 0 { digits.clear(); }
+        }
+
+        let prefix = match conversion {
+            b'o' if self.alternate && !digits.starts_with('0') => "0",
+            b'x' if self.alternate && number != 0 => "0x",
+            b'X' if self.alternate && number != 0 => "0X",
+            _ => "",
+        };
+
+        self.number("", prefix, &digits, self.precision.is_none())
+    }
+
+    pub fn float(&self, number : f64, conversion : u8) -> Vec<u8> {
+        //! `%e`, `%E`, `%f`, `%g` and `%G`
+
+        let sign = self.sign(number.is_sign_negative() && !number.is_nan());
+
+        if !number.is_finite() {
+            let text = match number.is_nan() {
+                true => "nan",
+                false => "inf",
+            };
+            let text = if conversion.is_ascii_uppercase() { text.to_uppercase() } else { text.to_string() };
+            return self.number(sign, "", &text, false);
+        }
+
+        let precision = self.precision.unwrap_or(6);
+        let number = number.abs();
+
+        let body = match conversion {
+            b'e' | b'E' => format_exponent(number, precision, self.alternate),
+            b'g' | b'G' => format_general(number, precision, self.alternate),
+            _ => format_fixed(number, precision, self.alternate),
+        };
+        let body = if conversion.is_ascii_uppercase() { body.to_uppercase() } else { body };
+
+        self.number(sign, "", &body, true)
+    }
+
+    // PRIVATE FUNCTIONS /////////////////////////////////////
+
+    fn sign(&self, negative : bool) -> &'static str {
+        match (negative, self.plus, self.space) {
+            (true, _, _) => "-",
+            (false, true, _) => "+",
+            (false, false, true) => " ",
+            _ => "",
+        }
+    }
+
+    fn number(&self, sign : &str, prefix : &str, digits : &str, zero_pad : bool) -> Vec<u8> {
+        //! puts the parts of a number together, zeros go between the
+        //! sign and the digits
+
+        let length = sign.len() + prefix.len() + digits.len();
+
+        if self.zero && !self.left && zero_pad && length < self.width {
+            let zeros = "0".repeat(self.width - length);
+            return format!("{}{}{}{}", sign, prefix, zeros, digits).into_bytes();
+        }
+
+        self.pad(format!("{}{}{}", sign, prefix, digits).as_bytes())
+    }
+}
+
+pub fn format_general(number : f64, precision : usize, alternate : bool) -> String {
+    //! `%g`, uses `%e` for very big or small numbers and `%f` otherwise,
+    //! the precision is how many digits there are in total. only works
+    //! on positive numbers.
+
+    let precision = precision.max(1);
+
+    // the exponent after rounding to the number of digits we keep
+    let exponent = if number == 0.0 {
+        0
+    } else {
+        let text = format!("{:.*e}", precision - 1, number);
+        text[text.find('e').map(|i| i + 1).unwrap_or(0) ..].parse::<i32>().unwrap_or(0)
+    };
+
+    let text = if exponent < -4 || exponent >= precision as i32 {
+        format_exponent(number, precision - 1, alternate)
+    } else {
+        format_fixed(number, (precision as i32 - 1 - exponent) as usize, alternate)
+    };
+
+    match alternate {
+        true => text,
+        false => strip_zeros(&text),
+    }
+}
+
+fn format_fixed(number : f64, precision : usize, alternate : bool) -> String {
+    let text = format!("{:.*}", precision, number);
+
+    match alternate && precision == 0 {
+        true => format!("{}.", text),
+        false => text,
+    }
+}
+
+fn format_exponent(number : f64, precision : usize, alternate : bool) -> String {
+    //! `%e`, the exponent always has a sign and at least two digits
+
+    let text = format!("{:.*e}", precision, number);
+    let (mantissa, exponent) = text.split_at(text.find('e').unwrap_or(text.len()));
+    let exponent : i32 = exponent.get(1 ..).and_then(|exponent| exponent.parse().ok()).unwrap_or(0);
+
+    let point = if alternate && precision == 0 { "." } else { "" };
+    let sign = if exponent < 0 { '-' } else { '+' };
+
+    format!("{}{}e{}{:02}", mantissa, point, sign, exponent.abs())
+}
+
+fn strip_zeros(text : &str) -> String {
+    //! takes off the zeros at the end of the fraction, and the point if
+    //! there is nothing after it
+
+    let (mantissa, exponent) = text.split_at(text.find('e').unwrap_or(text.len()));
+
+    let mantissa = match mantissa.contains('.') {
+        true => mantissa.trim_end_matches('0').trim_end_matches('.'),
+        false => mantissa,
+    };
+
+    format!("{}{}", mantissa, exponent)
+}
+
+fn zero_extend(digits : &str, precision : usize) -> String {
+    match digits.len() < precision {
+        true => format!("{}{}", "0".repeat(precision - digits.len()), digits),
+        false => digits.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::stdlib::format::{Spec, format_general};
+
+    #[test]
+    pub fn conversions() {
+        assert_eq!(format_general(100.0, 14, false), "100");
+        assert_eq!(format_general(0.1, 14, false), "0.1");
+        assert_eq!(format_general(1e15, 14, false), "1e+15");
+        assert_eq!(format_general(1234567890123.0, 14, false), "1234567890123");
+        assert_eq!(format_general(0.00001, 6, false), "1e-05");
+        assert_eq!(format_general(std::f64::consts::PI, 14, false), "3.1415926535898");
+
+        let spec = Spec { zero : true, width : 10, .. Spec::default() };
+        assert_eq!(spec.integer(23), b"0000000023");
+        assert_eq!(spec.integer(-23), b"-000000023");
+
+        let spec = Spec { left : true, width : 5, .. Spec::default() };
+        assert_eq!(spec.string(b"a"), b"a    ");
+
+        let spec = Spec { alternate : true, .. Spec::default() };
+        assert_eq!(spec.unsigned(255, b'x'), b"0xff");
+        assert_eq!(Spec { precision : Some(2), .. Spec::default() }.float(10.256, b'f'), b"10.26");
+        assert_eq!(Spec::default().float(-1.5, b'e'), b"-1.500000e+00");
+        assert_eq!(Spec::default().float(f64::INFINITY, b'g'), b"inf");
+    }
+}
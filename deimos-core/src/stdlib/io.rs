@@ -0,0 +1,612 @@
+//! the io library, https://www.lua.org/manual/5.1/manual.html#5.7
+//!
+//! files are userdata holding whatever the interpreter's host opened,
+//! so the library never touches the filesystem itself. the default
+//! input and output are shared by the functions that use them.
+
+use std::io::{self, SeekFrom};
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, Table, LuaString, AnyUserData, string_to_number};
+use crate::error::runtime::RuntimeError;
+use crate::host::{HostFile, OpenMode, Buffering, BUFFER_SIZE};
+use crate::stdlib::{arg, arg_error, type_error, check_any, check_string, opt_integer, io_message, io_failure};
+
+/// what the io functions share, the metatable every file gets and the
+/// files `io.read` and `io.write` use
+struct Io {
+    metatable : Rc<RefCell<Table>>,
+    input : RefCell<Value>,
+    output : RefCell<Value>,
+}
+
+/// a library function that uses the shared io state
+type IoFunction = fn(&Io, &Interpreter, Vec<Value>) -> Result<Vec<Value>,Error>;
+
+/// the data inside of a file userdata
+struct LuaFile {
+    // `None` once the file is closed
+    handle : Option<Box<dyn HostFile>>,
+    // the standard files can't be closed
+    standard : bool,
+    // a byte that was looked at but not used, like C's `ungetc`
+    peeked : Option<u8>,
+}
+
+pub fn load(interpreter : &Interpreter) {
+    let mut methods = Table::new();
+    methods.set_str("close", Value::NativeFunction(file_close));
+    methods.set_str("flush", Value::NativeFunction(file_flush));
+    methods.set_str("lines", Value::NativeFunction(file_lines));
+    methods.set_str("read", Value::NativeFunction(file_read));
+    methods.set_str("seek", Value::NativeFunction(file_seek));
+    methods.set_str("setvbuf", Value::NativeFunction(file_setvbuf));
+    methods.set_str("write", Value::NativeFunction(file_write));
+
+    let mut metatable = Table::new();
+    metatable.set_str("__index", Value::from(methods));
+    metatable.set_str("__tostring", Value::NativeFunction(file_tostring));
+    metatable.set_str("__gc", Value::NativeFunction(file_gc));
+    let metatable = Rc::new(RefCell::new(metatable));
+
+    let host = interpreter.host();
+    let stdin = new_file(interpreter, &metatable, host.stdin(), true);
+    let stdout = new_file(interpreter, &metatable, host.stdout(), true);
+    let stderr = new_file(interpreter, &metatable, host.stderr(), true);
+
+    let io = Rc::new(Io {
+        metatable,
+        input : RefCell::new(stdin.clone()),
+        output : RefCell::new(stdout.clone()),
+    });
+
+    let mut table = Table::new();
+    table.set_str("close", with_io(&io, close));
+    table.set_str("flush", with_io(&io, flush));
+    table.set_str("input", with_io(&io, input));
+    table.set_str("lines", with_io(&io, lines));
+    table.set_str("open", with_io(&io, open));
+    table.set_str("output", with_io(&io, output));
+    table.set_str("popen", Value::NativeFunction(popen));
+    table.set_str("read", with_io(&io, read));
+    table.set_str("tmpfile", with_io(&io, tmpfile));
+    table.set_str("type", Value::NativeFunction(type_name));
+    table.set_str("write", with_io(&io, write));
+    table.set_str("stdin", stdin);
+    table.set_str("stdout", stdout);
+    table.set_str("stderr", stderr);
+
+    interpreter.set_global("io", Value::from(table));
+}
+
+fn close(io : &Io, _ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! io.close ([file])
+
+    let file = match args.is_empty() {
+        true => io.output.borrow().clone(),
+        false => arg(&args, 1),
+    };
+
+    close_file(&check_file(&[file], 1, "close")?)
+}
+
+fn flush(io : &Io, _ : &Interpreter, _ : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! io.flush ()
+
+    let file = default_file(&io.output, "output")?;
+    Ok(result(with_file(&file, |file| file.handle()?.flush())))
+}
+
+fn input(io : &Io, interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! io.input ([file])
+
+    set_default(io, &io.input, interpreter, args, "input", OpenMode::parse("r"))
+}
+
+fn lines(io : &Io, interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! io.lines ([filename])
+    //!
+    //! a file opened by name is closed when the lines run out, the
+    //! default input is left open.
+
+    if arg(&args, 1).is_nil() {
+        let file = default_file(&io.input, "input")?;
+        return Ok(vec![lines_iterator(file, false)]);
+    }
+
+    let name = check_string(&args, 1, "lines")?;
+    match open_file(io, interpreter, &name, OpenMode::parse("r")) {
+        Ok(Value::UserData(file)) => Ok(vec![lines_iterator(file, true)]),
+        Ok(_) => Ok(Vec::new()),
+        Err(error) => Err(arg_error(1, "lines", &format!("{}: {}", name, io_message(&error)))),
+    }
+}
+
+fn open(io : &Io, interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! io.open (filename [, mode])
+
+    let name = check_string(&args, 1, "open")?;
+    let mode = match arg(&args, 2) {
+        Value::Nil => LuaString::from("r"),
+        _ => check_string(&args, 2, "open")?,
+    };
+
+    let mode = match mode.to_str().and_then(OpenMode::parse) {
+        Some(mode) => mode,
+        None => return Err(arg_error(2, "open", "invalid mode")),
+    };
+
+    match open_file(io, interpreter, &name, Some(mode)) {
+        Ok(file) => Ok(vec![file]),
+        Err(error) => Ok(io_failure(&error, Some(&name))),
+    }
+}
+
+fn output(io : &Io, interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! io.output ([file])
+
+    set_default(io, &io.output, interpreter, args, "output", OpenMode::parse("w"))
+}
+
+fn popen(_ : &Interpreter, _ : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! io.popen (prog [, mode])
+    //!
+    //! running other programs isn't something the host can give us
+
+    Err(RuntimeError::general("'popen' not supported"))
+}
+
+fn read(io : &Io, _ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! io.read (···)
+
+    let file = default_file(&io.input, "input")?;
+    read_formats(&file, &args, 1, "read")
+}
+
+fn tmpfile(io : &Io, interpreter : &Interpreter, _ : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! io.tmpfile ()
+
+    match interpreter.host().tmpfile() {
+        Ok(handle) => Ok(vec![new_file(interpreter, &io.metatable, handle, false)]),
+        Err(error) => Ok(io_failure(&error, None)),
+    }
+}
+
+fn type_name(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! io.type (obj)
+
+    let value = check_any(&args, 1, "type")?;
+    let name = match value {
+        Value::UserData(ref data) if data.is::<LuaFile>() => match with_file(data, |file| file.handle.is_some()) {
+            true => Value::from("file"),
+            false => Value::from("closed file"),
+        },
+        _ => Value::Nil,
+    };
+
+    Ok(vec![name])
+}
+
+fn write(io : &Io, _ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! io.write (···)
+
+    let file = default_file(&io.output, "output")?;
+    write_values(&file, &args, 1, "write")
+}
+
+// FILE METHODS //////////////////////////////////////////
+
+fn file_close(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! file:close ()
+
+    close_file(&check_file(&args, 1, "close")?)
+}
+
+fn file_flush(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! file:flush ()
+
+    let file = check_file(&args, 1, "flush")?;
+    Ok(result(with_file(&file, |file| file.handle()?.flush())))
+}
+
+fn file_lines(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! file:lines ()
+
+    let file = check_file(&args, 1, "lines")?;
+    Ok(vec![lines_iterator(file, false)])
+}
+
+fn file_read(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! file:read (···)
+
+    let file = check_file(&args, 1, "read")?;
+    read_formats(&file, &args, 2, "read")
+}
+
+fn file_seek(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! file:seek ([whence] [, offset])
+
+    let file = check_file(&args, 1, "seek")?;
+    let whence = match arg(&args, 2) {
+        Value::Nil => LuaString::from("cur"),
+        _ => check_string(&args, 2, "seek")?,
+    };
+    let offset = opt_integer(&args, 3, "seek", 0)?;
+
+    let position = match (whence.as_bytes(), offset) {
+        (b"set", offset) if offset < 0 => return Ok(io_failure(&io::Error::from_raw_os_error(libc::EINVAL), None)),
+        (b"set", offset) => SeekFrom::Start(offset as u64),
+        (b"cur", offset) => SeekFrom::Current(offset),
+        (b"end", offset) => SeekFrom::End(offset),
+        _ => return Err(arg_error(2, "seek", &format!("invalid option '{}'", whence))),
+    };
+
+    match with_file(&file, |file| file.seek(position)) {
+        Ok(position) => Ok(vec![Value::Number(position as f64)]),
+        Err(error) => Ok(io_failure(&error, None)),
+    }
+}
+
+fn file_setvbuf(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! file:setvbuf (mode [, size])
+
+    let file = check_file(&args, 1, "setvbuf")?;
+    let mode = check_string(&args, 2, "setvbuf")?;
+    let size = opt_integer(&args, 3, "setvbuf", BUFFER_SIZE as i64)?.max(1) as usize;
+
+    let buffering = match mode.as_bytes() {
+        b"no" => Buffering::No,
+        b"full" => Buffering::Full(size),
+        b"line" => Buffering::Line(size),
+        _ => return Err(arg_error(2, "setvbuf", &format!("invalid option '{}'", mode))),
+    };
+
+    Ok(result(with_file(&file, |file| file.handle()?.set_buffering(buffering))))
+}
+
+fn file_write(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! file:write (···)
+
+    let file = check_file(&args, 1, "write")?;
+    write_values(&file, &args, 2, "write")
+}
+
+fn file_tostring(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! __tostring for files
+
+    let file = match arg(&args, 1) {
+        Value::UserData(data) if data.is::<LuaFile>() => data,
+        _ => return Err(type_error(&args, 1, "tostring", "file")),
+    };
+
+    let text = match with_file(&file, |file| file.handle.is_some()) {
+        true => format!("file ({:p})", Rc::as_ptr(&file)),
+        false => "file (closed)".to_string(),
+    };
+
+    Ok(vec![Value::from(text.as_str())])
+}
+
+fn file_gc(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! __gc for files, closes the file if it isn't already. the standard
+    //! files are left open.
+
+    let file = match arg(&args, 1) {
+        Value::UserData(data) if data.is::<LuaFile>() => data,
+        _ => return Err(type_error(&args, 1, "__gc", "file")),
+    };
+
+    if with_file(&file, |file| file.handle.is_some()) {
+        close_file(&file)?;
+    }
+    Ok(Vec::new())
+}
+
+// PRIVATE FUNCTIONS /////////////////////////////////////
+
+impl LuaFile {
+    fn handle(&mut self) -> io::Result<&mut Box<dyn HostFile>> {
+        match &mut self.handle {
+            Some(handle) => Ok(handle),
+            None => Err(io::Error::from_raw_os_error(libc::EBADF)),
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.peeked.take() {
+            return Ok(Some(byte));
+        }
+
+        let handle = self.handle()?;
+        let mut byte = [0];
+        loop {
+            match handle.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn read_into(&mut self, buffer : &mut Vec<u8>, limit : Option<usize>) -> io::Result<()> {
+        //! reads until the end of the file, or until there are `limit`
+        //! bytes in the buffer
+
+        if let Some(byte) = self.peeked.take() {
+            buffer.push(byte);
+        }
+
+        let handle = self.handle()?;
+        let mut chunk = [0; BUFFER_SIZE];
+        loop {
+            let wanted = match limit {
+                Some(limit) if buffer.len() >= limit => return Ok(()),
+                Some(limit) => (limit - buffer.len()).min(BUFFER_SIZE),
+                None => BUFFER_SIZE,
+            };
+
+            match handle.read(&mut chunk[.. wanted]) {
+                Ok(0) => return Ok(()),
+                Ok(count) => buffer.extend_from_slice(&chunk[.. count]),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        //! the next line without the newline, `None` at the end of the file
+
+        let mut line = Vec::new();
+        loop {
+            match self.read_byte()? {
+                Some(b'\n') => return Ok(Some(line)),
+                Some(byte) => line.push(byte),
+                None if line.is_empty() => return Ok(None),
+                None => return Ok(Some(line)),
+            }
+        }
+    }
+
+    fn read_number(&mut self) -> io::Result<Option<f64>> {
+        //! reads a number the way `scanf("%lf")` does, taking as much as
+        //! could be part of a number and leaving the rest
+
+        let mut byte = self.read_byte()?;
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') | Some(0x0B) | Some(0x0C) = byte {
+            byte = self.read_byte()?;
+        }
+
+        let mut text : Vec<u8> = Vec::new();
+        let mut hex = false;
+        while let Some(next) = byte {
+            let fits = match (next, text.last()) {
+                (b'+', None) | (b'-', None) => true,
+                (b'+', Some(b'p')) | (b'-', Some(b'p')) | (b'+', Some(b'P')) | (b'-', Some(b'P')) => hex,
+                (b'+', Some(b'e')) | (b'-', Some(b'e')) | (b'+', Some(b'E')) | (b'-', Some(b'E')) => !hex,
+                (b'x', _) | (b'X', _) => !hex && text.iter().filter(|&&c| c != b'+' && c != b'-').eq([b'0'].iter()),
+                (b'0' ..= b'9', _) | (b'.', _) => true,
+                (b'a' ..= b'f', _) | (b'A' ..= b'F', _) if hex => true,
+                (b'e', _) | (b'E', _) => !text.is_empty(),
+                (b'p', _) | (b'P', _) => hex,
+                _ => false,
+            };
+
+            if !fits {
+                self.peeked = Some(next);
+                break;
+            }
+
+            hex = hex || next == b'x' || next == b'X';
+            text.push(next);
+            byte = self.read_byte()?;
+        }
+
+        Ok(string_to_number(&text))
+    }
+
+    fn seek(&mut self, position : SeekFrom) -> io::Result<u64> {
+        //! a byte that was looked at is given back to the file first
+
+        let position = match (self.peeked.take(), position) {
+            (Some(_), SeekFrom::Current(offset)) => SeekFrom::Current(offset - 1),
+            (_, position) => position,
+        };
+
+        self.handle()?.seek(position)
+    }
+
+    fn write(&mut self, bytes : &[u8]) -> io::Result<()> {
+        if self.peeked.is_some() {
+            self.seek(SeekFrom::Current(0))?;
+        }
+
+        self.handle()?.write(bytes)
+    }
+}
+
+fn with_io(io : &Rc<Io>, function : IoFunction) -> Value {
+    //! makes a lua function out of one that needs the shared io state
+
+    let io = io.clone();
+    Value::NativeClosure(Rc::new(move |interpreter : &Interpreter, args : Vec<Value>| function(&io, interpreter, args)))
+}
+
+fn with_file<T>(file : &AnyUserData, action : impl FnOnce(&mut LuaFile) -> T) -> T {
+    let mut data = file.data().borrow_mut();
+    action(data.downcast_mut::<LuaFile>().expect("userdata is a file"))
+}
+
+fn new_file(interpreter : &Interpreter, metatable : &Rc<RefCell<Table>>, handle : Box<dyn HostFile>, standard : bool) -> Value {
+    //! a file userdata, which is closed by its `__gc` once nothing is
+    //! using it
+
+    let file = Rc::new(AnyUserData::new(LuaFile { handle : Some(handle), standard, peeked : None }));
+    file.set_metatable(Some(metatable.clone()));
+    interpreter.watch_finalizer(&file);
+    Value::UserData(file)
+}
+
+fn open_file(io : &Io, interpreter : &Interpreter, name : &LuaString, mode : Option<OpenMode>) -> io::Result<Value> {
+    let mode = mode.ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+    let handle = interpreter.host().open(&name.to_string_lossy(), mode)?;
+    Ok(new_file(interpreter, &io.metatable, handle, false))
+}
+
+fn check_file(args : &[Value], n : usize, function : &str) -> Result<Rc<AnyUserData>,Error> {
+    //! the file at the arguement, it must still be open
+
+    match arg(args, n) {
+        Value::UserData(data) if data.is::<LuaFile>() => match with_file(&data, |file| file.handle.is_some()) {
+            true => Ok(data),
+            false => Err(RuntimeError::general("attempt to use a closed file")),
+        },
+        _ => Err(type_error(args, n, function, "file")),
+    }
+}
+
+fn default_file(file : &RefCell<Value>, name : &str) -> Result<Rc<AnyUserData>,Error> {
+    //! the default input or output, which must still be open
+
+    match &*file.borrow() {
+        Value::UserData(data) if with_file(data, |file| file.handle.is_some()) => Ok(data.clone()),
+        _ => Err(RuntimeError::general(&format!("standard {} file is closed", name))),
+    }
+}
+
+fn set_default(io : &Io, file : &RefCell<Value>, interpreter : &Interpreter, args : Vec<Value>, function : &str, mode : Option<OpenMode>) -> Result<Vec<Value>,Error> {
+    //! changes the default input or output to the file, or opens the
+    //! file if it is given by name, and gives back the current one
+
+    match arg(&args, 1) {
+        Value::Nil => { },
+        Value::String(name) => match open_file(io, interpreter, &name, mode) {
+            Ok(opened) => *file.borrow_mut() = opened,
+            Err(error) => return Err(arg_error(1, function, &format!("{}: {}", name, io_message(&error)))),
+        },
+        _ => {
+            let data = check_file(&args, 1, function)?;
+            *file.borrow_mut() = Value::UserData(data);
+        },
+    }
+
+    Ok(vec![file.borrow().clone()])
+}
+
+fn close_file(file : &Rc<AnyUserData>) -> Result<Vec<Value>,Error> {
+    let closed = with_file(file, |file| match file.standard {
+        true => None,
+        false => {
+            let mut handle = file.handle.take()?;
+            Some(handle.flush())
+        },
+    });
+
+    match closed {
+        Some(closed) => Ok(result(closed)),
+        None => Ok(vec![Value::Nil, Value::from("cannot close standard file")]),
+    }
+}
+
+fn read_formats(file : &Rc<AnyUserData>, args : &[Value], first : usize, function : &str) -> Result<Vec<Value>,Error> {
+    //! reads from the file for each of the formats starting at the
+    //! arguement `first`, stopping at the first one that fails
+
+    let formats = args.get(first - 1 ..).unwrap_or(&[]);
+    let formats = match formats.is_empty() {
+        true => vec![Value::from("*l")],
+        false => formats.to_vec(),
+    };
+
+    let mut values = Vec::new();
+    for (i, format) in formats.iter().enumerate() {
+        let n = first + i;
+
+        let read = with_file(file, |file| -> Result<io::Result<Option<Value>>,Error> {
+            Ok(match format {
+                Value::Number(count) if *count as usize == 0 => file.read_byte().map(|byte| byte.map(|byte| {
+                    file.peeked = Some(byte);
+                    Value::from("")
+                })),
+                Value::Number(count) => {
+                    let mut bytes = Vec::new();
+                    file.read_into(&mut bytes, Some(*count as usize))
+                        .map(|_| Some(bytes).filter(|bytes| !bytes.is_empty()).map(|bytes| Value::from(LuaString::from(bytes))))
+                },
+                Value::String(option) => match option.as_bytes() {
+                    [b'*', b'n', ..] => file.read_number().map(|number| number.map(Value::Number)),
+                    [b'*', b'l', ..] => file.read_line().map(|line| line.map(|line| Value::from(LuaString::from(line)))),
+                    [b'*', b'a', ..] => {
+                        let mut bytes = Vec::new();
+                        file.read_into(&mut bytes, None).map(|_| Some(Value::from(LuaString::from(bytes))))
+                    },
+                    [b'*', ..] => return Err(arg_error(n, function, "invalid format")),
+                    _ => return Err(arg_error(n, function, "invalid option")),
+                },
+                _ => return Err(arg_error(n, function, "invalid option")),
+            })
+        })?;
+
+        match read {
+            Ok(Some(value)) => values.push(value),
+            Ok(None) => {
+                values.push(Value::Nil);
+                break;
+            },
+            Err(error) => return Ok(io_failure(&error, None)),
+        }
+    }
+
+    Ok(values)
+}
+
+fn write_values(file : &Rc<AnyUserData>, args : &[Value], first : usize, function : &str) -> Result<Vec<Value>,Error> {
+    //! writes the arguements starting at `first`, numbers are written
+    //! the same way `tostring` would
+
+    for n in first ..= args.len() {
+        let string = match arg(args, n) {
+            Value::String(string) => string,
+            Value::Number(_) => check_string(args, n, function)?,
+            _ => return Err(type_error(args, n, function, "string")),
+        };
+
+        if let Err(error) = with_file(file, |file| file.write(string.as_bytes())) {
+            return Ok(io_failure(&error, None));
+        }
+    }
+
+    Ok(vec![Value::Boolean(true)])
+}
+
+fn lines_iterator(file : Rc<AnyUserData>, close_at_end : bool) -> Value {
+    //! the function that `lines` gives back for the generic `for`
+
+    Value::NativeClosure(Rc::new(move |_ : &Interpreter, _ : Vec<Value>| {
+        let line = with_file(&file, |file| match file.handle {
+            Some(_) => Ok(file.read_line()),
+            None => Err(RuntimeError::general("file is already closed")),
+        })?;
+
+        match line {
+            Ok(Some(line)) => Ok(vec![Value::from(LuaString::from(line))]),
+            Ok(None) => {
+                if close_at_end { close_file(&file)?; }
+                Ok(vec![Value::Nil])
+            },
+            Err(error) => Err(RuntimeError::general(&io_message(&error))),
+        }
+    }))
+}
+
+fn result(result : io::Result<()>) -> Vec<Value> {
+    match result {
+        Ok(()) => vec![Value::Boolean(true)],
+        Err(error) => io_failure(&error, None),
+    }
+}
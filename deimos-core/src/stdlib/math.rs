@@ -0,0 +1,347 @@
+//! the math library, https://www.lua.org/manual/5.1/manual.html#5.6
+//!
+//! everything is a `f64` like lua's numbers, so most of these are just
+//! the rust functions with the lua names.
+
+use std::rc::Rc;
+use std::cell::Cell;
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, Table};
+use crate::error::runtime::RuntimeError;
+use crate::stdlib::{arg_error, check_number, check_integer};
+
+pub fn load(interpreter : &Interpreter) {
+    let mut math = Table::new();
+
+    math.set_str("abs", Value::NativeFunction(abs));
+    math.set_str("acos", Value::NativeFunction(acos));
+    math.set_str("asin", Value::NativeFunction(asin));
+    math.set_str("atan", Value::NativeFunction(atan));
+    math.set_str("atan2", Value::NativeFunction(atan2));
+    math.set_str("ceil", Value::NativeFunction(ceil));
+    math.set_str("cos", Value::NativeFunction(cos));
+    math.set_str("cosh", Value::NativeFunction(cosh));
+    math.set_str("deg", Value::NativeFunction(deg));
+    math.set_str("exp", Value::NativeFunction(exp));
+    math.set_str("floor", Value::NativeFunction(floor));
+    math.set_str("fmod", Value::NativeFunction(fmod));
+    // the old name of `fmod`
+    math.set_str("mod", Value::NativeFunction(fmod));
+    math.set_str("frexp", Value::NativeFunction(frexp));
+    math.set_str("ldexp", Value::NativeFunction(ldexp));
+    math.set_str("log", Value::NativeFunction(log));
+    math.set_str("log10", Value::NativeFunction(log10));
+    math.set_str("max", Value::NativeFunction(max));
+    math.set_str("min", Value::NativeFunction(min));
+    math.set_str("modf", Value::NativeFunction(modf));
+    math.set_str("pow", Value::NativeFunction(pow));
+    math.set_str("rad", Value::NativeFunction(rad));
+    math.set_str("sin", Value::NativeFunction(sin));
+    math.set_str("sinh", Value::NativeFunction(sinh));
+    math.set_str("sqrt", Value::NativeFunction(sqrt));
+    math.set_str("tan", Value::NativeFunction(tan));
+    math.set_str("tanh", Value::NativeFunction(tanh));
+    math.set_str("huge", Value::Number(f64::INFINITY));
+    math.set_str("pi", Value::Number(std::f64::consts::PI));
+
+    // `random` and `randomseed` share the generator
+    let generator = Rc::new(Random::new(0));
+
+    let random = generator.clone();
+    math.set_str("random", Value::NativeClosure(Rc::new(move |_ : &Interpreter, args : Vec<Value>| random_number(&random, args))));
+    math.set_str("randomseed", Value::NativeClosure(Rc::new(move |_ : &Interpreter, args : Vec<Value>| {
+        // math.randomseed (x)
+        generator.seed(check_integer(&args, 1, "randomseed")?);
+        Ok(Vec::new())
+    })));
+
+    interpreter.set_global("math", Value::from(math));
+}
+
+fn abs(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.abs (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "abs")?.abs())])
+}
+
+fn acos(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.acos (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "acos")?.acos())])
+}
+
+fn asin(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.asin (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "asin")?.asin())])
+}
+
+fn atan(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.atan (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "atan")?.atan())])
+}
+
+fn atan2(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.atan2 (y, x)
+
+    let y = check_number(&args, 1, "atan2")?;
+    let x = check_number(&args, 2, "atan2")?;
+    Ok(vec![Value::Number(y.atan2(x))])
+}
+
+fn ceil(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.ceil (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "ceil")?.ceil())])
+}
+
+fn cos(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.cos (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "cos")?.cos())])
+}
+
+fn cosh(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.cosh (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "cosh")?.cosh())])
+}
+
+fn deg(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.deg (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "deg")?.to_degrees())])
+}
+
+fn exp(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.exp (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "exp")?.exp())])
+}
+
+fn floor(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.floor (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "floor")?.floor())])
+}
+
+fn fmod(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.fmod (x, y)
+
+    // rust's `%` is C's `fmod`, the sign is the same as `x`
+    let x = check_number(&args, 1, "fmod")?;
+    let y = check_number(&args, 2, "fmod")?;
+    Ok(vec![Value::Number(x % y)])
+}
+
+fn frexp(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.frexp (x)
+
+    let (mantissa, exponent) = split_exponent(check_number(&args, 1, "frexp")?);
+    Ok(vec![Value::Number(mantissa), Value::Number(f64::from(exponent))])
+}
+
+fn ldexp(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.ldexp (m, e)
+
+    let mantissa = check_number(&args, 1, "ldexp")?;
+    let exponent = check_integer(&args, 2, "ldexp")?.clamp(-2200, 2200) as i32;
+
+    // done in two steps so `2^e` doesn't overflow when the result wouldn't
+    let half = exponent / 2;
+    Ok(vec![Value::Number(mantissa * 2f64.powi(half) * 2f64.powi(exponent - half))])
+}
+
+fn log(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.log (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "log")?.ln())])
+}
+
+fn log10(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.log10 (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "log10")?.log10())])
+}
+
+fn max(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.max (x, ···)
+
+    let mut max = check_number(&args, 1, "max")?;
+    for n in 2 ..= args.len() {
+        let number = check_number(&args, n, "max")?;
+        if number > max { max = number; }
+    }
+
+    Ok(vec![Value::Number(max)])
+}
+
+fn min(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.min (x, ···)
+
+    let mut min = check_number(&args, 1, "min")?;
+    for n in 2 ..= args.len() {
+        let number = check_number(&args, n, "min")?;
+        if number < min { min = number; }
+    }
+
+    Ok(vec![Value::Number(min)])
+}
+
+fn modf(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.modf (x)
+
+    let number = check_number(&args, 1, "modf")?;
+    let fraction = match number.is_infinite() {
+        true => 0.0,
+        false => number.fract(),
+    };
+
+    Ok(vec![Value::Number(number.trunc()), Value::Number(fraction)])
+}
+
+fn pow(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.pow (x, y)
+
+    let x = check_number(&args, 1, "pow")?;
+    let y = check_number(&args, 2, "pow")?;
+    Ok(vec![Value::Number(x.powf(y))])
+}
+
+fn rad(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.rad (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "rad")?.to_radians())])
+}
+
+fn sin(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.sin (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "sin")?.sin())])
+}
+
+fn sinh(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.sinh (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "sinh")?.sinh())])
+}
+
+fn sqrt(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.sqrt (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "sqrt")?.sqrt())])
+}
+
+fn tan(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.tan (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "tan")?.tan())])
+}
+
+fn tanh(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.tanh (x)
+
+    Ok(vec![Value::Number(check_number(&args, 1, "tanh")?.tanh())])
+}
+
+fn random_number(generator : &Random, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! math.random ([m [, n]])
+
+    let random = generator.next();
+
+    let (low, high) = match args.len() {
+        0 => return Ok(vec![Value::Number(random)]),
+        1 => (1, check_integer(&args, 1, "random")?),
+        2 => (check_integer(&args, 1, "random")?, check_integer(&args, 2, "random")?),
+        _ => return Err(RuntimeError::general("wrong number of arguments")),
+    };
+
+    if low > high {
+        return Err(arg_error(args.len(), "random", "interval is empty"));
+    }
+
+    Ok(vec![Value::Number((random * (high - low + 1) as f64).floor() + low as f64)])
+}
+
+// PRIVATE FUNCTIONS /////////////////////////////////////
+
+fn split_exponent(number : f64) -> (f64, i32) {
+    //! C's `frexp`, the number is `mantissa * 2^exponent` where the
+    //! mantissa is between 0.5 and 1
+
+    if number == 0.0 || !number.is_finite() {
+        return (number, 0);
+    }
+
+    let bits = number.to_bits();
+    let exponent = ((bits >> 52) & 0x7FF) as i32;
+
+    // subnormal numbers don't have the leading 1, so we scale them up first
+    if exponent == 0 {
+        let (mantissa, exponent) = split_exponent(number * 2f64.powi(54));
+        return (mantissa, exponent - 54);
+    }
+
+    let mantissa = f64::from_bits((bits & !(0x7FF << 52)) | (1022 << 52));
+    (mantissa, exponent - 1022)
+}
+
+/// the random number generator, a xorshift so we don't depend on C's
+/// `rand`. the numbers won't be the same ones lua gives for a seed.
+struct Random {
+    state : Cell<u64>,
+}
+
+impl Random {
+    fn new(seed : i64) -> Random {
+        let random = Random { state : Cell::new(0) };
+        random.seed(seed);
+        random
+    }
+
+    fn seed(&self, seed : i64) {
+        // splitmix, so seeds that are close together still start far apart
+        // (and the state is never zero)
+        let mut z = (seed as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        self.state.set((z ^ (z >> 31)) | 1);
+    }
+
+    fn next(&self) -> f64 {
+        //! a number in `[0, 1)`
+
+        let mut x = self.state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state.set(x);
+
+        (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::stdlib::math::{split_exponent, Random};
+
+    #[test]
+    pub fn exponents_and_random() {
+        assert_eq!(split_exponent(8.0), (0.5, 4));
+        assert_eq!(split_exponent(-3.0), (-0.75, 2));
+        assert_eq!(split_exponent(0.0), (0.0, 0));
+        assert_eq!(split_exponent(f64::MIN_POSITIVE / 4.0), (0.5, -1023));
+
+        let random = Random::new(42);
+        let first : Vec<f64> = (0 .. 100).map(|_| random.next()).collect();
+        assert!(first.iter().all(|n| (0.0 .. 1.0).contains(n)));
+
+        random.seed(42);
+        assert_eq!(random.next(), first[0]);
+    }
+}
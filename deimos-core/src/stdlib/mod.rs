@@ -0,0 +1,160 @@
+//! the lua standard library, each part of the library is in its own
+//! module and gets loaded into the interpreter's globals.
+
+mod base;
+mod coroutine;
+mod string;
+mod pattern;
+pub(crate) mod format;
+mod math;
+mod table;
+mod io;
+mod os;
+pub(crate) mod package;
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, Table, LuaString};
+use crate::error::runtime::RuntimeError;
+use crate::host::OpenMode;
+use crate::chunk::chunk_id;
+use crate::bytecode::undump::SIGNATURE;
+
+pub fn load(interpreter : &Interpreter) {
+    //! loads all the standard library into the interpreter
+
+    base::load(interpreter);
+    coroutine::load(interpreter);
+    string::load(interpreter);
+    math::load(interpreter);
+    table::load(interpreter);
+    io::load(interpreter);
+    os::load(interpreter);
+    package::load(interpreter);
+}
+
+// HELPERS FOR THE LIBRARY FUNCTIONS ////////////////////
+
+pub(crate) fn arg(args : &[Value], n : usize) -> Value {
+    //! the `n`th arguement, starting at `1`, missing arguements are `nil`
+
+    args.get(n - 1).cloned().unwrap_or(Value::Nil)
+}
+
+pub(crate) fn arg_error(n : usize, function : &str, message : &str) -> Error {
+    RuntimeError::general(&format!("bad argument #{} to '{}' ({})", n, function, message))
+}
+
+pub(crate) fn type_error(args : &[Value], n : usize, function : &str, expected : &str) -> Error {
+    let got = match args.get(n - 1) {
+        Some(value) => value.type_name(),
+        None => "no value",
+    };
+
+    arg_error(n, function, &format!("{} expected, got {}", expected, got))
+}
+
+pub(crate) fn check_any(args : &[Value], n : usize, function : &str) -> Result<Value,Error> {
+    match args.get(n - 1) {
+        Some(value) => Ok(value.clone()),
+        None => Err(arg_error(n, function, "value expected")),
+    }
+}
+
+pub(crate) fn check_table(args : &[Value], n : usize, function : &str) -> Result<Rc<RefCell<Table>>,Error> {
+    match args.get(n - 1) {
+        Some(Value::Table(table)) => Ok(table.clone()),
+        _ => Err(type_error(args, n, function, "table")),
+    }
+}
+
+pub(crate) fn check_string(args : &[Value], n : usize, function : &str) -> Result<LuaString,Error> {
+    //! numbers are turned into strings, like `luaL_checklstring`
+
+    match args.get(n - 1).and_then(|value| value.to_lua_string()) {
+        Some(string) => Ok(string),
+        None => Err(type_error(args, n, function, "string")),
+    }
+}
+
+pub(crate) fn check_number(args : &[Value], n : usize, function : &str) -> Result<f64,Error> {
+    match args.get(n - 1).and_then(|value| value.to_number()) {
+        Some(number) => Ok(number),
+        None => Err(type_error(args, n, function, "number")),
+    }
+}
+
+pub(crate) fn check_integer(args : &[Value], n : usize, function : &str) -> Result<i64,Error> {
+    //! numbers are cut down to whole numbers, like `luaL_checkint`
+
+    Ok(check_number(args, n, function)? as i64)
+}
+
+pub(crate) fn opt_integer(args : &[Value], n : usize, function : &str, default : i64) -> Result<i64,Error> {
+    match args.get(n - 1) {
+        None | Some(Value::Nil) => Ok(default),
+        Some(_) => check_integer(args, n, function),
+    }
+}
+
+fn io_message(error : &std::io::Error) -> String {
+    //! the message without rust's "(os error 2)" at the end, so it looks
+    //! like C's `strerror`
+
+    let message = error.to_string();
+    match error.raw_os_error() {
+        Some(code) => message.trim_end_matches(&format!(" (os error {})", code)).to_string(),
+        None => message,
+    }
+}
+
+fn io_failure(error : &std::io::Error, file_name : Option<&LuaString>) -> Vec<Value> {
+    //! what the `io` and `os` functions give back when they fail, `nil`,
+    //! the message and the error number, like lua's `pushresult`
+
+    let message = match file_name {
+        Some(name) => format!("{}: {}", name, io_message(error)),
+        None => io_message(error),
+    };
+
+    vec![Value::Nil, Value::from(message.as_str()), Value::Number(error.raw_os_error().unwrap_or(0) as f64)]
+}
+
+fn load_file(interpreter : &Interpreter, path : Option<&str>) -> Result<Value,Error> {
+    //! compiles a file into a function, `None` reads the standard input.
+    //! the error is what went wrong opening, reading or compiling it.
+
+    let mut code = Vec::new();
+    let chunk_name = match path {
+        Some(path) => {
+            let mut file = interpreter.host().open(path, OpenMode::parse("r").expect("a mode"))
+                .map_err(|error| RuntimeError::general(&format!("cannot open {}: {}", path, io_message(&error))))?;
+            file.read_to_end(&mut code)
+                .map_err(|error| RuntimeError::general(&format!("cannot read {}: {}", path, io_message(&error))))?;
+            format!("@{}", path)
+        },
+        None => {
+            interpreter.host().stdin().read_to_end(&mut code)
+                .map_err(|error| RuntimeError::general(&format!("cannot read stdin: {}", io_message(&error))))?;
+            String::from("=stdin")
+        },
+    };
+
+    // the first line is skipped if it is a `#!`, but the line is kept
+    // so the line numbers stay the same
+    if code.first() == Some(&b'#') {
+        let end = code.iter().position(|&byte| byte == b'\n').unwrap_or(code.len());
+        code.drain(.. end);
+
+        // a binary chunk doesn't have lines to keep
+        if code.get(1) == Some(&SIGNATURE[0]) {
+            code.remove(0);
+        }
+    }
+
+    interpreter.load(&code, &chunk_id(chunk_name.as_bytes()))
+}
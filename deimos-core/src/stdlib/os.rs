@@ -0,0 +1,367 @@
+//! the os library, https://www.lua.org/manual/5.1/manual.html#5.8
+//!
+//! the clock, the environment and the files all come from the
+//! interpreter's host. the dates are worked out here from the host's
+//! time and timezone, only the "C" locale is known.
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, Table, LuaString};
+use crate::error::runtime::RuntimeError;
+use crate::host::Timezone;
+use crate::stdlib::{arg, arg_error, check_string, check_table, check_number, opt_integer, io_message, io_failure};
+
+const SHORT_DAYS : [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const DAYS : [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+const SHORT_MONTHS : [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+const MONTHS : [&str; 12] = ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"];
+
+pub fn load(interpreter : &Interpreter) {
+    let mut table = Table::new();
+
+    table.set_str("clock", Value::NativeFunction(clock));
+    table.set_str("date", Value::NativeFunction(date));
+    table.set_str("difftime", Value::NativeFunction(difftime));
+    table.set_str("exit", Value::NativeFunction(exit));
+    table.set_str("getenv", Value::NativeFunction(getenv));
+    table.set_str("remove", Value::NativeFunction(remove));
+    table.set_str("rename", Value::NativeFunction(rename));
+    table.set_str("setlocale", Value::NativeFunction(setlocale));
+    table.set_str("time", Value::NativeFunction(time));
+    table.set_str("tmpname", Value::NativeFunction(tmpname));
+
+    interpreter.set_global("os", Value::from(table));
+}
+
+fn clock(interpreter : &Interpreter, _ : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! os.clock ()
+
+    Ok(vec![Value::Number(interpreter.host().clock())])
+}
+
+fn date(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! os.date ([format [, time]])
+    //!
+    //! a format starting with `!` is in UTC instead of the local time.
+
+    let format = match arg(&args, 1) {
+        Value::Nil => LuaString::from("%c"),
+        _ => check_string(&args, 1, "date")?,
+    };
+    let time = match arg(&args, 2) {
+        Value::Nil => interpreter.host().time(),
+        _ => check_number(&args, 2, "date")? as i64,
+    };
+
+    let (format, zone) = match format.as_bytes() {
+        [b'!', format @ ..] => (format, Timezone::utc()),
+        format => (format, interpreter.host().timezone(time)),
+    };
+    let date = Date::new(time, zone);
+
+    if format.starts_with(b"*t") {
+        let mut table = Table::new();
+        table.set_str("year", Value::Number(date.year as f64));
+        table.set_str("month", Value::Number(date.month as f64));
+        table.set_str("day", Value::Number(date.day as f64));
+        table.set_str("hour", Value::Number(date.hour as f64));
+        table.set_str("min", Value::Number(date.minute as f64));
+        table.set_str("sec", Value::Number(date.second as f64));
+        table.set_str("wday", Value::Number((date.weekday + 1) as f64));
+        table.set_str("yday", Value::Number((date.yearday + 1) as f64));
+        table.set_str("isdst", Value::Boolean(date.zone.dst));
+        return Ok(vec![Value::from(table)]);
+    }
+
+    Ok(vec![Value::from(LuaString::from(date.format(format)))])
+}
+
+fn difftime(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! os.difftime (t2, t1)
+
+    let end = check_number(&args, 1, "difftime")?;
+    let start = match arg(&args, 2) {
+        Value::Nil => 0.0,
+        _ => check_number(&args, 2, "difftime")?,
+    };
+
+    Ok(vec![Value::Number(end - start)])
+}
+
+fn exit(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! os.exit ([code])
+
+    let code = opt_integer(&args, 1, "exit", 0)?;
+    match interpreter.host().exit(code as i32) {
+        Ok(()) => Ok(Vec::new()),
+        Err(error) => Err(RuntimeError::general(&format!("unable to exit: {}", io_message(&error)))),
+    }
+}
+
+fn getenv(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! os.getenv (varname)
+
+    let name = check_string(&args, 1, "getenv")?;
+    match interpreter.host().getenv(&name.to_string_lossy()) {
+        Some(value) => Ok(vec![Value::from(value.as_str())]),
+        None => Ok(vec![Value::Nil]),
+    }
+}
+
+fn remove(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! os.remove (filename)
+
+    let name = check_string(&args, 1, "remove")?;
+    match interpreter.host().remove(&name.to_string_lossy()) {
+        Ok(()) => Ok(vec![Value::Boolean(true)]),
+        Err(error) => Ok(io_failure(&error, Some(&name))),
+    }
+}
+
+fn rename(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! os.rename (oldname, newname)
+
+    let from = check_string(&args, 1, "rename")?;
+    let to = check_string(&args, 2, "rename")?;
+    match interpreter.host().rename(&from.to_string_lossy(), &to.to_string_lossy()) {
+        Ok(()) => Ok(vec![Value::Boolean(true)]),
+        Err(error) => Ok(io_failure(&error, Some(&from))),
+    }
+}
+
+fn setlocale(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! os.setlocale (locale [, category])
+    //!
+    //! only the "C" locale is there, asking for any other one fails.
+
+    let category = match arg(&args, 2) {
+        Value::Nil => LuaString::from("all"),
+        _ => check_string(&args, 2, "setlocale")?,
+    };
+    match category.as_bytes() {
+        b"all" | b"collate" | b"ctype" | b"monetary" | b"numeric" | b"time" => { },
+        _ => return Err(arg_error(2, "setlocale", &format!("invalid option '{}'", category))),
+    }
+
+    let locale = match arg(&args, 1) {
+        Value::Nil => return Ok(vec![Value::from("C")]),
+        _ => check_string(&args, 1, "setlocale")?,
+    };
+
+    match locale.as_bytes() {
+        b"" | b"C" | b"POSIX" => Ok(vec![Value::from("C")]),
+        _ => Ok(vec![Value::Nil]),
+    }
+}
+
+fn time(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! os.time ([table])
+
+    if arg(&args, 1).is_nil() {
+        return Ok(vec![Value::Number(interpreter.host().time() as f64)]);
+    }
+
+    let table = check_table(&args, 1, "time")?;
+    let table = table.borrow();
+    let field = |name : &str, default : Option<i64>| match table.get_str(name).to_number() {
+        Some(number) => Ok(number as i64),
+        None => default.ok_or_else(|| RuntimeError::general(&format!("field '{}' missing in date table", name))),
+    };
+
+    let second = field("sec", Some(0))?;
+    let minute = field("min", Some(0))?;
+    let hour = field("hour", Some(12))?;
+    let day = field("day", None)?;
+    let month = field("month", None)? - 1;
+    let year = field("year", None)?;
+    let dst = match table.get_str("isdst") {
+        Value::Nil => None,
+        value => Some(value.is_truthy()),
+    };
+
+    // the months and days can be out of range and still count
+    let days = days_from_civil(year + month.div_euclid(12), month.rem_euclid(12) + 1, 1) + day - 1;
+    let local = days * 86400 + hour * 3600 + minute * 60 + second;
+
+    // the offset depends on the time, which depends on the offset
+    let host = interpreter.host();
+    let guess = local - host.timezone(local).offset;
+    let zone = host.timezone(guess);
+    let time = match (dst, zone.dst) {
+        (Some(true), false) => local - zone.offset - 3600,
+        (Some(false), true) => local - zone.offset + 3600,
+        _ => local - zone.offset,
+    };
+
+    Ok(vec![Value::Number(time as f64)])
+}
+
+fn tmpname(interpreter : &Interpreter, _ : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! os.tmpname ()
+
+    match interpreter.host().tmpname() {
+        Ok(name) => Ok(vec![Value::from(name.as_str())]),
+        Err(_) => Err(RuntimeError::general("unable to generate a unique filename")),
+    }
+}
+
+// PRIVATE FUNCTIONS /////////////////////////////////////
+
+/// a moment in time split into the calendar, in a timezone
+struct Date {
+    year : i64,
+    month : i64,
+    day : i64,
+    hour : i64,
+    minute : i64,
+    second : i64,
+    // days since sunday
+    weekday : i64,
+    // days since the first of january
+    yearday : i64,
+    zone : Timezone,
+}
+
+impl Date {
+    fn new(time : i64, zone : Timezone) -> Date {
+        let local = time + zone.offset;
+        let days = local.div_euclid(86400);
+        let seconds = local.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+
+        Date {
+            year, month, day,
+            hour : seconds / 3600,
+            minute : seconds / 60 % 60,
+            second : seconds % 60,
+            // the first of january 1970 was a thursday
+            weekday : (days + 4).rem_euclid(7),
+            yearday : days - days_from_civil(year, 1, 1),
+            zone,
+        }
+    }
+
+    fn format(&self, format : &[u8]) -> Vec<u8> {
+        //! C's `strftime` in the "C" locale
+
+        let mut text = Vec::new();
+        let mut bytes = format.iter();
+
+        while let Some(&byte) = bytes.next() {
+            if byte != b'%' {
+                text.push(byte);
+                continue;
+            }
+
+            let conversion = match bytes.next() {
+                Some(&conversion) => conversion,
+                None => {
+                    text.push(b'%');
+                    break;
+                },
+            };
+
+            let part = match conversion {
+                b'a' => SHORT_DAYS[self.weekday as usize].to_string(),
+                b'A' => DAYS[self.weekday as usize].to_string(),
+                b'b' | b'h' => SHORT_MONTHS[self.month as usize - 1].to_string(),
+                b'B' => MONTHS[self.month as usize - 1].to_string(),
+                b'c' => String::from_utf8_lossy(&self.format(b"%a %b %e %H:%M:%S %Y")).into_owned(),
+                b'C' => format!("{:02}", self.year.div_euclid(100)),
+                b'd' => format!("{:02}", self.day),
+                b'D' | b'x' => String::from_utf8_lossy(&self.format(b"%m/%d/%y")).into_owned(),
+                b'e' => format!("{:2}", self.day),
+                b'F' => String::from_utf8_lossy(&self.format(b"%Y-%m-%d")).into_owned(),
+                b'H' => format!("{:02}", self.hour),
+                b'I' => format!("{:02}", (self.hour + 11) % 12 + 1),
+                b'j' => format!("{:03}", self.yearday + 1),
+                b'm' => format!("{:02}", self.month),
+                b'M' => format!("{:02}", self.minute),
+                b'n' => "\n".to_string(),
+                b'p' => if self.hour < 12 { "AM" } else { "PM" }.to_string(),
+                b'R' => String::from_utf8_lossy(&self.format(b"%H:%M")).into_owned(),
+                b'S' => format!("{:02}", self.second),
+                b't' => "\t".to_string(),
+                b'T' | b'X' => String::from_utf8_lossy(&self.format(b"%H:%M:%S")).into_owned(),
+                b'u' => format!("{}", (self.weekday + 6) % 7 + 1),
+                b'U' => format!("{:02}", (self.yearday + 7 - self.weekday) / 7),
+                b'w' => format!("{}", self.weekday),
+                b'W' => format!("{:02}", (self.yearday + 7 - (self.weekday + 6) % 7) / 7),
+                b'y' => format!("{:02}", self.year.rem_euclid(100)),
+                b'Y' => format!("{}", self.year),
+                b'z' => {
+                    let minutes = self.zone.offset.abs() / 60;
+                    let sign = if self.zone.offset < 0 { '-' } else { '+' };
+                    format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+                },
+                b'Z' => self.zone.name.clone(),
+                b'%' => "%".to_string(),
+                other => {
+                    text.extend_from_slice(&[b'%', other]);
+                    continue;
+                },
+            };
+
+            text.extend_from_slice(part.as_bytes());
+        }
+
+        text
+    }
+}
+
+fn days_from_civil(year : i64, month : i64, day : i64) -> i64 {
+    //! the days since the first of january 1970, months start at `1`.
+    //! from http://howardhinnant.github.io/date_algorithms.html
+
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days : i64) -> (i64, i64, i64) {
+    //! the year, month and day from the days since the first of january
+    //! 1970, the other way around from `days_from_civil`
+
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::host::Timezone;
+    use crate::stdlib::os::{Date, days_from_civil, civil_from_days};
+
+    #[test]
+    pub fn calendar() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+
+        for days in -1000 .. 1000 {
+            let (year, month, day) = civil_from_days(days * 37);
+            assert_eq!(days_from_civil(year, month, day), days * 37);
+        }
+
+        // 2000-10-01 23:12:17 UTC, a sunday
+        let date = Date::new(970441937, Timezone::utc());
+        assert_eq!(date.format(b"%Y-%m-%d %H:%M:%S %a %j %w"), b"2000-10-01 23:12:17 Sun 275 0");
+        assert_eq!(date.format(b"%c"), b"Sun Oct  1 23:12:17 2000");
+        assert_eq!(date.format(b"%x %X %p %I %%"), b"10/01/00 23:12:17 PM 11 %");
+    }
+}
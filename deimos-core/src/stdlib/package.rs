@@ -0,0 +1,293 @@
+//! the package library and `require`, https://www.lua.org/manual/5.1/manual.html#5.3
+//!
+//! modules are found by the functions in `package.loaders`, first the
+//! ones in `package.preload` and then lua files found with the templates
+//! in `package.path`. there are no C modules, `package.cpath` is only
+//! there for the scripts that look at it.
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, Table, LuaString, AnyUserData};
+use crate::error::runtime::RuntimeError;
+use crate::host::OpenMode;
+use crate::stdlib::{check_string, check_table, load_file};
+
+/// where modules are looked for when `LUA_PATH` isn't set
+const DEFAULT_PATH : &str = "./?.lua;/usr/local/share/lua/5.1/?.lua;/usr/local/share/lua/5.1/?/init.lua;/usr/local/lib/lua/5.1/?.lua;/usr/local/lib/lua/5.1/?/init.lua";
+
+/// where C modules would be looked for
+const DEFAULT_CPATH : &str = "./?.so;/usr/local/lib/lua/5.1/?.so;/usr/local/lib/lua/5.1/loadall.so";
+
+/// a function that uses the package table
+type PackageFunction = fn(&Rc<RefCell<Table>>, &Interpreter, Vec<Value>) -> Result<Vec<Value>,Error>;
+
+/// put in `package.loaded` while a module is loading, so a module that
+/// requires itself is caught
+struct Loading;
+
+pub fn load(interpreter : &Interpreter) {
+    let package = Rc::new(RefCell::new(Table::new()));
+
+    // the libraries that are already loaded
+    let mut loaded = Table::new();
+    for name in ["_G", "coroutine", "string", "math", "table", "io", "os"].iter() {
+        loaded.set_str(name, interpreter.get_global(name));
+    }
+    loaded.set_str("package", Value::Table(package.clone()));
+    let loaded = Rc::new(RefCell::new(loaded));
+
+    // `;;` in the environment's path is where the default one goes
+    let path = match interpreter.host().getenv("LUA_PATH") {
+        Some(path) => path.replace(";;", &format!(";{};", DEFAULT_PATH)),
+        None => DEFAULT_PATH.to_string(),
+    };
+
+    let loaders = Table::from_values(vec![
+        with_package(&package, preload_loader),
+        with_package(&package, lua_loader),
+    ]);
+
+    {
+        let mut package = package.borrow_mut();
+        package.set_str("path", Value::from(path.as_str()));
+        package.set_str("cpath", Value::from(DEFAULT_CPATH));
+        package.set_str("config", Value::from("/\n;\n?\n!\n-"));
+        package.set_str("loaded", Value::Table(loaded));
+        package.set_str("preload", Value::from(Table::new()));
+        package.set_str("loaders", Value::from(loaders));
+        package.set_str("seeall", Value::NativeFunction(seeall));
+    }
+
+    interpreter.set_global("module", with_package(&package, module));
+    interpreter.set_global("require", with_package(&package, require));
+    interpreter.set_global("package", Value::Table(package));
+}
+
+fn require(package : &Rc<RefCell<Table>>, interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! require (modname)
+    //!
+    //! asks each of the loaders for the module, the first one that
+    //! gives back a function is called to load it.
+
+    let name = check_string(&args, 1, "require")?;
+    let loaded = match package.borrow().get_str("loaded") {
+        Value::Table(loaded) => loaded,
+        _ => return Err(RuntimeError::general("'package.loaded' must be a table")),
+    };
+
+    match loaded.borrow().get(&Value::from(name.clone())) {
+        Value::UserData(data) if data.is::<Loading>() =>
+            return Err(RuntimeError::general(&format!("loop or previous error loading module '{}'", name))),
+        Value::Nil | Value::Boolean(false) => { },
+        module => return Ok(vec![module]),
+    }
+
+    let loaders = match package.borrow().get_str("loaders") {
+        Value::Table(loaders) => loaders,
+        _ => return Err(RuntimeError::general("'package.loaders' must be a table")),
+    };
+
+    // every loader that doesn't find it says where it looked
+    let mut searched = String::new();
+    let mut i = 1;
+    let loader = loop {
+        let loader = loaders.borrow().get_index(i);
+        if loader.is_nil() {
+            return Err(RuntimeError::general(&format!("module '{}' not found:{}", name, searched)));
+        }
+
+        match interpreter.call(&loader, vec![Value::from(name.clone())])?.into_iter().next() {
+            Some(found) if found.is_function() => break found,
+            Some(Value::String(message)) => searched.push_str(&message.to_string_lossy()),
+            _ => { },
+        }
+
+        i += 1;
+    };
+
+    let key = Value::from(name.clone());
+    loaded.borrow_mut().set(key.clone(), Value::UserData(Rc::new(AnyUserData::new(Loading))))?;
+
+    let module = interpreter.call(&loader, vec![key.clone()])?.into_iter().next().unwrap_or(Value::Nil);
+
+    // a module that didn't give back anything can still have set itself
+    let mut loaded = loaded.borrow_mut();
+    if !module.is_nil() {
+        loaded.set(key.clone(), module)?;
+    }
+
+    match loaded.get(&key) {
+        Value::UserData(data) if data.is::<Loading>() => {
+            loaded.set(key, Value::Boolean(true))?;
+            Ok(vec![Value::Boolean(true)])
+        },
+        module => Ok(vec![module]),
+    }
+}
+
+fn module(package : &Rc<RefCell<Table>>, interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! module (name [, ...])
+    //!
+    //! makes the module's table the environment of the chunk that called
+    //! it, then each option is called with the module.
+
+    let name = check_string(&args, 1, "module")?;
+    let loaded = match package.borrow().get_str("loaded") {
+        Value::Table(loaded) => loaded,
+        _ => return Err(RuntimeError::general("'package.loaded' must be a table")),
+    };
+
+    let key = Value::from(name.clone());
+    let existing = loaded.borrow().get(&key);
+    let module = match existing {
+        Value::Table(module) => module,
+        _ => {
+            let module = find_table(&interpreter.globals(), &name)
+                .ok_or_else(|| RuntimeError::general(&format!("name conflict for module '{}'", name)))?;
+            loaded.borrow_mut().set(key, Value::Table(module.clone()))?;
+            module
+        },
+    };
+
+    if module.borrow().get_str("_NAME").is_nil() {
+        // `_PACKAGE` is everything up to and including the last dot
+        let bytes = name.as_bytes();
+        let prefix = match bytes.iter().rposition(|c| *c == b'.') {
+            Some(dot) => &bytes[..=dot],
+            None => &bytes[..0],
+        };
+
+        let mut table = module.borrow_mut();
+        table.set_str("_M", Value::Table(module.clone()));
+        table.set_str("_NAME", Value::from(name.clone()));
+        table.set_str("_PACKAGE", Value::from(LuaString::from(prefix)));
+    }
+
+    match interpreter.function_at(1) {
+        Some(Some(function)) => function.set_environment(module.clone()),
+        _ => return Err(RuntimeError::general("'module' not called from a Lua function")),
+    }
+
+    for option in args.iter().skip(1) {
+        interpreter.call(option, vec![Value::Table(module.clone())])?;
+    }
+
+    Ok(Vec::new())
+}
+
+fn seeall(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! package.seeall (module)
+    //!
+    //! lets the module see the globals through its metatable
+
+    let module = check_table(&args, 1, "seeall")?;
+    let metatable = module.borrow().metatable();
+    let metatable = match metatable {
+        Some(metatable) => metatable,
+        None => {
+            let metatable = Rc::new(RefCell::new(Table::new()));
+            module.borrow_mut().set_metatable(Some(metatable.clone()));
+            metatable
+        },
+    };
+
+    metatable.borrow_mut().set_str("__index", Value::Table(interpreter.globals()));
+    Ok(Vec::new())
+}
+
+// LOADERS ///////////////////////////////////////////////
+
+fn preload_loader(package : &Rc<RefCell<Table>>, _ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! looks for the module in `package.preload`
+
+    let name = check_string(&args, 1, "require")?;
+    let preload = match package.borrow().get_str("preload") {
+        Value::Table(preload) => preload,
+        _ => return Err(RuntimeError::general("'package.preload' must be a table")),
+    };
+
+    let loader = preload.borrow().get(&Value::from(name.clone()));
+    match loader {
+        Value::Nil => Ok(vec![Value::from(format!("\n\tno field package.preload['{}']", name).as_str())]),
+        loader => Ok(vec![loader]),
+    }
+}
+
+fn lua_loader(package : &Rc<RefCell<Table>>, interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! looks for a lua file for the module using `package.path`
+
+    let name = check_string(&args, 1, "require")?;
+    let path = match package.borrow().get_str("path").to_lua_string() {
+        Some(path) => path,
+        None => return Err(RuntimeError::general("'package.path' must be a string")),
+    };
+
+    match find_file(interpreter, &name, &path) {
+        Ok(file) => match load_file(interpreter, Some(&file)) {
+            Ok(function) => Ok(vec![function]),
+            Err(error) => {
+                let message = interpreter.catch(error)?.tostring().to_string_lossy();
+                Err(RuntimeError::general(&format!("error loading module '{}' from file '{}':\n\t{}", name, file, message)))
+            },
+        },
+        Err(searched) => Ok(vec![Value::from(searched.as_str())]),
+    }
+}
+
+// PRIVATE FUNCTIONS /////////////////////////////////////
+
+fn with_package(package : &Rc<RefCell<Table>>, function : PackageFunction) -> Value {
+    //! the loaders and `require` use the package table they were made
+    //! with, even if the global `package` is changed
+
+    let package = package.clone();
+    Value::NativeClosure(Rc::new(move |interpreter : &Interpreter, args : Vec<Value>| function(&package, interpreter, args)))
+}
+
+pub(crate) fn find_table(globals : &Rc<RefCell<Table>>, name : &LuaString) -> Option<Rc<RefCell<Table>>> {
+    //! walks down the dotted name from the globals, making the tables
+    //! that aren't there. gives back nothing if something else is in the
+    //! way.
+
+    let mut table = globals.clone();
+    for part in name.as_bytes().split(|c| *c == b'.') {
+        let key = Value::from(LuaString::from(part));
+        let field = table.borrow().get(&key);
+        let next = match field {
+            Value::Table(next) => next,
+            Value::Nil => {
+                let next = Rc::new(RefCell::new(Table::new()));
+                table.borrow_mut().set(key, Value::Table(next.clone())).ok()?;
+                next
+            },
+            _ => return None,
+        };
+        table = next;
+    }
+
+    Some(table)
+}
+
+fn find_file(interpreter : &Interpreter, name : &LuaString, path : &LuaString) -> Result<String,String> {
+    //! fills in each template in the path with the module name, the dots
+    //! in the name are folders, and gives back the first one that can be
+    //! read. if none can, gives back all the files that were tried.
+
+    let name = name.to_string_lossy().replace('.', "/");
+    let mut searched = String::new();
+
+    for template in path.to_string_lossy().split(';').filter(|template| !template.is_empty()) {
+        let file = template.replace('?', &name);
+        if interpreter.host().open(&file, OpenMode::parse("r").expect("a mode")).is_ok() {
+            return Ok(file);
+        }
+
+        searched.push_str(&format!("\n\tno file '{}'", file));
+    }
+
+    Err(searched)
+}
@@ -0,0 +1,466 @@
+//! lua's patterns, https://www.lua.org/manual/5.1/manual.html#5.4.1
+//!
+//! this works the same way as `lstrlib.c` does, by walking through the
+//! pattern and the string together and backtracking when something doesn't
+//! match. everything works on bytes, like lua, so the character classes are
+//! the ones from the "C" locale.
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, LuaString};
+use crate::error::runtime::RuntimeError;
+
+/// the most captures a pattern can have
+const MAX_CAPTURES : usize = 32;
+
+const ESCAPE : u8 = b'%';
+
+/// the characters that make a pattern more than a plain string
+const SPECIALS : &[u8] = b"^$*+?.([%-";
+
+#[derive(Clone,Copy)]
+enum CaptureLength {
+    // the capture was opened but hasn't been closed yet
+    Unfinished,
+    // a `()` capture, that captures where it is
+    Position,
+    Closed(usize),
+}
+
+/// something captured by the pattern
+pub enum Capture<'a> {
+    Bytes(&'a [u8]),
+    Position(usize),
+}
+
+impl<'a> Capture<'a> {
+    pub fn to_value(&self) -> Value {
+        //! the capture as lua sees it, positions start at `1`
+
+        match self {
+            Capture::Bytes(bytes) => Value::String(LuaString::new(bytes)),
+            Capture::Position(position) => Value::Number((*position + 1) as f64),
+        }
+    }
+}
+
+pub fn has_specials(pattern : &[u8]) -> bool {
+    //! if the pattern has anything that isn't matched as it is
+
+    pattern.iter().any(|c| SPECIALS.contains(c))
+}
+
+pub fn find_plain(interpreter : &Interpreter, source : &[u8], pattern : &[u8], init : usize) -> Result<Option<usize>,Error> {
+    //! where the pattern first shows up in the source at or after `init`,
+    //! without looking at any special characters
+
+    if pattern.is_empty() {
+        return Ok(Some(init));
+    }
+
+    for (position, window) in source[init ..].windows(pattern.len()).enumerate() {
+        interpreter.count_step()?;
+        if window == pattern {
+            return Ok(Some(position + init));
+        }
+    }
+
+    Ok(None)
+}
+
+pub struct Matcher<'a> {
+    // the limits are checked as it goes, backtracking can take forever
+    interpreter : &'a Interpreter,
+    source : &'a [u8],
+    pattern : &'a [u8],
+    level : usize,
+    captures : [(usize, CaptureLength); MAX_CAPTURES],
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new(interpreter : &'a Interpreter, source : &'a [u8], pattern : &'a [u8]) -> Matcher<'a> {
+        Matcher {
+            interpreter,
+            source,
+            pattern,
+            level : 0,
+            captures : [(0, CaptureLength::Unfinished); MAX_CAPTURES],
+        }
+    }
+
+    pub fn source(&self) -> &'a [u8] {
+        self.source
+    }
+
+    pub fn find(&mut self, start : usize, pattern_start : usize) -> Result<Option<usize>,Error> {
+        //! tries to match the pattern (from `pattern_start`) right at `start`
+        //! in the source, gives where the match ends
+
+        self.level = 0;
+        self.do_match(start, pattern_start)
+    }
+
+    pub fn captures(&self, start : usize, end : usize, whole : bool) -> Result<Vec<Capture<'a>>,Error> {
+        //! the captures of the last match, when the pattern doesn't have any
+        //! the whole match is the only capture if `whole` is set
+
+        let count = if self.level == 0 && whole { 1 } else { self.level };
+        (0 .. count).map(|i| self.capture(i, start, end)).collect()
+    }
+
+    pub fn capture(&self, i : usize, start : usize, end : usize) -> Result<Capture<'a>,Error> {
+        //! one of the captures of the last match, the first capture is the
+        //! whole match if there aren't any
+
+        if i >= self.level {
+            return match i {
+                0 => Ok(Capture::Bytes(&self.source[start .. end])),
+                _ => Err(RuntimeError::general("invalid capture index")),
+            };
+        }
+
+        match self.captures[i] {
+            (_, CaptureLength::Unfinished) => Err(RuntimeError::general("unfinished capture")),
+            (init, CaptureLength::Position) => Ok(Capture::Position(init)),
+            (init, CaptureLength::Closed(len)) => Ok(Capture::Bytes(&self.source[init .. init + len])),
+        }
+    }
+
+    // PRIVATE FUNCTIONS /////////////////////////////////////
+
+    fn pattern_at(&self, p : usize) -> Option<u8> {
+        self.pattern.get(p).cloned()
+    }
+
+    fn do_match(&mut self, mut s : usize, mut p : usize) -> Result<Option<usize>,Error> {
+        //! matches the pattern from `p` against the source from `s`, the
+        //! loop is for the cases that would just call this again at the end
+
+        self.interpreter.count_step()?;
+
+        loop {
+            let c = match self.pattern_at(p) {
+                // the end of the pattern, everything matched
+                None => return Ok(Some(s)),
+                Some(c) => c,
+            };
+
+            match c {
+                b'(' => return match self.pattern_at(p + 1) {
+                    Some(b')') => self.start_capture(s, p + 2, CaptureLength::Position),
+                    _ => self.start_capture(s, p + 1, CaptureLength::Unfinished),
+                },
+                b')' => return self.end_capture(s, p + 1),
+                b'$' if p + 1 == self.pattern.len() => {
+                    return Ok(if s == self.source.len() { Some(s) } else { None });
+                },
+                ESCAPE if self.pattern_at(p + 1) == Some(b'b') => {
+                    match self.match_balance(s, p + 2)? {
+                        Some(end) => { s = end; p += 4; },
+                        None => return Ok(None),
+                    }
+                },
+                ESCAPE if self.pattern_at(p + 1) == Some(b'f') => {
+                    p += 2;
+                    if self.pattern_at(p) != Some(b'[') {
+                        return Err(RuntimeError::general("missing '[' after '%f' in pattern"));
+                    }
+
+                    let ep = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.source[s - 1] };
+                    let current = self.source.get(s).cloned().unwrap_or(0);
+                    if self.match_bracket_class(previous, p, ep - 1) || !self.match_bracket_class(current, p, ep - 1) {
+                        return Ok(None);
+                    }
+                    p = ep;
+                },
+                ESCAPE if self.pattern_at(p + 1).map(|c| c.is_ascii_digit()).unwrap_or(false) => {
+                    match self.match_capture(s, self.pattern[p + 1])? {
+                        Some(end) => { s = end; p += 2; },
+                        None => return Ok(None),
+                    }
+                },
+                _ => {
+                    let ep = self.class_end(p)?;
+                    let matches = s < self.source.len() && self.single_match(self.source[s], p, ep);
+
+                    match self.pattern_at(ep) {
+                        Some(b'?') => {
+                            if matches {
+                                if let Some(end) = self.do_match(s + 1, ep + 1)? {
+                                    return Ok(Some(end));
+                                }
+                            }
+                            p = ep + 1;
+                        },
+                        Some(b'*') => return self.max_expand(s, p, ep),
+                        Some(b'+') => return match matches {
+                            true => self.max_expand(s + 1, p, ep),
+                            false => Ok(None),
+                        },
+                        Some(b'-') => return self.min_expand(s, p, ep),
+                        _ => {
+                            if !matches { return Ok(None); }
+                            s += 1;
+                            p = ep;
+                        },
+                    }
+                },
+            }
+        }
+    }
+
+    fn class_end(&self, p : usize) -> Result<usize,Error> {
+        //! where the single character class starting at `p` ends
+
+        let mut p = p;
+        let c = self.pattern[p];
+        p += 1;
+
+        match c {
+            ESCAPE => match self.pattern_at(p) {
+                None => Err(RuntimeError::general("malformed pattern (ends with '%')")),
+                Some(_) => Ok(p + 1),
+            },
+            b'[' => {
+                if self.pattern_at(p) == Some(b'^') { p += 1; }
+
+                // the first `]` is part of the set
+                loop {
+                    let c = match self.pattern_at(p) {
+                        None => return Err(RuntimeError::general("malformed pattern (missing ']')")),
+                        Some(c) => c,
+                    };
+                    p += 1;
+
+                    if c == ESCAPE && p < self.pattern.len() {
+                        p += 1;
+                    }
+
+                    if self.pattern_at(p) == Some(b']') {
+                        return Ok(p + 1);
+                    }
+                }
+            },
+            _ => Ok(p),
+        }
+    }
+
+    fn single_match(&self, c : u8, p : usize, ep : usize) -> bool {
+        match self.pattern[p] {
+            b'.' => true,
+            ESCAPE => match_class(c, self.pattern[p + 1]),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    fn match_bracket_class(&self, c : u8, p : usize, ec : usize) -> bool {
+        //! if the character is in the set `[...]`, `p` is on the `[` and
+        //! `ec` is on the `]`
+
+        let mut p = p;
+        let mut found = true;
+        if self.pattern[p + 1] == b'^' {
+            found = false;
+            p += 1;
+        }
+
+        p += 1;
+        while p < ec {
+            if self.pattern[p] == ESCAPE {
+                p += 1;
+                if match_class(c, self.pattern[p]) {
+                    return found;
+                }
+            } else if self.pattern[p + 1] == b'-' && p + 2 < ec {
+                if self.pattern[p] <= c && c <= self.pattern[p + 2] {
+                    return found;
+                }
+                p += 2;
+            } else if self.pattern[p] == c {
+                return found;
+            }
+            p += 1;
+        }
+
+        !found
+    }
+
+    fn match_balance(&self, s : usize, p : usize) -> Result<Option<usize>,Error> {
+        //! `%bxy`, a string that starts with `x` and ends with the `y`
+        //! that balances it
+
+        let (open, close) = match (self.pattern_at(p), self.pattern_at(p + 1)) {
+            (Some(open), Some(close)) => (open, close),
+            _ => return Err(RuntimeError::general("unbalanced pattern")),
+        };
+
+        if self.source.get(s) != Some(&open) {
+            return Ok(None);
+        }
+
+        let mut depth = 1;
+        for i in s + 1 .. self.source.len() {
+            let c = self.source[i];
+            if c == close {
+                depth -= 1;
+                if depth == 0 { return Ok(Some(i + 1)); }
+            } else if c == open {
+                depth += 1;
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn max_expand(&mut self, s : usize, p : usize, ep : usize) -> Result<Option<usize>,Error> {
+        //! matches as many as it can and then backs off until the rest
+        //! of the pattern matches
+
+        let mut count = 0;
+        while s + count < self.source.len() && self.single_match(self.source[s + count], p, ep) {
+            count += 1;
+        }
+
+        loop {
+            if let Some(end) = self.do_match(s + count, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if count == 0 { return Ok(None); }
+            count -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, s : usize, p : usize, ep : usize) -> Result<Option<usize>,Error> {
+        //! matches as few as it can, adding more until the rest of
+        //! the pattern matches
+
+        let mut s = s;
+        loop {
+            if let Some(end) = self.do_match(s, ep + 1)? {
+                return Ok(Some(end));
+            }
+
+            if s < self.source.len() && self.single_match(self.source[s], p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(&mut self, s : usize, p : usize, what : CaptureLength) -> Result<Option<usize>,Error> {
+        if self.level >= MAX_CAPTURES {
+            return Err(RuntimeError::general("too many captures"));
+        }
+
+        self.captures[self.level] = (s, what);
+        self.level += 1;
+
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.level -= 1;
+        }
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s : usize, p : usize) -> Result<Option<usize>,Error> {
+        let open = (0 .. self.level).rev()
+            .find(|&i| matches!(self.captures[i].1, CaptureLength::Unfinished));
+
+        let i = match open {
+            Some(i) => i,
+            None => return Err(RuntimeError::general("invalid pattern capture")),
+        };
+
+        self.captures[i].1 = CaptureLength::Closed(s - self.captures[i].0);
+
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures[i].1 = CaptureLength::Unfinished;
+        }
+        Ok(result)
+    }
+
+    fn match_capture(&self, s : usize, digit : u8) -> Result<Option<usize>,Error> {
+        //! `%1`, matches the same text a capture did
+
+        let captured = match (digit as usize).checked_sub(b'1' as usize) {
+            Some(i) if i < self.level => match self.captures[i] {
+                (init, CaptureLength::Closed(len)) => &self.source[init .. init + len],
+                (_, CaptureLength::Position) => &self.source[0 .. 0],
+                (_, CaptureLength::Unfinished) => return Err(RuntimeError::general("invalid capture index")),
+            },
+            _ => return Err(RuntimeError::general("invalid capture index")),
+        };
+
+        match self.source[s ..].starts_with(captured) {
+            true => Ok(Some(s + captured.len())),
+            false => Ok(None),
+        }
+    }
+}
+
+fn match_class(c : u8, class : u8) -> bool {
+    //! if the character is in the class `%x`, the upper case classes
+    //! are everything that isn't in the lower case one
+
+    let matches = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => c == b' ' || (0x09 ..= 0x0D).contains(&c),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        b'z' => c == 0,
+        _ => return class == c,
+    };
+
+    match class.is_ascii_uppercase() {
+        true => !matches,
+        false => matches,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::interpreter::Interpreter;
+    use crate::stdlib::pattern::{Matcher, Capture};
+
+    fn find(source : &str, pattern : &str) -> Option<(usize, usize)> {
+        let interpreter = Interpreter::new();
+        let mut matcher = Matcher::new(&interpreter, source.as_bytes(), pattern.as_bytes());
+        (0 ..= source.len()).find_map(|start| matcher.find(start, 0).unwrap().map(|end| (start, end)))
+    }
+
+    #[test]
+    pub fn matching() {
+        assert_eq!(find("aaab", "a*"), Some((0, 3)));
+        assert_eq!(find("aaab", "a-b"), Some((0, 4)));
+        assert_eq!(find("a$a", ".$"), Some((2, 3)));
+        assert_eq!(find("x(a(b)c)y", "%b()"), Some((1, 8)));
+        assert_eq!(find("THE (quick) fox", "%f[%a]%a+"), Some((0, 3)));
+        assert_eq!(find("]]]a", "[^]]"), Some((3, 4)));
+        assert_eq!(find("alo alo", "(%w+) %1"), Some((0, 7)));
+        assert_eq!(find("abc", "%d"), None);
+
+        let interpreter = Interpreter::new();
+        let mut matcher = Matcher::new(&interpreter, b"hello world", b"(o)()");
+        let end = matcher.find(4, 0).unwrap().unwrap();
+        match matcher.captures(4, end, true).unwrap().as_slice() {
+            [Capture::Bytes(b"o"), Capture::Position(5)] => { },
+            _ => panic!("wrong captures"),
+        }
+
+        assert!(Matcher::new(&interpreter, b"a", b"%").find(0, 0).is_err());
+        assert!(Matcher::new(&interpreter, b"a", b"[a").find(0, 0).is_err());
+        assert!(Matcher::new(&interpreter, b"a", b"(a").find(0, 0).is_ok());
+        assert!(Matcher::new(&interpreter, b"a", b"a)").find(0, 0).is_err());
+    }
+}
@@ -0,0 +1,519 @@
+//! the string library, https://www.lua.org/manual/5.1/manual.html#5.4
+//!
+//! strings also get a metatable that points back at this library, so
+//! `s:upper()` works the same as `string.upper(s)`.
+
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, Table, LuaString};
+use crate::error::runtime::RuntimeError;
+use crate::bytecode;
+use crate::stdlib::{arg, arg_error, type_error, check_any, check_string, check_number, check_integer, opt_integer};
+use crate::stdlib::pattern::{self, Matcher};
+use crate::stdlib::format::Spec;
+
+/// the longest string `string.rep` will make, lua keeps the length of a
+/// string in an `int`
+const MAX_LENGTH : usize = i32::MAX as usize;
+
+pub fn load(interpreter : &Interpreter) {
+    let mut string = Table::new();
+
+    string.set_str("len", Value::NativeFunction(len));
+    string.set_str("sub", Value::NativeFunction(sub));
+    string.set_str("upper", Value::NativeFunction(upper));
+    string.set_str("lower", Value::NativeFunction(lower));
+    string.set_str("rep", Value::NativeFunction(rep));
+    string.set_str("reverse", Value::NativeFunction(reverse));
+    string.set_str("byte", Value::NativeFunction(byte));
+    string.set_str("char", Value::NativeFunction(char));
+    string.set_str("format", Value::NativeFunction(format));
+    string.set_str("find", Value::NativeFunction(find));
+    string.set_str("match", Value::NativeFunction(string_match));
+    string.set_str("gmatch", Value::NativeFunction(gmatch));
+    // the old name of `gmatch`
+    string.set_str("gfind", Value::NativeFunction(gmatch));
+    string.set_str("gsub", Value::NativeFunction(gsub));
+    string.set_str("dump", Value::NativeFunction(dump));
+
+    let string = Rc::new(RefCell::new(string));
+
+    let mut metatable = Table::new();
+    metatable.set_str("__index", Value::Table(string.clone()));
+    interpreter.set_type_metatable("string", Some(Rc::new(RefCell::new(metatable))));
+
+    interpreter.set_global("string", Value::Table(string));
+}
+
+fn len(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! string.len (s)
+
+    let string = check_string(&args, 1, "len")?;
+    Ok(vec![Value::Number(string.len() as f64)])
+}
+
+fn sub(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! string.sub (s, i [, j])
+
+    let string = check_string(&args, 1, "sub")?;
+    let length = string.len() as i64;
+
+    let start = relative(check_integer(&args, 2, "sub")?, length).max(1);
+    let end = relative(opt_integer(&args, 3, "sub", -1)?, length).min(length);
+
+    match start <= end {
+        true => Ok(vec![Value::from(LuaString::new(&string.as_bytes()[start as usize - 1 .. end as usize]))]),
+        false => Ok(vec![Value::from("")]),
+    }
+}
+
+fn upper(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! string.upper (s)
+
+    let string = check_string(&args, 1, "upper")?;
+    Ok(vec![Value::from(LuaString::from(string.as_bytes().to_ascii_uppercase()))])
+}
+
+fn lower(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! string.lower (s)
+
+    let string = check_string(&args, 1, "lower")?;
+    Ok(vec![Value::from(LuaString::from(string.as_bytes().to_ascii_lowercase()))])
+}
+
+fn rep(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! string.rep (s, n)
+
+    let string = check_string(&args, 1, "rep")?;
+    let count = check_integer(&args, 2, "rep")?.max(0) as usize;
+
+    interpreter.check_allocation(string.len().saturating_mul(count))?;
+
+    let length = match string.len().checked_mul(count) {
+        Some(length) if length <= MAX_LENGTH => length,
+        _ => return Err(RuntimeError::general("resulting string too large")),
+    };
+
+    // the allocation can still fail without a memory limit, which should
+    // be an error and not take the program down
+    let mut bytes = Vec::new();
+    if bytes.try_reserve_exact(length).is_err() {
+        return Err(RuntimeError::general("not enough memory"));
+    }
+    // nothing to copy, but there can still be a lot of nothing to go through
+    if !string.is_empty() {
+        for _ in 0 .. count {
+            bytes.extend_from_slice(string.as_bytes());
+        }
+    }
+
+    Ok(vec![Value::from(LuaString::from(bytes))])
+}
+
+fn reverse(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! string.reverse (s)
+
+    let string = check_string(&args, 1, "reverse")?;
+    let mut bytes = string.as_bytes().to_vec();
+    bytes.reverse();
+
+    Ok(vec![Value::from(LuaString::from(bytes))])
+}
+
+fn byte(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! string.byte (s [, i [, j]])
+
+    let string = check_string(&args, 1, "byte")?;
+    let length = string.len() as i64;
+
+    let start = relative(opt_integer(&args, 2, "byte", 1)?, length);
+    let end = relative(opt_integer(&args, 3, "byte", start)?, length).min(length);
+    let start = start.max(1);
+
+    if start > end {
+        return Ok(Vec::new());
+    }
+
+    Ok(string.as_bytes()[start as usize - 1 .. end as usize].iter()
+        .map(|byte| Value::Number(f64::from(*byte)))
+        .collect())
+}
+
+fn char(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! string.char (···)
+
+    let mut bytes = Vec::with_capacity(args.len());
+    for n in 1 ..= args.len() {
+        match check_integer(&args, n, "char")? {
+            code @ 0 ..= 255 => bytes.push(code as u8),
+            _ => return Err(arg_error(n, "char", "invalid value")),
+        }
+    }
+
+    Ok(vec![Value::from(LuaString::from(bytes))])
+}
+
+fn format(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! string.format (formatstring, ···)
+
+    let format = check_string(&args, 1, "format")?;
+    let format = format.as_bytes();
+
+    let mut result : Vec<u8> = Vec::with_capacity(format.len());
+    let mut n = 1;
+    let mut i = 0;
+
+    while i < format.len() {
+        if format[i] != b'%' {
+            result.push(format[i]);
+            i += 1;
+            continue;
+        }
+
+        i += 1;
+        if format.get(i) == Some(&b'%') {
+            result.push(b'%');
+            i += 1;
+            continue;
+        }
+
+        let (spec, end) = scan_format(format, i)?;
+        i = end + 1;
+
+        let conversion = match format.get(end) {
+            Some(conversion) => *conversion,
+            None => return Err(RuntimeError::general("invalid option '%' to 'format'")),
+        };
+
+        n += 1;
+        if n > args.len() {
+            return Err(arg_error(n, "format", "no value"));
+        }
+
+        match conversion {
+            b'c' => result.extend(spec.pad(&[check_number(&args, n, "format")? as i64 as u8])),
+            b'd' | b'i' => result.extend(spec.integer(check_number(&args, n, "format")? as i64)),
+            b'o' | b'u' | b'x' | b'X' => {
+                let number = check_number(&args, n, "format")?;
+                // negative numbers wrap around, like they do in C
+                let number = if number < 0.0 { number as i64 as u64 } else { number as u64 };
+                result.extend(spec.unsigned(number, conversion));
+            },
+            b'e' | b'E' | b'f' | b'g' | b'G' => result.extend(spec.float(check_number(&args, n, "format")?, conversion)),
+            b'q' => add_quoted(&mut result, check_string(&args, n, "format")?.as_bytes()),
+            b's' => {
+                let string = check_string(&args, n, "format")?;
+                result.extend(spec.string(string.as_bytes()));
+            },
+            conversion => {
+                let option = String::from_utf8_lossy(&[conversion]).to_string();
+                return Err(RuntimeError::general(&format!("invalid option '%{}' to 'format'", option)));
+            },
+        }
+    }
+
+    Ok(vec![Value::from(LuaString::from(result))])
+}
+
+fn find(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! string.find (s, pattern [, init [, plain]])
+
+    find_or_match(interpreter, args, true)
+}
+
+fn string_match(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! string.match (s, pattern [, init])
+
+    find_or_match(interpreter, args, false)
+}
+
+fn gmatch(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! string.gmatch (s, pattern)
+
+    let string = check_string(&args, 1, "gmatch")?;
+    let pattern = check_string(&args, 2, "gmatch")?;
+    let position = Cell::new(0);
+
+    let iterator = move |interpreter : &Interpreter, _ : Vec<Value>| {
+        let source = string.as_bytes();
+        let mut matcher = Matcher::new(interpreter, source, pattern.as_bytes());
+
+        for start in position.get() ..= source.len() {
+            if let Some(end) = matcher.find(start, 0)? {
+                // an empty match still has to move forward
+                position.set(if end == start { end + 1 } else { end });
+                return captures(&matcher, start, end, true);
+            }
+        }
+
+        position.set(source.len() + 1);
+        Ok(Vec::new())
+    };
+
+    Ok(vec![Value::NativeClosure(Rc::new(iterator))])
+}
+
+fn gsub(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! string.gsub (s, pattern, repl [, n])
+
+    let string = check_string(&args, 1, "gsub")?;
+    let pattern = check_string(&args, 2, "gsub")?;
+    let replacement = check_any(&args, 3, "gsub")?;
+    let source = string.as_bytes();
+
+    match replacement {
+        Value::Number(_) | Value::String(_) | Value::Table(_) => { },
+        ref value if value.is_function() => { },
+        _ => return Err(arg_error(3, "gsub", "string/function/table expected")),
+    }
+
+    let max = opt_integer(&args, 4, "gsub", source.len() as i64 + 1)?;
+    let (anchor, pattern_start) = anchored(pattern.as_bytes());
+
+    let mut matcher = Matcher::new(interpreter, source, pattern.as_bytes());
+    let mut result : Vec<u8> = Vec::with_capacity(source.len());
+    let mut count = 0;
+    let mut s = 0;
+
+    while count < max {
+        let end = matcher.find(s, pattern_start)?;
+
+        if let Some(end) = end {
+            count += 1;
+            add_value(interpreter, &mut result, &matcher, s, end, &replacement)?;
+        }
+
+        match end {
+            Some(end) if end > s => s = end,
+            _ if s < source.len() => {
+                result.push(source[s]);
+                s += 1;
+            },
+            _ => break,
+        }
+
+        if anchor { break; }
+    }
+
+    result.extend_from_slice(&source[s ..]);
+
+    Ok(vec![Value::from(LuaString::from(result)), Value::Number(count as f64)])
+}
+
+fn dump(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! string.dump (function)
+
+    match args.first() {
+        Some(Value::Function(function)) => Ok(vec![Value::String(LuaString::new(&bytecode::dump::dump(function.proto())))]),
+        Some(Value::NativeFunction(_)) | Some(Value::NativeClosure(_)) => Err(RuntimeError::general("unable to dump given function")),
+        _ => Err(type_error(&args, 1, "dump", "function")),
+    }
+}
+
+// PRIVATE FUNCTIONS /////////////////////////////////////
+
+fn relative(position : i64, length : i64) -> i64 {
+    //! negative positions count back from the end of the string
+
+    match position < 0 {
+        true => (length + position + 1).max(0),
+        false => position,
+    }
+}
+
+fn anchored(pattern : &[u8]) -> (bool, usize) {
+    //! if the pattern has to match at the start, and where the rest of
+    //! the pattern starts
+
+    match pattern.first() {
+        Some(b'^') => (true, 1),
+        _ => (false, 0),
+    }
+}
+
+fn captures(matcher : &Matcher, start : usize, end : usize, whole : bool) -> Result<Vec<Value>,Error> {
+    Ok(matcher.captures(start, end, whole)?.iter().map(|capture| capture.to_value()).collect())
+}
+
+fn find_or_match(interpreter : &Interpreter, args : Vec<Value>, find : bool) -> Result<Vec<Value>,Error> {
+    let function = if find { "find" } else { "match" };
+
+    let string = check_string(&args, 1, function)?;
+    let pattern = check_string(&args, 2, function)?;
+    let source = string.as_bytes();
+
+    let init = relative(opt_integer(&args, 3, function, 1)?, source.len() as i64) - 1;
+    let init = init.max(0).min(source.len() as i64) as usize;
+
+    if find && (arg(&args, 4).is_truthy() || !pattern::has_specials(pattern.as_bytes())) {
+        return match pattern::find_plain(interpreter, source, pattern.as_bytes(), init)? {
+            Some(start) => Ok(vec![Value::Number((start + 1) as f64), Value::Number((start + pattern.len()) as f64)]),
+            None => Ok(vec![Value::Nil]),
+        };
+    }
+
+    let (anchor, pattern_start) = anchored(pattern.as_bytes());
+    let mut matcher = Matcher::new(interpreter, source, pattern.as_bytes());
+
+    for start in init ..= source.len() {
+        if let Some(end) = matcher.find(start, pattern_start)? {
+            if !find {
+                return captures(&matcher, start, end, true);
+            }
+
+            let mut results = vec![Value::Number((start + 1) as f64), Value::Number(end as f64)];
+            results.extend(captures(&matcher, start, end, false)?);
+            return Ok(results);
+        }
+
+        if anchor { break; }
+    }
+
+    Ok(vec![Value::Nil])
+}
+
+fn add_value(interpreter : &Interpreter, result : &mut Vec<u8>, matcher : &Matcher, start : usize, end : usize, replacement : &Value) -> Result<(),Error> {
+    //! adds what the match is replaced with in `gsub`
+
+    let value = match replacement {
+        Value::Table(_) => {
+            let key = matcher.capture(0, start, end)?.to_value();
+            interpreter.index(replacement, &key)?
+        },
+        Value::String(_) | Value::Number(_) => {
+            let replacement = replacement.to_lua_string().unwrap_or_else(|| LuaString::from(""));
+            return add_string(result, matcher, start, end, replacement.as_bytes());
+        },
+        function => {
+            let args = captures(matcher, start, end, true)?;
+            interpreter.call(function, args)?.into_iter().next().unwrap_or(Value::Nil)
+        },
+    };
+
+    if !value.is_truthy() {
+        // keeps the text that was matched
+        result.extend_from_slice(&matcher.source()[start .. end]);
+        return Ok(());
+    }
+
+    match value.to_lua_string() {
+        Some(string) => result.extend_from_slice(string.as_bytes()),
+        None => return Err(RuntimeError::general(&format!("invalid replacement value (a {})", value.type_name()))),
+    }
+
+    Ok(())
+}
+
+fn add_string(result : &mut Vec<u8>, matcher : &Matcher, start : usize, end : usize, replacement : &[u8]) -> Result<(),Error> {
+    //! a replacement string, `%1` to `%9` are the captures and `%0` is
+    //! the whole match
+
+    let mut i = 0;
+    while i < replacement.len() {
+        let c = replacement[i];
+        i += 1;
+
+        if c != b'%' {
+            result.push(c);
+            continue;
+        }
+
+        // a `%` at the very end is kept as a `\0`, the same as lua
+        let c = replacement.get(i).cloned().unwrap_or(0);
+        i += 1;
+
+        match c {
+            b'0' => result.extend_from_slice(&matcher.source()[start .. end]),
+            b'1' ..= b'9' => {
+                let capture = matcher.capture((c - b'1') as usize, start, end)?.to_value();
+                if let Some(string) = capture.to_lua_string() {
+                    result.extend_from_slice(string.as_bytes());
+                }
+            },
+            c => result.push(c),
+        }
+    }
+
+    Ok(())
+}
+
+fn add_quoted(result : &mut Vec<u8>, bytes : &[u8]) {
+    //! `%q`, the string in a way that lua can read back in
+
+    result.push(b'"');
+    for byte in bytes {
+        match byte {
+            b'"' | b'\\' | b'\n' => {
+                result.push(b'\\');
+                result.push(*byte);
+            },
+            b'\r' => result.extend_from_slice(b"\\r"),
+            0 => result.extend_from_slice(b"\\000"),
+            byte => result.push(*byte),
+        }
+    }
+    result.push(b'"');
+}
+
+fn scan_format(format : &[u8], start : usize) -> Result<(Spec, usize),Error> {
+    //! reads the flags, width and precision of a conversion, gives back
+    //! where the conversion character is
+
+    let mut spec = Spec::default();
+    let mut i = start;
+
+    while let Some(flag) = format.get(i) {
+        match flag {
+            b'-' => spec.left = true,
+            b'+' => spec.plus = true,
+            b' ' => spec.space = true,
+            b'#' => spec.alternate = true,
+            b'0' => spec.zero = true,
+            _ => break,
+        }
+        i += 1;
+    }
+
+    if i - start >= 6 {
+        return Err(RuntimeError::general("invalid format (repeated flags)"));
+    }
+
+    let (width, end) = scan_digits(format, i);
+    spec.width = width.unwrap_or(0);
+    i = end;
+
+    if format.get(i) == Some(&b'.') {
+        let (precision, end) = scan_digits(format, i + 1);
+        spec.precision = Some(precision.unwrap_or(0));
+        i = end;
+    }
+
+    if format.get(i).map(|c| c.is_ascii_digit()).unwrap_or(false) {
+        return Err(RuntimeError::general("invalid format (width or precision too long)"));
+    }
+
+    Ok((spec, i))
+}
+
+fn scan_digits(format : &[u8], start : usize) -> (Option<usize>, usize) {
+    //! reads a number of up to two digits
+
+    let mut number = None;
+    let mut i = start;
+
+    while i < start + 2 {
+        match format.get(i) {
+            Some(digit) if digit.is_ascii_digit() => {
+                number = Some(number.unwrap_or(0) * 10 + (digit - b'0') as usize);
+                i += 1;
+            },
+            _ => break,
+        }
+    }
+
+    (number, i)
+}
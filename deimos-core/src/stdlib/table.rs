@@ -0,0 +1,312 @@
+//! the table library, https://www.lua.org/manual/5.1/manual.html#5.5
+//!
+//! like lua, these use the raw length of the table and don't look at
+//! `__index` or `__newindex`.
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, Table, LuaString};
+use crate::error::runtime::RuntimeError;
+use crate::stdlib::{arg, arg_error, type_error, check_any, check_table, check_string, check_integer, opt_integer};
+
+pub fn load(interpreter : &Interpreter) {
+    let mut table = Table::new();
+
+    table.set_str("insert", Value::NativeFunction(insert));
+    table.set_str("remove", Value::NativeFunction(remove));
+    table.set_str("concat", Value::NativeFunction(concat));
+    table.set_str("sort", Value::NativeFunction(sort));
+    table.set_str("maxn", Value::NativeFunction(maxn));
+    table.set_str("getn", Value::NativeFunction(getn));
+    table.set_str("setn", Value::NativeFunction(setn));
+    table.set_str("foreach", Value::NativeFunction(foreach));
+    table.set_str("foreachi", Value::NativeFunction(foreachi));
+
+    interpreter.set_global("table", Value::from(table));
+}
+
+fn insert(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! table.insert (table, [pos,] value)
+
+    let table = check_table(&args, 1, "insert")?;
+    let mut table = table.borrow_mut();
+
+    match args.len() {
+        2 => {
+            let end = table.len() + 1;
+            table.set(Value::Number(end as f64), arg(&args, 2))?;
+        },
+        3 => match check_integer(&args, 2, "insert")? {
+            position if position >= 1 => table.insert(position as usize, arg(&args, 3))?,
+            _ => return Err(arg_error(2, "insert", "position out of bounds")),
+        },
+        _ => return Err(RuntimeError::general("wrong number of arguments to 'insert'")),
+    }
+
+    Ok(Vec::new())
+}
+
+fn remove(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! table.remove (table [, pos])
+
+    let table = check_table(&args, 1, "remove")?;
+    let mut table = table.borrow_mut();
+
+    let length = table.len() as i64;
+    let position = opt_integer(&args, 2, "remove", length)?;
+
+    // nothing is removed from outside of the array
+    if position < 1 || position > length {
+        return Ok(Vec::new());
+    }
+
+    Ok(vec![table.remove(position as usize)?])
+}
+
+fn concat(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! table.concat (table [, sep [, i [, j]]])
+
+    let table = check_table(&args, 1, "concat")?;
+    let table = table.borrow();
+
+    let separator = match arg(&args, 2) {
+        Value::Nil => LuaString::from(""),
+        _ => check_string(&args, 2, "concat")?,
+    };
+    let i = opt_integer(&args, 3, "concat", 1)?;
+    let j = match arg(&args, 4) {
+        Value::Nil => table.len() as i64,
+        _ => check_integer(&args, 4, "concat")?,
+    };
+
+    let mut result : Vec<u8> = Vec::new();
+    for k in i ..= j {
+        match table.get(&Value::Number(k as f64)).to_lua_string() {
+            Some(string) => result.extend_from_slice(string.as_bytes()),
+            None => return Err(RuntimeError::general(&format!("invalid value (at index {}) in table for 'concat'", k))),
+        }
+
+        if k != j {
+            result.extend_from_slice(separator.as_bytes());
+        }
+    }
+
+    Ok(vec![Value::from(LuaString::from(result))])
+}
+
+fn sort(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! table.sort (table [, comp])
+    //!
+    //! the values are sorted outside of the table so the comparison can
+    //! use the table, and are put back even if the sort fails part way.
+
+    let table = check_table(&args, 1, "sort")?;
+    let comparison = match arg(&args, 2) {
+        Value::Nil => None,
+        function if function.is_function() => Some(function),
+        _ => return Err(type_error(&args, 2, "sort", "function")),
+    };
+
+    let values : Vec<Value> = {
+        let table = table.borrow();
+        (1 ..= table.len()).map(|i| table.get_index(i)).collect()
+    };
+
+    let mut sorter = Sorter { interpreter, comparison, values };
+    let length = sorter.values.len() as i64;
+    let result = sorter.sort(1, length);
+
+    put_back(&table, sorter.values)?;
+    result?;
+
+    Ok(Vec::new())
+}
+
+fn maxn(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! table.maxn (table)
+
+    let table = check_table(&args, 1, "maxn")?;
+    let table = table.borrow();
+
+    let mut max = 0.0;
+    let mut key = Value::Nil;
+    while let Some((next, _)) = table.next(&key)? {
+        if let Value::Number(number) = next {
+            if number > max { max = number; }
+        }
+        key = next;
+    }
+
+    Ok(vec![Value::Number(max)])
+}
+
+fn getn(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! table.getn (table)
+
+    let table = check_table(&args, 1, "getn")?;
+    let length = table.borrow().len();
+    Ok(vec![Value::Number(length as f64)])
+}
+
+fn setn(_ : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! table.setn (table, n)
+
+    check_table(&args, 1, "setn")?;
+    Err(RuntimeError::general("'setn' is obsolete"))
+}
+
+fn foreach(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! table.foreach (table, f)
+
+    let table = check_table(&args, 1, "foreach")?;
+    let function = check_any(&args, 2, "foreach")?;
+
+    let mut key = Value::Nil;
+    loop {
+        // the table isn't borrowed during the call, so `f` can change it
+        let next = table.borrow().next(&key)?;
+        let (next, value) = match next {
+            Some(pair) => pair,
+            None => return Ok(Vec::new()),
+        };
+
+        let result = interpreter.call(&function, vec![next.clone(), value])?;
+        if let Some(value) = result.into_iter().next().filter(|value| !value.is_nil()) {
+            return Ok(vec![value]);
+        }
+
+        key = next;
+    }
+}
+
+fn foreachi(interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+    //! table.foreachi (table, f)
+
+    let table = check_table(&args, 1, "foreachi")?;
+    let function = check_any(&args, 2, "foreachi")?;
+
+    let length = table.borrow().len();
+    for i in 1 ..= length {
+        let value = table.borrow().get_index(i);
+        let result = interpreter.call(&function, vec![Value::Number(i as f64), value])?;
+        if let Some(value) = result.into_iter().next().filter(|value| !value.is_nil()) {
+            return Ok(vec![value]);
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+// PRIVATE FUNCTIONS /////////////////////////////////////
+
+fn put_back(table : &Rc<RefCell<Table>>, values : Vec<Value>) -> Result<(),Error> {
+    let mut table = table.borrow_mut();
+    for (i, value) in values.into_iter().enumerate() {
+        table.set(Value::Number((i + 1) as f64), value)?;
+    }
+    Ok(())
+}
+
+/// lua's quicksort from `ltablib.c`, kept the same so a comparison that
+/// isn't a valid order gives the same "invalid order function" errors.
+/// positions start at `1` like they do in the table.
+struct Sorter<'a> {
+    interpreter : &'a Interpreter,
+    comparison : Option<Value>,
+    values : Vec<Value>,
+}
+
+impl<'a> Sorter<'a> {
+    fn get(&self, i : i64) -> Value {
+        //! past the ends is `nil`, like reading outside the array in lua
+
+        match i {
+            i if i >= 1 => self.values.get(i as usize - 1).cloned().unwrap_or(Value::Nil),
+            _ => Value::Nil,
+        }
+    }
+
+    fn swap(&mut self, i : i64, j : i64) {
+        //! a bad comparison can make lua write just past the end, so
+        //! we do the same
+        let end = i.max(j) as usize;
+        if end > self.values.len() {
+            self.values.resize(end, Value::Nil);
+        }
+
+        self.values.swap(i as usize - 1, j as usize - 1);
+    }
+
+    fn less(&self, a : &Value, b : &Value) -> Result<bool,Error> {
+        self.interpreter.count_step()?;
+
+        match &self.comparison {
+            Some(function) => Ok(self.interpreter.call(function, vec![a.clone(), b.clone()])?
+                .first().map(|value| value.is_truthy()).unwrap_or(false)),
+            None => self.interpreter.less_than(a, b),
+        }
+    }
+
+    fn sort(&mut self, mut l : i64, mut u : i64) -> Result<(),Error> {
+        // loops for the larger half, and recurses for the smaller one
+        while l < u {
+            // sorts a[l], a[(l+u)/2] and a[u]
+            if self.less(&self.get(u), &self.get(l))? {
+                self.swap(l, u);
+            }
+            if u - l == 1 { break; }
+
+            let mut i = (l + u) / 2;
+            if self.less(&self.get(i), &self.get(l))? {
+                self.swap(i, l);
+            } else if self.less(&self.get(u), &self.get(i))? {
+                self.swap(i, u);
+            }
+            if u - l == 2 { break; }
+
+            // a[l] <= pivot == a[u-1] <= a[u], so we only sort l+1 to u-2
+            let pivot = self.get(i);
+            self.swap(i, u - 1);
+
+            i = l;
+            let mut j = u - 1;
+            loop {
+                i += 1;
+                while self.less(&self.get(i), &pivot)? {
+                    if i > u { return Err(RuntimeError::general("invalid order function for sorting")); }
+                    i += 1;
+                }
+
+                j -= 1;
+                while self.less(&pivot, &self.get(j))? {
+                    if j < l { return Err(RuntimeError::general("invalid order function for sorting")); }
+                    j -= 1;
+                }
+
+                if j < i { break; }
+                self.swap(i, j);
+            }
+
+            self.swap(u - 1, i);
+
+            // a[l..i-1] <= a[i] == pivot <= a[i+1..u]
+            let (start, end) = if i - l < u - i {
+                let smaller = (l, i - 1);
+                l = i + 1;
+                smaller
+            } else {
+                let smaller = (i + 1, u);
+                u = i - 1;
+                smaller
+            };
+
+            self.sort(start, end)?;
+        }
+
+        Ok(())
+    }
+}
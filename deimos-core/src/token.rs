@@ -1,5 +1,6 @@
 use crate::coderef::CodeRef;
 use crate::element::CodeElement;
+use crate::value::{LuaString, number_to_string};
 
 pub type CodeToken = CodeRef<Token>; 
 
@@ -30,8 +31,10 @@ pub enum Token {
     While,
 
     // literals ///////////////////////////////////
-    Identifier(String),    String(String),
-    Number(f32),           MultiLineString(String),
+    // names and strings are interned, so the compiler and the code it
+    // makes share them
+    Identifier(LuaString), String(LuaString),
+    Number(f64),           MultiLineString(LuaString),
 
     // other /////////////////////////////////////
     Comment(String),
@@ -44,12 +47,42 @@ pub enum Token {
 
 impl std::fmt::Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Token::String(string) => write!(f, "\"{}\"",string),
-            Token::Number(number) => write!(f, "{}",number),
-            Token::Identifier(var_name) => write!(f, "{}",var_name),
-            token => write!(f, "{:?}", token),
-        }
+        //! the token the way it is written in lua, so errors can point
+        //! at it like lua's do
+        
+        let text = match self {
+            Token::String(string) => return write!(f, "\"{}\"",string),
+            Token::MultiLineString(string) => return write!(f, "[[{}]]",string),
+            Token::Number(number) => return write!(f, "{}",number_to_string(*number)),
+            Token::Identifier(var_name) => return write!(f, "{}",var_name),
+            Token::Comment(comment) => return write!(f, "--{}",comment),
+
+            Token::Plus => "+",             Token::Minus => "-",            Token::Star => "*",
+            Token::Slash => "/",            Token::Percent => "%",          Token::Carrot => "^",
+            Token::Pound => "#",            Token::LessThan => "<",         Token::GreaterThan => ">",
+            Token::Equal => "=",            Token::LeftParen => "(",        Token::RightParen => ")",
+            Token::LeftMoustache => "{",    Token::RightMoustache => "}",   Token::LeftBracket => "[",
+            Token::RightBracket => "]",     Token::SemiColon => ";",        Token::Colon => ":",
+            Token::Comma => ",",            Token::Period => ".",
+
+            Token::DoublePeriod => "..",    Token::EqualEqual => "==",      Token::NotEqual => "~=",
+            Token::GreaterEqual => ">=",    Token::LessEqual => "<=",
+            Token::TriplePeriod => "...",
+
+            Token::And => "and",        Token::Break => "break",    Token::Do => "do",
+            Token::Else => "else",      Token::Elseif => "elseif",  Token::End => "end",
+            Token::False => "false",    Token::For => "for",        Token::Function => "function",
+            Token::If => "if",          Token::In => "in",          Token::Local => "local",
+            Token::Nil => "nil",        Token::Not => "not",        Token::Or => "or",
+            Token::Repeat => "repeat",  Token::Return => "return",  Token::Then => "then",
+            Token::True => "true",      Token::Until => "until",    Token::While => "while",
+
+            Token::WhiteSpace => " ",
+            Token::EOL => "\n",
+            Token::EOF => "<eof>",
+        };
+
+        write!(f, "{}", text)
     }
 }
 
@@ -129,7 +162,9 @@ impl Token {
 
             Token::Identifier(string) => string.len(),
             Token::String(string) => string.len() + 2,
-            Token::Number(number) => format!("{}",number).len(),
+            // the number the way lua writes it, which isn't always how it was
+            // written in the code (`1e300` is `1e+300`)
+            Token::Number(number) => number_to_string(*number).len(),
             Token::MultiLineString(string) => string.len() + 2, // TODO : FIX THIS THING
 
             Token::Comment(string) => string.len(),
@@ -178,7 +213,7 @@ impl Token {
         //! so we are checking for spaces and tabs
         
         match char {
-            " " => true,
+            " " | "\t" | "\u{0B}" | "\u{0C}" => true,
             _ => false,
         }
     }
@@ -0,0 +1,370 @@
+//! turning lua values into rust types and back, so the values given to
+//! and coming back from lua don't have to be matched by hand.
+//!
+//! the conversions are as strict as the standard library's arguements,
+//! strings that look like numbers are numbers and numbers are strings,
+//! but integers have to be whole and fit in the type. multiple values,
+//! like the ones a function returns, are tuples.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, Table, LuaString, ReturnValues, number_to_string};
+use crate::error::runtime::RuntimeError;
+
+/// a rust value that can be given to lua
+pub trait IntoLua {
+    fn into_lua(self, interpreter : &Interpreter) -> Result<Value,Error>;
+}
+
+/// a rust value that can be made from a lua value
+pub trait FromLua : Sized {
+    fn from_lua(value : Value, interpreter : &Interpreter) -> Result<Self,Error>;
+}
+
+/// any number of values, like what a function is called with or returns.
+/// a single value is one value, tuples are one for each part.
+pub trait IntoLuaMulti {
+    fn into_lua_multi(self, interpreter : &Interpreter) -> Result<Vec<Value>,Error>;
+}
+
+/// made from any number of values, the missing ones are `nil` and the
+/// extra ones are dropped like they are in lua.
+pub trait FromLuaMulti : Sized {
+    fn from_lua_multi(values : Vec<Value>, interpreter : &Interpreter) -> Result<Self,Error>;
+}
+
+impl IntoLua for Value {
+    fn into_lua(self, _ : &Interpreter) -> Result<Value,Error> {
+        Ok(self)
+    }
+}
+
+impl FromLua for Value {
+    fn from_lua(value : Value, _ : &Interpreter) -> Result<Value,Error> {
+        Ok(value)
+    }
+}
+
+impl IntoLua for bool {
+    fn into_lua(self, _ : &Interpreter) -> Result<Value,Error> {
+        Ok(Value::Boolean(self))
+    }
+}
+
+impl FromLua for bool {
+    fn from_lua(value : Value, _ : &Interpreter) -> Result<bool,Error> {
+        //! anything but `nil` and `false` is true, so it can't fail
+
+        Ok(value.is_truthy())
+    }
+}
+
+macro_rules! float {
+    ($($float:ty),*) => { $(
+        impl IntoLua for $float {
+            fn into_lua(self, _ : &Interpreter) -> Result<Value,Error> {
+                Ok(Value::Number(self as f64))
+            }
+        }
+
+        impl FromLua for $float {
+            fn from_lua(value : Value, _ : &Interpreter) -> Result<$float,Error> {
+                match value.to_number() {
+                    Some(number) => Ok(number as $float),
+                    None => Err(conversion_error(&value, stringify!($float))),
+                }
+            }
+        }
+    )* };
+}
+
+macro_rules! integer {
+    ($($integer:ty),*) => { $(
+        impl IntoLua for $integer {
+            fn into_lua(self, _ : &Interpreter) -> Result<Value,Error> {
+                //! lua's numbers are doubles, the biggest integers can't
+                //! be one without changing
+
+                let number = self as f64;
+                match number as i128 == self as i128 {
+                    true => Ok(Value::Number(number)),
+                    false => Err(RuntimeError::general(&format!("integer {} can't be a number without losing precision", self))),
+                }
+            }
+        }
+
+        impl FromLua for $integer {
+            fn from_lua(value : Value, _ : &Interpreter) -> Result<$integer,Error> {
+                let number = value.to_number().ok_or_else(|| conversion_error(&value, stringify!($integer)))?;
+                if number.fract() != 0.0 {
+                    return Err(RuntimeError::general(&format!("number {} has no integer representation", number_to_string(number))));
+                }
+
+                // the cast saturates, and nothing fits at the ends of an i128
+                use std::convert::TryFrom;
+                <$integer>::try_from(number as i128)
+                    .map_err(|_| RuntimeError::general(&format!("number {} is out of range for {}", number_to_string(number), stringify!($integer))))
+            }
+        }
+    )* };
+}
+
+float!(f32, f64);
+integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl IntoLua for LuaString {
+    fn into_lua(self, _ : &Interpreter) -> Result<Value,Error> {
+        Ok(Value::String(self))
+    }
+}
+
+impl FromLua for LuaString {
+    fn from_lua(value : Value, _ : &Interpreter) -> Result<LuaString,Error> {
+        value.to_lua_string().ok_or_else(|| conversion_error(&value, "string"))
+    }
+}
+
+impl IntoLua for &str {
+    fn into_lua(self, _ : &Interpreter) -> Result<Value,Error> {
+        Ok(Value::from(self))
+    }
+}
+
+impl IntoLua for String {
+    fn into_lua(self, _ : &Interpreter) -> Result<Value,Error> {
+        Ok(Value::String(LuaString::from(self)))
+    }
+}
+
+impl FromLua for String {
+    fn from_lua(value : Value, interpreter : &Interpreter) -> Result<String,Error> {
+        //! lua strings are bytes, they have to be utf-8 to be a `String`
+
+        let string = LuaString::from_lua(value, interpreter)?;
+        match string.to_str() {
+            Some(string) => Ok(string.to_string()),
+            None => Err(RuntimeError::general("string is not valid utf-8")),
+        }
+    }
+}
+
+impl<T : IntoLua> IntoLua for Option<T> {
+    fn into_lua(self, interpreter : &Interpreter) -> Result<Value,Error> {
+        match self {
+            Some(value) => value.into_lua(interpreter),
+            None => Ok(Value::Nil),
+        }
+    }
+}
+
+impl<T : FromLua> FromLua for Option<T> {
+    fn from_lua(value : Value, interpreter : &Interpreter) -> Result<Option<T>,Error> {
+        match value {
+            Value::Nil => Ok(None),
+            value => Ok(Some(T::from_lua(value, interpreter)?)),
+        }
+    }
+}
+
+impl IntoLua for Rc<RefCell<Table>> {
+    fn into_lua(self, _ : &Interpreter) -> Result<Value,Error> {
+        Ok(Value::Table(self))
+    }
+}
+
+impl FromLua for Rc<RefCell<Table>> {
+    fn from_lua(value : Value, _ : &Interpreter) -> Result<Rc<RefCell<Table>>,Error> {
+        match value {
+            Value::Table(table) => Ok(table),
+            value => Err(conversion_error(&value, "table")),
+        }
+    }
+}
+
+impl<T : IntoLua> IntoLua for Vec<T> {
+    fn into_lua(self, interpreter : &Interpreter) -> Result<Value,Error> {
+        //! a new table with the values from `1`
+
+        let values = self.into_iter()
+            .map(|value| value.into_lua(interpreter))
+            .collect::<Result<Vec<Value>,Error>>()?;
+        new_table(interpreter, Table::from_values(values))
+    }
+}
+
+impl<T : FromLua> FromLua for Vec<T> {
+    fn from_lua(value : Value, interpreter : &Interpreter) -> Result<Vec<T>,Error> {
+        //! the values of the table from `1` up to its length
+
+        let table = Rc::<RefCell<Table>>::from_lua(value, interpreter)?;
+        let values : Vec<Value> = {
+            let table = table.borrow();
+            (1 ..= table.len()).map(|i| table.get_index(i)).collect()
+        };
+
+        values.into_iter().map(|value| T::from_lua(value, interpreter)).collect()
+    }
+}
+
+impl<K : IntoLua, V : IntoLua> IntoLua for HashMap<K,V> {
+    fn into_lua(self, interpreter : &Interpreter) -> Result<Value,Error> {
+        let mut table = Table::with_capacity(0, self.len());
+        for (key, value) in self {
+            table.set(key.into_lua(interpreter)?, value.into_lua(interpreter)?)?;
+        }
+        new_table(interpreter, table)
+    }
+}
+
+impl<K : FromLua + Eq + Hash, V : FromLua> FromLua for HashMap<K,V> {
+    fn from_lua(value : Value, interpreter : &Interpreter) -> Result<HashMap<K,V>,Error> {
+        let table = Rc::<RefCell<Table>>::from_lua(value, interpreter)?;
+        let pairs = pairs(&table.borrow())?;
+
+        let mut map = HashMap::with_capacity(pairs.len());
+        for (key, value) in pairs {
+            map.insert(K::from_lua(key, interpreter)?, V::from_lua(value, interpreter)?);
+        }
+        Ok(map)
+    }
+}
+
+impl<T : IntoLua> IntoLuaMulti for T {
+    fn into_lua_multi(self, interpreter : &Interpreter) -> Result<Vec<Value>,Error> {
+        Ok(vec![self.into_lua(interpreter)?])
+    }
+}
+
+impl<T : FromLua> FromLuaMulti for T {
+    fn from_lua_multi(values : Vec<Value>, interpreter : &Interpreter) -> Result<T,Error> {
+        let value = values.into_iter().next().unwrap_or(Value::Nil);
+        T::from_lua(value, interpreter)
+    }
+}
+
+impl IntoLuaMulti for () {
+    fn into_lua_multi(self, _ : &Interpreter) -> Result<Vec<Value>,Error> {
+        Ok(Vec::new())
+    }
+}
+
+impl FromLuaMulti for () {
+    fn from_lua_multi(_ : Vec<Value>, _ : &Interpreter) -> Result<(),Error> {
+        Ok(())
+    }
+}
+
+impl IntoLuaMulti for ReturnValues {
+    fn into_lua_multi(self, _ : &Interpreter) -> Result<Vec<Value>,Error> {
+        //! all of the values as they are, for when there can be any
+        //! number of them
+
+        Ok(self.into_values())
+    }
+}
+
+impl FromLuaMulti for ReturnValues {
+    fn from_lua_multi(values : Vec<Value>, _ : &Interpreter) -> Result<ReturnValues,Error> {
+        Ok(ReturnValues::new(values))
+    }
+}
+
+macro_rules! tuple {
+    ($($name:ident),+) => {
+        impl<$($name : IntoLua),+> IntoLuaMulti for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn into_lua_multi(self, interpreter : &Interpreter) -> Result<Vec<Value>,Error> {
+                let ($($name,)+) = self;
+                Ok(vec![$($name.into_lua(interpreter)?),+])
+            }
+        }
+
+        impl<$($name : FromLua),+> FromLuaMulti for ($($name,)+) {
+            fn from_lua_multi(values : Vec<Value>, interpreter : &Interpreter) -> Result<($($name,)+),Error> {
+                let mut values = values.into_iter();
+                Ok(($($name::from_lua(values.next().unwrap_or(Value::Nil), interpreter)?,)+))
+            }
+        }
+    };
+}
+
+tuple!(A);
+tuple!(A, B);
+tuple!(A, B, C);
+tuple!(A, B, C, D);
+tuple!(A, B, C, D, E);
+tuple!(A, B, C, D, E, F);
+tuple!(A, B, C, D, E, F, G);
+tuple!(A, B, C, D, E, F, G, H);
+
+pub(crate) fn new_table(interpreter : &Interpreter, table : Table) -> Result<Value,Error> {
+    //! a table made from rust, known to the garbage collector like the
+    //! ones lua makes
+
+    let table = Value::from(table);
+    interpreter.track(&table)?;
+    Ok(table)
+}
+
+pub(crate) fn pairs(table : &Table) -> Result<Vec<(Value,Value)>,Error> {
+    //! everything in the table, in the order `next` gives it
+
+    let mut pairs = Vec::new();
+    let mut key = Value::Nil;
+    while let Some((next, value)) = table.next(&key)? {
+        pairs.push((next.clone(), value));
+        key = next;
+    }
+
+    Ok(pairs)
+}
+
+// PRIVATE FUNCTIONS /////////////////////////////////////
+
+fn conversion_error(value : &Value, expected : &str) -> Error {
+    RuntimeError::general(&format!("cannot convert a {} value to {}", value.type_name(), expected))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use crate::interpreter::Interpreter;
+    use crate::value::{Value, ReturnValues};
+    use crate::value::convert::{IntoLua, FromLua, IntoLuaMulti, FromLuaMulti};
+
+    #[test]
+    pub fn conversions() {
+        let interpreter = Interpreter::new();
+        let values = interpreter.run("return 200, '12', 1.5, 'text', nil, {1, 2, 3}, {a = 1, b = 2}, print", None).unwrap().into_values();
+
+        type Values = (u8, i32, f64, String, Option<i64>, Vec<u16>, HashMap<String, i64>, Value);
+        let (a, b, c, d, e, f, g, _) = Values::from_lua_multi(values.clone(), &interpreter).unwrap();
+        assert_eq!((a, b, c, d.as_str(), e, f), (200, 12, 1.5, "text", None, vec![1, 2, 3]));
+        assert_eq!(g.get("b"), Some(&2));
+
+        let error = |value : &Value, result : Result<(),failure::Error>| {
+            let message = result.unwrap_err().to_string();
+            assert!(message.contains(&format!("{}", value)) || message.contains(value.type_name()), "{}", message);
+            message
+        };
+        assert!(error(&values[0], i8::from_lua(values[0].clone(), &interpreter).map(|_| ())).contains("out of range for i8"));
+        assert!(error(&values[2], u32::from_lua(values[2].clone(), &interpreter).map(|_| ())).contains("no integer representation"));
+        assert!(error(&values[7], String::from_lua(values[7].clone(), &interpreter).map(|_| ())).contains("cannot convert a function value to string"));
+        assert!(Vec::<String>::from_lua(values[3].clone(), &interpreter).is_err());
+        assert!(u64::MAX.into_lua(&interpreter).is_err());
+
+        let chunk = interpreter.load(b"local list, map, n, s = ... return #list, map.x, n, s", "=test").unwrap();
+        let args = (vec!["a", "b"], Some([("x".to_string(), true)].iter().cloned().collect::<HashMap<_,_>>()), 3usize, "s")
+            .into_lua_multi(&interpreter).unwrap();
+        let results = ReturnValues::new(interpreter.call(&chunk, args).unwrap());
+        assert_eq!(results.into_lua_multi(&interpreter).unwrap(), vec![Value::Number(2.0), Value::Boolean(true), Value::Number(3.0), Value::from("s")]);
+    }
+}
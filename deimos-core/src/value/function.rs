@@ -0,0 +1,80 @@
+//! functions that are written in lua, and rust functions that carry
+//! their own state
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::bytecode::Proto;
+use crate::value::{Value, Table};
+
+/// a local variable that a function has captured, it is shared with the
+/// block it came from and any other function that captured it.
+pub type Upvalue = Rc<RefCell<Value>>;
+
+/// a function written in rust that holds onto its own state, like the
+/// functions `coroutine.wrap` makes. every rust closure is one, a type
+/// that holds lua values can implement it itself to say what they are.
+pub trait NativeClosure {
+    fn call(&self, interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error>;
+
+    fn trace(&self, _visit : &mut dyn FnMut(&Value)) {
+        //! gives each of the lua values it is holding on to, the garbage
+        //! collector can't see them otherwise. a cycle that goes through
+        //! a function that doesn't say what it holds is never collected.
+    }
+}
+
+impl<F> NativeClosure for F where F : Fn(&Interpreter, Vec<Value>) -> Result<Vec<Value>,Error> {
+    fn call(&self, interpreter : &Interpreter, args : Vec<Value>) -> Result<Vec<Value>,Error> {
+        self(interpreter, args)
+    }
+}
+
+/// a function that was written in lua, the compiled code it runs and the
+/// upvalues it captured when it was made.
+pub struct LuaFunction {
+    proto : Rc<Proto>,
+    upvalues : Vec<Upvalue>,
+    // where the globals used by the function are, `setfenv` changes it
+    environment : RefCell<Rc<RefCell<Table>>>,
+}
+
+impl LuaFunction {
+    pub fn new(proto : Rc<Proto>, upvalues : Vec<Upvalue>, environment : Rc<RefCell<Table>>) -> LuaFunction {
+        LuaFunction { proto, upvalues, environment : RefCell::new(environment) }
+    }
+
+    pub fn main(proto : Rc<Proto>, environment : Rc<RefCell<Table>>) -> LuaFunction {
+        //! the whole chunk as a function, it takes any number of
+        //! arguements as `...`. a function that was dumped on its own can
+        //! have upvalues, they start out as `nil`.
+
+        let upvalues = (0 .. proto.upvalues).map(|_| Rc::new(RefCell::new(Value::Nil))).collect();
+        LuaFunction::new(proto, upvalues, environment)
+    }
+
+    pub fn is_main(&self) -> bool {
+        //! if this is a whole chunk and not a function inside of one
+
+        self.proto.is_main()
+    }
+
+    pub fn proto(&self) -> &Rc<Proto> {
+        &self.proto
+    }
+
+    pub fn upvalues(&self) -> &[Upvalue] {
+        &self.upvalues
+    }
+
+    pub fn environment(&self) -> Rc<RefCell<Table>> {
+        self.environment.borrow().clone()
+    }
+
+    pub fn set_environment(&self, environment : Rc<RefCell<Table>>) {
+        *self.environment.borrow_mut() = environment;
+    }
+}
@@ -0,0 +1,393 @@
+//! the values that lua code works with, per the manual
+//! https://www.lua.org/manual/5.1/manual.html#2.2
+//!
+//! the simple values (nil, booleans, numbers and strings) are copied around,
+//! everything else is a reference, so two variables can point at the same
+//! table and changing it in one place will be seen in the other.
+
+pub mod string;
+pub mod table;
+pub mod function;
+pub mod userdata;
+pub mod thread;
+pub mod convert;
+#[cfg(feature = "serde")]
+pub mod serialize;
+
+use std::rc::{Rc, Weak};
+use std::cell::{Cell, RefCell};
+use std::hash::{Hash, Hasher};
+
+use failure::Error;
+
+use crate::interpreter::Interpreter;
+use crate::stdlib::format::Spec;
+
+pub use crate::value::string::LuaString;
+pub use crate::value::table::Table;
+pub use crate::value::function::{LuaFunction, Upvalue, NativeClosure};
+pub use crate::value::userdata::{AnyUserData, UserData, UserDataMethods};
+pub use crate::value::thread::{Thread, ThreadStatus};
+pub use crate::value::convert::{IntoLua, FromLua, IntoLuaMulti, FromLuaMulti};
+#[cfg(feature = "serde")]
+pub use crate::value::serialize::{Serializer, Deserializer, SerdeError};
+
+thread_local! {
+    // where the interpreter that is running counts what it makes, tables
+    // and strings don't know which interpreter they belong to
+    static COUNTING : RefCell<Weak<Allocations>> = const { RefCell::new(Weak::new()) };
+}
+
+/// the signature for functions that are written in rust and
+/// called from lua.
+pub type NativeFunction = fn(&Interpreter, Vec<Value>) -> Result<Vec<Value>,Error>;
+
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(LuaString),
+    Table(Rc<RefCell<Table>>),
+    Function(Rc<LuaFunction>),
+    NativeFunction(NativeFunction),
+    NativeClosure(Rc<dyn NativeClosure>),
+    UserData(Rc<AnyUserData>),
+    Thread(Rc<Thread>),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        //! the same as lua's `tostring` without any metatables
+
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(boolean) => write!(f, "{}", boolean),
+            Value::Number(number) => write!(f, "{}", number_to_string(*number)),
+            Value::String(string) => write!(f, "{}", string),
+            Value::Table(table) => write!(f, "table: {:p}", Rc::as_ptr(table)),
+            Value::Function(function) => write!(f, "function: {:p}", Rc::as_ptr(function)),
+            Value::NativeFunction(function) => write!(f, "function: {:p}", *function as *const ()),
+            Value::NativeClosure(function) => write!(f, "function: {:p}", Rc::as_ptr(function) as *const ()),
+            Value::UserData(data) => write!(f, "userdata: {:p}", Rc::as_ptr(data)),
+            Value::Thread(thread) => write!(f, "thread: {:p}", Rc::as_ptr(thread)),
+        }
+    }
+}
+
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::String(string) => write!(f, "{:?}", string),
+            value => write!(f, "{}", value),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other : &Value) -> bool {
+        //! the lua raw equality, values of different types are never
+        //! equal, there is no converting of strings to numbers here.
+        //! everything that isn't a simple value is only equal to itself.
+
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(a, b),
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::NativeFunction(a), Value::NativeFunction(b)) => *a as usize == *b as usize,
+            (Value::NativeClosure(a), Value::NativeClosure(b)) => Rc::ptr_eq(a, b),
+            (Value::UserData(a), Value::UserData(b)) => Rc::ptr_eq(a, b),
+            (Value::Thread(a), Value::Thread(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+// NaN is the only value that isn't equal to itself, and it can't
+// be used as a table key, so values can be treated as `Eq`
+impl Eq for Value { }
+
+impl Hash for Value {
+    fn hash<H : Hasher>(&self, state : &mut H) {
+        std::mem::discriminant(self).hash(state);
+
+        match self {
+            Value::Nil => { },
+            Value::Boolean(boolean) => boolean.hash(state),
+            // `0` and `-0` are the same key
+            Value::Number(number) => if *number == 0.0 { 0u64.hash(state) } else { number.to_bits().hash(state) },
+            Value::String(string) => string.hash(state),
+            Value::Table(table) => Rc::as_ptr(table).hash(state),
+            Value::Function(function) => Rc::as_ptr(function).hash(state),
+            Value::NativeFunction(function) => (*function as usize).hash(state),
+            Value::NativeClosure(function) => (Rc::as_ptr(function) as *const ()).hash(state),
+            Value::UserData(data) => Rc::as_ptr(data).hash(state),
+            Value::Thread(thread) => Rc::as_ptr(thread).hash(state),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(boolean : bool) -> Value {
+        Value::Boolean(boolean)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(number : f64) -> Value {
+        Value::Number(number)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(string : &str) -> Value {
+        Value::String(LuaString::from(string))
+    }
+}
+
+impl From<LuaString> for Value {
+    fn from(string : LuaString) -> Value {
+        Value::String(string)
+    }
+}
+
+impl From<Table> for Value {
+    fn from(table : Table) -> Value {
+        Value::Table(Rc::new(RefCell::new(table)))
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        //! the name of the type, as given by lua's `type` function
+
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) | Value::NativeFunction(_) | Value::NativeClosure(_) => "function",
+            Value::UserData(_) => "userdata",
+            Value::Thread(_) => "thread",
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        //! only `nil` and `false` are false in lua, everything
+        //! else is true.
+
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    pub fn is_function(&self) -> bool {
+        matches!(self, Value::Function(_) | Value::NativeFunction(_) | Value::NativeClosure(_))
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    pub fn to_number(&self) -> Option<f64> {
+        //! attempts to get a number out of the value, following the lua
+        //! rules that strings that look like numbers can be used as numbers.
+
+        match self {
+            Value::Number(number) => Some(*number),
+            Value::String(string) => string_to_number(string.as_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn to_lua_string(&self) -> Option<LuaString> {
+        //! gets the string that would be used when this value is concatenated,
+        //! only strings and numbers can be turned into strings.
+
+        match self {
+            Value::String(string) => Some(string.clone()),
+            Value::Number(_) => Some(LuaString::from(format!("{}", self))),
+            _ => None,
+        }
+    }
+
+    pub fn tostring(&self) -> LuaString {
+        //! the value as a string, the same as lua's `tostring` without
+        //! looking at any metatables.
+
+        match self {
+            Value::String(string) => string.clone(),
+            value => LuaString::from(format!("{}", value)),
+        }
+    }
+}
+
+pub fn number_to_string(number : f64) -> String {
+    //! formats the number the way lua does, with `%.14g`
+
+    let spec = Spec { precision : Some(14), .. Spec::default() };
+    String::from_utf8_lossy(&spec.float(number, b'g')).to_string()
+}
+
+/// what an interpreter's values took up that its collector hasn't
+/// counted yet, each interpreter's heap has its own
+#[derive(Default)]
+pub(crate) struct Allocations {
+    // what tables grew by after they were made, and the new strings
+    bytes : Cell<usize>,
+    // the strings that were made, the collector counts the ones that are
+    // still alive
+    strings : RefCell<Vec<Weak<string::Interned>>>,
+}
+
+impl Allocations {
+    pub fn take_bytes(&self) -> usize {
+        self.bytes.replace(0)
+    }
+
+    pub fn strings_in_use(&self) -> usize {
+        //! the memory the strings that are still alive use, the dead ones
+        //! are forgotten
+
+        let mut strings = self.strings.borrow_mut();
+        strings.retain(|string| string.strong_count() > 0);
+        strings.iter().filter_map(Weak::upgrade).map(|string| string::size(string.len())).sum()
+    }
+}
+
+pub(crate) fn count_to(allocations : Weak<Allocations>) -> Weak<Allocations> {
+    //! what is made from now on is counted to these, gives back the ones
+    //! it was counted to before so they can be put back
+
+    COUNTING.with(|counting| counting.replace(allocations))
+}
+
+pub(crate) fn grow(bytes : usize) {
+    //! counts memory a table took up after it was made
+
+    COUNTING.with(|counting| if let Some(allocations) = counting.borrow().upgrade() {
+        allocations.bytes.set(allocations.bytes.get() + bytes);
+    });
+}
+
+pub(crate) fn count_string(string : &Rc<string::Interned>) {
+    //! counts a string that was just made
+
+    let _ = COUNTING.try_with(|counting| if let Some(allocations) = counting.borrow().upgrade() {
+        allocations.bytes.set(allocations.bytes.get() + string::size(string.len()));
+        allocations.strings.borrow_mut().push(Rc::downgrade(string));
+    });
+}
+
+pub fn string_to_number(bytes : &[u8]) -> Option<f64> {
+    //! converts the string to a number the same way lua does, surrounding
+    //! whitespace is ignored and hex numbers (`0x10`) are allowed.
+
+    let text = std::str::from_utf8(bytes).ok()?.trim();
+
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+
+    if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) { return None; }
+
+        let number = hex.chars().fold(0.0, |number, c| number * 16.0 + f64::from(c.to_digit(16).unwrap_or(0)));
+        return Some(if negative { -number } else { number });
+    }
+
+    // rust can parse a few things that lua can't, like `infinity`
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || c == '-' || c == '+') {
+        return None;
+    }
+
+    text.parse::<f64>().ok()
+}
+
+/// what is given back after running a chunk of code,
+/// the values that the chunk returned.
+pub struct ReturnValues {
+    values : Vec<Value>,
+}
+
+impl std::fmt::Display for ReturnValues {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts : Vec<String> = self.values.iter().map(|value| format!("{}", value)).collect();
+        write!(f, "{}", parts.join("\t"))
+    }
+}
+
+impl ReturnValues {
+    pub fn new(values : Vec<Value>) -> ReturnValues {
+        ReturnValues { values }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+
+    pub fn as_user_output(&self) -> Option<String> {
+        //! the values formatted like the lua repl would print them, or
+        //! `None` if there is nothing to show.
+
+        match self.is_empty() {
+            true => None,
+            false => Some(format!("{}", self)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::value::{Value, Table, string_to_number, number_to_string};
+
+    #[test]
+    pub fn equality_and_truthiness() {
+        assert_eq!(Value::from("a"), Value::from("a"));
+        assert_ne!(Value::from("1"), Value::Number(1.0));
+        assert_ne!(Value::Number(f64::NAN), Value::Number(f64::NAN));
+
+        let table = Value::from(Table::new());
+        assert_eq!(table, table.clone());
+        assert_ne!(table, Value::from(Table::new()));
+
+        assert!(Value::Number(0.0).is_truthy());
+        assert!(Value::from("").is_truthy());
+        assert!(!Value::Nil.is_truthy());
+        assert!(!Value::Boolean(false).is_truthy());
+    }
+
+    #[test]
+    pub fn conversions() {
+        assert_eq!(string_to_number(b" 10 "), Some(10.0));
+        assert_eq!(string_to_number(b"0x10"), Some(16.0));
+        assert_eq!(string_to_number(b"-0XfF"), Some(-255.0));
+        assert_eq!(string_to_number(b"1e2"), Some(100.0));
+        assert_eq!(string_to_number(b"inf"), None);
+        assert_eq!(string_to_number(b"10a"), None);
+        assert_eq!(string_to_number(b""), None);
+
+        assert_eq!(number_to_string(1234567890123.0), "1234567890123");
+        assert_eq!(number_to_string(0.1), "0.1");
+        assert_eq!(number_to_string(1e300), "1e+300");
+        assert_eq!(number_to_string(-0.5), "-0.5");
+        assert_eq!(number_to_string(2f64.powi(63)), "9.2233720368548e+18");
+        assert_eq!(number_to_string(f64::NEG_INFINITY), "-inf");
+
+        assert_eq!(format!("{}", Value::Boolean(true)), "true");
+        assert_eq!(format!("{}", Value::Nil), "nil");
+        assert!(format!("{}", Value::from(Table::new())).starts_with("table: 0x"));
+    }
+}
@@ -0,0 +1,693 @@
+//! a serde `Serializer` and `Deserializer` for lua values, so anything
+//! that derives `Serialize` or `Deserialize` can be turned into a lua
+//! value or read out of one, like config structs read from a table.
+//!
+//! structs and maps are tables with keys, sequences and tuples are tables
+//! from `1`, `None` and unit are `nil`. unit enum variants are their name
+//! as a string and the others are a table with the name as the only key,
+//! `{ Variant = ... }`.
+
+use std::fmt;
+
+use serde::ser::{self, Serialize};
+use serde::de::{self, Visitor, IntoDeserializer, DeserializeOwned};
+use serde::forward_to_deserialize_any;
+
+use crate::interpreter::Interpreter;
+use crate::value::{Value, Table, LuaString};
+use crate::value::convert::{new_table, pairs};
+use crate::error::runtime::RuntimeError;
+
+/// how many tables deep a value can be read, a table that has itself
+/// inside of it would go on forever
+const MAX_DEPTH : usize = 128;
+
+/// what went wrong turning something into a lua value or back
+#[derive(Debug)]
+pub struct SerdeError(String);
+
+impl fmt::Display for SerdeError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for SerdeError { }
+
+impl ser::Error for SerdeError {
+    fn custom<T : fmt::Display>(message : T) -> SerdeError {
+        SerdeError(message.to_string())
+    }
+}
+
+impl de::Error for SerdeError {
+    fn custom<T : fmt::Display>(message : T) -> SerdeError {
+        SerdeError(message.to_string())
+    }
+}
+
+impl From<failure::Error> for SerdeError {
+    fn from(error : failure::Error) -> SerdeError {
+        SerdeError(error.to_string())
+    }
+}
+
+impl Interpreter {
+    pub fn to_value<T : Serialize + ?Sized>(&self, value : &T) -> Result<Value,failure::Error> {
+        //! the rust value as a lua value
+
+        value.serialize(Serializer::new(self))
+            .map_err(|error| RuntimeError::general(&error.0))
+    }
+
+    pub fn from_value<T : DeserializeOwned>(&self, value : Value) -> Result<T,failure::Error> {
+        //! reads the rust value out of the lua value
+
+        T::deserialize(Deserializer::new(value))
+            .map_err(|error| RuntimeError::general(&error.0))
+    }
+}
+
+/// makes lua values, the tables it makes belong to the interpreter
+pub struct Serializer<'a> {
+    interpreter : &'a Interpreter,
+}
+
+impl<'a> Serializer<'a> {
+    pub fn new(interpreter : &'a Interpreter) -> Serializer<'a> {
+        Serializer { interpreter }
+    }
+
+    fn table(self, variant : Option<&'static str>, size : Option<usize>) -> SerializeTable<'a> {
+        SerializeTable {
+            interpreter : self.interpreter,
+            table : Table::with_capacity(0, size.unwrap_or(0)),
+            variant,
+            next_index : 1,
+            key : None,
+        }
+    }
+
+    fn variant(&self, variant : &'static str, value : Value) -> Result<Value,SerdeError> {
+        //! `{ variant = value }`
+
+        let mut table = Table::new();
+        table.set_str(variant, value);
+        Ok(new_table(self.interpreter, table)?)
+    }
+}
+
+impl<'a> ser::Serializer for Serializer<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    type SerializeSeq = SerializeTable<'a>;
+    type SerializeTuple = SerializeTable<'a>;
+    type SerializeTupleStruct = SerializeTable<'a>;
+    type SerializeTupleVariant = SerializeTable<'a>;
+    type SerializeMap = SerializeTable<'a>;
+    type SerializeStruct = SerializeTable<'a>;
+    type SerializeStructVariant = SerializeTable<'a>;
+
+    fn serialize_bool(self, value : bool) -> Result<Value,SerdeError> {
+        Ok(Value::Boolean(value))
+    }
+
+    fn serialize_i8(self, value : i8) -> Result<Value,SerdeError> { self.serialize_f64(value as f64) }
+    fn serialize_i16(self, value : i16) -> Result<Value,SerdeError> { self.serialize_f64(value as f64) }
+    fn serialize_i32(self, value : i32) -> Result<Value,SerdeError> { self.serialize_f64(value as f64) }
+    fn serialize_u8(self, value : u8) -> Result<Value,SerdeError> { self.serialize_f64(value as f64) }
+    fn serialize_u16(self, value : u16) -> Result<Value,SerdeError> { self.serialize_f64(value as f64) }
+    fn serialize_u32(self, value : u32) -> Result<Value,SerdeError> { self.serialize_f64(value as f64) }
+    fn serialize_f32(self, value : f32) -> Result<Value,SerdeError> { self.serialize_f64(value as f64) }
+
+    fn serialize_i64(self, value : i64) -> Result<Value,SerdeError> {
+        Ok(crate::value::IntoLua::into_lua(value, self.interpreter)?)
+    }
+
+    fn serialize_u64(self, value : u64) -> Result<Value,SerdeError> {
+        Ok(crate::value::IntoLua::into_lua(value, self.interpreter)?)
+    }
+
+    fn serialize_f64(self, value : f64) -> Result<Value,SerdeError> {
+        Ok(Value::Number(value))
+    }
+
+    fn serialize_char(self, value : char) -> Result<Value,SerdeError> {
+        Ok(Value::String(LuaString::from(value.to_string())))
+    }
+
+    fn serialize_str(self, value : &str) -> Result<Value,SerdeError> {
+        Ok(Value::from(value))
+    }
+
+    fn serialize_bytes(self, value : &[u8]) -> Result<Value,SerdeError> {
+        //! lua strings can have any bytes in them
+
+        Ok(Value::String(LuaString::from(value)))
+    }
+
+    fn serialize_none(self) -> Result<Value,SerdeError> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_some<T : Serialize + ?Sized>(self, value : &T) -> Result<Value,SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value,SerdeError> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_struct(self, _ : &'static str) -> Result<Value,SerdeError> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_variant(self, _ : &'static str, _ : u32, variant : &'static str) -> Result<Value,SerdeError> {
+        Ok(Value::from(variant))
+    }
+
+    fn serialize_newtype_struct<T : Serialize + ?Sized>(self, _ : &'static str, value : &T) -> Result<Value,SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T : Serialize + ?Sized>(self, _ : &'static str, _ : u32, variant : &'static str, value : &T) -> Result<Value,SerdeError> {
+        let value = value.serialize(Serializer::new(self.interpreter))?;
+        self.variant(variant, value)
+    }
+
+    fn serialize_seq(self, size : Option<usize>) -> Result<SerializeTable<'a>,SerdeError> {
+        Ok(self.table(None, size))
+    }
+
+    fn serialize_tuple(self, size : usize) -> Result<SerializeTable<'a>,SerdeError> {
+        Ok(self.table(None, Some(size)))
+    }
+
+    fn serialize_tuple_struct(self, _ : &'static str, size : usize) -> Result<SerializeTable<'a>,SerdeError> {
+        Ok(self.table(None, Some(size)))
+    }
+
+    fn serialize_tuple_variant(self, _ : &'static str, _ : u32, variant : &'static str, size : usize) -> Result<SerializeTable<'a>,SerdeError> {
+        Ok(self.table(Some(variant), Some(size)))
+    }
+
+    fn serialize_map(self, size : Option<usize>) -> Result<SerializeTable<'a>,SerdeError> {
+        Ok(self.table(None, size))
+    }
+
+    fn serialize_struct(self, _ : &'static str, size : usize) -> Result<SerializeTable<'a>,SerdeError> {
+        Ok(self.table(None, Some(size)))
+    }
+
+    fn serialize_struct_variant(self, _ : &'static str, _ : u32, variant : &'static str, size : usize) -> Result<SerializeTable<'a>,SerdeError> {
+        Ok(self.table(Some(variant), Some(size)))
+    }
+}
+
+/// a table being filled in, sequences count up from `1` and maps and
+/// structs set keys
+pub struct SerializeTable<'a> {
+    interpreter : &'a Interpreter,
+    table : Table,
+    // the enum variant the table is wrapped in when it is finished
+    variant : Option<&'static str>,
+    next_index : usize,
+    // the key of a map entry, waiting for its value
+    key : Option<Value>,
+}
+
+impl<'a> SerializeTable<'a> {
+    fn push<T : Serialize + ?Sized>(&mut self, value : &T) -> Result<(),SerdeError> {
+        let value = value.serialize(Serializer::new(self.interpreter))?;
+        self.table.set(Value::Number(self.next_index as f64), value)?;
+        self.next_index += 1;
+        Ok(())
+    }
+
+    fn field<T : Serialize + ?Sized>(&mut self, key : &'static str, value : &T) -> Result<(),SerdeError> {
+        let value = value.serialize(Serializer::new(self.interpreter))?;
+        self.table.set_str(key, value);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value,SerdeError> {
+        let serializer = Serializer::new(self.interpreter);
+        let table = new_table(self.interpreter, self.table)?;
+        match self.variant {
+            Some(variant) => serializer.variant(variant, table),
+            None => Ok(table),
+        }
+    }
+}
+
+impl<'a> ser::SerializeSeq for SerializeTable<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_element<T : Serialize + ?Sized>(&mut self, value : &T) -> Result<(),SerdeError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value,SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTuple for SerializeTable<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_element<T : Serialize + ?Sized>(&mut self, value : &T) -> Result<(),SerdeError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value,SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for SerializeTable<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T : Serialize + ?Sized>(&mut self, value : &T) -> Result<(),SerdeError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value,SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleVariant for SerializeTable<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T : Serialize + ?Sized>(&mut self, value : &T) -> Result<(),SerdeError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value,SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeMap for SerializeTable<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_key<T : Serialize + ?Sized>(&mut self, key : &T) -> Result<(),SerdeError> {
+        self.key = Some(key.serialize(Serializer::new(self.interpreter))?);
+        Ok(())
+    }
+
+    fn serialize_value<T : Serialize + ?Sized>(&mut self, value : &T) -> Result<(),SerdeError> {
+        let key = self.key.take().ok_or_else(|| SerdeError("a map value was given without a key".to_string()))?;
+        let value = value.serialize(Serializer::new(self.interpreter))?;
+        self.table.set(key, value)?;
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value,SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStruct for SerializeTable<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T : Serialize + ?Sized>(&mut self, key : &'static str, value : &T) -> Result<(),SerdeError> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<Value,SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for SerializeTable<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T : Serialize + ?Sized>(&mut self, key : &'static str, value : &T) -> Result<(),SerdeError> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<Value,SerdeError> {
+        self.finish()
+    }
+}
+
+/// reads rust values out of a lua value. it doesn't look at metatables,
+/// only what is in the tables.
+pub struct Deserializer {
+    value : Value,
+    // how many tables this value is inside of
+    depth : usize,
+}
+
+impl Deserializer {
+    pub fn new(value : Value) -> Deserializer {
+        Deserializer { value, depth : 0 }
+    }
+
+    fn nested(value : Value, depth : usize) -> Deserializer {
+        Deserializer { value, depth }
+    }
+
+    fn enter(&self) -> Result<usize,SerdeError> {
+        //! the depth of the values inside of this table
+
+        match self.depth < MAX_DEPTH {
+            true => Ok(self.depth + 1),
+            false => Err(SerdeError("tables are nested too deeply, or a table is inside of itself".to_string())),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = SerdeError;
+
+    fn deserialize_any<V : Visitor<'de>>(self, visitor : V) -> Result<V::Value,SerdeError> {
+        match self.value {
+            Value::Nil => visitor.visit_unit(),
+            Value::Boolean(value) => visitor.visit_bool(value),
+            Value::Number(number) => match number.fract() == 0.0 && number.abs() < 9007199254740992.0 {
+                true => visitor.visit_i64(number as i64),
+                false => visitor.visit_f64(number),
+            },
+            Value::String(string) => match string.to_str() {
+                Some(text) => visitor.visit_str(text),
+                None => visitor.visit_bytes(string.as_bytes()),
+            },
+            Value::Table(ref table) => {
+                // a table with only the keys `1` to its length is a list,
+                // anything else is a map
+                let depth = self.enter()?;
+                let (length, pairs) = {
+                    let table = table.borrow();
+                    (table.len(), pairs(&table)?)
+                };
+                match length > 0 && length == pairs.len() {
+                    true => visitor.visit_seq(SeqAccess::new(pairs.into_iter().map(|(_, value)| value).collect(), depth)),
+                    false => visitor.visit_map(MapAccess::new(pairs, depth)),
+                }
+            },
+            value => Err(SerdeError(format!("cannot deserialize a {} value", value.type_name()))),
+        }
+    }
+
+    fn deserialize_option<V : Visitor<'de>>(self, visitor : V) -> Result<V::Value,SerdeError> {
+        match self.value {
+            Value::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V : Visitor<'de>>(self, _ : &'static str, visitor : V) -> Result<V::Value,SerdeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V : Visitor<'de>>(self, visitor : V) -> Result<V::Value,SerdeError> {
+        //! the values from `1` up to the length, so an empty table is an
+        //! empty list and not an empty map
+
+        match self.value {
+            Value::Table(ref table) => {
+                let depth = self.enter()?;
+                let values = {
+                    let table = table.borrow();
+                    (1 ..= table.len()).map(|i| table.get_index(i)).collect()
+                };
+                visitor.visit_seq(SeqAccess::new(values, depth))
+            },
+            value => Err(de::Error::invalid_type(unexpected(&value), &visitor)),
+        }
+    }
+
+    fn deserialize_tuple<V : Visitor<'de>>(self, _ : usize, visitor : V) -> Result<V::Value,SerdeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V : Visitor<'de>>(self, _ : &'static str, _ : usize, visitor : V) -> Result<V::Value,SerdeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V : Visitor<'de>>(self, visitor : V) -> Result<V::Value,SerdeError> {
+        match self.value {
+            Value::Table(ref table) => {
+                let depth = self.enter()?;
+                let pairs = pairs(&table.borrow())?;
+                visitor.visit_map(MapAccess::new(pairs, depth))
+            },
+            value => Err(de::Error::invalid_type(unexpected(&value), &visitor)),
+        }
+    }
+
+    fn deserialize_struct<V : Visitor<'de>>(self, _ : &'static str, _ : &'static [&'static str], visitor : V) -> Result<V::Value,SerdeError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V : Visitor<'de>>(self, _ : &'static str, _ : &'static [&'static str], visitor : V) -> Result<V::Value,SerdeError> {
+        //! the name of a unit variant, or a table with the name of the
+        //! variant as its only key
+
+        let depth = self.enter()?;
+        let (variant, value) = match self.value {
+            Value::String(_) => (self.value, None),
+            Value::Table(table) => {
+                let mut pairs = pairs(&table.borrow())?;
+                match pairs.len() {
+                    1 => { let (variant, value) = pairs.remove(0); (variant, Some(value)) },
+                    _ => return Err(SerdeError("an enum table needs exactly one key".to_string())),
+                }
+            },
+            value => return Err(de::Error::invalid_type(unexpected(&value), &visitor)),
+        };
+
+        visitor.visit_enum(EnumAccess { variant, value, depth })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, SerdeError> for Deserializer {
+    type Deserializer = Deserializer;
+
+    fn into_deserializer(self) -> Deserializer {
+        self
+    }
+}
+
+// PRIVATE FUNCTIONS /////////////////////////////////////
+
+fn unexpected(value : &Value) -> de::Unexpected<'_> {
+    match value {
+        Value::Nil => de::Unexpected::Unit,
+        Value::Boolean(value) => de::Unexpected::Bool(*value),
+        Value::Number(number) => de::Unexpected::Float(*number),
+        Value::String(string) => match string.to_str() {
+            Some(text) => de::Unexpected::Str(text),
+            None => de::Unexpected::Bytes(string.as_bytes()),
+        },
+        Value::Table(_) => de::Unexpected::Map,
+        value => de::Unexpected::Other(value.type_name()),
+    }
+}
+
+struct SeqAccess {
+    values : std::vec::IntoIter<Value>,
+    depth : usize,
+}
+
+impl SeqAccess {
+    fn new(values : Vec<Value>, depth : usize) -> SeqAccess {
+        SeqAccess { values : values.into_iter(), depth }
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess {
+    type Error = SerdeError;
+
+    fn next_element_seed<T : de::DeserializeSeed<'de>>(&mut self, seed : T) -> Result<Option<T::Value>,SerdeError> {
+        match self.values.next() {
+            Some(value) => seed.deserialize(Deserializer::nested(value, self.depth)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len())
+    }
+}
+
+struct MapAccess {
+    pairs : std::vec::IntoIter<(Value,Value)>,
+    value : Option<Value>,
+    depth : usize,
+}
+
+impl MapAccess {
+    fn new(pairs : Vec<(Value,Value)>, depth : usize) -> MapAccess {
+        MapAccess { pairs : pairs.into_iter(), value : None, depth }
+    }
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = SerdeError;
+
+    fn next_key_seed<K : de::DeserializeSeed<'de>>(&mut self, seed : K) -> Result<Option<K::Value>,SerdeError> {
+        match self.pairs.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(Deserializer::nested(key, self.depth)).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V : de::DeserializeSeed<'de>>(&mut self, seed : V) -> Result<V::Value,SerdeError> {
+        let value = self.value.take().unwrap_or(Value::Nil);
+        seed.deserialize(Deserializer::nested(value, self.depth))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.pairs.len())
+    }
+}
+
+struct EnumAccess {
+    variant : Value,
+    value : Option<Value>,
+    depth : usize,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = SerdeError;
+    type Variant = VariantAccess;
+
+    fn variant_seed<V : de::DeserializeSeed<'de>>(self, seed : V) -> Result<(V::Value, VariantAccess),SerdeError> {
+        let variant = seed.deserialize(Deserializer::nested(self.variant, self.depth))?;
+        Ok((variant, VariantAccess { value : self.value, depth : self.depth }))
+    }
+}
+
+struct VariantAccess {
+    value : Option<Value>,
+    depth : usize,
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(),SerdeError> {
+        match self.value {
+            None | Some(Value::Nil) => Ok(()),
+            Some(value) => Err(de::Error::invalid_type(unexpected(&value), &"unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T : de::DeserializeSeed<'de>>(self, seed : T) -> Result<T::Value,SerdeError> {
+        seed.deserialize(Deserializer::nested(self.value.unwrap_or(Value::Nil), self.depth))
+    }
+
+    fn tuple_variant<V : Visitor<'de>>(self, _ : usize, visitor : V) -> Result<V::Value,SerdeError> {
+        de::Deserializer::deserialize_seq(Deserializer::nested(self.value.unwrap_or(Value::Nil), self.depth), visitor)
+    }
+
+    fn struct_variant<V : Visitor<'de>>(self, _ : &'static [&'static str], visitor : V) -> Result<V::Value,SerdeError> {
+        de::Deserializer::deserialize_map(Deserializer::nested(self.value.unwrap_or(Value::Nil), self.depth), visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use serde::{Serialize, Deserialize};
+
+    use crate::interpreter::Interpreter;
+    use crate::value::Value;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Mode {
+        Windowed,
+        Fullscreen { monitor : u8 },
+        Scaled(f64),
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Node {
+        name : String,
+        child : Option<Box<Node>>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Config {
+        title : String,
+        size : (u32, u32),
+        vsync : bool,
+        icon : Option<String>,
+        modes : Vec<Mode>,
+        keys : HashMap<String, String>,
+    }
+
+    #[test]
+    pub fn serde_tables() {
+        let interpreter = Interpreter::new();
+
+        let code = r#"
+            return {
+                title = "game",
+                size = { 800, 600 },
+                vsync = true,
+                modes = { "Windowed", { Fullscreen = { monitor = 2 } }, { Scaled = 1.5 } },
+                keys = { jump = "space" },
+            }
+        "#;
+        let value = interpreter.run(code, None).unwrap().into_values().remove(0);
+        let config : Config = interpreter.from_value(value).unwrap();
+        assert_eq!(config, Config {
+            title : "game".to_string(),
+            size : (800, 600),
+            vsync : true,
+            icon : None,
+            modes : vec![Mode::Windowed, Mode::Fullscreen { monitor : 2 }, Mode::Scaled(1.5)],
+            keys : [("jump".to_string(), "space".to_string())].iter().cloned().collect(),
+        });
+
+        // and back again, for lua to read
+        let value = interpreter.to_value(&config).unwrap();
+        interpreter.set_global("config", value.clone());
+        let check = r#"
+            return config.title, config.size[2], config.modes[1], config.modes[2].Fullscreen.monitor, config.keys.jump, config.icon
+        "#;
+        assert_eq!(interpreter.run(check, None).unwrap().into_values(), vec![
+            Value::from("game"),
+            Value::Number(600.0),
+            Value::from("Windowed"),
+            Value::Number(2.0),
+            Value::from("space"),
+            Value::Nil,
+        ]);
+        assert_eq!(interpreter.from_value::<Config>(value).unwrap(), config);
+
+        let bad = interpreter.run("return { title = 1 }", None).unwrap().into_values().remove(0);
+        let error = interpreter.from_value::<Config>(bad).unwrap_err().to_string();
+        assert!(error.contains("invalid type: integer `1`, expected a string"), "{}", error);
+
+        // a table inside of itself is an error, not a stack overflow
+        let cycle = interpreter.run("local t = { name = 'a' } t.child = t return t", None).unwrap().into_values().remove(0);
+        let error = interpreter.from_value::<Node>(cycle).unwrap_err().to_string();
+        assert!(error.contains("a table is inside of itself"), "{}", error);
+        let cycle = interpreter.run("local t = {} t[1] = t return t", None).unwrap().into_values().remove(0);
+        assert!(interpreter.from_value::<serde::de::IgnoredAny>(cycle).is_err());
+    }
+}
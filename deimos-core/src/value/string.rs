@@ -0,0 +1,258 @@
+//! lua strings are just bytes, they don't have to be valid UTF-8 (and
+//! a lot of the test suite isn't). every string is interned, the scanner's
+//! names and literals, the constants of the compiled code and everything
+//! made at runtime, so there is only ever one copy of each string around.
+//! comparing and hashing them is a pointer check, which is what makes
+//! looking up a table's string keys cheap.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::rc::{Rc, Weak};
+
+thread_local! {
+    // all of the strings that are alive, the interner doesn't keep them
+    // alive, each one takes itself out when it is dropped.
+    static INTERNER : RefCell<Interner> = RefCell::new(Interner::new());
+}
+
+struct Interner {
+    // the strings by the hash of their bytes, the bytes themselves are only
+    // kept by the strings
+    strings : HashMap<u64, Vec<Weak<Interned>>>,
+}
+
+impl Interner {
+    fn new() -> Interner {
+        Interner {
+            strings : HashMap::new(),
+        }
+    }
+
+    fn intern(&mut self, bytes : &[u8]) -> Rc<Interned> {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if let Some(bucket) = self.strings.get(&hash) {
+            let found = bucket.iter()
+                .filter_map(|weak| weak.upgrade())
+                .find(|string| string.as_bytes() == bytes);
+            if let Some(string) = found {
+                return string;
+            }
+        }
+
+        let string = Rc::new(Interned { hash, bytes : Box::from(bytes) });
+        crate::value::count_string(&string);
+        self.strings.entry(hash).or_default().push(Rc::downgrade(&string));
+        string
+    }
+
+    fn forget(&mut self, string : *const Interned, hash : u64) {
+        if let Some(bucket) = self.strings.get_mut(&hash) {
+            bucket.retain(|weak| weak.as_ptr() != string);
+            if bucket.is_empty() {
+                self.strings.remove(&hash);
+            }
+        }
+    }
+}
+
+/// the bytes of an interned string. they are boxed on their own so that
+/// the weak references to a string only keep this much alive after it is
+/// dead, and not all of its bytes.
+pub(crate) struct Interned {
+    hash : u64,
+    bytes : Box<[u8]>,
+}
+
+impl Interned {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl std::ops::Deref for Interned {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Drop for Interned {
+    fn drop(&mut self) {
+        // the interner might be gone already when the thread is ending
+        let string : *const Interned = self;
+        let _ = INTERNER.try_with(|interner| if let Ok(mut interner) = interner.try_borrow_mut() {
+            interner.forget(string, self.hash);
+        });
+    }
+}
+
+#[derive(Clone)]
+pub struct LuaString {
+    bytes : Rc<Interned>,
+}
+
+impl LuaString {
+    pub fn new(bytes : &[u8]) -> LuaString {
+        LuaString {
+            bytes : INTERNER.with(|interner| interner.borrow_mut().intern(bytes)),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn to_str(&self) -> Option<&str> {
+        //! the string as rust text, if it is valid UTF-8
+
+        std::str::from_utf8(&self.bytes).ok()
+    }
+
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.bytes).to_string()
+    }
+
+    pub fn concat(&self, other : &LuaString) -> LuaString {
+        let mut bytes = Vec::with_capacity(self.len() + other.len());
+        bytes.extend_from_slice(&self.bytes);
+        bytes.extend_from_slice(&other.bytes);
+
+        LuaString::new(&bytes)
+    }
+}
+
+pub(crate) fn size(length : usize) -> usize {
+    //! how much memory a string uses, the same as lua's strings would
+
+    24 + length
+}
+
+impl std::fmt::Display for LuaString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.bytes))
+    }
+}
+
+impl std::fmt::Debug for LuaString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", String::from_utf8_lossy(&self.bytes))
+    }
+}
+
+impl PartialEq for LuaString {
+    fn eq(&self, other : &LuaString) -> bool {
+        // strings can't be sent to another thread, so they all came from
+        // the same interner
+        Rc::ptr_eq(&self.bytes, &other.bytes)
+    }
+}
+
+impl Eq for LuaString { }
+
+impl PartialOrd for LuaString {
+    fn partial_cmp(&self, other : &LuaString) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LuaString {
+    fn cmp(&self, other : &LuaString) -> std::cmp::Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+impl Hash for LuaString {
+    fn hash<H : Hasher>(&self, state : &mut H) {
+        // equal strings are the same string, so where it is will do
+        Rc::as_ptr(&self.bytes).hash(state);
+    }
+}
+
+impl From<&str> for LuaString {
+    fn from(string : &str) -> LuaString {
+        LuaString::new(string.as_bytes())
+    }
+}
+
+impl From<String> for LuaString {
+    fn from(string : String) -> LuaString {
+        LuaString::new(string.as_bytes())
+    }
+}
+
+impl From<&[u8]> for LuaString {
+    fn from(bytes : &[u8]) -> LuaString {
+        LuaString::new(bytes)
+    }
+}
+
+impl From<Vec<u8>> for LuaString {
+    fn from(bytes : Vec<u8>) -> LuaString {
+        LuaString::new(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::value::string::{LuaString, INTERNER};
+    use crate::scanner::Scanner;
+    use crate::token::Token;
+    use std::rc::Rc;
+
+    #[test]
+    pub fn interning() {
+        let a = LuaString::from("hello");
+        let b = LuaString::from(String::from("hel") + "lo");
+
+        assert!(Rc::ptr_eq(&a.bytes, &b.bytes));
+        assert_eq!(a, b);
+        assert_ne!(a, LuaString::from("hello!"));
+        assert!(LuaString::from("a") < LuaString::from("b"));
+        assert_eq!(LuaString::from(&[255u8, 0][..]).len(), 2);
+    }
+
+    #[test]
+    pub fn dead_strings_are_freed() {
+        // nothing but the small part the weak references point at is kept
+        // once a string is dead, and the interner forgets about it
+        let big = LuaString::from(vec![b'x'; 1 << 20]);
+        let hash = big.bytes.hash;
+        let weak = Rc::downgrade(&big.bytes);
+        drop(big);
+
+        assert!(weak.upgrade().is_none());
+        assert!(INTERNER.with(|interner| !interner.borrow().strings.contains_key(&hash)));
+
+        // the same bytes make a new string after that
+        let again = LuaString::from(vec![b'x'; 1 << 20]);
+        assert_eq!(again.len(), 1 << 20);
+        assert!(INTERNER.with(|interner| interner.borrow().strings[&hash].len() == 1));
+    }
+
+    #[test]
+    pub fn shared_with_the_scanner() {
+        let scanner = Scanner::from_str("local hello = 'hello' .. [[hello]]", None).unwrap();
+        let hello = LuaString::from("hello");
+
+        let shared = scanner.tokens.iter().filter(|token| match token.item() {
+            Token::Identifier(string) | Token::String(string) | Token::MultiLineString(string) => Rc::ptr_eq(&string.bytes, &hello.bytes),
+            _ => false,
+        }).count();
+        assert_eq!(shared, 3);
+    }
+}
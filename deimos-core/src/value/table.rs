@@ -0,0 +1,499 @@
+//! the lua table, the only way of structuring data in lua.
+//!
+//! like the real lua the table has two parts, an array part for the keys
+//! `1 .. n` and a hash part for everything else. the hash part remembers
+//! the order things were added so that `next` can always find where it
+//! left off, even if values are removed while going through the table.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use failure::Error;
+
+use crate::value::Value;
+use crate::error::runtime::RuntimeError;
+
+#[derive(Default)]
+pub struct Table {
+    // the values for the keys `1 .. array.len()`, can have `nil` holes
+    array : Vec<Value>,
+
+    // everything else, removed entries are left as `nil` so that `next`
+    // still works on them, they are cleaned up when new keys are added.
+    entries : Vec<(Value,Value)>,
+    index : HashMap<Value,usize>,
+    removed : usize,
+
+    metatable : Option<Rc<RefCell<Table>>>,
+}
+
+impl Table {
+    pub fn new() -> Table {
+        Table::default()
+    }
+
+    pub fn with_capacity(array : usize, hash : usize) -> Table {
+        Table {
+            array : Vec::with_capacity(array),
+            entries : Vec::with_capacity(hash),
+            index : HashMap::with_capacity(hash),
+            removed : 0,
+            metatable : None,
+        }
+    }
+
+    pub fn from_values(values : Vec<Value>) -> Table {
+        //! creates a table with the values as an array, starting at `1`.
+        //! the array is as long as there are values, the `nil`s stay in
+        //! it like they do in lua's so `#` finds the same border.
+
+        crate::value::grow(16 * values.len());
+
+        let mut table = Table::new();
+        table.array = values;
+        table
+    }
+
+    pub fn get(&self, key : &Value) -> Value {
+        //! gets the value without looking at any metatables
+
+        if let Some(i) = array_index(key) {
+            if i <= self.array.len() {
+                return self.array[i - 1].clone();
+            }
+        }
+
+        match self.index.get(key) {
+            Some(i) => self.entries[*i].1.clone(),
+            None => Value::Nil,
+        }
+    }
+
+    pub fn get_str(&self, key : &str) -> Value {
+        self.get(&Value::from(key))
+    }
+
+    pub fn get_index(&self, i : usize) -> Value {
+        //! gets the value at the position `i` of the array, starting at `1`
+
+        match i >= 1 && i <= self.array.len() {
+            true => self.array[i - 1].clone(),
+            false => self.get(&Value::Number(i as f64)),
+        }
+    }
+
+    pub fn set(&mut self, key : Value, value : Value) -> Result<(),Error> {
+        //! sets the value without looking at any metatables, setting
+        //! something to `nil` removes it from the table.
+
+        match key {
+            Value::Nil => return Err(RuntimeError::general("table index is nil")),
+            Value::Number(number) if number.is_nan() => return Err(RuntimeError::general("table index is NaN")),
+            _ => { },
+        }
+
+        if let Some(i) = array_index(&key) {
+            if i <= self.array.len() + 1 {
+                self.array_set(i, value);
+                return Ok(());
+            }
+        }
+
+        self.hash_set(key, value);
+        Ok(())
+    }
+
+    pub fn set_str(&mut self, key : &str, value : Value) {
+        self.hash_set(Value::from(key), value);
+    }
+
+    pub fn metatable(&self) -> Option<Rc<RefCell<Table>>> {
+        self.metatable.clone()
+    }
+
+    pub fn set_metatable(&mut self, metatable : Option<Rc<RefCell<Table>>>) {
+        self.metatable = metatable;
+    }
+
+    pub fn len(&self) -> usize {
+        //! the length of the table, this is a 'border' in the table. any
+        //! place where `t[n]` is not nil and `t[n+1]` is nil, so if the
+        //! array has holes it could be any of them.
+
+        // there is a hole at the end of the array, so a border is in there
+        if let Some(Value::Nil) = self.array.last() {
+            let (mut i, mut j) = (0, self.array.len());
+            while j - i > 1 {
+                let middle = (i + j) / 2;
+                match self.array[middle - 1] {
+                    Value::Nil => j = middle,
+                    _ => i = middle,
+                }
+            }
+            return i;
+        }
+
+        if self.index.is_empty() {
+            return self.array.len();
+        }
+
+        // the border is past the array, so we go looking for it by
+        // doubling until we find a nil and then searching between.
+        let (mut i, mut j) = (self.array.len(), self.array.len() + 1);
+        while !self.get_index(j).is_nil() {
+            i = j;
+            match j.checked_mul(2) {
+                Some(next) if next < (1 << 53) => j = next,
+                _ => {
+                    // something is strange, so just count one at a time
+                    let mut i = 1;
+                    while !self.get_index(i).is_nil() { i += 1; }
+                    return i - 1;
+                },
+            }
+        }
+
+        while j - i > 1 {
+            let middle = (i + j) / 2;
+            match self.get_index(middle) {
+                Value::Nil => j = middle,
+                _ => i = middle,
+            }
+        }
+
+        i
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.next(&Value::Nil).map(|next| next.is_none()).unwrap_or(true)
+    }
+
+    pub fn next(&self, key : &Value) -> Result<Option<(Value,Value)>,Error> {
+        //! the key and value that come after the given key, the first one
+        //! if the key is `nil` and `None` when we get to the end.
+
+        // where we start looking, the array first and then the entries
+        let start = match key {
+            Value::Nil => 0,
+            key => match array_index(key) {
+                Some(i) if i <= self.array.len() => i,
+                _ => match self.index.get(key) {
+                    Some(i) => self.array.len() + i + 1,
+                    None => return Err(RuntimeError::general("invalid key to 'next'")),
+                },
+            },
+        };
+
+        for i in start .. self.array.len() {
+            if !self.array[i].is_nil() {
+                return Ok(Some((Value::Number((i + 1) as f64), self.array[i].clone())));
+            }
+        }
+
+        let start = start.saturating_sub(self.array.len());
+        for (key, value) in self.entries.iter().skip(start) {
+            if !value.is_nil() {
+                return Ok(Some((key.clone(), value.clone())));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn insert(&mut self, position : usize, value : Value) -> Result<(),Error> {
+        //! puts the value at the position, moving everything after it
+        //! up one.
+
+        let length = self.len();
+        let mut i = length;
+        while i >= position && i > 0 {
+            let moving = self.get_index(i);
+            self.set(Value::Number((i + 1) as f64), moving)?;
+            i -= 1;
+        }
+
+        self.set(Value::Number(position as f64), value)
+    }
+
+    pub fn remove(&mut self, position : usize) -> Result<Value,Error> {
+        //! takes out the value at the position, moving everything after it
+        //! down one.
+
+        let length = self.len();
+        let value = self.get_index(position);
+
+        for i in position .. length {
+            let moving = self.get_index(i + 1);
+            self.set(Value::Number(i as f64), moving)?;
+        }
+
+        if position <= length {
+            self.set(Value::Number(length as f64), Value::Nil)?;
+        }
+
+        Ok(value)
+    }
+
+    pub(crate) fn resize_array(&mut self, size : usize) {
+        //! makes the array part at least `size` long, like lua's
+        //! `luaH_resizearray` does for a table constructor. the keys that
+        //! were in the hash part move over and the rest are `nil`.
+
+        while self.array.len() < size {
+            let key = Value::Number((self.array.len() + 1) as f64);
+            let value = match self.index.get(&key) {
+                Some(&i) if !self.entries[i].1.is_nil() => {
+                    self.removed += 1;
+                    std::mem::replace(&mut self.entries[i].1, Value::Nil)
+                },
+                _ => Value::Nil,
+            };
+
+            self.array.push(value);
+            crate::value::grow(16);
+        }
+    }
+
+    pub(crate) fn trace(&self, keys : bool, values : bool, visit : &mut dyn FnMut(&Value)) {
+        //! goes through every value the table is holding on to, the keys
+        //! of the hash part are held twice because the index has its own.
+        //! the metatable isn't included.
+
+        if values {
+            for value in self.array.iter() {
+                visit(value);
+            }
+        }
+
+        for (key, value) in self.entries.iter() {
+            if keys {
+                visit(key);
+                visit(key);
+            }
+            if values {
+                visit(value);
+            }
+        }
+    }
+
+    pub(crate) fn retain(&mut self, keep : &dyn Fn(&Value, &Value) -> bool) {
+        //! sets everything that shouldn't be kept to `nil`, used to clear
+        //! the collected keys and values out of weak tables. the keys stay
+        //! like any other removed entry, so a `next` going through the
+        //! table can still find where it is.
+
+        for i in 0 .. self.array.len() {
+            if !keep(&Value::Number((i + 1) as f64), &self.array[i]) {
+                self.array[i] = Value::Nil;
+            }
+        }
+
+        for (key, value) in self.entries.iter_mut() {
+            if !value.is_nil() && !keep(key, value) {
+                *value = Value::Nil;
+                self.removed += 1;
+            }
+        }
+    }
+
+    pub(crate) fn take_all(&mut self) -> Vec<Value> {
+        //! empties the table, giving back everything that was in it and
+        //! the metatable
+
+        let mut values = std::mem::take(&mut self.array);
+        self.index.clear();
+        for (key, value) in std::mem::take(&mut self.entries) {
+            values.push(key);
+            values.push(value);
+        }
+        if let Some(metatable) = self.metatable.take() {
+            values.push(Value::Table(metatable));
+        }
+        self.removed = 0;
+
+        values
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        //! about how many bytes the table is using, the same as lua's
+        //! tables would so `collectgarbage("count")` makes sense. the
+        //! strings are counted by themselves.
+
+        56 + 16 * self.array.len() + 40 * self.entries.len()
+    }
+
+    // PRIVATE FUNCTIONS /////////////////////////////////////
+
+    fn array_set(&mut self, i : usize, value : Value) {
+        //! sets something in the array part, `i` can only be one
+        //! past the end of the array.
+
+        if i <= self.array.len() {
+            self.array[i - 1] = value;
+            return;
+        }
+
+        if value.is_nil() {
+            // might still be in the hash part from before
+            self.hash_set(Value::Number(i as f64), value);
+            return;
+        }
+
+        self.array.push(value);
+        crate::value::grow(16);
+        self.hash_set(Value::Number(i as f64), Value::Nil);
+
+        // the next keys might be in the hash part, so we move them over
+        // so the array stays as long as it can be.
+        loop {
+            let key = Value::Number((self.array.len() + 1) as f64);
+            match self.index.get(&key) {
+                Some(i) if !self.entries[*i].1.is_nil() => {
+                    let value = std::mem::replace(&mut self.entries[*i].1, Value::Nil);
+                    self.removed += 1;
+                    self.array.push(value);
+                    crate::value::grow(16);
+                },
+                _ => break,
+            }
+        }
+    }
+
+    fn hash_set(&mut self, key : Value, value : Value) {
+        match self.index.get(&key) {
+            Some(i) => {
+                let entry = &mut self.entries[*i].1;
+                match (entry.is_nil(), value.is_nil()) {
+                    (false, true) => self.removed += 1,
+                    (true, false) => self.removed -= 1,
+                    _ => { },
+                }
+                *entry = value;
+            },
+            None => {
+                if value.is_nil() { return; }
+
+                // new keys are where the removed entries get cleaned up, it
+                // isn't allowed to add new keys while going through a table
+                // with `next` so we can move things around.
+                if self.removed > 8 && self.removed * 2 > self.entries.len() {
+                    self.compact();
+                }
+
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                crate::value::grow(40);
+            },
+        }
+    }
+
+    fn compact(&mut self) {
+        //! removes all the entries that have been set to `nil`
+
+        self.entries.retain(|(_, value)| !value.is_nil());
+        self.index.clear();
+        for (i, (key, _)) in self.entries.iter().enumerate() {
+            self.index.insert(key.clone(), i);
+        }
+        self.removed = 0;
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        //! the tables inside of this one that go away with it are emptied
+        //! here one at a time, instead of each one dropping the next, so
+        //! a long chain of tables doesn't run out of stack.
+
+        let mut values = self.take_all();
+        while let Some(value) = values.pop() {
+            if let Value::Table(table) = value {
+                if let Ok(table) = Rc::try_unwrap(table) {
+                    values.extend(table.borrow_mut().take_all());
+                }
+            }
+        }
+    }
+}
+
+fn array_index(key : &Value) -> Option<usize> {
+    //! checks if the key could go in the array part, which is only
+    //! for positive whole numbers.
+
+    match key {
+        Value::Number(number) if *number >= 1.0 && number.fract() == 0.0 && *number < (1u64 << 53) as f64 => Some(*number as usize),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::value::{Value, Table};
+
+    fn number(n : f64) -> Value { Value::Number(n) }
+
+    #[test]
+    pub fn array_and_hash() {
+        let mut table = Table::new();
+
+        // added backwards so it all starts in the hash part
+        for i in (1 .. 6).rev() {
+            table.set(number(i as f64), number(i as f64 * 10.0)).unwrap();
+        }
+        table.set(Value::from("x"), Value::from("y")).unwrap();
+
+        assert_eq!(table.len(), 5);
+        assert_eq!(table.get(&number(3.0)), number(30.0));
+        assert_eq!(table.get_str("x"), Value::from("y"));
+        assert!(table.set(Value::Nil, number(1.0)).is_err());
+        assert!(table.set(number(f64::NAN), number(1.0)).is_err());
+
+        // a border is any n where t[n] ~= nil and t[n+1] == nil
+        table.set(number(5.0), Value::Nil).unwrap();
+        assert_eq!(table.len(), 4);
+        table.set(number(2.0), Value::Nil).unwrap();
+        let border = table.len();
+        assert!(border == 1 || border == 4);
+    }
+
+    #[test]
+    pub fn next_order() {
+        let mut table = Table::from_values(vec![number(1.0), number(2.0)]);
+        table.set(Value::from("a"), number(3.0)).unwrap();
+        table.set(Value::from("b"), number(4.0)).unwrap();
+
+        let mut keys : Vec<Value> = Vec::new();
+        let mut key = Value::Nil;
+        while let Some((next, _)) = table.next(&key).unwrap() {
+            // removing things while going through the table is allowed
+            table.set(next.clone(), Value::Nil).unwrap();
+            keys.push(next.clone());
+            key = next;
+        }
+
+        assert_eq!(keys, vec![number(1.0), number(2.0), Value::from("a"), Value::from("b")]);
+        assert!(table.is_empty());
+        assert!(table.next(&Value::from("c")).is_err());
+    }
+
+    #[test]
+    pub fn values_with_holes() {
+        let table = Table::from_values(vec![number(1.0), Value::Nil, number(3.0), Value::Nil, number(5.0), Value::Nil]);
+
+        assert_eq!(table.get_index(1), number(1.0));
+        assert_eq!(table.get_index(2), Value::Nil);
+        assert_eq!(table.get_index(3), number(3.0));
+        assert_eq!(table.get_index(4), Value::Nil);
+        assert_eq!(table.get_index(5), number(5.0));
+        assert_eq!(table.get_index(6), Value::Nil);
+
+        // the same borders lua 5.1 finds, the nils are in the array
+        assert_eq!(table.len(), 3);
+        assert_eq!(Table::from_values(vec![number(1.0), number(2.0), Value::Nil, number(4.0)]).len(), 4);
+        assert_eq!(Table::from_values(vec![Value::Nil, Value::Nil, number(3.0)]).len(), 3);
+        assert_eq!(Table::from_values(vec![Value::Nil, number(2.0)]).len(), 2);
+        assert_eq!(Table::from_values(vec![Value::Nil, Value::Nil]).len(), 0);
+    }
+}
@@ -0,0 +1,215 @@
+//! threads are lua's coroutines, each one has its own stack of
+//! function calls that can be paused and started again.
+//!
+//! the interpreter walks the code by calling itself, so a thread needs a
+//! real stack of its own to be able to stop in the middle of a few nested
+//! calls. each thread runs on a separate stack and switches back to whoever
+//! resumed it when it yields.
+//!
+//! a suspended thread only holds on to what is on its stack, so nothing
+//! keeps it alive but the values that point at it. its calls are kept
+//! with it while it is suspended so the collector can see them. once started it runs
+//! on a clone of the interpreter that made it, the interpreter closes its
+//! threads when the program lets go of it so that clone doesn't keep it
+//! alive.
+
+use std::rc::{Rc, Weak};
+use std::cell::{Cell, Ref, RefCell};
+
+use failure::Error;
+use corosensei::{Coroutine, CoroutineResult, Yielder};
+use corosensei::stack::{Stack, DefaultStack};
+
+use crate::interpreter::{Interpreter, Handles, CallInfo};
+use crate::value::{Value, Table};
+
+/// how big the stack of each thread starts out, it gets more stack the
+/// same way the main thread does when it calls deep enough.
+const STACK_SIZE : usize = 256 * 1024;
+
+/// what the thread is given when it is resumed, the interpreter to run
+/// on and the function are only given the first time
+type Input = (Option<(Interpreter, Value)>, Vec<Value>);
+
+type Body = Coroutine<Input, Vec<Value>, Result<Vec<Value>,Error>>;
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum ThreadStatus {
+    Suspended,
+    Running,
+    Normal,
+    Dead,
+}
+
+pub struct Thread {
+    status : Cell<ThreadStatus>,
+    // the function it calls, until it is dead
+    function : RefCell<Option<Value>>,
+    // if it has been resumed, its stack has the function too then
+    started : Cell<bool>,
+    // the function running on its own stack, it is taken out while it
+    // is running
+    body : RefCell<Option<Body>>,
+    // the calls on its stack while it is suspended, so the collector
+    // can see what they are holding on to
+    calls : RefCell<Vec<CallInfo>>,
+    // what new chunks get as their globals while it is running, its own
+    // so `setfenv(0, table)` doesn't change anyone else's
+    environment : RefCell<Rc<RefCell<Table>>>,
+    // how the running thread gets back to whoever resumed it, only set
+    // once the thread has started
+    yielder : Rc<Cell<*const Yielder<Input, Vec<Value>>>>,
+    // the lowest address of the thread's stack
+    stack_limit : usize,
+    // the interpreter that made it, the only one that can resume it
+    owner : Weak<Handles>,
+}
+
+impl Thread {
+    pub(crate) fn new(function : Value, environment : Rc<RefCell<Table>>, owner : Weak<Handles>) -> Result<Thread,Error> {
+        //! makes a thread that will call the function the first time it
+        //! is resumed, with the environment of the thread that made it
+
+        let yielder : Rc<Cell<*const Yielder<Input, Vec<Value>>>> = Rc::new(Cell::new(std::ptr::null()));
+        let slot = yielder.clone();
+
+        let stack = DefaultStack::new(STACK_SIZE)?;
+        let stack_limit = stack.limit().get();
+        let body = Coroutine::with_stack(stack, move |yielder : &Yielder<Input, Vec<Value>>, (start, args) : Input| {
+            slot.set(yielder as *const _);
+
+            match start {
+                Some((interpreter, function)) => interpreter.call(&function, args),
+                None => Ok(Vec::new()),
+            }
+        });
+
+        Ok(Thread {
+            status : Cell::new(ThreadStatus::Suspended),
+            function : RefCell::new(Some(function)),
+            started : Cell::new(false),
+            body : RefCell::new(Some(body)),
+            calls : RefCell::new(Vec::new()),
+            environment : RefCell::new(environment),
+            yielder,
+            stack_limit,
+            owner,
+        })
+    }
+
+    pub fn status(&self) -> ThreadStatus {
+        self.status.get()
+    }
+
+    // PRIVATE FUNCTIONS /////////////////////////////////////
+
+    pub(crate) fn set_status(&self, status : ThreadStatus) {
+        self.status.set(status);
+    }
+
+    pub(crate) fn environment(&self) -> Rc<RefCell<Table>> {
+        self.environment.borrow().clone()
+    }
+
+    pub(crate) fn set_environment(&self, environment : Rc<RefCell<Table>>) {
+        *self.environment.borrow_mut() = environment;
+    }
+
+    pub(crate) fn stack_limit(&self) -> usize {
+        self.stack_limit
+    }
+
+    pub(crate) fn owner(&self) -> &Weak<Handles> {
+        &self.owner
+    }
+
+    pub(crate) fn function(&self) -> Option<Value> {
+        //! the function it calls, if it isn't dead
+
+        self.function.try_borrow().ok()?.clone()
+    }
+
+    pub(crate) fn is_started(&self) -> bool {
+        self.started.get()
+    }
+
+    pub(crate) fn calls(&self) -> Option<Ref<'_, Vec<CallInfo>>> {
+        //! the calls on its stack, there are only any while it is
+        //! suspended after yielding
+
+        self.calls.try_borrow().ok()
+    }
+
+    pub(crate) fn save_calls(&self, calls : Vec<CallInfo>) {
+        *self.calls.borrow_mut() = calls;
+    }
+
+    pub(crate) fn take_calls(&self) -> Vec<CallInfo> {
+        std::mem::take(&mut *self.calls.borrow_mut())
+    }
+
+    pub(crate) fn close(&self) -> Option<Value> {
+        //! kills a suspended thread, everything on its stack is dropped
+        //! right away. gives back the function.
+
+        if self.status() != ThreadStatus::Suspended {
+            return None;
+        }
+
+        self.set_status(ThreadStatus::Dead);
+        let body = self.body.try_borrow_mut().ok()?.take();
+        drop(body);
+        let calls = std::mem::take(&mut *self.calls.try_borrow_mut().ok()?);
+        drop(calls);
+        self.function.try_borrow_mut().ok()?.take()
+    }
+
+    pub(crate) fn resume(&self, interpreter : &Interpreter, args : Vec<Value>) -> CoroutineResult<Vec<Value>, Result<Vec<Value>,Error>> {
+        //! runs the thread until it yields or finishes
+
+        let mut body = match self.body.borrow_mut().take() {
+            Some(body) => body,
+            None => return CoroutineResult::Return(Ok(Vec::new())),
+        };
+
+        let start = match self.started.replace(true) {
+            false => self.function().map(|function| (interpreter.thread_clone(), function)),
+            true => None,
+        };
+        let result = body.resume((start, args));
+
+        match result {
+            CoroutineResult::Yield(_) => *self.body.borrow_mut() = Some(body),
+            CoroutineResult::Return(_) => { self.function.borrow_mut().take(); },
+        }
+
+        result
+    }
+
+    pub(crate) fn suspend(thread : Rc<Thread>, values : Vec<Value>) -> Vec<Value> {
+        //! stops the thread and goes back to whoever resumed it, gives
+        //! back what the thread is resumed with. must only be called from
+        //! inside the thread. the thread is let go of first, its stack
+        //! can't be what keeps it alive.
+
+        let yielder = thread.yielder.get();
+        assert!(!yielder.is_null(), "suspending a thread that isn't running");
+        drop(thread);
+
+        // the yielder lives at the bottom of the thread's stack, it is
+        // there for as long as the thread is running
+        let (_, args) = unsafe { &*yielder }.suspend(values);
+        args
+    }
+}
+
+impl std::fmt::Display for ThreadStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThreadStatus::Suspended => write!(f, "suspended"),
+            ThreadStatus::Running => write!(f, "running"),
+            ThreadStatus::Normal => write!(f, "normal"),
+            ThreadStatus::Dead => write!(f, "dead"),
+        }
+    }
+}
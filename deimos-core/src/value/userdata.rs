@@ -0,0 +1,314 @@
+//! userdata is how rust data is given to lua, lua can't look inside
+//! of it and can only pass it around.
+//!
+//! types that implement `UserData` say what lua can do with them, the
+//! methods and fields and metamethods that call back into rust. all the
+//! values of a type share one metatable that is made from them.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::cell::{Cell, Ref, RefCell, RefMut};
+
+use failure::Error;
+
+use crate::interpreter::{Interpreter, Args, native_function};
+use crate::value::{Value, Table, LuaString};
+use crate::error::runtime::RuntimeError;
+
+/// gives the lua values inside of the data to the garbage collector
+type Trace = fn(&dyn Any, &mut dyn FnMut(&Value));
+
+pub struct AnyUserData {
+    data : RefCell<Box<dyn Any>>,
+    // the type of the data, so it can be checked while it is borrowed
+    type_id : TypeId,
+    // what the data is holding on to, if its type says
+    trace : Option<Trace>,
+    metatable : RefCell<Option<Rc<RefCell<Table>>>>,
+    // if its `__gc` has been called, or it didn't have one when the
+    // collector found it, it is never called again
+    finalized : Cell<bool>,
+}
+
+impl AnyUserData {
+    pub fn new<T : Any>(data : T) -> AnyUserData {
+        AnyUserData {
+            data : RefCell::new(Box::new(data)),
+            type_id : TypeId::of::<T>(),
+            trace : None,
+            metatable : RefCell::new(None),
+            finalized : Cell::new(false),
+        }
+    }
+
+    pub fn from_userdata<T : UserData>(data : T) -> AnyUserData {
+        //! the data with what its `UserData::trace` says it holds on to
+        //! given to the collector
+
+        AnyUserData {
+            trace : Some(trace_userdata::<T>),
+            .. AnyUserData::new(data)
+        }
+    }
+
+    pub fn data(&self) -> &RefCell<Box<dyn Any>> {
+        &self.data
+    }
+
+    pub fn is<T : Any>(&self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
+
+    pub fn borrow<T : UserData>(&self) -> Result<Ref<'_, T>,Error> {
+        //! the data, which can't be borrowed while something is changing
+        //! it. failing is a lua error.
+
+        let data = self.data.try_borrow()
+            .map_err(|_| RuntimeError::general(&format!("'{}' is already mutably borrowed", T::type_name())))?;
+        Ref::filter_map(data, |data| data.downcast_ref::<T>())
+            .map_err(|_| RuntimeError::general(&format!("userdata is not a '{}'", T::type_name())))
+    }
+
+    pub fn borrow_mut<T : UserData>(&self) -> Result<RefMut<'_, T>,Error> {
+        //! the data to change, which can't be borrowed anywhere else
+
+        let data = self.data.try_borrow_mut()
+            .map_err(|_| RuntimeError::general(&format!("'{}' is already borrowed", T::type_name())))?;
+        RefMut::filter_map(data, |data| data.downcast_mut::<T>())
+            .map_err(|_| RuntimeError::general(&format!("userdata is not a '{}'", T::type_name())))
+    }
+
+    pub fn metatable(&self) -> Option<Rc<RefCell<Table>>> {
+        self.metatable.borrow().clone()
+    }
+
+    pub fn set_metatable(&self, metatable : Option<Rc<RefCell<Table>>>) {
+        *self.metatable.borrow_mut() = metatable;
+    }
+
+    pub(crate) fn is_finalized(&self) -> bool {
+        self.finalized.get()
+    }
+
+    pub(crate) fn set_finalized(&self) {
+        self.finalized.set(true);
+    }
+
+    pub(crate) fn trace(&self, visit : &mut dyn FnMut(&Value)) -> Option<()> {
+        //! goes through the lua values inside of the data, `None` if it
+        //! is being changed and can't be looked at
+
+        if let Some(trace) = self.trace {
+            trace(&**self.data.try_borrow().ok()?, visit);
+        }
+        Some(())
+    }
+}
+
+/// a rust type that lua can use, made into a value with
+/// `Interpreter::create_userdata`
+pub trait UserData : Any + Sized {
+    fn type_name() -> &'static str {
+        //! what the errors call it, the name of the type without the
+        //! module it is in or its generic arguments
+
+        let name = std::any::type_name::<Self>();
+        let name = name.split('<').next().unwrap_or(name);
+        name.rsplit("::").next().unwrap_or(name)
+    }
+
+    fn add_methods(_methods : &mut UserDataMethods<Self>) { }
+
+    fn trace(&self, _visit : &mut dyn FnMut(&Value)) {
+        //! gives each of the lua values it is holding on to, like a
+        //! `Function` kept for a callback. the garbage collector can't see
+        //! them otherwise, so a cycle through the data is never collected.
+    }
+}
+
+type Getter = Rc<dyn Fn(&Interpreter, &Value) -> Result<Value,Error>>;
+type Setter = Rc<dyn Fn(&Interpreter, &Value, Value) -> Result<(),Error>>;
+
+/// what `UserData::add_methods` adds the type's methods, fields and
+/// metamethods to. methods are called as `object:name(...)` and the
+/// arguements they get don't have the object in them.
+pub struct UserDataMethods<T> {
+    methods : Table,
+    meta_methods : Table,
+    getters : HashMap<LuaString, Getter>,
+    setters : HashMap<LuaString, Setter>,
+    data : PhantomData<T>,
+}
+
+impl<T : UserData> UserDataMethods<T> {
+    pub(crate) fn new() -> UserDataMethods<T> {
+        UserDataMethods {
+            methods : Table::new(),
+            meta_methods : Table::new(),
+            getters : HashMap::new(),
+            setters : HashMap::new(),
+            data : PhantomData,
+        }
+    }
+
+    pub fn method<F>(&mut self, name : &str, method : F)
+        where F : Fn(&Interpreter, &T, Args) -> Result<Vec<Value>,Error> + 'static {
+        self.methods.set_str(name, borrowing_method(name, method));
+    }
+
+    pub fn method_mut<F>(&mut self, name : &str, method : F)
+        where F : Fn(&Interpreter, &mut T, Args) -> Result<Vec<Value>,Error> + 'static {
+        //! a method that changes the data, the data can't be used by
+        //! anything else until it is done
+
+        self.methods.set_str(name, mutating_method(name, method));
+    }
+
+    pub fn function<F>(&mut self, name : &str, function : F)
+        where F : Fn(&Interpreter, Args) -> Result<Vec<Value>,Error> + 'static {
+        //! a function that is looked up like a method, it gets all of its
+        //! arguements as they are
+
+        self.methods.set_str(name, native_function(name, function));
+    }
+
+    pub fn field_getter<F>(&mut self, name : &str, getter : F)
+        where F : Fn(&Interpreter, &T) -> Result<Value,Error> + 'static {
+        //! what `object.name` is
+
+        let field = name.to_string();
+        self.getters.insert(LuaString::from(name), Rc::new(move |interpreter : &Interpreter, object : &Value| {
+            let data = borrow_self::<T>(object, &field)?;
+            let data = data.borrow::<T>()?;
+            getter(interpreter, &data)
+        }));
+    }
+
+    pub fn field_setter<F>(&mut self, name : &str, setter : F)
+        where F : Fn(&Interpreter, &mut T, Value) -> Result<(),Error> + 'static {
+        //! what happens with `object.name = value`
+
+        let field = name.to_string();
+        self.setters.insert(LuaString::from(name), Rc::new(move |interpreter : &Interpreter, object : &Value, value : Value| {
+            let data = borrow_self::<T>(object, &field)?;
+            let mut data = data.borrow_mut::<T>()?;
+            setter(interpreter, &mut data, value)
+        }));
+    }
+
+    pub fn meta_method<F>(&mut self, event : &str, method : F)
+        where F : Fn(&Interpreter, &T, Args) -> Result<Vec<Value>,Error> + 'static {
+        //! a metamethod like `__add` or `__tostring`, the object has to be
+        //! the first operand
+
+        self.meta_methods.set_str(event, borrowing_method(event, method));
+    }
+
+    pub fn meta_method_mut<F>(&mut self, event : &str, method : F)
+        where F : Fn(&Interpreter, &mut T, Args) -> Result<Vec<Value>,Error> + 'static {
+        self.meta_methods.set_str(event, mutating_method(event, method));
+    }
+
+    pub fn meta_function<F>(&mut self, event : &str, function : F)
+        where F : Fn(&Interpreter, Args) -> Result<Vec<Value>,Error> + 'static {
+        //! a metamethod that gets all the operands as they are, for when
+        //! the object might not be the first one
+
+        self.meta_methods.set_str(event, native_function(event, function));
+    }
+
+    pub(crate) fn into_metatable(self) -> Table {
+        //! the metatable all the values of the type share. methods and
+        //! fields are looked up first, then the `__index` and `__newindex`
+        //! the type added itself, if it did.
+
+        let UserDataMethods { methods, meta_methods : mut metatable, getters, setters, .. } = self;
+        let methods = Rc::new(RefCell::new(methods));
+
+        let index = metatable.get_str("__index");
+        if getters.is_empty() && index.is_nil() {
+            metatable.set_str("__index", Value::Table(methods));
+        } else {
+            metatable.set_str("__index", native_function("__index", move |interpreter, args| {
+                let (object, key) = (args.get(1), args.get(2));
+
+                let method = methods.borrow().get(&key);
+                if !method.is_nil() {
+                    return Ok(vec![method]);
+                }
+                if let Some(getter) = key.to_lua_string().and_then(|name| getters.get(&name)) {
+                    return Ok(vec![getter(interpreter, &object)?]);
+                }
+
+                match index {
+                    Value::Nil => Ok(vec![Value::Nil]),
+                    Value::Table(_) => Ok(vec![interpreter.index(&index, &key)?]),
+                    ref function => interpreter.call(function, vec![object, key]),
+                }
+            }));
+        }
+
+        let new_index = metatable.get_str("__newindex");
+        if !setters.is_empty() {
+            metatable.set_str("__newindex", native_function("__newindex", move |interpreter, args| {
+                let (object, key, value) = (args.get(1), args.get(2), args.get(3));
+
+                if let Some(setter) = key.to_lua_string().and_then(|name| setters.get(&name)) {
+                    setter(interpreter, &object, value)?;
+                    return Ok(Vec::new());
+                }
+
+                match new_index {
+                    Value::Nil => Err(RuntimeError::general(&format!("attempt to set unknown field '{}' of '{}'", key, T::type_name()))),
+                    Value::Table(_) => { interpreter.set_index(&new_index, key, value)?; Ok(Vec::new()) },
+                    ref function => interpreter.call(function, vec![object, key, value]),
+                }
+            }));
+        }
+
+        metatable
+    }
+}
+
+// PRIVATE FUNCTIONS /////////////////////////////////////
+
+fn borrowing_method<T, F>(name : &str, method : F) -> Value
+    where T : UserData, F : Fn(&Interpreter, &T, Args) -> Result<Vec<Value>,Error> + 'static {
+    let method_name = name.to_string();
+
+    native_function(name, move |interpreter, args| {
+        let (object, args) = args.split_self();
+        let data = borrow_self::<T>(&object, &method_name)?;
+        let data = data.borrow::<T>()?;
+        method(interpreter, &data, args)
+    })
+}
+
+fn mutating_method<T, F>(name : &str, method : F) -> Value
+    where T : UserData, F : Fn(&Interpreter, &mut T, Args) -> Result<Vec<Value>,Error> + 'static {
+    let method_name = name.to_string();
+
+    native_function(name, move |interpreter, args| {
+        let (object, args) = args.split_self();
+        let data = borrow_self::<T>(&object, &method_name)?;
+        let mut data = data.borrow_mut::<T>()?;
+        method(interpreter, &mut data, args)
+    })
+}
+
+fn borrow_self<T : UserData>(object : &Value, name : &str) -> Result<Rc<AnyUserData>,Error> {
+    //! the object a method was called on, if it is the right type
+
+    match object {
+        Value::UserData(data) if data.is::<T>() => Ok(data.clone()),
+        object => Err(RuntimeError::general(&format!("calling '{}' on bad self ({} expected, got {})", name, T::type_name(), object.type_name()))),
+    }
+}
+
+fn trace_userdata<T : UserData>(data : &dyn Any, visit : &mut dyn FnMut(&Value)) {
+    if let Some(data) = data.downcast_ref::<T>() {
+        data.trace(visit);
+    }
+}
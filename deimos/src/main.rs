@@ -2,29 +2,22 @@ use std::env;
 use std::io::{stdin,stdout,Write,prelude::*};
 use std::fs::File;
 
-use log::{error, debug, info};
-use pretty_env_logger;
-
-use deimos_core;
+use log::{error, debug};
 
+#[derive(Default)]
 pub struct Options {
     pub show_every_result : bool,
     pub interactive_mode : bool,
     pub run_file : Option<String>,
-    pub load_file : Vec<String>,
+    // the code given with `-e`, run before the script
+    pub execute : Vec<String>,
+    // `-l` lists the bytecode instead of running it, twice for everything
+    pub list : usize,
     pub file_args : Vec<String>,
-}
-
-impl Default for Options {
-    fn default() -> Self {
-        Options {
-            show_every_result : false,
-            interactive_mode : false,
-            run_file : None,
-            file_args : Vec::new(),
-            load_file : Vec::new(),
-        }
-    }
+    // everything that was on the command line, and where the script is in
+    // it, for the `arg` table
+    pub args : Vec<String>,
+    pub script : usize,
 }
 
 
@@ -43,40 +36,69 @@ fn main() {
     let mut i = 1;
     loop {
         if i >= args.len() { break; }
+        // everything after the script belongs to the script
+        if options.script > 0 {
+            options.file_args.push(args[i].to_string());
+            i += 1;
+            continue;
+        }
         match args[i].as_str() {
             "-d" => { options.show_every_result = true; },
             "-v" => { print_version_string(); break; },
             "-i" => options.interactive_mode = true,
-            "-l" => { options.load_file.push(args[i+1].to_string()); i += 1; }
-            "-e" => { options.run_file = Some(args[i+1].to_string()); i += 1; }
+            "-l" => options.list += 1,
+            "-e" => match args.get(i+1) {
+                Some(code) => { options.execute.push(code.to_string()); i += 1; },
+                None => failed("'-e' needs argument"),
+            },
             string => match &string[0 .. 1] {
                 "-" => { error!("Found argument '{}' which wasn't expected, or isn't valid in this context",string); },
                 _ => {
-                    if options.run_file.is_none() { options.run_file = Some(string.to_string()); }
+                    if options.run_file.is_none() { options.run_file = Some(string.to_string()); options.script = i; }
                     else { options.file_args.push(string.to_string()); }
                 }
             }
         }
         i += 1;
     }
-    
+    options.args = args;
+
     process_args(&options);
 }
 
 fn process_args(options : &Options) {
-    for i in 0 .. options.load_file.len() {
-        info!("loading file: {}",options.load_file[i]);
-    }
-    
     if options.interactive_mode {
         interactive_mode(options);
         return;
     }
 
     if let Some(ref file) = options.run_file {
-        run_file(&file,options);
-        return;
+        if options.list > 0 {
+            list_file(file,options);
+            return;
+        }
     }
+
+    // the `-e` code and the script all run in the same interpreter
+    let interpreter = deimos_core::Interpreter::new();
+    for code in options.execute.iter() {
+        if let Err(error) = interpreter.run(code, Some("(command line)")) {
+            failed(error);
+        }
+    }
+
+    if let Some(ref file) = options.run_file {
+        run_file(&interpreter,file,options);
+    }
+}
+
+fn failed<E : std::fmt::Display>(error : E) -> ! {
+    //! stops with the error the way `lua` does, exiting with 1 so whoever
+    //! ran it can tell. what was printed is flushed first, exiting skips it
+
+    error!("{}",error);
+    let _ = stdout().flush();
+    std::process::exit(1)
 }
 
 fn print_version_string() {
@@ -84,20 +106,24 @@ fn print_version_string() {
 }
 
 fn get_prompt() -> String {
-    format!(">")
+    String::from(">")
 }
 
-fn run_file(file_path : &str, options : &Options) {
+fn run_file(interpreter : &deimos_core::Interpreter, file_path : &str, options : &Options) {
     match File::open(file_path) {
-        Err(error) => error!("{}",error),
+        Err(error) => failed(error),
         Ok(mut file) => {
-            let mut buffer : String = String::new();
-            match file.read_to_string(&mut buffer) {
-                Err(error) => error!("{}",error),
+            let mut buffer : Vec<u8> = Vec::new();
+            match file.read_to_end(&mut buffer) {
+                Err(error) => failed(error),
                 Ok(_) => {
-                    match deimos_core::run(&buffer) {
-                        Err(error) => error!("{}",error),
-                        Ok(result) => if !result.is_empty() { println!("{}",result); },
+                    let args = script_args(interpreter, options);
+                    match interpreter.load(&buffer, file_path).and_then(|function| interpreter.call(&function, args)) {
+                        Err(error) => failed(error),
+                        Ok(values) => {
+                            let result = deimos_core::ReturnValues::new(values);
+                            if !result.is_empty() { println!("{}",result); }
+                        },
                     }
                 }
             }
@@ -105,6 +131,34 @@ fn run_file(file_path : &str, options : &Options) {
     }
 }
 
+fn script_args(interpreter : &deimos_core::Interpreter, options : &Options) -> Vec<deimos_core::Value> {
+    //! sets the global `arg` the way `lua` does, the script is at 0, its
+    //! arguements count up from 1 and the interpreter and the options
+    //! before the script count down from -1. gives back the arguements
+    //! so the script gets them as `...` too
+
+    let mut table = deimos_core::Table::new();
+    for (i, arg) in options.args.iter().enumerate() {
+        let index = i as f64 - options.script as f64;
+        table.set(deimos_core::Value::Number(index), deimos_core::Value::from(arg.as_str())).expect("a number key");
+    }
+    interpreter.set_global("arg", deimos_core::Value::from(table));
+
+    options.file_args.iter().map(|arg| deimos_core::Value::from(arg.as_str())).collect()
+}
+
+fn list_file(file_path : &str, options : &Options) {
+    //! prints the bytecode the file compiles to, like `luac -l`
+
+    match std::fs::read(file_path) {
+        Err(error) => failed(error),
+        Ok(buffer) => match deimos_core::listing(&buffer, file_path, options.list > 1) {
+            Err(error) => failed(error),
+            Ok(listing) => print!("{}",listing),
+        }
+    }
+}
+
 fn interactive_mode(options : &Options) {
     print_version_string();
     
@@ -116,10 +170,15 @@ fn interactive_mode(options : &Options) {
     loop {
         print!("{}{}",&prompt,&prompt_extra);
         let _ = stdout().flush();
-        stdin().read_line(&mut input);
+        match stdin().read_line(&mut input) {
+            // end of the input stream (ctrl-d), nothing else will ever come
+            Ok(0) => { println!(); break; },
+            Ok(_) => { },
+            Err(error) => { error!("{}",error); break; },
+        }
 
         match deimos_core::Repl::check_for_complete_statement(&input) {
-            Ok(false) => { prompt_extra = format!(">"); continue },
+            Ok(false) => { prompt_extra = String::from(">"); continue },
             Err(error) => error!("{}",error),
             Ok(true) => prompt_extra = String::new(),
         }